@@ -0,0 +1,69 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use sage::{lir::*, parse::*, vm::*};
+use std::fs::read_to_string;
+
+const CALL_STACK_SIZE: usize = 8192;
+
+/// The example programs to run, and the input to give them.
+const EXAMPLES: &[(&str, &str)] = &[
+    ("AES", "hello world!"),
+    ("chacha20", ""),
+    ("hashmap", "hello world!"),
+    ("rng", ""),
+    ("sequence", "hello world!"),
+    ("sequence2", "hello world!"),
+    ("trees", ""),
+];
+
+fn compile_frontend_file(filename: &str) -> Result<CoreProgram, StandardProgram> {
+    let code = read_to_string(filename).unwrap();
+    match parse_frontend(code, Some(filename))
+        .unwrap()
+        .compile()
+        .unwrap()
+    {
+        Ok(asm_code) => Ok(asm_code.assemble(CALL_STACK_SIZE).unwrap()),
+        Err(asm_code) => Err(asm_code.assemble(CALL_STACK_SIZE).unwrap()),
+    }
+}
+
+fn bench_interpreters(c: &mut Criterion) {
+    let mut group = c.benchmark_group("VM Interpreters");
+    group.sample_size(10);
+
+    for (name, input) in EXAMPLES {
+        // Compile the example once, and only measure running it.
+        match compile_frontend_file(&format!("examples/frontend/{name}.sg")) {
+            Ok(program) => {
+                group.bench_function(format!("{name} (core interpreter)"), |b| {
+                    b.iter(|| {
+                        CoreInterpreter::new(TestingDevice::new(input))
+                            .run(&program)
+                            .unwrap()
+                    })
+                });
+                let program = StandardProgram::from(program);
+                group.bench_function(format!("{name} (standard interpreter)"), |b| {
+                    b.iter(|| {
+                        StandardInterpreter::new(TestingDevice::new(input))
+                            .run(&program)
+                            .unwrap()
+                    })
+                });
+            }
+            Err(program) => {
+                group.bench_function(format!("{name} (standard interpreter)"), |b| {
+                    b.iter(|| {
+                        StandardInterpreter::new(TestingDevice::new(input))
+                            .run(&program)
+                            .unwrap()
+                    })
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_interpreters);
+criterion_main!(benches);
@@ -218,7 +218,7 @@ impl Device for WasmDevice {
         Ok(())
     }
 
-    fn ffi_call(&mut self, ffi: &FFIBinding, tape: Option<&mut Vec<i64>>) -> Result<(), String> {
+    fn ffi_call(&mut self, ffi: &FFIBinding, tape: Option<&mut [i64]>) -> Result<(), String> {
         if let Some(f) = self.ffi.get(ffi) {
             f(&mut self.ffi_channel, tape);
             Ok(())
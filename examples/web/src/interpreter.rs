@@ -640,7 +640,8 @@ impl<T> WasmInterpreter<T> where T: Device {
                 }
                 StandardOp::Free => {}
                 StandardOp::Call(binding) => {
-                    self.device.ffi_call(binding, Some(&mut self.cells))?;
+                    self.device
+                        .ffi_call(binding, Some(self.cells.as_mut_slice()))?;
                 }
             }
             self.i += 1
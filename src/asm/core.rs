@@ -31,6 +31,7 @@ use super::{
     AssemblyProgram, Env, Error, Location, StandardOp, FP, GP, SP, STACK_START, START_OF_FP_STACK,
 };
 use crate::{
+    parse::SourceCodeLocation,
     side_effects::{Input, InputMode, Output, OutputMode},
     vm::{self, VirtualMachineProgram},
 };
@@ -48,6 +49,9 @@ pub struct CoreProgram {
     /// that has been defined so far. This helps the LIR compiler
     /// determine if a function has been compiled yet or not.
     labels: BTreeSet<String>,
+    /// The source code location marked by each location marker in the program,
+    /// and the index of the marker's comment.
+    locations: Vec<(usize, SourceCodeLocation)>,
 }
 
 /// A default program is an empty program.
@@ -66,7 +70,11 @@ impl CoreProgram {
                 labels.insert(label.clone());
             }
         }
-        Self { code, labels }
+        Self {
+            code,
+            labels,
+            locations: vec![],
+        }
     }
 
     /// Get the size of the globals in the program.
@@ -86,6 +94,17 @@ impl CoreProgram {
     /// Assemble a program of core assembly instructions into the
     /// core virtual machine instructions.
     pub fn assemble(&self, allowed_recursion_depth: usize) -> Result<vm::CoreProgram, Error> {
+        let (program, _) = self.assemble_unflattened(allowed_recursion_depth)?;
+        Ok(program.flatten())
+    }
+
+    /// Assemble the program into virtual machine instructions, before its functions
+    /// are flattened, along with the index of the first instruction assembled from
+    /// each assembly instruction.
+    fn assemble_unflattened(
+        &self,
+        allowed_recursion_depth: usize,
+    ) -> Result<(vm::CoreProgram, Vec<usize>), Error> {
         // Create the result program.
         let mut result = vm::CoreProgram(vec![]);
         // Create the environment in which to assemble the program.
@@ -125,7 +144,9 @@ impl CoreProgram {
         // Copy the stack pointer to the frame pointer
         SP.copy_to(&FP, &mut result);
         // For all the operations in the program, assemble them.
+        let mut starts = Vec::with_capacity(self.code.len());
         for (i, op) in self.code.iter().enumerate() {
+            starts.push(result.0.len());
             op.assemble(i, &mut env, &mut result)?
         }
 
@@ -135,7 +156,29 @@ impl CoreProgram {
         }
 
         // Return the result.
-        Ok(result.flatten())
+        Ok((result, starts))
+    }
+
+    /// Assemble the program into a virtual machine program, along with the source map
+    /// from each of its instructions to the source code it was compiled from.
+    ///
+    /// The source map is built from the locations recorded with the program's
+    /// location markers, so they keep their offsets and lengths.
+    pub fn assemble_with_source_map(
+        &self,
+        allowed_recursion_depth: usize,
+    ) -> Result<(vm::CoreProgram, vm::SourceMap), Error> {
+        let (program, starts) = self.assemble_unflattened(allowed_recursion_depth)?;
+        let (program, indices) = program.flatten_with_indices();
+        // Each marker is assembled into a single comment, which is moved with its function.
+        let markers = self
+            .locations
+            .iter()
+            .map(|(i, location)| (indices[starts[*i]], location.clone()))
+            .collect::<Vec<_>>();
+        let map =
+            vm::SourceMap::with_locations(&vm::StandardProgram::from(program.clone()).0, markers);
+        Ok((program, map))
     }
 }
 
@@ -256,6 +299,11 @@ impl AssemblyProgram for CoreProgram {
         self.labels.contains(label)
     }
 
+    fn location(&mut self, location: &SourceCodeLocation) {
+        self.locations.push((self.code.len(), location.clone()));
+        self.comment(location.to_comment());
+    }
+
     fn current_instruction(&self) -> usize {
         self.code.len()
     }
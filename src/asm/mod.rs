@@ -29,6 +29,8 @@
 use ::core::fmt::{Display, Formatter, Result as FmtResult};
 use ::std::collections::HashMap;
 
+use crate::parse::SourceCodeLocation;
+
 use log::{debug, error, trace, warn};
 
 pub mod core;
@@ -58,6 +60,11 @@ pub trait AssemblyProgram {
     fn comment(&mut self, comment: String) {
         self.op(CoreOp::Comment(comment))
     }
+    /// Mark the instructions which follow as coming from a location in the source code.
+    /// The marker is a comment, so it's kept when the program is assembled.
+    fn location(&mut self, location: &SourceCodeLocation) {
+        self.comment(location.to_comment())
+    }
     /// Is the given label defined yet in the operations?
     /// I.E., has a `CoreOp::Fn` with this label been inserted
     /// into the program code yet?
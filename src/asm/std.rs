@@ -10,8 +10,8 @@ use super::{
     location::*, AssemblyProgram, CoreOp, CoreProgram, Env, Error, Location, FP, GP, SP,
     START_OF_FP_STACK,
 };
-use crate::side_effects::ffi::FFIBinding;
 use crate::vm::{self, VirtualMachineProgram};
+use crate::{parse::SourceCodeLocation, side_effects::ffi::FFIBinding};
 use std::{collections::BTreeSet, fmt};
 
 use log::info;
@@ -26,6 +26,9 @@ pub struct StandardProgram {
     /// that has been defined so far. This helps the LIR compiler
     /// determine if a function has been compiled yet or not.
     labels: BTreeSet<String>,
+    /// The source code location marked by each location marker in the program,
+    /// and the index of the marker's comment.
+    locations: Vec<(usize, SourceCodeLocation)>,
 }
 
 /// A default program is an empty program.
@@ -45,7 +48,11 @@ impl StandardProgram {
                 labels.insert(label.clone());
             }
         }
-        Self { code, labels }
+        Self {
+            code,
+            labels,
+            locations: vec![],
+        }
     }
 
     /// Get the size of the globals.
@@ -67,6 +74,17 @@ impl StandardProgram {
     /// The frame pointer stack is used to keep track of the frame pointers
     /// of each function call.
     pub fn assemble(&self, allowed_recursion_depth: usize) -> Result<vm::StandardProgram, Error> {
+        let (program, _) = self.assemble_unflattened(allowed_recursion_depth)?;
+        Ok(program.flatten())
+    }
+
+    /// Assemble the program into virtual machine instructions, before its functions
+    /// are flattened, along with the index of the first instruction assembled from
+    /// each assembly instruction.
+    fn assemble_unflattened(
+        &self,
+        allowed_recursion_depth: usize,
+    ) -> Result<(vm::StandardProgram, Vec<usize>), Error> {
         let mut result = vm::StandardProgram(vec![]);
         let mut env = Env::default();
 
@@ -97,7 +115,9 @@ impl StandardProgram {
         starting_sp_addr.copy_address_to(&STACK_START, &mut result);
 
         SP.copy_to(&FP, &mut result);
+        let mut starts = Vec::with_capacity(self.code.len());
         for (i, op) in self.code.iter().enumerate() {
+            starts.push(result.0.len());
             op.assemble(i, &mut env, &mut result)?
         }
 
@@ -105,7 +125,28 @@ impl StandardProgram {
             return Err(Error::Unmatched(unmatched, last_instruction));
         }
 
-        Ok(result.flatten())
+        Ok((result, starts))
+    }
+
+    /// Assemble the program into a virtual machine program, along with the source map
+    /// from each of its instructions to the source code it was compiled from.
+    ///
+    /// The source map is built from the locations recorded with the program's
+    /// location markers, so they keep their offsets and lengths.
+    pub fn assemble_with_source_map(
+        &self,
+        allowed_recursion_depth: usize,
+    ) -> Result<(vm::StandardProgram, vm::SourceMap), Error> {
+        let (program, starts) = self.assemble_unflattened(allowed_recursion_depth)?;
+        let (program, indices) = program.flatten_with_indices();
+        // Each marker is assembled into a single comment, which is moved with its function.
+        let markers = self
+            .locations
+            .iter()
+            .map(|(i, location)| (indices[starts[*i]], location.clone()))
+            .collect::<Vec<_>>();
+        let map = vm::SourceMap::with_locations(&program.0, markers);
+        Ok((program, map))
     }
 }
 
@@ -248,6 +289,11 @@ impl AssemblyProgram for StandardProgram {
         self.labels.contains(label)
     }
 
+    fn location(&mut self, location: &SourceCodeLocation) {
+        self.locations.push((self.code.len(), location.clone()));
+        self.comment(location.to_comment());
+    }
+
     /// Get the current instruction number.
     fn current_instruction(&self) -> usize {
         self.code.len()
@@ -4,16 +4,19 @@
 //! language. This can compile sage's various source languages to
 //! the supported targets provided by the compiler.
 use clap::*;
+use codespan_reporting::diagnostic::Severity;
 use sage::{
-    lir::*,
     parse::*,
+    runtime::{AsmProgram, Program, RuntimeError, SourceType},
     targets::{self, CompiledTarget},
     vm::*,
     LOGO_WITH_COLOR, *,
 };
 use std::{
+    collections::HashMap,
     fmt,
-    fs::{read_to_string, write},
+    fs::{read, read_to_string, write},
+    io::{stdin, stdout, Write},
 };
 
 use log::error;
@@ -40,6 +43,18 @@ enum LogLevel {
     Off,
 }
 
+/// How warnings about the source code are reported.
+#[derive(clap::ValueEnum, Default, Clone, Copy, Debug, PartialEq)]
+enum WarningLevel {
+    /// Don't check the source code for warnings.
+    Allow,
+    /// Report the warnings, and keep compiling.
+    #[default]
+    Warn,
+    /// Report the warnings as errors, and stop compiling if there are any.
+    Deny,
+}
+
 /// The target options to compile the given source code to.
 #[derive(clap::ValueEnum, Clone, Copy, Debug)]
 enum TargetType {
@@ -53,38 +68,57 @@ enum TargetType {
     CoreVM,
     /// Compile to the standard variant of the virtual machine.
     StdVM,
-    // /// Compile to My OS source code (GCC only).
-    // SageOS,
-    /// Compile to C source code (GCC only).
+    /// Compile to the binary bytecode of the virtual machine, which can be run with Sage.
+    Bytecode,
+    /// Compile to C99 source code (for GCC or Clang).
     C,
-    // /// Compile to x86 assembly code.
-    // X86,
+    /// Compile to C source code for the SageOS userspace.
+    SageOS,
+    /// Compile to x86-64 assembly code for Linux (GNU assembler syntax).
+    X86,
 }
 
-/// The source language options to compile.
-#[derive(clap::ValueEnum, Clone, Copy, Debug)]
-enum SourceType {
-    /// Compile Sage Frontend code.
-    Sage,
-    /// Compile LIR code.
-    LowIR,
-    /// Compile core variant assembly code.
-    CoreASM,
-    /// Compile standard variant assembly code.
-    StdASM,
-    /// Compile core variant virtual machine code.
-    CoreVM,
-    /// Compile standard variant virtual machine code.
-    StdVM,
+/// The tools the CLI can run instead of compiling an input file.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Format frontend source files in place.
+    Fmt {
+        /// The frontend source files to format.
+        #[clap(value_parser, required = true)]
+        files: Vec<String>,
+
+        /// Only check that the files are formatted, without changing them.
+        /// Fails if any of the files aren't formatted.
+        #[clap(long)]
+        check: bool,
+    },
+    /// Step through a program one instruction at a time in the interactive debugger.
+    Debug {
+        /// The program to debug.
+        #[clap(value_parser)]
+        input: String,
+
+        /// The source language of the program.
+        #[clap(short, value_parser, default_value = "sage")]
+        source_type: SourceType,
+
+        /// The number of cells allocated for the call stack.
+        #[clap(short, long, value_parser, default_value = "8192")]
+        call_stack_size: usize,
+    },
 }
 
 /// The argument parser for the CLI.
 #[derive(Parser, Debug)]
-#[clap(author, version, about = Some(LOGO_WITH_COLOR), long_about = Some(LOGO_WITH_COLOR), max_term_width=90)]
+#[clap(author, version, about = Some(LOGO_WITH_COLOR), long_about = Some(LOGO_WITH_COLOR), max_term_width=90, subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
+    /// A tool to run instead of compiling an input file.
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// The input file to compiler.
-    #[clap(value_parser)]
-    input: String,
+    #[clap(value_parser, required = true)]
+    input: Option<String>,
 
     /// The file to write the output of the compiler to.
     #[clap(short, long, value_parser, default_value = "out")]
@@ -102,6 +136,19 @@ struct Args {
     #[clap(short, long, value_parser, default_value = "8192")]
     call_stack_size: usize,
 
+    /// The optimization level for the virtual machine code. Level 1 folds
+    /// redundant instructions, and level 2 also removes dead code.
+    #[clap(short = 'O', long, value_parser, default_value = "0")]
+    opt_level: usize,
+
+    /// How to report warnings about the source code, like unused variables.
+    #[clap(short = 'W', long, value_parser, default_value = "warn")]
+    warnings: WarningLevel,
+
+    /// Turn warnings into errors. This is the same as `--warnings deny`.
+    #[clap(long)]
+    deny_warnings: bool,
+
     /// The log level to use.
     #[clap(short, long, value_parser, default_value = "off")]
     log_level: LogLevel,
@@ -110,6 +157,12 @@ struct Args {
     /// also enable debug logging.
     #[clap(short, long, value_parser)]
     debug: Option<String>,
+
+    /// Count the instructions executed by the program when running it. The flat
+    /// profile is printed to stderr, and the folded stacks for flame graph tools
+    /// are written to the output file with the `.folded` extension.
+    #[clap(long)]
+    profile: bool,
 }
 
 /// The types of errors returned by the CLI.
@@ -119,7 +172,12 @@ enum Error {
         loc: SourceCodeLocation,
         source_code: String,
         err: Box<Self>,
+        /// Other locations in the source code related to the error,
+        /// with a message describing each of them.
+        related: Vec<(SourceCodeLocation, String)>,
     },
+    /// Several errors, each of which is reported as its own diagnostic.
+    Many(Vec<Self>),
     /// Error in reading source or writing generated code.
     IO(std::io::Error),
     /// Error parsing the source code.
@@ -129,22 +187,57 @@ enum Error {
     /// Error generated when assembling input code.
     AsmError(asm::Error),
     /// Error generated by the interpreter executing input code.
-    InterpreterError(String),
+    InterpreterError(Trap),
     /// Error when building the virtual machine code for a given target.
     BuildError(String),
     /// Invalid source code (expected core but got standard).
     InvalidSource(String),
+    /// The source code had the given number of warnings, and warnings are denied.
+    DeniedWarnings(usize),
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Self {
+        match e {
+            RuntimeError::Parse(e) => Self::Parse(e),
+            RuntimeError::Lir(e) => Self::LirError(*e),
+            RuntimeError::Asm(e) => Self::AsmError(e),
+            RuntimeError::Bytecode(e) => Self::Parse(e.to_string()),
+            RuntimeError::InvalidSource(e) => Self::InvalidSource(e),
+            RuntimeError::Trap(trap) => Self::InterpreterError(trap),
+            RuntimeError::CompileThreads(e) => Self::IO(std::io::Error::other(e)),
+        }
+    }
 }
 
 impl Error {
     pub fn annotate_with_source(self, code: &str) -> Self {
         match self {
+            Self::LirError(err @ lir::Error::Many(_)) => {
+                // Report each of the errors separately.
+                Self::Many(
+                    err.into_errors()
+                        .into_iter()
+                        .map(|err| Self::LirError(err).annotate_with_source(code))
+                        .collect(),
+                )
+            }
             Self::LirError(lir::Error::Annotated(ref err, ref metadata)) => {
                 if let Some(loc) = metadata.location().cloned() {
+                    // The error may be in an imported module, rather than in the given source.
+                    let source_code = match loc.filename.as_deref() {
+                        Some(filename) => sage::frontend::std_module(filename)
+                            .map(String::from)
+                            .or_else(|| std::fs::read_to_string(filename).ok()),
+                        None => None,
+                    }
+                    .unwrap_or_else(|| code.to_owned());
+                    let related = related_definitions(metadata, &loc);
                     Self::WithSourceCode {
                         loc,
-                        source_code: code.to_owned(),
+                        source_code,
                         err: Box::new(Error::LirError(*err.clone())),
+                        related,
                     }
                 } else {
                     self
@@ -155,6 +248,20 @@ impl Error {
     }
 }
 
+/// Get the definitions of the symbols related to an error, which the type checker
+/// annotated it with, other than at the location of the error itself.
+fn related_definitions(
+    metadata: &lir::Annotation,
+    loc: &SourceCodeLocation,
+) -> Vec<(SourceCodeLocation, String)> {
+    metadata
+        .definitions()
+        .into_iter()
+        .filter(|(_, def)| def.filename != loc.filename || def.offset != loc.offset)
+        .map(|(name, def)| (def.clone(), format!("`{name}` is defined here")))
+        .collect()
+}
+
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -166,182 +273,184 @@ impl fmt::Debug for Error {
                 loc,
                 source_code,
                 err,
+                related,
             } => {
-                // use codespan_reporting::files::SimpleFiles;
-                use codespan_reporting::diagnostic::{Diagnostic, Label};
-                use codespan_reporting::files::SimpleFiles;
-                use codespan_reporting::term::{
-                    emit,
-                    termcolor::{ColorChoice, StandardStream},
-                };
-                use no_comment::{languages, IntoWithoutComments};
-
-                let SourceCodeLocation {
-                    line,
-                    column,
-                    filename,
-                    offset,
-                    length,
-                } = loc;
-
-                let mut files = SimpleFiles::new();
-
-                let source_code = source_code
-                    .to_string()
-                    .chars()
-                    .without_comments(languages::rust())
-                    .collect::<String>();
-
-                let filename = filename.clone().unwrap_or("unknown".to_string());
-
-                let file_id = files.add(filename.clone(), source_code);
-
-                let loc = format!("{}:{}:{}:{}", filename, line, column, offset);
-
-                // let code = format!("{}\n{}^", code, " ".repeat(*column - 1));
-                // write!(f, "Error at {}:\n{}\n{:?}", loc, code, err)?
-
-                let diagnostic = Diagnostic::error()
-                    .with_message(format!("Error at {}", loc))
-                    .with_labels(vec![Label::primary(
-                        file_id,
-                        *offset..*offset + length.unwrap_or(0),
-                    )
-                    .with_message(format!("{err:?}"))]);
-
-                let writer = StandardStream::stderr(ColorChoice::Always);
-                let config = codespan_reporting::term::Config::default();
-
-                emit(&mut writer.lock(), &config, &files, &diagnostic).unwrap();
-
+                emit_diagnostic(
+                    Severity::Error,
+                    loc,
+                    source_code,
+                    format!("{err:?}"),
+                    related,
+                );
+                Ok(())
+            }
+            Error::Many(errs) => {
+                for (i, err) in errs.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{err:?}")?;
+                }
                 Ok(())
             }
-            Error::InterpreterError(e) => write!(f, "Interpreter error: {}", e),
+            Error::InterpreterError(trap) => write!(f, "{trap}"),
             Error::BuildError(e) => write!(f, "Build error: {}", e),
             Error::InvalidSource(e) => write!(f, "Invalid source: {}", e),
+            Error::DeniedWarnings(count) => {
+                write!(f, "Compilation failed because of {count} denied warning(s)")
+            }
         }
     }
 }
 
-/// Compile a given source language to virtual machine code.
-fn compile_source_to_vm(
+/// Print a diagnostic about a location in some source code to standard error,
+/// with labels for the other locations related to it.
+fn emit_diagnostic(
+    severity: Severity,
+    loc: &SourceCodeLocation,
+    source_code: &str,
+    message: String,
+    related: &[(SourceCodeLocation, String)],
+) {
+    use codespan_reporting::diagnostic::{Diagnostic, Label};
+    use codespan_reporting::files::SimpleFiles;
+    use codespan_reporting::term::{
+        emit,
+        termcolor::{ColorChoice, StandardStream},
+    };
+
+    let SourceCodeLocation {
+        line,
+        column,
+        filename,
+        offset,
+        length,
+    } = loc;
+
+    let mut files = SimpleFiles::new();
+
+    let filename = filename.clone().unwrap_or("unknown".to_string());
+
+    let file_id = files.add(filename.clone(), source_code.to_string());
+
+    let loc = format!("{}:{}:{}:{}", filename, line, column, offset);
+    let kind = match severity {
+        Severity::Warning => "Warning",
+        _ => "Error",
+    };
+
+    let diagnostic = Diagnostic::new(severity)
+        .with_message(format!("{kind} at {loc}"))
+        .with_labels(vec![Label::primary(
+            file_id,
+            *offset..*offset + length.unwrap_or(0),
+        )
+        .with_message(message)]);
+    // Point out the other parts of the code involved, which may be in other files.
+    let mut file_ids = HashMap::new();
+    let mut labels = vec![];
+    for (related_loc, message) in related {
+        let related_filename = related_loc
+            .filename
+            .clone()
+            .unwrap_or("unknown".to_string());
+        let related_file_id = if related_filename == filename {
+            file_id
+        } else if let Some(id) = file_ids.get(&related_filename) {
+            *id
+        } else {
+            let Some(code) = sage::frontend::std_module(&related_filename)
+                .map(String::from)
+                .or_else(|| read_to_string(&related_filename).ok())
+            else {
+                continue;
+            };
+            let id = files.add(related_filename.clone(), code);
+            file_ids.insert(related_filename, id);
+            id
+        };
+        let start = related_loc.offset;
+        labels.push(
+            Label::secondary(
+                related_file_id,
+                start..start + related_loc.length.unwrap_or(0),
+            )
+            .with_message(message),
+        );
+    }
+    let diagnostic = diagnostic.with_labels(labels);
+
+    let writer = StandardStream::stderr(ColorChoice::Always);
+    let config = codespan_reporting::term::Config::default();
+
+    emit(&mut writer.lock(), &config, &files, &diagnostic).unwrap();
+}
+
+/// Create the runtime which compiles and runs the input. The CLI's threads already
+/// have large stacks, so the runtime compiles the code on them.
+fn runtime(
     filename: Option<&str>,
-    src: String,
     src_type: SourceType,
     call_stack_size: usize,
-) -> Result<Result<sage::vm::CoreProgram, sage::vm::StandardProgram>, Error> {
-    match src_type {
-        SourceType::StdVM => {
-            // Simply parse the virtual machine code
-            parse_vm(src).map_err(Error::Parse)
-        }
-        SourceType::CoreVM => {
-            // Parse the virtual machine code
-            match parse_vm(src).map_err(Error::Parse)? {
-                // If we got a core program back, return it.
-                Ok(prog) => Ok(Ok(prog)),
-                // Otherwise, our core program was actually a standard program. Throw an error.
-                Err(_) => Err(Error::InvalidSource(
-                    "expected core VM program, got standard VM program".to_string(),
-                )),
-            }
-        }
-        SourceType::StdASM => {
-            // Parse the assembly code.
-            // Then, assembly the program with the given recursion depth,
-            // and return the virtual machine output.
-            match parse_asm(src).map_err(Error::Parse)? {
-                Ok(prog) => Ok(Ok(prog
-                    .assemble(call_stack_size)
-                    .map_err(Error::AsmError)?)),
-                Err(prog) => Ok(Err(prog
-                    .assemble(call_stack_size)
-                    .map_err(Error::AsmError)?)),
-            }
-        }
-        SourceType::CoreASM => {
-            // Parse the assembly code.
-            match parse_asm(src).map_err(Error::Parse)? {
-                // If we got back a core program, assembly it and return the virtual machine code.
-                Ok(prog) => Ok(Ok(prog
-                    .assemble(call_stack_size)
-                    .map_err(Error::AsmError)?)),
-                // Otherwise, our core program was actually a standard program. Throw an error.
-                Err(_) => Err(Error::InvalidSource(
-                    "expected core assembly program, got standard assembly program".to_string(),
-                )),
-            }
-        }
-        SourceType::LowIR => {
-            // Parse the lower intermediate representation code.
-            match parse_lir(src)
-                .map_err(Error::Parse)?
-                .compile()
-                .map_err(Error::LirError)?
-            {
-                // If we got back a valid program, assemble it and return the result.
-                Ok(asm_code) => Ok(Ok(asm_code
-                    .assemble(call_stack_size)
-                    .map_err(Error::AsmError)?)),
-                Err(asm_code) => Ok(Err(asm_code
-                    .assemble(call_stack_size)
-                    .map_err(Error::AsmError)?)),
-            }
-        }
-        SourceType::Sage => {
-            match parse_frontend(&src, filename)
-                .map_err(Error::Parse)?
-                .compile()
-                .map_err(Error::LirError)
-                .map_err(|e| e.annotate_with_source(&src))?
-            {
-                // If we got back a valid program, assemble it and return the result.
-                Ok(asm_code) => Ok(Ok(asm_code
-                    .assemble(call_stack_size)
-                    .map_err(Error::AsmError)?)),
-                Err(asm_code) => Ok(Err(asm_code
-                    .assemble(call_stack_size)
-                    .map_err(Error::AsmError)?)),
-            }
+    opt_level: usize,
+) -> Runtime {
+    let runtime = Runtime::new()
+        .with_source_type(src_type)
+        .with_call_stack_size(call_stack_size)
+        .with_opt_level(opt_level)
+        .with_compile_stack_size(None);
+    match filename {
+        Some(filename) => runtime.with_filename(filename),
+        None => runtime,
+    }
+}
+
+/// Report the warnings about some source code at the given level.
+/// If warnings are denied, this fails if there are any.
+fn check_warnings(runtime: &Runtime, src: &str, level: WarningLevel) -> Result<(), Error> {
+    if level == WarningLevel::Allow {
+        return Ok(());
+    }
+
+    let warnings = runtime.warnings(src)?;
+    let severity = match level {
+        WarningLevel::Deny => Severity::Error,
+        _ => Severity::Warning,
+    };
+    for warning in &warnings {
+        if let Some(loc) = warning.location() {
+            emit_diagnostic(severity, loc, src, warning.to_string(), &[]);
         }
     }
+
+    if level == WarningLevel::Deny && !warnings.is_empty() {
+        return Err(Error::DeniedWarnings(warnings.len()));
+    }
+    Ok(())
+}
+
+/// Compile source code to optimized virtual machine code.
+fn compile_source_to_vm(
+    runtime: &Runtime,
+    src: &str,
+    warnings: WarningLevel,
+) -> Result<Program, Error> {
+    check_warnings(runtime, src, warnings)?;
+    runtime
+        .compile(src)
+        .map_err(|e| Error::from(e).annotate_with_source(src))
 }
 
-/// Compile code in a given source language to assembly code.
+/// Compile source code to assembly code.
 fn compile_source_to_asm(
-    filename: Option<&str>,
-    src: String,
-    src_type: SourceType,
-) -> Result<Result<sage::asm::CoreProgram, sage::asm::StandardProgram>, Error> {
-    match src_type {
-        // If the source language is standard assembly, then parse it and return it.
-        SourceType::StdASM => parse_asm(src).map_err(Error::Parse),
-        // If the source language is core assembly, then parse it and return it if it's actually a core variant program.
-        // Otherwise, throw an error.
-        SourceType::CoreASM => match parse_asm(src).map_err(Error::Parse)? {
-            Ok(prog) => Ok(Ok(prog)),
-            Err(_) => Err(Error::InvalidSource(
-                "expected core assembly program, got standard assembly program".to_string(),
-            )),
-        },
-        // If the source language is LIR, parse it and compile it to assembly code.
-        SourceType::LowIR => parse_lir(src)
-            .map_err(Error::Parse)?
-            .compile()
-            .map_err(Error::LirError),
-
-        // If the source language is Sage, parse it and compile it to assembly code.
-        SourceType::Sage => parse_frontend(&src, filename)
-            .map_err(Error::Parse)?
-            .compile()
-            .map_err(Error::LirError)
-            .map_err(|e| e.annotate_with_source(&src)),
-        // If the source language is a virtual machine program,
-        // then we cannot compile it to assembly. Throw an error.
-        SourceType::CoreVM | SourceType::StdVM => Err(Error::InvalidSource(
-            "cannot compile a core VM program to assembly".to_string(),
-        )),
-    }
+    runtime: &Runtime,
+    src: &str,
+    warnings: WarningLevel,
+) -> Result<AsmProgram, Error> {
+    check_warnings(runtime, src, warnings)?;
+    runtime
+        .compile_to_asm(src)
+        .map_err(|e| Error::from(e).annotate_with_source(src))
 }
 
 /// Compile code in a given source language to a given target language.
@@ -352,63 +461,16 @@ fn compile(
     target: TargetType,
     output: String,
     call_stack_size: usize,
+    opt_level: usize,
+    warnings: WarningLevel,
     debug: bool,
+    profile: bool,
 ) -> Result<(), Error> {
+    let runtime = runtime(filename, src_type, call_stack_size, opt_level);
     match target {
-        // If the target is `Run`, then compile the code and execute it with the interpreter.
-        TargetType::Run => match compile_source_to_vm(filename, src, src_type, call_stack_size)? {
-            // If the code is core variant virtual machine code
-            Ok(vm_code) => {
-                CoreInterpreter::new(StandardDevice::default())
-                    .run(&vm_code)
-                    .map_err(Error::InterpreterError)?;
-            }
-            // If the code is standard variant virtual machine code
-            Err(vm_code) => {
-                StandardInterpreter::new(StandardDevice::default())
-                    .run(&vm_code) 
-                    .map_err(Error::InterpreterError)?;
-            }
-        },
-
-        // If the target is C source code, then compile the code to virtual machine code,
-        // and then use the C target implementation to build the output source code.
-        TargetType::C => write_file(
-            format!("{output}.c"),
-            match compile_source_to_vm(filename, src, src_type, call_stack_size)? {
-                Ok(vm_code) => targets::C.build_core(&vm_code.flatten()),
-                Err(vm_code) => targets::C.build_std(&vm_code.flatten()),
-            }
-            .map_err(Error::BuildError)?,
-        )?,
-
-        // If the target is core virtual machine code, then try to compile the source to the core variant.
-        // If not possible, throw an error.
-        TargetType::CoreVM => match compile_source_to_vm(filename, src, src_type, call_stack_size)?
-        {
-            Ok(vm_code) if debug => write_file(
-                format!("{output}.vm.sg"),
-                format!("{:#}", vm_code.flatten()),
-            ),
-            Ok(vm_code) => write_file(format!("{output}.vm.sg"), vm_code.flatten().to_string()),
-            Err(_) => Err(Error::InvalidSource(
-                "expected core VM program, got standard VM program".to_string(),
-            )),
-        }?,
-        // If the target is standard virtual machine code, the compile it to virtual machine code.
-        // If the result is core variant, we don't care. Just return the generated code.
-        TargetType::StdVM => write_file(
-            format!("{output}.vm.sg"),
-            match compile_source_to_vm(filename, src, src_type, call_stack_size)? {
-                Ok(vm_code) if debug => format!("{:#}", vm_code.flatten()),
-                Err(vm_code) if debug => format!("{:#}", vm_code.flatten()),
-                Ok(vm_code) => vm_code.flatten().to_string(),
-                Err(vm_code) => vm_code.flatten().to_string(),
-            },
-        )?,
         // If the target is core assembly code, then try to compile the source to the core variant.
         // If not possible, throw an error.
-        TargetType::CoreASM => match compile_source_to_asm(filename, src, src_type)? {
+        TargetType::CoreASM => match compile_source_to_asm(&runtime, &src, warnings)? {
             Ok(asm_code) if debug => {
                 write_file(format!("{output}.asm.sg"), format!("{:#}", asm_code))
             }
@@ -421,27 +483,287 @@ fn compile(
         // If the result is core variant, we don't care. Just return the generated code.
         TargetType::StdASM => write_file(
             format!("{output}.asm.sg"),
-            match compile_source_to_asm(filename, src, src_type)? {
+            match compile_source_to_asm(&runtime, &src, warnings)? {
                 Ok(core_asm_code) if debug => format!("{:#}", core_asm_code),
                 Err(std_asm_code) if debug => format!("{:#}", std_asm_code),
                 Ok(core_asm_code) => core_asm_code.to_string(),
                 Err(std_asm_code) => std_asm_code.to_string(),
             },
         )?,
+        // Every other target is built from the virtual machine code.
+        _ => {
+            let vm_code = compile_source_to_vm(&runtime, &src, warnings)?;
+            build_vm(
+                vm_code,
+                Some((filename, &src)),
+                target,
+                output,
+                debug,
+                profile,
+            )?
+        }
     }
     Ok(())
 }
 
+/// Load a virtual machine program from bytecode, and run it or build it for a given target.
+/// The source type is only used to check that core VM programs are really core variant.
+fn load_bytecode(
+    bytes: &[u8],
+    src_type: SourceType,
+    target: TargetType,
+    output: String,
+    opt_level: usize,
+    debug: bool,
+    profile: bool,
+) -> Result<(), Error> {
+    let vm_code = Runtime::new()
+        .with_source_type(src_type)
+        .with_opt_level(opt_level)
+        .load(bytes)?;
+    build_vm(vm_code, None, target, output, debug, profile)
+}
+
+/// Run a virtual machine program, or build it for a given target. The source code
+/// it was compiled from (and its filename) is used to show the source lines, if it's known.
+fn build_vm(
+    vm_code: Program,
+    source: Option<(Option<&str>, &str)>,
+    target: TargetType,
+    output: String,
+    debug: bool,
+    profile: bool,
+) -> Result<(), Error> {
+    match target {
+        // If the target is `Run`, then execute the code with the interpreter.
+        TargetType::Run if profile => {
+            // If the program is profiled, the profile is reported even if the program fails.
+            let mut report = match &vm_code {
+                Program::Core(vm_code, map) => Profile::from(vm_code).with_source_map(map.clone()),
+                Program::Standard(vm_code, map) => {
+                    Profile::from(vm_code).with_source_map(map.clone())
+                }
+            };
+            let result = Runtime::new().run_program_with_profile(&vm_code, &mut report);
+            write_profile(&output, &report)?;
+            result?;
+        }
+        TargetType::Run => {
+            Runtime::new().run_program(&vm_code)?;
+        }
+
+        // If the target is C source code, then use the C target implementation
+        // to build the output source code from the virtual machine code.
+        TargetType::C => write_file(
+            format!("{output}.c"),
+            match vm_code {
+                Program::Core(vm_code, _) => targets::C.build_core(&vm_code.flatten()),
+                Program::Standard(vm_code, _) => targets::C.build_std(&vm_code.flatten()),
+            }
+            .map_err(Error::BuildError)?,
+        )?,
+
+        // If the target is SageOS, then use the SageOS target implementation
+        // to build the output C source code from the virtual machine code.
+        TargetType::SageOS => write_file(
+            format!("{output}.c"),
+            match vm_code {
+                Program::Core(vm_code, _) => targets::SageOS.build_core(&vm_code.flatten()),
+                Program::Standard(vm_code, _) => targets::SageOS.build_std(&vm_code.flatten()),
+            }
+            .map_err(Error::BuildError)?,
+        )?,
+
+        // If the target is x86-64 assembly, then use the x86-64 target implementation
+        // to build the output assembly from the virtual machine code.
+        TargetType::X86 => write_file(
+            format!("{output}.s"),
+            match vm_code {
+                Program::Core(vm_code, _) => {
+                    targets::X86_64::default().build_core(&vm_code.flatten())
+                }
+                Program::Standard(vm_code, _) => {
+                    targets::X86_64::default().build_std(&vm_code.flatten())
+                }
+            }
+            .map_err(Error::BuildError)?,
+        )?,
+
+        // If the target is core virtual machine code, then the code must be the core variant.
+        // If not, throw an error.
+        // With debug information, the source lines are interleaved with the instructions.
+        TargetType::CoreVM => match vm_code.flatten() {
+            Program::Core(vm_code, map) if debug => write_file(
+                format!("{output}.vm.sg"),
+                format!(
+                    "{:#}",
+                    vm_code.with_source_lines(&map, source_lines(source))
+                ),
+            ),
+            Program::Core(vm_code, _) => write_file(format!("{output}.vm.sg"), vm_code.to_string()),
+            Program::Standard(..) => Err(Error::InvalidSource(
+                "expected core VM program, got standard VM program".to_string(),
+            )),
+        }?,
+        // If the target is standard virtual machine code, then just return the code.
+        // If it's core variant, we don't care.
+        TargetType::StdVM => write_file(
+            format!("{output}.vm.sg"),
+            match vm_code.flatten() {
+                Program::Core(vm_code, map) if debug => {
+                    format!(
+                        "{:#}",
+                        vm_code.with_source_lines(&map, source_lines(source))
+                    )
+                }
+                Program::Standard(vm_code, map) if debug => {
+                    format!(
+                        "{:#}",
+                        vm_code.with_source_lines(&map, source_lines(source))
+                    )
+                }
+                program => program.to_string(),
+            },
+        )?,
+        // If the target is bytecode, then encode the code as it is, so it runs
+        // exactly the same as the source code would.
+        TargetType::Bytecode => write_bytes(format!("{output}.sgb"), vm_code.to_bytecode())?,
+        // Virtual machine code can't be turned back into assembly code.
+        TargetType::CoreASM | TargetType::StdASM => {
+            return Err(Error::InvalidSource(
+                "cannot compile a VM program to assembly".to_string(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Get the source code of the files named in a program's location markers.
+/// Unnamed files and the input file are the given source code, if it's known,
+/// and the others are standard library modules or files on disk.
+fn source_lines<'a>(
+    source: Option<(Option<&'a str>, &'a str)>,
+) -> impl FnMut(Option<&str>) -> Option<String> + 'a {
+    move |file| match (file, source) {
+        (None, Some((_, src))) => Some(src.to_owned()),
+        (Some(file), Some((filename, src))) if Some(file) == filename => Some(src.to_owned()),
+        (None, None) => None,
+        (Some(file), _) => sage::frontend::std_module(file)
+            .map(String::from)
+            .or_else(|| read_to_string(file).ok()),
+    }
+}
+
+/// Report the profile of a program: print the flat profile to stderr,
+/// and write the folded stacks to the output file.
+fn write_profile(output: &str, profile: &Profile) -> Result<(), Error> {
+    eprint!("{profile}");
+    write_file(format!("{output}.folded"), profile.folded_stacks())
+}
+
+/// Compile a program without optimizing it, and step through it with the debugger.
+/// Compiled bytecode is loaded as it is, whatever the source type.
+fn debug_file(input: &str, src_type: SourceType, call_stack_size: usize) -> Result<(), Error> {
+    let bytes = read(input).map_err(Error::IO)?;
+    let runtime = runtime(Some(input), src_type, call_stack_size, 0);
+    if is_bytecode(&bytes) {
+        return debug_vm(runtime.load(&bytes)?, None);
+    }
+    let src = String::from_utf8(bytes)
+        .map_err(|e| Error::Parse(format!("the input file is not valid UTF-8: {e}")))?;
+    let vm_code = compile_source_to_vm(&runtime, &src, WarningLevel::Warn)?;
+    debug_vm(vm_code, Some(&src))
+}
+
+/// Step through a virtual machine program with the debugger. The source code it
+/// was compiled from is shown alongside the instructions, if it's known.
+/// The code is flattened, so that the instructions are numbered the same as in the VM listing.
+fn debug_vm(vm_code: Program, source: Option<&str>) -> Result<(), Error> {
+    match vm_code.flatten() {
+        Program::Core(vm_code, map) => {
+            let debugger = Debugger::new(CoreInterpreter::new(StandardDevice::default()), vm_code)
+                .with_source_map(map);
+            debug_program(match source {
+                Some(src) => debugger.with_source(src),
+                None => debugger,
+            })
+        }
+        Program::Standard(vm_code, map) => {
+            let debugger =
+                Debugger::new(StandardInterpreter::new(StandardDevice::default()), vm_code)
+                    .with_source_map(map);
+            debug_program(match source {
+                Some(src) => debugger.with_source(src),
+                None => debugger,
+            })
+        }
+    }
+}
+
+/// Run the debugger, reading commands from standard input until the user quits.
+fn debug_program(mut debugger: Debugger<impl Debuggable>) -> Result<(), Error> {
+    let mut stdout = stdout();
+    debugger.start(&mut stdout).map_err(Error::IO)?;
+    loop {
+        print!("(sage-debug) ");
+        stdout.flush().map_err(Error::IO)?;
+
+        // Read one line at a time, so that the rest of the input is left for the program.
+        let mut command = String::new();
+        if stdin().read_line(&mut command).map_err(Error::IO)? == 0 {
+            return Ok(());
+        }
+        if !debugger.execute(&command, &mut stdout).map_err(Error::IO)? {
+            return Ok(());
+        }
+    }
+}
+
 /// Write some contents to a file.
 fn write_file(file: String, contents: String) -> Result<(), Error> {
     write(file, contents).map_err(Error::IO)
 }
 
+/// Write some binary contents to a file.
+fn write_bytes(file: String, contents: Vec<u8>) -> Result<(), Error> {
+    write(file, contents).map_err(Error::IO)
+}
+
 /// Read the contents of a file.
 fn read_file(name: &str) -> Result<String, Error> {
     read_to_string(name).map_err(Error::IO)
 }
 
+/// Format the given frontend source files in place, or only check that they're
+/// formatted. Returns whether every file was formatted successfully.
+fn format_files(files: &[String], check: bool) -> bool {
+    let mut success = true;
+    for file in files {
+        let result = read_file(file).and_then(|code| {
+            let formatted = sage::frontend::format_code(&code).map_err(Error::Parse)?;
+            if formatted == code {
+                return Ok(true);
+            }
+            if !check {
+                write(file, formatted).map_err(Error::IO)?;
+            }
+            Ok(!check)
+        });
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("{file} is not formatted");
+                success = false;
+            }
+            Err(e) => {
+                error!("Error formatting {file}: {e:?}");
+                success = false;
+            }
+        }
+    }
+    success
+}
+
 /// Run the CLI.
 fn cli() {
     // Parse the arguments to the CLI.
@@ -466,25 +788,74 @@ fn cli() {
 
     builder.init();
 
-    match read_file(&args.input) {
-        Ok(file_contents) => {
-            match compile(
-                Some(&args.input),
+    let result = match args.command {
+        Some(Command::Fmt { files, check }) => {
+            if !format_files(&files, check) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Debug {
+            input,
+            source_type,
+            call_stack_size,
+        }) => debug_file(&input, source_type, call_stack_size),
+        None => run_input(args),
+    };
+    match result {
+        Ok(_) => {}
+        // A program which fails at runtime reports the error without the logger.
+        Err(Error::InterpreterError(trap)) => {
+            eprint!("{trap}");
+            std::process::exit(1);
+        }
+        // Any other error, including denied warnings, also exits with a nonzero
+        // status, so that scripts and CI jobs can tell that it failed.
+        Err(e) => {
+            error!("{e:#?}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Compile the input file to the target, or run it.
+fn run_input(args: Args) -> Result<(), Error> {
+    // The input is required when there's no subcommand.
+    let input = args.input.unwrap_or_default();
+    let bytes = read(&input).map_err(Error::IO)?;
+    let debug = args.debug.is_some();
+    // Compiled bytecode is loaded as it is, whatever the source type.
+    if is_bytecode(&bytes) {
+        load_bytecode(
+            &bytes,
+            args.source_type,
+            args.target_type,
+            args.output,
+            args.opt_level,
+            debug,
+            args.profile,
+        )
+    } else {
+        match String::from_utf8(bytes) {
+            Ok(file_contents) => compile(
+                Some(&input),
                 file_contents,
                 args.source_type,
                 args.target_type,
                 args.output,
                 args.call_stack_size,
-                args.debug.is_some(),
-            ) {
-                Ok(_) => {}
-                Err(e) => {
-                    error!("{e:#?}");
-                }
-            }
-        }
-        Err(e) => {
-            error!("Error reading file: {e:?}");
+                args.opt_level,
+                if args.deny_warnings {
+                    WarningLevel::Deny
+                } else {
+                    args.warnings
+                },
+                debug,
+                args.profile,
+            ),
+            Err(e) => Err(Error::Parse(format!(
+                "the input file is not valid UTF-8: {e}"
+            ))),
         }
     }
 }
@@ -0,0 +1,612 @@
+//! # Formatter
+//!
+//! This module implements the formatter for frontend code, used by `sage fmt`.
+//!
+//! The formatter splits the code into tokens, and uses the syntax tree from the
+//! frontend grammar to tell apart tokens which are spaced differently depending on
+//! where they're used: a `-` can negate a value or subtract two values, and a `<`
+//! can compare two values or apply a type to its parameters.
+//!
+//! The formatter keeps the line breaks in the code (collapsing runs of blank lines),
+//! along with all of its comments. It normalizes the indentation of every line,
+//! the spacing between tokens, and the trailing commas in the bodies of structs,
+//! enums, and `match` expressions: a body whose closing brace is on its own line
+//! ends with a comma, and a body on a single line doesn't.
+use super::parse::{FrontendParser, Rule};
+use pest::{iterators::Pair, Parser};
+
+/// The text used to indent a single level.
+const INDENT: &str = "    ";
+
+/// The operators made of multiple characters, which are lexed as a single token.
+const MULTI_CHAR_OPERATORS: &[&str] = &[
+    "::", "->", "=>", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "*=", "/=", "%=", "&=", "|=",
+    "^=", "~|", "~&",
+];
+
+/// The operators which are spaced on both sides when used as binary operators.
+const BINARY_OPERATORS: &[&str] = &[
+    "=", "==", "!=", "<", "<=", ">", ">=", "+", "-", "*", "/", "%", "&&", "||", "&", "|", "^",
+    "~|", "~&", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "=>", "->", "?",
+];
+
+/// The rules whose operators apply to a single operand.
+const UNARY_RULES: &[Rule] = &[
+    Rule::expr_unary_op,
+    Rule::const_int,
+    Rule::const_float,
+    Rule::pattern_ptr,
+    Rule::type_slice,
+    Rule::type_mut_slice,
+    Rule::type_ptr,
+    Rule::type_mut_ptr,
+    Rule::closure_capture_ref,
+];
+
+/// The rules whose angle brackets enclose types.
+const TYPE_BRACKET_RULES: &[Rule] = &[
+    Rule::type_application_suffix,
+    Rule::type_parameters,
+    Rule::bounded_type_parameters,
+    Rule::const_size_of_type,
+];
+
+/// The rules whose opening parenthesis directly follows a name, like a call.
+const CALL_RULES: &[Rule] = &[
+    Rule::expr_call,
+    Rule::decl_proc_block,
+    Rule::decl_proc_expr,
+    Rule::decl_trait_method,
+    Rule::decl_extern,
+    Rule::expr_closure,
+    Rule::const_size_of_type,
+    Rule::const_size_of_expr,
+];
+
+/// The rules whose names are directly followed by their payload, like `Some(x)`.
+const VARIANT_RULES: &[Rule] = &[
+    Rule::pattern_variant,
+    Rule::expr_variant,
+    Rule::const_variant,
+    Rule::decl_enum_variant,
+];
+
+/// The rules whose braces enclose code, which is padded with spaces on a single line.
+const BLOCK_RULES: &[Rule] = &[
+    Rule::stmt_block,
+    Rule::decl_impl,
+    Rule::decl_trait,
+    Rule::stmt_match,
+];
+
+/// The rules whose bodies are lists of items separated by commas.
+const COMMA_BODY_RULES: &[Rule] = &[
+    Rule::decl_struct,
+    Rule::decl_enum,
+    Rule::type_struct,
+    Rule::type_enum,
+    Rule::stmt_match,
+    Rule::expr_struct,
+    Rule::const_struct,
+    Rule::pattern_struct,
+];
+
+/// The rules in `COMMA_BODY_RULES` which require a comma after their first item,
+/// so the trailing comma can't always be removed.
+const REQUIRED_COMMA_RULES: &[Rule] = &[Rule::type_struct, Rule::type_enum, Rule::stmt_match];
+
+/// The rules which bind several variables separated by commas, where the lines
+/// after the first binding are indented to continue the statement.
+const LET_RULES: &[Rule] = &[
+    Rule::stmt_let,
+    Rule::stmt_let_pat,
+    Rule::stmt_let_static,
+    Rule::stmt_let_in_block,
+    Rule::stmt_let_in_expr,
+    Rule::stmt_let_static_in_block,
+    Rule::stmt_let_static_in_expr,
+];
+
+/// The kind of a token in the source code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TokenKind {
+    /// A name or a keyword.
+    Word,
+    /// A number literal.
+    Number,
+    /// A string or character literal.
+    Literal,
+    /// A loop label, like `'outer`.
+    Label,
+    /// An operator or a bracket.
+    Punct,
+    /// A comment running to the end of the line.
+    LineComment,
+    /// A comment delimited by `/*` and `*/`.
+    BlockComment,
+}
+
+/// A token in the source code.
+#[derive(Clone, Debug)]
+struct Token<'a> {
+    /// The text of the token.
+    text: &'a str,
+    /// The kind of the token.
+    kind: TokenKind,
+    /// The byte offset of the token in the source code.
+    start: usize,
+    /// The number of line breaks before the token.
+    newlines: usize,
+    /// The innermost node of the syntax tree containing the token.
+    node: Option<usize>,
+}
+
+impl Token<'_> {
+    fn is_comment(&self) -> bool {
+        matches!(self.kind, TokenKind::LineComment | TokenKind::BlockComment)
+    }
+
+    fn is_punct(&self, text: &str) -> bool {
+        self.kind == TokenKind::Punct && self.text == text
+    }
+
+    fn is_opener(&self) -> bool {
+        self.kind == TokenKind::Punct && matches!(self.text, "(" | "[" | "{")
+    }
+
+    fn is_closer(&self) -> bool {
+        self.kind == TokenKind::Punct && matches!(self.text, ")" | "]" | "}")
+    }
+}
+
+/// Source code split into tokens, along with the syntax tree of the code.
+struct Tokens<'a> {
+    tokens: Vec<Token<'a>>,
+    /// The nodes of the syntax tree, with the indices of their parents.
+    nodes: Vec<(Rule, Option<usize>)>,
+}
+
+impl<'a> Tokens<'a> {
+    /// Split the code into tokens, and parse it to find the rules each token belongs to.
+    fn new(code: &'a str) -> Result<Self, String> {
+        let tokens = lex(code)?;
+
+        // The grammar doesn't allow comments, so they're blanked out before parsing.
+        // This keeps the offsets of the tokens the same in the parsed code.
+        let mut blanked = code.to_string();
+        for token in tokens.iter().filter(|token| token.is_comment()) {
+            let blank = token
+                .text
+                .chars()
+                .map(|c| match c {
+                    '\n' => "\n".to_string(),
+                    // Multi-byte characters are replaced by a space for each byte.
+                    _ => " ".repeat(c.len_utf8()),
+                })
+                .collect::<String>();
+            blanked.replace_range(token.start..token.start + token.text.len(), &blank);
+        }
+
+        let program = FrontendParser::parse(Rule::program, &blanked).map_err(|e| e.to_string())?;
+        let mut result = Self {
+            tokens,
+            nodes: vec![],
+        };
+        for pair in program {
+            result.assign_nodes(pair, None);
+        }
+        Ok(result)
+    }
+
+    /// Record the node of a pair as the innermost node of the tokens it contains.
+    fn assign_nodes(&mut self, pair: Pair<Rule>, parent: Option<usize>) {
+        let node = self.nodes.len();
+        self.nodes.push((pair.as_rule(), parent));
+
+        let span = pair.as_span();
+        let first = self
+            .tokens
+            .partition_point(|token| token.start < span.start());
+        for token in &mut self.tokens[first..] {
+            if token.start >= span.end() {
+                break;
+            }
+            token.node = Some(node);
+        }
+
+        for child in pair.into_inner() {
+            self.assign_nodes(child, Some(node));
+        }
+    }
+
+    /// Get the innermost rule containing a token.
+    fn rule(&self, i: usize) -> Option<Rule> {
+        self.tokens[i].node.map(|node| self.nodes[node].0)
+    }
+
+    /// Get the rule of the parent of the innermost node containing a token.
+    fn parent_rule(&self, i: usize) -> Option<Rule> {
+        let node = self.tokens[i].node?;
+        self.nodes[node].1.map(|parent| self.nodes[parent].0)
+    }
+
+    fn rule_in(&self, i: usize, rules: &[Rule]) -> bool {
+        self.rule(i).is_some_and(|rule| rules.contains(&rule))
+    }
+
+    /// Is the token an operator applied to a single operand?
+    fn is_unary(&self, i: usize) -> bool {
+        let token = &self.tokens[i];
+        token.kind == TokenKind::Punct
+            && matches!(token.text, "-" | "+" | "*" | "&" | "&&" | "!" | "~")
+            && self.rule_in(i, UNARY_RULES)
+    }
+
+    /// Is the token an angle bracket around types?
+    fn is_type_bracket(&self, i: usize) -> bool {
+        let token = &self.tokens[i];
+        (token.is_punct("<") || token.is_punct(">")) && self.rule_in(i, TYPE_BRACKET_RULES)
+    }
+
+    /// Is the token a binary operator, which is spaced on both sides?
+    fn is_binary(&self, i: usize) -> bool {
+        let token = &self.tokens[i];
+        if token.kind != TokenKind::Punct {
+            return false;
+        }
+        if token.text == ":" {
+            // The colon of a ternary expression, rather than of a type annotation.
+            return self.rule(i) == Some(Rule::expr_ternary);
+        }
+        BINARY_OPERATORS.contains(&token.text) && !self.is_unary(i) && !self.is_type_bracket(i)
+    }
+
+    /// Are the braces at the given token padded with spaces when on a single line?
+    fn is_padded_brace(&self, i: usize) -> bool {
+        self.rule_in(i, BLOCK_RULES)
+    }
+
+    /// Should there be a space between two adjacent tokens on the same line?
+    fn space_between(&self, prev: usize, next: usize) -> bool {
+        let (a, b) = (&self.tokens[prev], &self.tokens[next]);
+        if a.is_comment() || b.is_comment() {
+            return true;
+        }
+        if b.kind == TokenKind::Punct && matches!(b.text, "," | ";" | ")" | "]" | "." | "::") {
+            return false;
+        }
+        if a.kind == TokenKind::Punct && matches!(a.text, "(" | "[" | "." | "::") {
+            return false;
+        }
+        if b.is_punct(":") {
+            return self.is_binary(next);
+        }
+        if self.is_unary(prev) {
+            return false;
+        }
+        if self.is_type_bracket(next) || (a.is_punct("<") && self.is_type_bracket(prev)) {
+            return false;
+        }
+        if b.is_punct("(") {
+            // Calls, parameter lists, and the payloads of variants follow their names directly.
+            let follows_name = matches!(a.kind, TokenKind::Word)
+                || a.is_punct(")")
+                || a.is_punct("]")
+                || (a.is_punct(">") && self.is_type_bracket(prev));
+            if follows_name && self.rule_in(next, CALL_RULES) {
+                return false;
+            }
+            if a.kind == TokenKind::Word
+                && self.rule(prev) == Some(Rule::symbol)
+                && self
+                    .parent_rule(prev)
+                    .is_some_and(|rule| VARIANT_RULES.contains(&rule))
+            {
+                return false;
+            }
+        }
+        if b.is_punct("[")
+            && self.rule_in(
+                next,
+                &[
+                    Rule::expr_index,
+                    Rule::expr_subslice,
+                    Rule::closure_captures,
+                ],
+            )
+        {
+            return false;
+        }
+        if a.is_punct("{") {
+            return !b.is_punct("}") && self.is_padded_brace(prev);
+        }
+        if b.is_punct("}") {
+            return self.is_padded_brace(next);
+        }
+        true
+    }
+
+    /// Does the line starting at the given token continue the statement on the line before?
+    fn continues_line(&self, prev: usize, next: usize) -> bool {
+        self.is_binary(prev)
+            || (self.is_binary(next) && !self.tokens[next].is_punct("=>"))
+            || self.tokens[next].is_punct(".")
+            || (self.tokens[prev].is_punct(",") && self.rule_in(prev, LET_RULES))
+    }
+
+    /// Find the matching opening bracket of every closing bracket.
+    fn matching_openers(&self) -> Vec<Option<usize>> {
+        let mut openers = vec![None; self.tokens.len()];
+        let mut stack = vec![];
+        for (i, token) in self.tokens.iter().enumerate() {
+            if token.is_opener() {
+                stack.push(i);
+            } else if token.is_closer() {
+                openers[i] = stack.pop();
+            }
+        }
+        openers
+    }
+
+    /// Add or remove the trailing commas in the bodies of structs, enums, and `match` expressions.
+    fn normalize_trailing_commas(&mut self) {
+        let openers = self.matching_openers();
+        // The edits are applied from the end, so that the indices stay valid.
+        for close in (0..self.tokens.len()).rev() {
+            if !self.tokens[close].is_punct("}") || !self.rule_in(close, COMMA_BODY_RULES) {
+                continue;
+            }
+            let Some(open) = openers[close] else {
+                continue;
+            };
+            let Some(last) = (open + 1..close)
+                .rev()
+                .find(|&i| !self.tokens[i].is_comment())
+            else {
+                continue;
+            };
+
+            let has_comma = self.tokens[last].is_punct(",");
+            if self.tokens[close].newlines > 0 {
+                if !has_comma {
+                    let comma = Token {
+                        text: ",",
+                        kind: TokenKind::Punct,
+                        start: self.tokens[last].start,
+                        newlines: 0,
+                        node: self.tokens[close].node,
+                    };
+                    self.tokens.insert(last + 1, comma);
+                }
+            } else if has_comma && !self.rule_in(close, REQUIRED_COMMA_RULES) {
+                self.tokens.remove(last);
+            }
+        }
+    }
+
+    /// Lay out the tokens.
+    fn print(&self) -> String {
+        let mut result = String::new();
+        // The indentation of the line each open bracket is on.
+        let mut brackets: Vec<usize> = vec![];
+        let mut line_indent = 0;
+        let mut prev_code = None;
+
+        for (i, token) in self.tokens.iter().enumerate() {
+            let mut breaks = if i == 0 { 0 } else { token.newlines.min(2) };
+            // No blank lines directly inside of brackets.
+            if breaks > 1 && (self.tokens[i - 1].is_opener() || token.is_closer()) {
+                breaks = 1;
+            }
+
+            if breaks > 0 {
+                let mut indent = match brackets.last() {
+                    Some(&indent) if token.is_closer() => indent,
+                    Some(&indent) => indent + 1,
+                    None => 0,
+                };
+                if !token.is_closer() && !token.is_comment() {
+                    if let Some(prev) = prev_code {
+                        if self.continues_line(prev, i) {
+                            indent += 1;
+                        }
+                    }
+                }
+                for _ in 0..breaks {
+                    result.push('\n');
+                }
+                for _ in 0..indent {
+                    result.push_str(INDENT);
+                }
+                line_indent = indent;
+            } else if i > 0 && self.space_between(i - 1, i) {
+                result.push(' ');
+            }
+            result.push_str(token.text);
+
+            if token.is_opener() {
+                brackets.push(line_indent);
+            } else if token.is_closer() {
+                // After closing a bracket opened on an earlier line, the rest of the line
+                // is at the indentation of the line the bracket was opened on.
+                if let Some(indent) = brackets.pop() {
+                    line_indent = line_indent.min(indent);
+                }
+            }
+            if !token.is_comment() {
+                prev_code = Some(i);
+            }
+        }
+
+        if !result.is_empty() {
+            result.push('\n');
+        }
+        result
+    }
+
+    /// Get the tokens which affect the meaning of the code. These are all the tokens
+    /// except for comments, and the commas before the closing brace of a body.
+    fn significant(&self) -> Vec<&str> {
+        let code = self
+            .tokens
+            .iter()
+            .filter(|token| !token.is_comment())
+            .collect::<Vec<_>>();
+        code.iter()
+            .enumerate()
+            .filter(|(i, token)| {
+                !(token.is_punct(",") && code.get(i + 1).is_some_and(|next| next.is_punct("}")))
+            })
+            .map(|(_, token)| token.text)
+            .collect()
+    }
+}
+
+/// Split source code into tokens.
+fn lex(code: &str) -> Result<Vec<Token<'_>>, String> {
+    let bytes = code.as_bytes();
+    let mut tokens = vec![];
+    let mut newlines = 0;
+    let mut i = 0;
+
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i];
+        let kind = match c {
+            b'\n' => {
+                newlines += 1;
+                i += 1;
+                continue;
+            }
+            _ if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = code[i..].find('\n').map_or(bytes.len(), |end| i + end);
+                TokenKind::LineComment
+            }
+            b'#' => {
+                i = code[i..].find('\n').map_or(bytes.len(), |end| i + end);
+                TokenKind::LineComment
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = code[i + 2..]
+                    .find("*/")
+                    .ok_or_else(|| "unterminated block comment".to_string())?;
+                i += end + 4;
+                TokenKind::BlockComment
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                if i >= bytes.len() {
+                    return Err("unterminated string literal".to_string());
+                }
+                i += 1;
+                TokenKind::Literal
+            }
+            b'\'' => match char_literal_len(&code[i..]) {
+                Some(len) => {
+                    i += len;
+                    TokenKind::Literal
+                }
+                None => {
+                    i += 1;
+                    while i < bytes.len() && is_ident(bytes[i]) {
+                        i += 1;
+                    }
+                    TokenKind::Label
+                }
+            },
+            b'0'..=b'9' => {
+                while i < bytes.len() && is_ident(bytes[i]) {
+                    i += 1;
+                }
+                // The fractional part and the exponent of a float.
+                if bytes.get(i) == Some(&b'.') && bytes.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                    i += 1;
+                    while i < bytes.len() && is_ident(bytes[i]) {
+                        i += 1;
+                    }
+                    if matches!(bytes[i - 1], b'e' | b'E')
+                        && matches!(bytes.get(i), Some(b'+' | b'-'))
+                    {
+                        i += 1;
+                        while i < bytes.len() && bytes[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                    }
+                }
+                TokenKind::Number
+            }
+            _ if is_ident(c) => {
+                while i < bytes.len() && is_ident(bytes[i]) {
+                    i += 1;
+                }
+                TokenKind::Word
+            }
+            _ => {
+                i += MULTI_CHAR_OPERATORS
+                    .iter()
+                    .find(|op| code[i..].starts_with(*op))
+                    .map_or_else(
+                        || code[i..].chars().next().map_or(1, char::len_utf8),
+                        |op| op.len(),
+                    );
+                TokenKind::Punct
+            }
+        };
+
+        tokens.push(Token {
+            text: &code[start..i],
+            kind,
+            start,
+            newlines,
+            node: None,
+        });
+        newlines = 0;
+    }
+    Ok(tokens)
+}
+
+/// Get the length of the character literal at the start of the code, if there is one.
+fn char_literal_len(code: &str) -> Option<usize> {
+    let mut chars = code.char_indices().skip(1);
+    let (_, c) = chars.next()?;
+    if c == '\\' {
+        // An escape sequence runs until the closing quote after the escaped character.
+        let escaped = code[2..].chars().next()?.len_utf8();
+        let end = code[2 + escaped..].find('\'')?;
+        return Some(2 + escaped + end + 1);
+    }
+    match chars.next() {
+        Some((i, '\'')) => Some(i + 1),
+        _ => None,
+    }
+}
+
+/// Format frontend source code in the canonical style.
+///
+/// This fails if the code can't be parsed. Comments are kept where they are.
+pub fn format_code(code: &str) -> Result<String, String> {
+    let mut tokens = Tokens::new(code)?;
+    tokens.normalize_trailing_commas();
+    let formatted = tokens.print();
+
+    // Make sure that formatting didn't change the meaning of the code.
+    let check = Tokens::new(&formatted)
+        .map_err(|e| format!("formatting produced code which doesn't parse: {e}"))?;
+    if check.significant() != tokens.significant() {
+        return Err("formatting changed the tokens of the code".to_string());
+    }
+    Ok(formatted)
+}
+
+/// Is the frontend source code already formatted in the canonical style?
+pub fn is_formatted(code: &str) -> Result<bool, String> {
+    Ok(format_code(code)? == code)
+}
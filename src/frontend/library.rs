@@ -0,0 +1,27 @@
+//! # Standard Library
+//!
+//! The modules of the standard library are written in Sage, and embedded in the compiler.
+//! A program can import them like any other module, with `import std::vec;` or
+//! `from std::option import Option;`.
+
+/// The prefix of the paths of standard library modules.
+pub const STD_MODULE_PREFIX: &str = "std::";
+
+/// The paths and source code of the standard library modules.
+const STD_MODULES: &[(&str, &str)] = &[
+    ("std::panic", include_str!("std/panic.sg")),
+    ("std::option", include_str!("std/option.sg")),
+    ("std::result", include_str!("std/result.sg")),
+    ("std::iter", include_str!("std/iter.sg")),
+    ("std::vec", include_str!("std/vec.sg")),
+    ("std::hashmap", include_str!("std/hashmap.sg")),
+    ("std::string", include_str!("std/string.sg")),
+];
+
+/// Get the source code of the standard library module with the given path, like `std::vec`.
+pub fn std_module(path: &str) -> Option<&'static str> {
+    STD_MODULES
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, code)| *code)
+}
@@ -3,17 +3,41 @@
 //! This module implements the frontend of the compiler, which is responsible for parsing
 //! the source code and converting it into the LIR.
 
+mod format;
+mod library;
 mod parse;
+use crate::parse::blank_comments;
 use crate::side_effects::Output;
-use no_comment::{languages, IntoWithoutComments};
+pub use format::{format_code, is_formatted};
+pub use library::*;
 use parse::*;
+pub use parse::{SymbolDefinition, SymbolKind};
+
+/// Find the definitions of the procedures, types, constants, traits, and
+/// associated constants declared in some frontend code.
+///
+/// This is used by editor tooling to jump to the definitions of symbols.
+pub fn definitions(
+    code: impl ToString,
+    filename: Option<&str>,
+) -> Result<Vec<SymbolDefinition>, String> {
+    let code = blank_comments(&code.to_string());
+
+    parse_definitions(code.as_ref(), filename).map_err(|e| e.to_string())
+}
+
+/// Find the imports in some frontend code which are never used.
+pub fn unused_imports(
+    code: impl ToString,
+    filename: Option<&str>,
+) -> Result<Vec<crate::lir::Warning>, String> {
+    let code = blank_comments(&code.to_string());
+
+    parse_unused_imports(code.as_ref(), filename).map_err(|e| e.to_string())
+}
 
 pub fn parse(code: impl ToString, filename: Option<&str>) -> Result<crate::lir::Expr, String> {
-    let code = code
-        .to_string()
-        .chars()
-        .without_comments(languages::rust())
-        .collect::<String>();
+    let code = blank_comments(&code.to_string());
 
     match parse_frontend(code.as_ref(), filename) {
         Ok(result) => {
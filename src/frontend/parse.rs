@@ -1,11 +1,23 @@
-use crate::{lir::*, parse::SourceCodeLocation};
-use pest::{error::Error, iterators::Pair, Parser};
+use super::{std_module, STD_MODULE_PREFIX};
+use crate::{
+    lir::*,
+    parse::{blank_comments, SourceCodeLocation},
+};
+use pest::{
+    error::{Error, ErrorVariant},
+    iterators::{Pair, Pairs},
+    Parser, Span,
+};
 use pest_derive::Parser;
 use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
 #[derive(Parser)]
 #[grammar = "frontend/parse.pest"] // relative to src
-struct FrontendParser;
+pub(super) struct FrontendParser;
 
 #[derive(Clone, Debug)]
 pub enum Statement {
@@ -20,9 +32,11 @@ pub enum Statement {
     If(Expr, Box<Self>, Option<Box<Self>>),
     When(ConstExpr, Box<Self>, Option<Box<Self>>),
     IfLet(Pattern, Expr, Box<Self>, Option<Box<Self>>),
-    While(Expr, Box<Self>),
-    For(Box<Self>, Expr, Box<Self>, Box<Self>),
+    While(Option<String>, Expr, Box<Self>),
+    For(Option<String>, Box<Self>, Expr, Box<Self>, Box<Self>),
     Return(Expr),
+    Break(Option<String>),
+    Continue(Option<String>),
     Block(Vec<Declaration>),
     LetIn(Vec<(String, Mutability, Option<Type>, Expr)>, Box<Self>),
     LetStaticIn(Vec<(String, Mutability, Type, ConstExpr)>, Box<Self>),
@@ -48,6 +62,18 @@ impl Statement {
 
         let stmt = match (self, rest.clone()) {
             (Self::AnnotatedWithSource { stmt, loc }, _) => {
+                // Consecutive declarations are merged together, so the initializers
+                // of the variables are also annotated to keep track of their locations.
+                let stmt = match *stmt {
+                    Self::Let(defs) => Self::Let(
+                        defs.into_iter()
+                            .map(|(name, mutability, ty, expr)| {
+                                (name, mutability, ty, expr.annotate(loc.clone()))
+                            })
+                            .collect(),
+                    ),
+                    stmt => stmt,
+                };
                 return stmt.to_expr(rest).annotate(loc);
             }
             (Self::Assign(lhs, op, rhs), _) => {
@@ -89,17 +115,23 @@ impl Statement {
                 Box::new(body.to_expr(None)),
                 Box::new(else_body.to_expr(None)),
             ),
-            (Self::While(cond, body), _) => {
+            (Self::While(None, cond, body), _) => {
                 Expr::While(Box::new(cond), Box::new(body.to_expr(None)))
             }
-            (Self::For(init, cond, step, body), _) => {
-                // init.to_expr(Some(Expr::While(Box::new(cond), Box::new(Expr::Many(vec![body.to_expr(None), step.to_expr(None)])))))
-                init.to_expr(Some(Expr::While(
-                    Box::new(cond),
-                    Box::new(body.to_expr(Some(step.to_expr(None)))),
+            (Self::While(label, cond, body), _) => {
+                cond.labeled_loop(label, body.to_expr(None), Expr::NONE)
+            }
+            (Self::For(label, init, cond, step, body), _) => {
+                // The step is kept separate from the body, so that it still runs after a `continue`.
+                init.to_expr(Some(cond.labeled_loop(
+                    label,
+                    body.to_expr(None),
+                    step.to_expr(None),
                 )))
             }
             (Self::Return(val), _) => Expr::Return(Box::new(val)),
+            (Self::Break(label), _) => Expr::Break(label),
+            (Self::Continue(label), _) => Expr::Continue(label),
 
             (Self::Block(stmts), Some(Expr::Many(mut rest))) => {
                 rest.insert(
@@ -166,6 +198,8 @@ impl Statement {
 #[derive(Clone, Debug)]
 pub enum Declaration {
     Impl(Type, Vec<(String, ConstExpr)>),
+    ImplTrait(String, Type, Vec<(String, ConstExpr)>),
+    Trait(String, Trait),
     Struct(String, Vec<(String, Type)>),
     Extern(String, Vec<(Option<String>, Type)>, Type),
     Enum(String, Vec<(String, Option<Type>)>),
@@ -179,12 +213,15 @@ pub enum Declaration {
     PolyProc(
         String,
         Vec<String>,
+        Vec<(String, String)>,
         Vec<(String, Mutability, Type)>,
         Option<Type>,
         Box<Statement>,
     ),
     Type(Vec<(String, Type)>),
     Statement(Statement),
+    /// A declaration, with the locations of the names it defines.
+    Located(Box<Self>, Vec<(String, SourceCodeLocation)>),
 }
 
 impl Declaration {
@@ -202,6 +239,45 @@ impl Declaration {
         )
     }
 
+    /// Prefix the names bound by this declaration with the namespace of its module.
+    fn with_namespace(self, namespace: &str) -> Self {
+        let prefix = |name: String| format!("{namespace}{MODULE_SEPARATOR}{name}");
+        match self {
+            Self::Struct(name, fields) => Self::Struct(prefix(name), fields),
+            Self::Enum(name, variants) => Self::Enum(prefix(name), variants),
+            Self::Const(consts) => Self::Const(
+                consts
+                    .into_iter()
+                    .map(|(name, expr)| (prefix(name), expr))
+                    .collect(),
+            ),
+            Self::Proc(name, params, ret, body) => Self::Proc(prefix(name), params, ret, body),
+            Self::PolyProc(name, ty_params, bounds, params, ret, body) => {
+                Self::PolyProc(prefix(name), ty_params, bounds, params, ret, body)
+            }
+            Self::Trait(name, trait_) => Self::Trait(prefix(name), trait_),
+            Self::Type(types) => Self::Type(
+                types
+                    .into_iter()
+                    .map(|(name, ty)| (prefix(name), ty))
+                    .collect(),
+            ),
+            Self::Located(decl, names) => {
+                let names = match *decl {
+                    Self::Extern(..) => names,
+                    _ => names
+                        .into_iter()
+                        .map(|(name, loc)| (prefix(name), loc))
+                        .collect(),
+                };
+                Self::Located(Box::new(decl.with_namespace(namespace)), names)
+            }
+            // Externs keep their names, since they refer to foreign functions.
+            // Impls and statements don't bind any names.
+            decl => decl,
+        }
+    }
+
     fn to_expr(self, rest: Option<Expr>) -> Expr {
         let rest_expr = Box::new(rest.clone().unwrap_or(Expr::ConstExpr(ConstExpr::None)));
         match (self, rest) {
@@ -217,6 +293,12 @@ impl Declaration {
             (Self::Impl(ty, methods), _) => {
                 rest_expr.with(crate::lir::Declaration::Impl(ty, methods))
             }
+            (Self::ImplTrait(trait_name, ty, methods), _) => {
+                rest_expr.with(crate::lir::Declaration::ImplTrait(trait_name, ty, methods))
+            }
+            (Self::Trait(name, trait_), _) => {
+                rest_expr.with(crate::lir::Declaration::Trait(name, trait_))
+            }
             (Self::Struct(name, fields), _) => {
                 rest_expr.with((name, Type::Struct(fields.into_iter().collect())))
             }
@@ -262,19 +344,35 @@ impl Declaration {
             (Self::Proc(name, params, ret, stmt), _) => {
                 rest_expr.with((name.clone(), Self::proc_to_expr(name, params, ret, *stmt)))
             }
-            (Self::PolyProc(name, ty_params, params, ret, stmt), _) => rest_expr.with((
+            (Self::PolyProc(name, ty_params, bounds, params, ret, stmt), _) => rest_expr.with((
                 name.clone(),
-                ConstExpr::PolyProc(PolyProcedure::new(
-                    name,
-                    ty_params,
-                    params,
-                    ret.unwrap_or(Type::None),
-                    stmt.to_expr(None),
-                )),
+                ConstExpr::PolyProc(
+                    PolyProcedure::new(
+                        name,
+                        ty_params,
+                        params,
+                        ret.unwrap_or(Type::None),
+                        stmt.to_expr(None),
+                    )
+                    .with_bounds(bounds),
+                ),
             )),
             (Self::Type(types), _) => rest_expr.with(types),
             (Self::Statement(stmt), Some(rest)) => stmt.to_expr(Some(rest)),
             (Self::Statement(stmt), None) => stmt.to_expr(None),
+            (Self::Located(decl, names), rest) => names
+                .into_iter()
+                .fold(decl.to_expr(rest), |expr, (name, loc)| {
+                    expr.annotate(Annotation::Definition(name, loc))
+                }),
+        }
+    }
+
+    /// Get the declaration, without the locations of the names it defines.
+    fn unlocated(&self) -> &Self {
+        match self {
+            Self::Located(decl, _) => decl.unlocated(),
+            decl => decl,
         }
     }
 }
@@ -299,38 +397,687 @@ impl Program {
 
 pub fn parse_frontend(code: &str, filename: Option<&str>) -> Result<Expr, Box<Error<Rule>>> {
     let x = FrontendParser::parse(Rule::program, code)?;
-    Ok(parse_program(x.into_iter().next().unwrap(), filename).to_expr())
+    let ctx = ParseContext::new(filename);
+    let mut imports = Imports::default();
+    let program = parse_program(x.into_iter().next().unwrap(), &ctx, &mut imports)?;
+    // The declarations of the imported modules come before the rest of the program.
+    let mut decls = imports.decls;
+    decls.extend(program.0);
+    Ok(Program(decls).to_expr())
+}
+
+/// The kind of symbol declared by a definition in the source code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// A procedure, or a foreign function.
+    Procedure,
+    /// A type, structure, enum, or unit.
+    Type,
+    /// A constant.
+    Const,
+    /// A trait.
+    Trait,
+    /// A constant or method associated with the type with the given name.
+    AssociatedConst(String),
+}
+
+/// A symbol defined in the source code, along with the location of its name.
+#[derive(Clone, Debug)]
+pub struct SymbolDefinition {
+    /// The name of the symbol.
+    pub name: String,
+    /// What kind of symbol the definition declares.
+    pub kind: SymbolKind,
+    /// The location of the symbol's name in its definition.
+    pub loc: SourceCodeLocation,
+}
+
+/// Find the definitions of the procedures, types, constants, traits,
+/// and associated constants declared in the source code.
+pub fn parse_definitions(
+    code: &str,
+    filename: Option<&str>,
+) -> Result<Vec<SymbolDefinition>, Box<Error<Rule>>> {
+    let program = FrontendParser::parse(Rule::program, code)?;
+    let mut defs = vec![];
+    for pair in program {
+        collect_definitions(pair, filename, None, &mut defs);
+    }
+    Ok(defs)
+}
+
+/// Collect the definitions declared in a pair, and all of its children.
+/// The `impl_ty` is the name of the type whose `impl` block the pair is in.
+fn collect_definitions(
+    pair: Pair<Rule>,
+    filename: Option<&str>,
+    impl_ty: Option<&str>,
+    defs: &mut Vec<SymbolDefinition>,
+) {
+    let kind = |kind| match impl_ty {
+        Some(ty) => SymbolKind::AssociatedConst(ty.to_string()),
+        None => kind,
+    };
+    let kind = match pair.as_rule() {
+        // Wrappers around declarations are still inside the `impl` block.
+        Rule::decl | Rule::decl_proc | Rule::decl_imp_child_decl => {
+            for child in pair.into_inner() {
+                collect_definitions(child, filename, impl_ty, defs);
+            }
+            return;
+        }
+        Rule::decl_impl => {
+            let mut inner_rules = pair.into_inner();
+            // Skip the name of the trait being implemented.
+            if let Some(Rule::type_symbol) = inner_rules.peek().map(|pair| pair.as_rule()) {
+                inner_rules.next();
+            }
+            // Generic types are referred to by the name of their template.
+            let ty = inner_rules.next().unwrap().as_str();
+            let ty = ty.split('<').next().unwrap_or(ty).trim();
+            for child in inner_rules {
+                collect_definitions(child, filename, Some(ty), defs);
+            }
+            return;
+        }
+        Rule::decl_proc_block | Rule::decl_proc_expr | Rule::decl_extern => {
+            kind(SymbolKind::Procedure)
+        }
+        Rule::decl_struct | Rule::decl_enum | Rule::decl_type | Rule::decl_unit => {
+            kind(SymbolKind::Type)
+        }
+        Rule::decl_const => kind(SymbolKind::Const),
+        Rule::decl_trait => SymbolKind::Trait,
+        _ => {
+            for child in pair.into_inner() {
+                collect_definitions(child, filename, None, defs);
+            }
+            return;
+        }
+    };
+
+    for (name, loc) in defined_names(pair.clone(), filename) {
+        defs.push(SymbolDefinition {
+            name,
+            kind: kind.clone(),
+            loc,
+        });
+    }
+
+    // Procedures may declare more symbols in their bodies.
+    for child in pair.into_inner() {
+        collect_definitions(child, filename, None, defs);
+    }
+}
+
+/// Get the names defined by a declaration, with the locations of the names.
+/// This doesn't include the names defined inside of it, like in the body of a
+/// procedure, or the associated constants in an `impl` block.
+fn defined_names(pair: Pair<Rule>, filename: Option<&str>) -> Vec<(String, SourceCodeLocation)> {
+    // Whether all of the symbol children are names the declaration defines,
+    // rather than only the first one.
+    let all_symbols = match pair.as_rule() {
+        Rule::decl | Rule::decl_proc => {
+            return pair
+                .into_inner()
+                .flat_map(|child| defined_names(child, filename))
+                .collect()
+        }
+        Rule::decl_proc_block
+        | Rule::decl_proc_expr
+        | Rule::decl_extern
+        | Rule::decl_struct
+        | Rule::decl_enum
+        | Rule::decl_trait => false,
+        Rule::decl_type | Rule::decl_unit | Rule::decl_const => true,
+        _ => return vec![],
+    };
+
+    let mut names = pair
+        .into_inner()
+        .filter(|child| child.as_rule() == Rule::symbol)
+        .collect::<Vec<_>>();
+    if !all_symbols {
+        names.truncate(1);
+    }
+    names
+        .into_iter()
+        .map(|name| {
+            let span = name.as_span();
+            let (line, column) = span.start_pos().line_col();
+            let loc = SourceCodeLocation {
+                filename: filename.map(|x| x.to_string()),
+                line,
+                column,
+                length: Some(span.end_pos().pos() - span.start_pos().pos()),
+                offset: span.start_pos().pos(),
+            };
+            (name.as_str().to_string(), loc)
+        })
+        .collect()
+}
+
+/// Find the imports in the source code which are never used.
+///
+/// An `import` is unused if nothing in its namespace is referred to, and a name
+/// brought in by a `from ... import` is unused if it's never referred to.
+pub fn parse_unused_imports(
+    code: &str,
+    filename: Option<&str>,
+) -> Result<Vec<Warning>, Box<Error<Rule>>> {
+    let program = FrontendParser::parse(Rule::program, code)?.next().unwrap();
+    let location = |span: Span| {
+        let (line, column) = span.start_pos().line_col();
+        SourceCodeLocation {
+            filename: filename.map(|x| x.to_string()),
+            line,
+            column,
+            length: Some(span.end_pos().pos() - span.start_pos().pos()),
+            offset: span.start_pos().pos(),
+        }
+    };
+
+    // The modules are loaded to find out which of them extend built-in types.
+    let mut imports = Imports::default();
+    // The imported namespaces and names, with their locations.
+    let mut namespaces = vec![];
+    let mut names = vec![];
+    // Every symbol referred to by the rest of the program.
+    let mut used = HashSet::new();
+    for pair in program.into_inner() {
+        match pair.as_rule() {
+            Rule::decl_import => {
+                let span = pair.as_span();
+                let mut inner_rules = pair.into_inner();
+                inner_rules.next();
+                let module_path = inner_rules.next().unwrap();
+                if let Ok((path, namespace)) =
+                    parse_module_path(module_path, inner_rules.next(), filename)
+                {
+                    // Modules which can't be loaded are reported by the compiler instead.
+                    let loaded = load_module(path, namespace.clone(), span, &mut imports);
+                    if loaded.is_ok() && !imports.builtin_extensions.contains(&namespace) {
+                        namespaces.push((namespace, location(span)));
+                    }
+                }
+            }
+            Rule::decl_from_import => {
+                for name in pair.into_inner().filter(|x| x.as_rule() == Rule::symbol) {
+                    names.push((name.as_str().to_string(), location(name.as_span())));
+                }
+            }
+            _ => {
+                let mut pairs = vec![pair];
+                while let Some(pair) = pairs.pop() {
+                    if matches!(
+                        pair.as_rule(),
+                        Rule::symbol | Rule::qualified_symbol | Rule::const_symbol
+                    ) {
+                        used.insert(pair.as_str().to_string());
+                    }
+                    pairs.extend(pair.into_inner());
+                }
+            }
+        }
+    }
+
+    let mut warnings = vec![];
+    for (namespace, loc) in namespaces {
+        let prefix = format!("{namespace}{MODULE_SEPARATOR}");
+        if !used.iter().any(|symbol| symbol.starts_with(&prefix)) {
+            warnings.push(Warning::UnusedImport(namespace).annotate(Annotation::Location(loc)));
+        }
+    }
+    for (name, loc) in names {
+        let prefix = format!("{name}{MODULE_SEPARATOR}");
+        if !used
+            .iter()
+            .any(|symbol| *symbol == name || symbol.starts_with(&prefix))
+        {
+            warnings.push(Warning::UnusedImport(name).annotate(Annotation::Location(loc)));
+        }
+    }
+    warnings.sort_by_key(|warning| warning.location().map(|loc| loc.offset));
+    Ok(warnings)
 }
 
-fn parse_symbol(pair: Pair<Rule>) -> (Mutability, String) {
+/// The separator between the namespace of a module and the names it declares.
+const MODULE_SEPARATOR: &str = "::";
+
+/// A name declared at the top level of a module.
+#[derive(Clone, Debug)]
+struct ModuleName {
+    /// The name the declaration is bound to in the program.
+    target: String,
+    /// What kind of declaration the name is bound to.
+    kind: NameKind,
+}
+
+/// The kind of declaration a name in a module is bound to.
+#[derive(Clone, Debug)]
+enum NameKind {
+    /// A type.
+    Type,
+    /// A constant or procedure.
+    Const,
+    /// A trait.
+    Trait(Trait),
+}
+
+/// The state used to resolve `import` declarations while parsing a program,
+/// which is shared by the program and every module it imports.
+#[derive(Default)]
+struct Imports {
+    /// The names declared by each imported module, keyed by its path and namespace.
+    loaded: HashMap<(PathBuf, String), HashMap<String, ModuleName>>,
+    /// The paths of the modules currently being parsed, used to detect circular imports.
+    loading: Vec<PathBuf>,
+    /// The declarations of every imported module, in the order they were loaded.
+    decls: Vec<Declaration>,
+    /// The namespaces of the modules which add associated constants to built-in types.
+    builtin_extensions: HashSet<String>,
+}
+
+/// The context in which the code of a program or module is parsed.
+struct ParseContext<'a> {
+    /// The file being parsed, used in the locations of its code.
+    filename: Option<&'a str>,
+    /// The namespace and top-level names of the module being parsed,
+    /// or `None` for the main program.
+    scope: Option<(String, HashSet<String>)>,
+}
+
+impl<'a> ParseContext<'a> {
+    /// Create the context for parsing the main program in a file.
+    fn new(filename: Option<&'a str>) -> Self {
+        Self {
+            filename,
+            scope: None,
+        }
+    }
+
+    /// Get the name a symbol refers to in the module being parsed.
+    ///
+    /// Names declared at the top level of a module are prefixed with its namespace,
+    /// so that they don't collide with the names of the program importing it.
+    fn scoped(&self, name: &str) -> String {
+        match &self.scope {
+            Some((namespace, names)) if names.contains(name) => {
+                format!("{namespace}{MODULE_SEPARATOR}{name}")
+            }
+            _ => name.to_string(),
+        }
+    }
+
+    /// Get the location of some code in the file being parsed.
+    fn location(&self, span: Span) -> SourceCodeLocation {
+        let (line, column) = span.start_pos().line_col();
+        SourceCodeLocation {
+            filename: self.filename.map(|x| x.to_string()),
+            line,
+            column,
+            length: Some(span.end_pos().pos() - span.start_pos().pos()),
+            offset: span.start_pos().pos(),
+        }
+    }
+}
+
+fn custom_error(message: impl ToString, span: Span) -> Box<Error<Rule>> {
+    Box::new(Error::new_from_span(
+        ErrorVariant::CustomError {
+            message: message.to_string(),
+        },
+        span,
+    ))
+}
+
+/// Resolve the path and namespace of the module named in an import declaration.
+fn parse_module_path(
+    pair: Pair<Rule>,
+    alias: Option<Pair<Rule>>,
+    filename: Option<&str>,
+) -> Result<(PathBuf, String), Box<Error<Rule>>> {
+    let span = pair.as_span();
+    let path = pair.into_inner().next().unwrap();
+    let (path, namespace) = match path.as_rule() {
+        Rule::std_module_path => {
+            // The standard library modules are embedded in the compiler.
+            if std_module(path.as_str()).is_none() {
+                return Err(custom_error(
+                    format!("there is no standard library module `{}`", path.as_str()),
+                    span,
+                ));
+            }
+            let name = path.as_str()[STD_MODULE_PREFIX.len()..].to_string();
+            (PathBuf::from(path.as_str()), name)
+        }
+        rule => {
+            let (path, namespace) = if rule == Rule::string {
+                let path = snailquote::unescape(path.as_str())
+                    .map_err(|e| custom_error(format!("invalid module path: {e}"), span))?;
+                let namespace = Path::new(&path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                (PathBuf::from(path), namespace)
+            } else {
+                let name = path.as_str().to_string();
+                (PathBuf::from(&name).with_extension("sg"), name)
+            };
+
+            // Modules are found relative to the file that imports them.
+            match filename.and_then(|filename| Path::new(filename).parent()) {
+                Some(dir) => (dir.join(path), namespace),
+                None => (path, namespace),
+            }
+        }
+    };
+
+    let namespace = match alias {
+        Some(alias) => alias.as_str().to_string(),
+        None => namespace,
+    };
+    let is_symbol = namespace
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_symbol {
+        return Err(custom_error(
+            format!(
+                "module `{}` does not have a valid name, use `import \"...\" as name` to name it",
+                path.display()
+            ),
+            span,
+        ));
+    }
+    Ok((path, namespace))
+}
+
+/// Parse the module at the given path, and add its declarations to the program under its namespace.
+fn load_module(
+    path: PathBuf,
+    namespace: String,
+    span: Span,
+    imports: &mut Imports,
+) -> Result<HashMap<String, ModuleName>, Box<Error<Rule>>> {
+    let std_code = path.to_str().and_then(std_module);
+    let canonical = match std_code {
+        Some(_) => path.clone(),
+        None => path.canonicalize().map_err(|e| {
+            custom_error(
+                format!("could not import module `{}`: {e}", path.display()),
+                span,
+            )
+        })?,
+    };
+
+    if let Some(names) = imports.loaded.get(&(canonical.clone(), namespace.clone())) {
+        return Ok(names.clone());
+    }
+    if imports.loading.contains(&canonical) {
+        return Err(custom_error(
+            format!("circular import of module `{}`", path.display()),
+            span,
+        ));
+    }
+    if let Some((other, _)) = imports.loaded.keys().find(|(_, other)| other == &namespace) {
+        return Err(custom_error(
+            format!(
+                "namespace `{namespace}` is already used by module `{}`",
+                other.display()
+            ),
+            span,
+        ));
+    }
+
+    let filename = path.to_string_lossy().to_string();
+    let code = match std_code {
+        Some(code) => code.to_string(),
+        None => std::fs::read_to_string(&path).map_err(|e| {
+            custom_error(format!("could not import module `{filename}`: {e}"), span)
+        })?,
+    };
+    let code = blank_comments(&code);
+    let with_path = |e: Box<Error<Rule>>| {
+        if e.path().is_none() {
+            Box::new(e.with_path(&filename))
+        } else {
+            e
+        }
+    };
+    let program = FrontendParser::parse(Rule::program, &code)
+        .map_err(|e| with_path(Box::new(e)))?
+        .next()
+        .unwrap();
+
+    // Find the names declared at the top level of the module.
+    let mut names = HashMap::new();
+    for decl in program.clone().into_inner() {
+        let decl = match decl.as_rule() {
+            Rule::decl => decl.into_inner().next().unwrap(),
+            Rule::decl_from_import => {
+                for name in decl.into_inner().filter(|x| x.as_rule() == Rule::symbol) {
+                    names.insert(name.as_str().to_string(), None);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let kind = match decl.as_rule() {
+            Rule::decl_type | Rule::decl_unit | Rule::decl_struct | Rule::decl_enum => {
+                NameKind::Type
+            }
+            Rule::decl_proc => {
+                let decl = decl.into_inner().next().unwrap();
+                let name = decl.into_inner().next().unwrap().as_str().to_string();
+                names.insert(name, Some(NameKind::Const));
+                continue;
+            }
+            Rule::decl_const => NameKind::Const,
+            Rule::decl_trait => {
+                let name = decl.into_inner().nth(1).unwrap().as_str().to_string();
+                names.insert(name, None);
+                continue;
+            }
+            Rule::decl_impl | Rule::decl_extern => continue,
+            _ => {
+                return Err(with_path(custom_error(
+                    "modules can only contain declarations",
+                    decl.as_span(),
+                )))
+            }
+        };
+        for name in decl.into_inner().filter(|x| x.as_rule() == Rule::symbol) {
+            names.insert(name.as_str().to_string(), Some(kind.clone()));
+        }
+    }
+
+    let ctx = ParseContext {
+        filename: Some(&filename),
+        scope: Some((namespace.clone(), names.keys().cloned().collect())),
+    };
+    imports.loading.push(canonical.clone());
+    let result = parse_program(program, &ctx, imports);
+    imports.loading.pop();
+    let Program(decls) = result.map_err(with_path)?;
+
+    // A module that adds methods to a built-in type is used by calling them,
+    // which doesn't mention its namespace.
+    if decls.iter().any(|decl| match decl {
+        Declaration::Impl(ty, _) | Declaration::ImplTrait(_, ty, _) => is_builtin_type(ty),
+        _ => false,
+    }) {
+        imports.builtin_extensions.insert(namespace.clone());
+    }
+
+    // The kinds of the names imported by the module, and its traits, are only known once it is parsed.
+    let mut kinds = HashMap::new();
+    for decl in &decls {
+        match decl.unlocated() {
+            Declaration::Type(types) => {
+                kinds.extend(types.iter().map(|(name, _)| (name.clone(), NameKind::Type)))
+            }
+            Declaration::Const(consts) => kinds.extend(
+                consts
+                    .iter()
+                    .map(|(name, _)| (name.clone(), NameKind::Const)),
+            ),
+            Declaration::Trait(name, trait_) => {
+                kinds.insert(name.clone(), NameKind::Trait(trait_.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    let names = names
+        .into_iter()
+        .map(|(name, kind)| {
+            let module_name = ModuleName {
+                target: format!("{namespace}{MODULE_SEPARATOR}{name}"),
+                kind: kind.unwrap_or_else(|| kinds.get(&name).cloned().unwrap_or(NameKind::Const)),
+            };
+            (name, module_name)
+        })
+        .collect::<HashMap<_, _>>();
+
+    for decl in decls {
+        if let Declaration::Statement(_) = decl {
+            continue;
+        }
+        imports.decls.push(decl.with_namespace(&namespace));
+    }
+    imports.loaded.insert((canonical, namespace), names.clone());
+    Ok(names)
+}
+
+/// Is the type built into the language, rather than declared in a program or module?
+/// The types declared in a module are named with its namespace by the time this is checked.
+fn is_builtin_type(ty: &Type) -> bool {
+    match ty {
+        Type::Apply(template, _) => is_builtin_type(template),
+        Type::Symbol(name) => Type::BUILTIN_TYPES
+            .iter()
+            .any(|(builtin, _)| builtin == name),
+        _ => true,
+    }
+}
+
+/// Parse an `import` or `from ... import` declaration, and load the module it names.
+///
+/// A `from` import returns the declarations which bring its names into scope.
+fn parse_import(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+    imports: &mut Imports,
+) -> Result<Vec<Declaration>, Box<Error<Rule>>> {
+    let span = pair.as_span();
+    let rule = pair.as_rule();
+    let mut inner_rules = pair.into_inner();
+    inner_rules.next();
+    let module_path = inner_rules.next().unwrap();
+    if rule == Rule::decl_import {
+        let alias = inner_rules.next();
+        let (path, namespace) = parse_module_path(module_path, alias, ctx.filename)?;
+        load_module(path, namespace, span, imports)?;
+        return Ok(vec![]);
+    }
+
+    let (path, namespace) = parse_module_path(module_path, None, ctx.filename)?;
+    let module_names = load_module(path.clone(), namespace, span, imports)?;
+    let mut decls = vec![];
+    for name in inner_rules.filter(|x| x.as_rule() == Rule::symbol) {
+        let Some(ModuleName { target, kind }) = module_names.get(name.as_str()).cloned() else {
+            return Err(custom_error(
+                format!(
+                    "module `{}` does not declare `{}`",
+                    path.display(),
+                    name.as_str()
+                ),
+                name.as_span(),
+            ));
+        };
+        let name = name.as_str().to_string();
+        decls.push(match kind {
+            NameKind::Type => Declaration::Type(vec![(name, Type::Symbol(target))]),
+            NameKind::Const => Declaration::Const(vec![(name, ConstExpr::Symbol(target))]),
+            NameKind::Trait(trait_) => Declaration::Trait(name, trait_),
+        });
+    }
+    Ok(decls)
+}
+
+fn parse_symbol(pair: Pair<Rule>, ctx: &ParseContext) -> (Mutability, String) {
     if pair.as_rule() == Rule::mut_symbol {
         (
             Mutability::Mutable,
-            pair.into_inner().next().unwrap().as_str().to_string(),
+            ctx.scoped(pair.into_inner().next().unwrap().as_str()),
         )
     } else {
-        (Mutability::Immutable, pair.as_str().to_string())
+        (Mutability::Immutable, ctx.scoped(pair.as_str()))
     }
 }
 
-fn parse_program(pair: Pair<Rule>, filename: Option<&str>) -> Program {
-    Program(pair.into_inner().map(|x| parse_decl(x, filename)).collect())
+fn parse_loop_label(pairs: &mut Pairs<Rule>) -> Option<String> {
+    if pairs.peek()?.as_rule() == Rule::loop_label {
+        // Strip the leading quote from the label.
+        Some(pairs.next().unwrap().as_str()[1..].to_string())
+    } else {
+        None
+    }
+}
+
+fn parse_program(
+    pair: Pair<Rule>,
+    ctx: &ParseContext,
+    imports: &mut Imports,
+) -> Result<Program, Box<Error<Rule>>> {
+    let mut decls = vec![];
+    for pair in pair.into_inner() {
+        match pair.as_rule() {
+            Rule::decl_import | Rule::decl_from_import => {
+                decls.extend(parse_import(pair, ctx, imports)?)
+            }
+            _ => decls.push(parse_located_decl(pair, ctx)),
+        }
+    }
+    Ok(Program(decls))
+}
+
+/// Parse a declaration, along with the locations of the names it defines.
+fn parse_located_decl(pair: Pair<Rule>, ctx: &ParseContext) -> Declaration {
+    let names = defined_names(pair.clone(), ctx.filename);
+    let decl = parse_decl(pair, ctx);
+    if names.is_empty() {
+        decl
+    } else {
+        Declaration::Located(Box::new(decl), names)
+    }
 }
 
-fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
+fn parse_decl(pair: Pair<Rule>, ctx: &ParseContext) -> Declaration {
     match pair.as_rule() {
         Rule::decl | Rule::decl_proc => pair
             .into_inner()
-            .map(|x| parse_decl(x, filename))
+            .map(|x| parse_decl(x, ctx))
             .next()
             .unwrap(),
 
         Rule::decl_impl => {
             let mut inner_rules = pair.into_inner();
-            let ty = parse_type(inner_rules.next().unwrap());
+            // An implementation of a trait starts with the name of the trait.
+            let trait_name = match inner_rules.peek() {
+                Some(pair) if pair.as_rule() == Rule::type_symbol => {
+                    Some(ctx.scoped(inner_rules.next().unwrap().as_str()))
+                }
+                _ => None,
+            };
+            let ty = parse_type(inner_rules.next().unwrap(), ctx);
             let mut constants = vec![];
             while inner_rules.peek().is_some() {
-                let decl = parse_decl(inner_rules.next().unwrap(), filename);
+                let decl = parse_decl(inner_rules.next().unwrap(), ctx);
                 match decl {
                     Declaration::Const(mut decls) => constants.append(&mut decls),
                     Declaration::Proc(name, args, ret, body) => constants.push((
@@ -342,16 +1089,20 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
                             body.to_expr(None),
                         )),
                     )),
-                    Declaration::PolyProc(name, ty_params, args, ret, body) => constants.push((
-                        name.clone(),
-                        ConstExpr::PolyProc(PolyProcedure::new(
-                            name,
-                            ty_params,
-                            args,
-                            ret.unwrap_or(Type::None),
-                            body.to_expr(None),
+                    Declaration::PolyProc(name, ty_params, bounds, args, ret, body) => constants
+                        .push((
+                            name.clone(),
+                            ConstExpr::PolyProc(
+                                PolyProcedure::new(
+                                    name,
+                                    ty_params,
+                                    args,
+                                    ret.unwrap_or(Type::None),
+                                    body.to_expr(None),
+                                )
+                                .with_bounds(bounds),
+                            ),
                         )),
-                    )),
                     Declaration::Type(types) => {
                         for (name, ty) in types {
                             constants.push((name, ConstExpr::Type(ty)))
@@ -375,23 +1126,65 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
                     }
                 }
             }
-            Declaration::Impl(ty, constants)
+            match trait_name {
+                Some(trait_name) => Declaration::ImplTrait(trait_name, ty, constants),
+                None => Declaration::Impl(ty, constants),
+            }
+        }
+        Rule::decl_trait => {
+            let mut inner_rules = pair.into_inner();
+            inner_rules.next();
+            let name = inner_rules.next().unwrap().as_str().to_string();
+            let mut methods = vec![];
+            for method in inner_rules {
+                let mut inner_rules = method.into_inner();
+                let method_name = inner_rules.next().unwrap().as_str().to_string();
+                let mut args = vec![];
+                let mut ret = Type::None;
+                for pair in inner_rules {
+                    match pair.as_rule() {
+                        Rule::decl_proc_param => {
+                            let mut inner_rules = pair.into_inner();
+                            inner_rules.next();
+                            args.push(parse_type(inner_rules.next().unwrap(), ctx));
+                        }
+                        Rule::r#type => {
+                            ret = parse_type(pair, ctx);
+                        }
+                        other => panic!("unexpected rule {:?}", other),
+                    }
+                }
+                methods.push((method_name, Type::Proc(args, Box::new(ret))));
+            }
+            // The trait keeps the name it's declared with in its module,
+            // so that imports of the trait under other names refer to the same trait.
+            Declaration::Trait(name.clone(), Trait::new(ctx.scoped(&name), methods))
         }
 
-        Rule::decl_imp_child_decl => parse_decl(pair.into_inner().next().unwrap(), filename),
+        Rule::decl_imp_child_decl => parse_decl(pair.into_inner().next().unwrap(), ctx),
 
         Rule::decl_proc_block | Rule::decl_proc_expr => {
+            let start = pair.as_span().start_pos();
             let mut inner_rules = pair.into_inner();
-            let name = inner_rules.next().unwrap().as_str().to_string();
+            let name_pair = inner_rules.next().unwrap();
+            // The end of the procedure's signature, before its body.
+            let mut header_end = name_pair.as_span().end_pos().pos();
+            let name = name_pair.as_str().to_string();
 
             let mut ty_params = vec![];
+            let mut bounds = vec![];
             if let Some(ty_params_pair) = inner_rules.peek() {
-                if ty_params_pair.as_rule() == Rule::type_parameters
+                if ty_params_pair.as_rule() == Rule::bounded_type_parameters
                     && ty_params_pair.into_inner().count() > 0
                 {
                     let ty_params_pair = inner_rules.next().unwrap();
                     for ty_param_pair in ty_params_pair.into_inner() {
-                        ty_params.push(ty_param_pair.as_str().to_string());
+                        let mut inner_rules = ty_param_pair.into_inner();
+                        let ty_param = ctx.scoped(inner_rules.next().unwrap().as_str());
+                        for trait_name in inner_rules {
+                            bounds.push((ty_param.clone(), ctx.scoped(trait_name.as_str())));
+                        }
+                        ty_params.push(ty_param);
                     }
                 }
             }
@@ -402,27 +1195,44 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
             for pair in inner_rules {
                 match pair.as_rule() {
                     Rule::decl_proc_param => {
+                        header_end = pair.as_span().end_pos().pos();
                         let mut inner_rules = pair.into_inner();
-                        let (mutability, name) = parse_symbol(inner_rules.next().unwrap());
-                        let ty = parse_type(inner_rules.next().unwrap());
+                        let (mutability, name) = parse_symbol(inner_rules.next().unwrap(), ctx);
+                        let ty = parse_type(inner_rules.next().unwrap(), ctx);
                         params.push((name, mutability, ty));
                     }
                     Rule::r#type => {
-                        ret = Some(parse_type(pair));
+                        header_end = pair.as_span().end_pos().pos();
+                        ret = Some(parse_type(pair, ctx));
                     }
                     Rule::stmt_block => {
-                        stmt = parse_stmt(pair, filename);
+                        stmt = parse_stmt(pair, ctx);
                     }
                     Rule::expr => {
-                        stmt = Statement::Expr(parse_expr(pair));
+                        stmt = Statement::Expr(parse_expr(pair, ctx));
                     }
                     other => panic!("unexpected rule {:?}", other),
                 }
             }
+            // The body is annotated with the location of the signature, so that problems
+            // with the procedure as a whole, like unused parameters, can be reported there.
+            // The locations of the statements in the body take precedence over it.
+            let (line, column) = start.line_col();
+            let loc = SourceCodeLocation {
+                filename: ctx.filename.map(|x| x.to_string()),
+                line,
+                column,
+                length: Some(header_end - start.pos()),
+                offset: start.pos(),
+            };
+            let stmt = Statement::Expr(Expr::Annotated(
+                Box::new(stmt.to_expr(None)),
+                Annotation::Location(loc),
+            ));
             if ty_params.is_empty() {
                 Declaration::Proc(name, params, ret, Box::new(stmt))
             } else {
-                Declaration::PolyProc(name, ty_params, params, ret, Box::new(stmt))
+                Declaration::PolyProc(name, ty_params, bounds, params, ret, Box::new(stmt))
             }
         }
         Rule::decl_type => {
@@ -438,12 +1248,12 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
                     {
                         let ty_params_pair = inner_rules.next().unwrap();
                         for ty_param_pair in ty_params_pair.into_inner() {
-                            ty_params.push(ty_param_pair.as_str().to_string());
+                            ty_params.push(ctx.scoped(ty_param_pair.as_str()));
                         }
                     }
                 }
 
-                let ty = parse_type(inner_rules.next().unwrap());
+                let ty = parse_type(inner_rules.next().unwrap(), ctx);
                 if ty_params.is_empty() {
                     types.push((name, ty));
                 } else {
@@ -458,8 +1268,8 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
             let mut types = Vec::new();
             while inner_rules.peek().is_some() {
                 let name = inner_rules.next().unwrap().as_str().to_string();
-                let ty = parse_type(inner_rules.next().unwrap());
-                types.push((name.clone(), Type::Unit(name, Box::new(ty))));
+                let ty = parse_type(inner_rules.next().unwrap(), ctx);
+                types.push((name.clone(), Type::Unit(ctx.scoped(&name), Box::new(ty))));
             }
 
             Declaration::Type(types)
@@ -475,7 +1285,7 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
                 {
                     let ty_params_pair = inner_rules.next().unwrap();
                     for ty_param_pair in ty_params_pair.into_inner() {
-                        ty_params.push(ty_param_pair.as_str().to_string());
+                        ty_params.push(ctx.scoped(ty_param_pair.as_str()));
                     }
                 }
             }
@@ -484,7 +1294,7 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
             while inner_rules.peek().is_some() {
                 let mut inner_rules = inner_rules.next().unwrap().into_inner();
                 let name = inner_rules.next().unwrap().as_str().to_string();
-                let ty = parse_type(inner_rules.next().unwrap());
+                let ty = parse_type(inner_rules.next().unwrap(), ctx);
                 fields.push((name, ty));
             }
             if ty_params.is_empty() {
@@ -510,7 +1320,7 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
                 {
                     let ty_params_pair = inner_rules.next().unwrap();
                     for ty_param_pair in ty_params_pair.into_inner() {
-                        ty_params.push(ty_param_pair.as_str().to_string());
+                        ty_params.push(ctx.scoped(ty_param_pair.as_str()));
                     }
                 }
             }
@@ -528,7 +1338,8 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
 
                 if let Some(ty) = ty {
                     if ty.as_rule() == Rule::r#type {
-                        variants.push((variant_name.as_str().to_string(), Some(parse_type(ty))));
+                        variants
+                            .push((variant_name.as_str().to_string(), Some(parse_type(ty, ctx))));
                     } else {
                         variants.push((variant_name.as_str().to_string(), None));
                     }
@@ -576,12 +1387,12 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
                 match pair.as_rule() {
                     Rule::decl_proc_param => {
                         let mut inner_rules = pair.into_inner();
-                        let (_mutability, name) = parse_symbol(inner_rules.next().unwrap());
-                        let ty = parse_type(inner_rules.next().unwrap());
+                        let (_mutability, name) = parse_symbol(inner_rules.next().unwrap(), ctx);
+                        let ty = parse_type(inner_rules.next().unwrap(), ctx);
                         args.push((Some(name), ty));
                     }
                     Rule::r#type => {
-                        ret = Some(parse_type(pair));
+                        ret = Some(parse_type(pair, ctx));
                     }
                     other => panic!("unexpected rule {:?}", other),
                 }
@@ -593,25 +1404,25 @@ fn parse_decl(pair: Pair<Rule>, filename: Option<&str>) -> Declaration {
             let mut defs = Vec::new();
             while inner_rules.peek().is_some() {
                 let name = inner_rules.next().unwrap().as_str().to_string();
-                let expr = parse_const(inner_rules.next().unwrap());
+                let expr = parse_const(inner_rules.next().unwrap(), ctx);
                 defs.push((name, expr));
             }
             Declaration::Const(defs)
         }
-        Rule::stmt | Rule::stmt_block => Declaration::Statement(parse_stmt(pair, filename)),
+        Rule::stmt | Rule::stmt_block => Declaration::Statement(parse_stmt(pair, ctx)),
         Rule::EOI => Declaration::Statement(Statement::Block(vec![])),
         other => panic!("Unexpected rule: {:?}: {:?}", other, pair),
     }
 }
 
-fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
+fn parse_stmt(pair: Pair<Rule>, ctx: &ParseContext) -> Statement {
     let span = pair.as_span();
     let (line, column) = span.start_pos().line_col();
     let length = span.end_pos().pos() - span.start_pos().pos();
     let offset = span.start_pos().pos();
 
     let loc = SourceCodeLocation {
-        filename: filename.map(|x| x.to_string()),
+        filename: ctx.filename.map(|x| x.to_string()),
         line,
         column,
         length: Some(length),
@@ -621,7 +1432,7 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
     match pair.as_rule() {
         Rule::stmt | Rule::long_stmt | Rule::short_stmt | Rule::stmt_let_in => pair
             .into_inner()
-            .map(|x| parse_stmt(x, filename))
+            .map(|x| parse_stmt(x, ctx))
             .next()
             .unwrap(),
 
@@ -629,9 +1440,9 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
             let mut inner_rules = pair.into_inner();
             let mut defs = vec![];
             while inner_rules.peek().is_some() {
-                let (mutability, symbol) = parse_symbol(inner_rules.next().unwrap());
-                let ty = parse_type(inner_rules.next().unwrap());
-                let expr = parse_const(inner_rules.next().unwrap());
+                let (mutability, symbol) = parse_symbol(inner_rules.next().unwrap(), ctx);
+                let ty = parse_type(inner_rules.next().unwrap(), ctx);
+                let expr = parse_const(inner_rules.next().unwrap(), ctx);
                 defs.push((symbol, mutability, ty, expr));
             }
             Statement::LetStatic(defs)
@@ -641,49 +1452,43 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
             let mut inner_rules = pair.into_inner();
             let mut defs = vec![];
             while inner_rules.clone().count() > 1 {
-                let (mutability, symbol) = parse_symbol(inner_rules.next().unwrap());
-                let ty = parse_type(inner_rules.next().unwrap());
-                let expr = parse_const(inner_rules.next().unwrap());
+                let (mutability, symbol) = parse_symbol(inner_rules.next().unwrap(), ctx);
+                let ty = parse_type(inner_rules.next().unwrap(), ctx);
+                let expr = parse_const(inner_rules.next().unwrap(), ctx);
                 defs.push((symbol, mutability, ty, expr));
             }
             let last = inner_rules.next().unwrap();
             match last.as_rule() {
-                Rule::stmt_block => {
-                    Statement::LetStaticIn(defs, Box::new(parse_stmt(last, filename)))
-                }
+                Rule::stmt_block => Statement::LetStaticIn(defs, Box::new(parse_stmt(last, ctx))),
                 Rule::expr => {
-                    Statement::LetStaticIn(defs, Box::new(Statement::Expr(parse_expr(last))))
+                    Statement::LetStaticIn(defs, Box::new(Statement::Expr(parse_expr(last, ctx))))
                 }
                 other => unreachable!("Unexpected rule {:?}", other),
             }
         }
-        Rule::stmt_match => Statement::Expr(parse_match(pair)),
+        Rule::stmt_match => Statement::Expr(parse_match(pair, ctx)),
 
         Rule::stmt_block => {
             let inner_rules = pair.into_inner();
             let mut stmts = Vec::new();
             for stmt in inner_rules {
-                stmts.push(parse_decl(stmt, filename));
+                stmts.push(parse_located_decl(stmt, ctx));
             }
             Statement::Block(stmts)
         }
 
         Rule::stmt_if => {
             let mut inner_rules = pair.into_inner();
-            let cond = parse_expr(inner_rules.next().unwrap());
-            let body = parse_stmt(inner_rules.next().unwrap(), filename);
-            let else_body = inner_rules
-                .next()
-                .map(|x| Box::new(parse_stmt(x, filename)));
+            let cond = parse_expr(inner_rules.next().unwrap(), ctx);
+            let body = parse_stmt(inner_rules.next().unwrap(), ctx);
+            let else_body = inner_rules.next().map(|x| Box::new(parse_stmt(x, ctx)));
             Statement::If(cond, Box::new(body), else_body)
         }
         Rule::stmt_when => {
             let mut inner_rules = pair.into_inner();
-            let cond = parse_const(inner_rules.next().unwrap());
-            let body = parse_stmt(inner_rules.next().unwrap(), filename);
-            let else_body = inner_rules
-                .next()
-                .map(|x| Box::new(parse_stmt(x, filename)));
+            let cond = parse_const(inner_rules.next().unwrap(), ctx);
+            let body = parse_stmt(inner_rules.next().unwrap(), ctx);
+            let else_body = inner_rules.next().map(|x| Box::new(parse_stmt(x, ctx)));
             Statement::When(cond, Box::new(body), else_body)
         }
 
@@ -693,12 +1498,12 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
             for _ in 0..inner_rules.clone().count() / 2 {
                 let cond = inner_rules.next().unwrap();
                 let body = inner_rules.next().unwrap();
-                elifs.push((parse_expr(cond), parse_stmt(body, filename)));
+                elifs.push((parse_expr(cond, ctx), parse_stmt(body, ctx)));
             }
 
             let mut else_body = inner_rules
                 .next()
-                .map(|x| parse_stmt(x, filename))
+                .map(|x| parse_stmt(x, ctx))
                 .unwrap_or(Statement::Block(vec![]));
 
             for (cond, body) in elifs.into_iter().rev() {
@@ -710,12 +1515,10 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
 
         Rule::stmt_if_let => {
             let mut inner_rules = pair.into_inner();
-            let pat = parse_pattern(inner_rules.next().unwrap());
-            let expr = parse_expr(inner_rules.next().unwrap());
-            let body = parse_stmt(inner_rules.next().unwrap(), filename);
-            let else_body = inner_rules
-                .next()
-                .map(|x| Box::new(parse_stmt(x, filename)));
+            let pat = parse_pattern(inner_rules.next().unwrap(), ctx);
+            let expr = parse_expr(inner_rules.next().unwrap(), ctx);
+            let body = parse_stmt(inner_rules.next().unwrap(), ctx);
+            let else_body = inner_rules.next().map(|x| Box::new(parse_stmt(x, ctx)));
             Statement::IfLet(pat, expr, Box::new(body), else_body)
         }
         Rule::stmt_if_elif_let => {
@@ -726,15 +1529,15 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
                 let expr = inner_rules.next().unwrap();
                 let body = inner_rules.next().unwrap();
                 elifs.push((
-                    parse_pattern(pat),
-                    parse_expr(expr),
-                    parse_stmt(body, filename),
+                    parse_pattern(pat, ctx),
+                    parse_expr(expr, ctx),
+                    parse_stmt(body, ctx),
                 ));
             }
 
             let mut else_body = inner_rules
                 .next()
-                .map(|x| parse_stmt(x, filename))
+                .map(|x| parse_stmt(x, ctx))
                 .unwrap_or(Statement::Block(vec![]));
 
             for (pat, expr, body) in elifs.into_iter().rev() {
@@ -746,26 +1549,28 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
 
         Rule::stmt_while => {
             let mut inner_rules = pair.into_inner();
-            let cond = parse_expr(inner_rules.next().unwrap());
-            let body = parse_stmt(inner_rules.next().unwrap(), filename);
-            Statement::While(cond, Box::new(body))
+            let label = parse_loop_label(&mut inner_rules);
+            let cond = parse_expr(inner_rules.next().unwrap(), ctx);
+            let body = parse_stmt(inner_rules.next().unwrap(), ctx);
+            Statement::While(label, cond, Box::new(body))
         }
 
         Rule::stmt_for => {
             let mut inner_rules = pair.into_inner();
-            let pre = parse_stmt(inner_rules.next().unwrap(), filename);
-            let cond = parse_expr(inner_rules.next().unwrap());
-            let post = parse_stmt(inner_rules.next().unwrap(), filename);
-            let body = parse_stmt(inner_rules.next().unwrap(), filename);
-            Statement::For(Box::new(pre), cond, Box::new(post), Box::new(body))
+            let label = parse_loop_label(&mut inner_rules);
+            let pre = parse_stmt(inner_rules.next().unwrap(), ctx);
+            let cond = parse_expr(inner_rules.next().unwrap(), ctx);
+            let post = parse_stmt(inner_rules.next().unwrap(), ctx);
+            let body = parse_stmt(inner_rules.next().unwrap(), ctx);
+            Statement::For(label, Box::new(pre), cond, Box::new(post), Box::new(body))
         }
 
         Rule::stmt_let_pat => {
             let mut inner_rules = pair.into_inner();
             let mut defs = vec![];
             while inner_rules.peek().is_some() {
-                let pattern = parse_pattern(inner_rules.next().unwrap());
-                let expr = parse_expr(inner_rules.next().unwrap());
+                let pattern = parse_pattern(inner_rules.next().unwrap(), ctx);
+                let expr = parse_expr(inner_rules.next().unwrap(), ctx);
                 defs.push((pattern, expr));
             }
             Statement::LetPattern(defs)
@@ -775,16 +1580,21 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
             let mut inner_rules = pair.into_inner();
             let mut defs = vec![];
             while inner_rules.peek().is_some() {
-                let (mutability, symbol) = parse_symbol(inner_rules.next().unwrap());
+                let (mutability, symbol) = parse_symbol(inner_rules.next().unwrap(), ctx);
                 let ty = inner_rules.next().unwrap();
                 if ty.as_rule() == Rule::expr {
-                    defs.push((symbol, mutability, None, parse_expr(ty)));
+                    defs.push((symbol, mutability, None, parse_expr(ty, ctx)));
                     continue;
                 }
                 if let Some(expr) = inner_rules.next() {
-                    defs.push((symbol, mutability, Some(parse_type(ty)), parse_expr(expr)));
+                    defs.push((
+                        symbol,
+                        mutability,
+                        Some(parse_type(ty, ctx)),
+                        parse_expr(expr, ctx),
+                    ));
                 } else {
-                    defs.push((symbol, mutability, None, parse_expr(ty)));
+                    defs.push((symbol, mutability, None, parse_expr(ty, ctx)));
                 }
             }
             Statement::Let(defs)
@@ -794,31 +1604,38 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
             let mut inner_rules = pair.into_inner();
             let mut defs = vec![];
             while inner_rules.clone().count() > 1 {
-                let (mutability, symbol) = parse_symbol(inner_rules.next().unwrap());
+                let (mutability, symbol) = parse_symbol(inner_rules.next().unwrap(), ctx);
                 let ty = inner_rules.next().unwrap();
                 if ty.as_rule() == Rule::expr {
-                    defs.push((symbol, mutability, None, parse_expr(ty)));
+                    defs.push((symbol, mutability, None, parse_expr(ty, ctx)));
                     continue;
                 }
                 if let Some(expr) = inner_rules.next() {
-                    defs.push((symbol, mutability, Some(parse_type(ty)), parse_expr(expr)));
+                    defs.push((
+                        symbol,
+                        mutability,
+                        Some(parse_type(ty, ctx)),
+                        parse_expr(expr, ctx),
+                    ));
                 } else {
-                    defs.push((symbol, mutability, None, parse_expr(ty)));
+                    defs.push((symbol, mutability, None, parse_expr(ty, ctx)));
                 }
             }
             let last = inner_rules.next().unwrap();
             match last.as_rule() {
-                Rule::stmt_block => Statement::LetIn(defs, Box::new(parse_stmt(last, filename))),
-                Rule::expr => Statement::LetIn(defs, Box::new(Statement::Expr(parse_expr(last)))),
+                Rule::stmt_block => Statement::LetIn(defs, Box::new(parse_stmt(last, ctx))),
+                Rule::expr => {
+                    Statement::LetIn(defs, Box::new(Statement::Expr(parse_expr(last, ctx))))
+                }
                 other => unreachable!("Unexpected rule {:?}", other),
             }
         }
 
         Rule::stmt_assign => {
             let mut inner_rules = pair.into_inner();
-            let lhs = parse_expr(inner_rules.next().unwrap());
+            let lhs = parse_expr(inner_rules.next().unwrap(), ctx);
             let op = inner_rules.next().unwrap().as_str();
-            let rhs = parse_expr(inner_rules.next().unwrap());
+            let rhs = parse_expr(inner_rules.next().unwrap(), ctx);
             Statement::Assign(
                 lhs,
                 match op {
@@ -839,11 +1656,21 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
 
         Rule::stmt_return => {
             let mut inner_rules = pair.into_inner();
-            let expr = inner_rules.next().map(parse_expr);
+            let expr = inner_rules.next().map(|x| parse_expr(x, ctx));
             Statement::Return(expr.unwrap_or(Expr::ConstExpr(ConstExpr::None)))
         }
 
-        Rule::expr => Statement::Expr(parse_expr(pair)),
+        Rule::stmt_break => {
+            let mut inner_rules = pair.into_inner();
+            Statement::Break(parse_loop_label(&mut inner_rules))
+        }
+
+        Rule::stmt_continue => {
+            let mut inner_rules = pair.into_inner();
+            Statement::Continue(parse_loop_label(&mut inner_rules))
+        }
+
+        Rule::expr => Statement::Expr(parse_expr(pair, ctx)),
 
         other => panic!("Unexpected rule: {:?}: {:?}", other, pair),
     }
@@ -874,7 +1701,7 @@ fn parse_stmt(pair: Pair<Rule>, filename: Option<&str>) -> Statement {
 //     }
 // }
 
-pub fn parse_expr(pair: Pair<Rule>) -> Expr {
+fn parse_expr(pair: Pair<Rule>, ctx: &ParseContext) -> Expr {
     let span = pair.as_span();
     let (line, column) = span.start_pos().line_col();
     let length = span.end_pos().pos() - span.start_pos().pos();
@@ -889,10 +1716,16 @@ pub fn parse_expr(pair: Pair<Rule>) -> Expr {
     };
 
     let result = match pair.as_rule() {
-        Rule::expr | Rule::expr_atom | Rule::expr_group => {
-            pair.into_inner().map(parse_expr).next().unwrap()
-        }
-        Rule::stmt_match => parse_match(pair),
+        Rule::expr
+        | Rule::expr_atom
+        | Rule::expr_group
+        | Rule::expr_subslice_start
+        | Rule::expr_subslice_end => pair
+            .into_inner()
+            .map(|x| parse_expr(x, ctx))
+            .next()
+            .unwrap(),
+        Rule::stmt_match => parse_match(pair, ctx),
         Rule::expr_logic_factor
         | Rule::expr_logic_term
         | Rule::expr_comparison
@@ -901,30 +1734,30 @@ pub fn parse_expr(pair: Pair<Rule>) -> Expr {
         | Rule::expr_factor
         | Rule::expr_bitwise_factor
         | Rule::expr_bitwise_term
-        | Rule::expr_bitwise_atom => parse_binop(pair),
+        | Rule::expr_bitwise_atom => parse_binop(pair, ctx),
         Rule::expr_ternary => {
             let mut inner_rules = pair.into_inner();
-            let cond = parse_expr(inner_rules.next().unwrap());
-            let if_true = parse_expr(inner_rules.next().unwrap());
-            let if_false = parse_expr(inner_rules.next().unwrap());
+            let cond = parse_expr(inner_rules.next().unwrap(), ctx);
+            let if_true = parse_expr(inner_rules.next().unwrap(), ctx);
+            let if_false = parse_expr(inner_rules.next().unwrap(), ctx);
             Expr::If(Box::new(cond), Box::new(if_true), Box::new(if_false))
         }
         Rule::expr_variant => {
             let mut inner_rules = pair.into_inner();
-            let ty = parse_type(inner_rules.next().unwrap());
+            let ty = parse_type(inner_rules.next().unwrap(), ctx);
             let variant = inner_rules.next().unwrap().as_str();
             if let Some(expr) = inner_rules.next() {
-                Expr::EnumUnion(ty, variant.to_string(), Box::new(parse_expr(expr)))
+                Expr::EnumUnion(ty, variant.to_string(), Box::new(parse_expr(expr, ctx)))
             } else {
                 Expr::ConstExpr(ConstExpr::Of(ty, variant.to_string()))
             }
         }
-        Rule::expr_term_non_keyword => parse_expr_term(pair),
+        Rule::expr_term_non_keyword => parse_expr_term(pair, ctx),
         Rule::expr_tuple => {
             let inner_rules = pair.into_inner();
             let mut result = vec![];
             for x in inner_rules {
-                result.push(parse_expr(x));
+                result.push(parse_expr(x, ctx));
             }
             Expr::Tuple(result)
         }
@@ -932,7 +1765,7 @@ pub fn parse_expr(pair: Pair<Rule>) -> Expr {
             let inner_rules = pair.into_inner();
             let mut result = vec![];
             for x in inner_rules {
-                result.push(parse_expr(x));
+                result.push(parse_expr(x, ctx));
             }
             Expr::Array(result)
         }
@@ -941,11 +1774,45 @@ pub fn parse_expr(pair: Pair<Rule>) -> Expr {
             let mut result = vec![];
             while inner_rules.peek().is_some() {
                 let field = inner_rules.next().unwrap().as_str().to_string();
-                let val = parse_expr(inner_rules.next().unwrap());
+                let val = parse_expr(inner_rules.next().unwrap(), ctx);
                 result.push((field, val));
             }
             Expr::Struct(result.into_iter().collect())
         }
+        Rule::expr_closure => {
+            let mut captures = vec![];
+            let mut params = vec![];
+            let mut ret = Type::None;
+            let mut body = Expr::NONE;
+            for pair in pair.into_inner() {
+                match pair.as_rule() {
+                    Rule::closure_captures => {
+                        for capture in pair.into_inner() {
+                            let mut inner_rules = capture.into_inner();
+                            let mut capture = Capture::Value;
+                            if inner_rules.peek().unwrap().as_rule() == Rule::closure_capture_ref {
+                                capture = match inner_rules.next().unwrap().as_str() {
+                                    "&mut" => Capture::Reference(Mutability::Mutable),
+                                    _ => Capture::Reference(Mutability::Immutable),
+                                };
+                            }
+                            let name = ctx.scoped(inner_rules.next().unwrap().as_str());
+                            captures.push((name, capture));
+                        }
+                    }
+                    Rule::decl_proc_param => {
+                        let mut inner_rules = pair.into_inner();
+                        let (mutability, name) = parse_symbol(inner_rules.next().unwrap(), ctx);
+                        let ty = parse_type(inner_rules.next().unwrap(), ctx);
+                        params.push((name, mutability, ty));
+                    }
+                    Rule::r#type => ret = parse_type(pair, ctx),
+                    Rule::stmt_block | Rule::expr => body = parse_expr(pair, ctx),
+                    other => panic!("unexpected rule {:?}", other),
+                }
+            }
+            Expr::Closure(Closure::new(captures, params, ret, body))
+        }
 
         Rule::expr_unary | Rule::expr_term => {
             let inner_rules = pair.into_inner();
@@ -963,16 +1830,16 @@ pub fn parse_expr(pair: Pair<Rule>) -> Expr {
                         "*" => result.deref(),
                         _ => panic!("Unexpected unary op: {}", x.as_str()),
                     },
-                    _ => parse_expr(x),
+                    _ => parse_expr(x, ctx),
                 }
             }
             result
         }
 
         Rule::r#const | Rule::const_term | Rule::const_monomorph | Rule::const_atom => {
-            Expr::ConstExpr(parse_const(pair))
+            Expr::ConstExpr(parse_const(pair, ctx))
         }
-        Rule::stmt_block => parse_stmt(pair, None).to_expr(None),
+        Rule::stmt_block => parse_stmt(pair, ctx).to_expr(None),
         other => panic!("Unexpected rule: {:?}: {:?}", other, pair),
     };
     // result
@@ -980,9 +1847,10 @@ pub fn parse_expr(pair: Pair<Rule>) -> Expr {
     result
 }
 
-fn parse_expr_term(pair: Pair<Rule>) -> Expr {
+fn parse_expr_term(pair: Pair<Rule>, ctx: &ParseContext) -> Expr {
+    let start = pair.as_span().start_pos();
     let mut inner_rules = pair.into_inner();
-    let mut head = parse_expr(inner_rules.next().unwrap());
+    let mut head = parse_expr(inner_rules.next().unwrap(), ctx);
     for suffix in inner_rules {
         head = match suffix.as_rule() {
             Rule::expr_int_field => head.field(ConstExpr::Int(
@@ -997,12 +1865,36 @@ fn parse_expr_term(pair: Pair<Rule>) -> Expr {
             Rule::expr_symbol_field => head.field(ConstExpr::Symbol(
                 suffix.into_inner().next().unwrap().as_str().to_string(),
             )),
-            Rule::expr_index => head.idx(parse_expr(suffix)),
+            Rule::expr_index => {
+                // Indexing can fail at runtime, so it's marked with its own location
+                // for the bounds check to report instead of the enclosing statement's.
+                let loc = ctx.location(start.span(&suffix.as_span().end_pos()));
+                head.idx(parse_expr(suffix, ctx))
+                    .annotate(Annotation::Location(loc))
+            }
+            Rule::expr_subslice => {
+                // A missing start is the first element, and a missing end is the length.
+                let mut start = Expr::ConstExpr(ConstExpr::Int(0));
+                let mut end = Expr::ConstExpr(ConstExpr::None);
+                for bound in suffix.into_inner() {
+                    match bound.as_rule() {
+                        Rule::expr_subslice_start => start = parse_expr(bound, ctx),
+                        Rule::expr_subslice_end => end = parse_expr(bound, ctx),
+                        _ => unreachable!(),
+                    }
+                }
+                Expr::TernaryOp(
+                    Box::new(Subslice),
+                    Box::new(head),
+                    Box::new(start),
+                    Box::new(end),
+                )
+            }
             Rule::expr_call => {
                 let inner_rules = suffix.into_inner();
                 let mut args = Vec::new();
                 for arg in inner_rules {
-                    args.push(parse_expr(arg));
+                    args.push(parse_expr(arg, ctx));
                 }
                 if head == Expr::ConstExpr(ConstExpr::Symbol("print".to_string())) {
                     let mut exprs: Vec<Expr> =
@@ -1034,30 +1926,35 @@ fn parse_expr_term(pair: Pair<Rule>) -> Expr {
                     head.app(args)
                 }
             }
-            Rule::expr_as_type => head.as_type(parse_type(suffix.into_inner().next().unwrap())),
+            Rule::expr_as_type => {
+                head.as_type(parse_type(suffix.into_inner().next().unwrap(), ctx))
+            }
             _ => unreachable!(),
         }
     }
     head
 }
 
-fn parse_binop(pair: Pair<Rule>) -> Expr {
-    let mut inner_rules = pair.into_inner().peekable();
-    let mut head = parse_expr(inner_rules.next().unwrap());
+fn parse_binop(pair: Pair<Rule>, ctx: &ParseContext) -> Expr {
+    let start = pair.as_span().start_pos();
+    let mut inner_rules = pair.into_inner();
+    let mut head = parse_expr(inner_rules.next().unwrap(), ctx);
     // let count = inner_rules.clone().count() / 2;
     for pair in inner_rules {
         let mut inner_rules = pair.clone().into_inner();
         let next_pair = inner_rules.next().unwrap();
         let op = pair.as_str()[..pair.as_str().len() - next_pair.as_str().len()].trim();
-        let tail = parse_expr(next_pair);
+        let tail = parse_expr(next_pair, ctx);
+        let loc = ctx.location(start.span(&pair.as_span().end_pos()));
         head = match op {
             "&&" => head.and(tail),
             "||" => head.or(tail),
             "+" => head.add(tail),
             "-" => head.sub(tail),
             "*" => head.mul(tail),
-            "/" => head.div(tail),
-            "%" => head.rem(tail),
+            // Division by zero traps at runtime, so these are marked with their locations.
+            "/" => head.div(tail).annotate(Annotation::Location(loc)),
+            "%" => head.rem(tail).annotate(Annotation::Location(loc)),
             "==" => head.eq(tail),
             "!=" => head.neq(tail),
             "<" => head.lt(tail),
@@ -1075,14 +1972,16 @@ fn parse_binop(pair: Pair<Rule>) -> Expr {
     head
 }
 
-fn parse_const(pair: Pair<Rule>) -> ConstExpr {
+fn parse_const(pair: Pair<Rule>, ctx: &ParseContext) -> ConstExpr {
     match pair.as_rule() {
-        Rule::r#const | Rule::const_atom | Rule::const_group => {
-            pair.into_inner().map(parse_const).next().unwrap()
-        }
+        Rule::r#const | Rule::const_atom | Rule::const_group => pair
+            .into_inner()
+            .map(|x| parse_const(x, ctx))
+            .next()
+            .unwrap(),
         Rule::const_term => {
             let mut inner_rules = pair.into_inner();
-            let mut head = parse_const(inner_rules.next().unwrap());
+            let mut head = parse_const(inner_rules.next().unwrap(), ctx);
             for suffix in inner_rules {
                 head = match suffix.as_rule() {
                     Rule::expr_int_field => head.field(ConstExpr::Int(
@@ -1116,10 +2015,10 @@ fn parse_const(pair: Pair<Rule>) -> ConstExpr {
         }
         Rule::const_monomorph => {
             let mut inner_rules = pair.into_inner();
-            let c = parse_const(inner_rules.next().unwrap());
+            let c = parse_const(inner_rules.next().unwrap(), ctx);
             let mut args = Vec::new();
             for arg in inner_rules.next().unwrap().into_inner() {
-                args.push(parse_type(arg));
+                args.push(parse_type(arg, ctx));
             }
             ConstExpr::Monomorphize(Box::new(c), args)
         }
@@ -1127,7 +2026,7 @@ fn parse_const(pair: Pair<Rule>) -> ConstExpr {
             let inner_rules = pair.into_inner();
             let mut exprs = Vec::new();
             for pair in inner_rules {
-                exprs.push(parse_const(pair));
+                exprs.push(parse_const(pair, ctx));
             }
             ConstExpr::Tuple(exprs)
         }
@@ -1135,7 +2034,7 @@ fn parse_const(pair: Pair<Rule>) -> ConstExpr {
             let inner_rules = pair.into_inner();
             let mut exprs = Vec::new();
             for pair in inner_rules {
-                exprs.push(parse_const(pair));
+                exprs.push(parse_const(pair, ctx));
             }
             ConstExpr::Array(exprs)
         }
@@ -1144,24 +2043,24 @@ fn parse_const(pair: Pair<Rule>) -> ConstExpr {
             let mut fields = Vec::new();
             while inner_rules.peek().is_some() {
                 let field = inner_rules.next().unwrap().as_str().to_string();
-                let val = parse_const(inner_rules.next().unwrap());
+                let val = parse_const(inner_rules.next().unwrap(), ctx);
                 fields.push((field, val));
             }
             ConstExpr::Struct(fields.into_iter().collect())
         }
         Rule::const_variant => {
             let mut inner_rules = pair.into_inner();
-            let ty = parse_type(inner_rules.next().unwrap());
+            let ty = parse_type(inner_rules.next().unwrap(), ctx);
             let symbol = inner_rules.next().unwrap().as_str().to_string();
             if let Some(inner_rules) = inner_rules.next() {
-                let expr = parse_const(inner_rules);
+                let expr = parse_const(inner_rules, ctx);
                 // ConstExpr::Variant(ty, symbol, Some(Box::new(expr)))
                 ConstExpr::EnumUnion(ty, symbol, Box::new(expr))
             } else {
                 ConstExpr::Of(ty, symbol)
             }
         }
-        Rule::const_symbol => ConstExpr::Symbol(pair.as_str().to_string()),
+        Rule::const_symbol => ConstExpr::Symbol(ctx.scoped(pair.as_str())),
         Rule::const_int => {
             let s = pair.as_str();
             ConstExpr::Int(if s.len() > 2 && &s[..2] == "0b" {
@@ -1212,7 +2111,7 @@ fn parse_const(pair: Pair<Rule>) -> ConstExpr {
             ConstExpr::Char(ch)
         }
         Rule::const_bool => ConstExpr::Bool(pair.as_str().to_lowercase().parse().unwrap()),
-        Rule::const_string => ConstExpr::Array(
+        Rule::const_string => ConstExpr::Str(
             snailquote::unescape(
                 &pair
                     .clone()
@@ -1232,35 +2131,32 @@ fn parse_const(pair: Pair<Rule>) -> ConstExpr {
                     .replace("\\0", "\\\\0")
                     .replace("\\/", "/")
             })
-            .replace("\\0", "\0")
-            .chars()
-            .map(ConstExpr::Char)
-            // Add a null terminator
-            .chain(std::iter::once(ConstExpr::Char('\0')))
-            .collect(),
+            .replace("\\0", "\0"),
         ),
         Rule::const_none => ConstExpr::None,
         Rule::const_null => ConstExpr::Null,
         Rule::const_size_of_type => {
-            ConstExpr::SizeOfType(parse_type(pair.into_inner().next().unwrap()))
+            ConstExpr::SizeOfType(parse_type(pair.into_inner().next().unwrap(), ctx))
         }
         Rule::const_size_of_expr => {
-            ConstExpr::SizeOfExpr(parse_expr(pair.into_inner().next().unwrap()).into())
+            ConstExpr::SizeOfExpr(parse_expr(pair.into_inner().next().unwrap(), ctx).into())
         }
         other => panic!("Unexpected rule: {:?}: {:?}", other, pair),
     }
 }
 
-fn parse_type(pair: Pair<Rule>) -> Type {
+fn parse_type(pair: Pair<Rule>, ctx: &ParseContext) -> Type {
     // todo!()
     match pair.as_rule() {
-        Rule::r#type | Rule::type_atom | Rule::type_term => {
-            pair.into_inner().map(parse_type).next().unwrap()
-        }
+        Rule::r#type | Rule::type_atom | Rule::type_term => pair
+            .into_inner()
+            .map(|x| parse_type(x, ctx))
+            .next()
+            .unwrap(),
 
         Rule::type_apply => {
             let mut inner_rules = pair.into_inner();
-            let mut head = parse_type(inner_rules.next().unwrap());
+            let mut head = parse_type(inner_rules.next().unwrap(), ctx);
 
             while inner_rules.peek().is_some() {
                 for parsed_args in inner_rules.by_ref() {
@@ -1268,7 +2164,7 @@ fn parse_type(pair: Pair<Rule>) -> Type {
                     // type_application_suffix
                     // args.push(parse_type(arg));
                     for parsed_arg in parsed_args.into_inner() {
-                        ty_args.push(parse_type(parsed_arg));
+                        ty_args.push(parse_type(parsed_arg, ctx));
                     }
                     head = Type::Apply(Box::new(head), ty_args);
                 }
@@ -1283,10 +2179,10 @@ fn parse_type(pair: Pair<Rule>) -> Type {
             for pair in inner_rules {
                 match pair.as_rule() {
                     Rule::symbol => {
-                        params.push(pair.as_str().to_string());
+                        params.push(ctx.scoped(pair.as_str()));
                     }
                     Rule::r#type => {
-                        let ty = parse_type(pair);
+                        let ty = parse_type(pair, ctx);
                         head = Type::Poly(params.clone(), Box::new(ty));
                     }
                     _ => unreachable!(),
@@ -1300,18 +2196,18 @@ fn parse_type(pair: Pair<Rule>) -> Type {
             // Get all but the last rule
             let mut result = vec![];
             while inner_rules.clone().count() > 2 {
-                let name = inner_rules.next().unwrap().as_str().to_string();
-                let ty = parse_type(inner_rules.next().unwrap());
+                let name = ctx.scoped(inner_rules.next().unwrap().as_str());
+                let ty = parse_type(inner_rules.next().unwrap(), ctx);
                 result.push((name, ty));
             }
-            let mut ty = parse_type(inner_rules.next().unwrap());
+            let mut ty = parse_type(inner_rules.next().unwrap(), ctx);
             for (name, var) in result.into_iter().rev() {
                 ty = Type::Let(name, Box::new(var), Box::new(ty));
             }
             ty
         }
 
-        Rule::type_symbol => Type::Symbol(pair.as_str().to_string()),
+        Rule::type_symbol => Type::Symbol(ctx.scoped(pair.as_str())),
         Rule::type_int => Type::Int,
         Rule::type_cell => Type::Cell,
         Rule::type_float => Type::Float,
@@ -1324,14 +2220,14 @@ fn parse_type(pair: Pair<Rule>) -> Type {
             let inner_rules = pair.into_inner();
             let mut tys = Vec::new();
             for pair in inner_rules {
-                tys.push(parse_type(pair));
+                tys.push(parse_type(pair, ctx));
             }
             Type::Tuple(tys)
         }
         Rule::type_array => {
             let mut inner_rules = pair.into_inner();
-            let ty = parse_type(inner_rules.next().unwrap());
-            let len = parse_const(inner_rules.next().unwrap());
+            let ty = parse_type(inner_rules.next().unwrap(), ctx);
+            let len = parse_const(inner_rules.next().unwrap(), ctx);
             Type::Array(Box::new(ty), Box::new(len))
         }
         Rule::type_struct => {
@@ -1339,7 +2235,7 @@ fn parse_type(pair: Pair<Rule>) -> Type {
             let mut fields = Vec::new();
             while inner_rules.peek().is_some() {
                 let name = inner_rules.next().unwrap().as_str().to_string();
-                let ty = parse_type(inner_rules.next().unwrap());
+                let ty = parse_type(inner_rules.next().unwrap(), ctx);
                 fields.push((name, ty));
             }
             Type::Struct(fields.into_iter().collect())
@@ -1360,7 +2256,8 @@ fn parse_type(pair: Pair<Rule>) -> Type {
 
                 if let Some(ty) = ty {
                     if ty.as_rule() == Rule::r#type {
-                        variants.push((variant_name.as_str().to_string(), Some(parse_type(ty))));
+                        variants
+                            .push((variant_name.as_str().to_string(), Some(parse_type(ty, ctx))));
                     } else {
                         variants.push((variant_name.as_str().to_string(), None));
                     }
@@ -1386,14 +2283,24 @@ fn parse_type(pair: Pair<Rule>) -> Type {
                 )
             }
         }
+        Rule::type_slice => {
+            let mut inner_rules = pair.into_inner();
+            let ty = parse_type(inner_rules.next().unwrap(), ctx);
+            Type::Slice(Mutability::Immutable, Box::new(ty))
+        }
+        Rule::type_mut_slice => {
+            let mut inner_rules = pair.into_inner();
+            let ty = parse_type(inner_rules.next().unwrap(), ctx);
+            Type::Slice(Mutability::Mutable, Box::new(ty))
+        }
         Rule::type_ptr => {
             let mut inner_rules = pair.into_inner();
-            let ty = parse_type(inner_rules.next().unwrap());
+            let ty = parse_type(inner_rules.next().unwrap(), ctx);
             Type::Pointer(Mutability::Immutable, Box::new(ty))
         }
         Rule::type_mut_ptr => {
             let mut inner_rules = pair.into_inner();
-            let ty = parse_type(inner_rules.next().unwrap());
+            let ty = parse_type(inner_rules.next().unwrap(), ctx);
             Type::Pointer(Mutability::Mutable, Box::new(ty))
         }
         Rule::type_proc => {
@@ -1401,10 +2308,10 @@ fn parse_type(pair: Pair<Rule>) -> Type {
             let mut args_rules = inner_rules.next().unwrap().into_inner();
             let mut args = Vec::new();
             while args_rules.peek().is_some() {
-                let ty = parse_type(args_rules.next().unwrap());
+                let ty = parse_type(args_rules.next().unwrap(), ctx);
                 args.push(ty);
             }
-            let ret = parse_type(inner_rules.next().unwrap());
+            let ret = parse_type(inner_rules.next().unwrap(), ctx);
             Type::Proc(args, Box::new(ret))
         }
 
@@ -1412,38 +2319,42 @@ fn parse_type(pair: Pair<Rule>) -> Type {
     }
 }
 
-fn parse_match(pair: Pair<Rule>) -> Expr {
+fn parse_match(pair: Pair<Rule>, ctx: &ParseContext) -> Expr {
     let mut inner_rules = pair.into_inner();
-    let expr = parse_expr(inner_rules.next().unwrap());
+    let expr = parse_expr(inner_rules.next().unwrap(), ctx);
     let mut patterns = Vec::new();
     let mut stmts = Vec::new();
     for pair in inner_rules {
         let mut inner_rules = pair.into_inner();
-        let pattern = parse_pattern(inner_rules.next().unwrap());
-        let stmt = parse_expr(inner_rules.next().unwrap());
+        let pattern = parse_pattern(inner_rules.next().unwrap(), ctx);
+        let stmt = parse_expr(inner_rules.next().unwrap(), ctx);
         patterns.push(pattern);
         stmts.push(stmt);
     }
     Expr::Match(Box::new(expr), patterns.into_iter().zip(stmts).collect())
 }
 
-fn parse_pattern(pair: Pair<Rule>) -> Pattern {
+fn parse_pattern(pair: Pair<Rule>, ctx: &ParseContext) -> Pattern {
     match pair.as_rule() {
-        Rule::pattern | Rule::pattern_term | Rule::pattern_atom | Rule::pattern_group => {
-            pair.into_inner().map(parse_pattern).next().unwrap()
+        Rule::pattern | Rule::pattern_term | Rule::pattern_atom | Rule::pattern_group => pair
+            .into_inner()
+            .map(|x| parse_pattern(x, ctx))
+            .next()
+            .unwrap(),
+        Rule::pattern_const => {
+            Pattern::ConstExpr(parse_const(pair.into_inner().next().unwrap(), ctx))
         }
-        Rule::pattern_const => Pattern::ConstExpr(parse_const(pair.into_inner().next().unwrap())),
         Rule::pattern_variant => {
             let mut inner_rules = pair.into_inner();
             let symbol = inner_rules.next().unwrap().as_str().to_string();
-            let pattern = inner_rules.next().map(parse_pattern);
+            let pattern = inner_rules.next().map(|x| parse_pattern(x, ctx));
             Pattern::Variant(symbol, pattern.map(Box::new))
         }
         Rule::pattern_tuple => {
             let inner_rules = pair.into_inner();
             let mut patterns = Vec::new();
             for pair in inner_rules {
-                let pattern = parse_pattern(pair);
+                let pattern = parse_pattern(pair, ctx);
                 patterns.push(pattern);
             }
             Pattern::Tuple(patterns)
@@ -1455,21 +2366,19 @@ fn parse_pattern(pair: Pair<Rule>) -> Pattern {
                 let mut inner_rules = pair.into_inner();
                 let symbol = inner_rules.next().unwrap().as_str().to_string();
                 if inner_rules.peek().is_none() {
-                    fields.push((
-                        symbol.clone(),
-                        Pattern::Symbol(Mutability::Immutable, symbol),
-                    ));
+                    let binding = ctx.scoped(&symbol);
+                    fields.push((symbol, Pattern::Symbol(Mutability::Immutable, binding)));
                     continue;
                 }
                 // let pattern = parse_pattern(inner_rules.next().unwrap());
-                let pattern = inner_rules.next().map(parse_pattern).unwrap();
+                let pattern = inner_rules.next().map(|x| parse_pattern(x, ctx)).unwrap();
                 fields.push((symbol, pattern));
             }
             Pattern::Struct(fields.into_iter().collect())
         }
         Rule::pattern_ptr => {
             let mut inner_rules = pair.into_inner();
-            let pattern = parse_pattern(inner_rules.next().unwrap());
+            let pattern = parse_pattern(inner_rules.next().unwrap(), ctx);
             Pattern::Pointer(Box::new(pattern))
         }
         Rule::pattern_wildcard => Pattern::Wildcard,
@@ -1479,7 +2388,7 @@ fn parse_pattern(pair: Pair<Rule>) -> Pattern {
             if symbol == "_" {
                 Pattern::Wildcard
             } else {
-                Pattern::Symbol(Mutability::Mutable, symbol)
+                Pattern::Symbol(Mutability::Mutable, ctx.scoped(&symbol))
             }
         }
         Rule::pattern_symbol => {
@@ -1487,14 +2396,14 @@ fn parse_pattern(pair: Pair<Rule>) -> Pattern {
             if symbol == "_" {
                 Pattern::Wildcard
             } else {
-                Pattern::Symbol(Mutability::Immutable, symbol)
+                Pattern::Symbol(Mutability::Immutable, ctx.scoped(&symbol))
             }
         }
         Rule::pattern_alt => {
             let inner_rules = pair.into_inner();
             let mut patterns = Vec::new();
             for pair in inner_rules {
-                let pattern = parse_pattern(pair);
+                let pattern = parse_pattern(pair, ctx);
                 patterns.push(pattern);
             }
             Pattern::Alt(patterns)
@@ -49,6 +49,7 @@
 //! 2. [The Assembly Language](./asm/index.html)
 //! 3. [The Virtual Machine](./vm/index.html)
 //! 4. [Target Backends](./targets/index.html)
+//! 5. [Embedding Sage in Rust](./runtime/index.html)
 //!
 //! ## Stages of IR
 //!
@@ -90,11 +91,15 @@
 pub mod asm;
 pub mod frontend;
 pub mod lir;
+pub mod lsp;
 pub mod parse;
+pub mod runtime;
 pub mod side_effects;
 pub mod targets;
 pub mod vm;
 
+pub use runtime::Runtime;
+
 /// The value of the NULL pointer constant.
 ///
 /// I've chosen to use the smallest value that can be expressed by an 8-bit signed integer.
@@ -102,6 +107,12 @@ pub mod vm;
 /// The value of this constant might change in the future though.
 pub const NULL: i64 = i8::MIN as i64;
 
+/// The address which compiled code dereferences to stop the program when it panics.
+///
+/// Like `NULL`, this is negative so that it can never be a valid address. The interpreters
+/// recognize it, so that they report the call stack without treating it as a null pointer.
+pub const PANIC: i64 = NULL - 1;
+
 /// The UNICODE character art for the logo of the language.
 pub const LOGO: &str = r#"
    █████   ██████    ███████  ██████   `-.        _.-'
@@ -9,6 +9,9 @@ use std::collections::BTreeSet;
 pub enum Annotation {
     /// The source code location of the expression.
     Location(SourceCodeLocation),
+    /// The location of the definition of a symbol. Declarations are annotated
+    /// with the symbols they define, and errors with the definitions involved in them.
+    Definition(String, SourceCodeLocation),
     /// Is this expression a constant?
     Constant(bool),
     /// Is this expression dead code?
@@ -74,6 +77,32 @@ impl Annotation {
         }
     }
 
+    /// Get the symbols this annotation has the definitions of, and their locations.
+    pub fn definitions(&self) -> Vec<(&str, &SourceCodeLocation)> {
+        match self {
+            Annotation::Definition(name, location) => vec![(name.as_str(), location)],
+            Annotation::Many(annotations) => {
+                annotations.iter().flat_map(|a| a.definitions()).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Get this annotation without the locations of any definitions.
+    pub fn without_definitions(&self) -> Self {
+        match self {
+            Annotation::Definition(..) => Annotation::None,
+            Annotation::Many(annotations) => Annotation::Many(
+                annotations
+                    .iter()
+                    .filter(|a| !matches!(a, Annotation::Definition(..)))
+                    .cloned()
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+
     /// Is this dead code?
     pub fn is_dead_code(&self) -> bool {
         match self {
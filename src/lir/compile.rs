@@ -11,15 +11,18 @@
 //! 3. If the expression cannot be compiled into a core assembly program, then compile it into a standard assembly program.
 use super::*;
 use crate::asm::{
-    AssemblyProgram, CoreOp, CoreProgram, StandardOp, StandardProgram, A, B, C, FP, SP,
+    AssemblyProgram, CoreOp, CoreProgram, StandardOp, StandardProgram, A, B, C, D, FP, SP,
 };
-use crate::NULL;
+use crate::{side_effects::Output, NULL, PANIC};
 use log::*;
 use rayon::prelude::*;
 use std::sync::Mutex;
 
 use log::{error, info, trace, warn};
 
+/// The label of the procedure which prints the message of a panic.
+const PANIC_MESSAGE: &str = "__PANIC_MESSAGE";
+
 /// A trait which allows an LIR expression to be compiled to one of the
 /// two variants of the assembly language.
 pub trait Compile: TypeCheck + std::fmt::Debug + std::fmt::Display {
@@ -65,25 +68,39 @@ pub trait Compile: TypeCheck + std::fmt::Debug + std::fmt::Display {
     fn compile_expr(self, env: &mut Env, output: &mut dyn AssemblyProgram) -> Result<(), Error>;
 }
 
+/// Shorten a string to at most `max` characters, without splitting a UTF-8 sequence.
+fn truncate_chars(text: &mut String, max: usize) {
+    if let Some((index, _)) = text.char_indices().nth(max) {
+        text.truncate(index);
+    }
+}
+
 /// Compile an LIR expression into several core assembly instructions.
 impl Compile for Expr {
     fn compile_expr(self, env: &mut Env, output: &mut dyn AssemblyProgram) -> Result<(), Error> {
         trace!("Compiling expression {self} in environment {env}");
         let mut debug_str = format!("{self:50}");
-        debug_str.truncate(50);
+        truncate_chars(&mut debug_str, 50);
 
         // Write a little comment about what we're compiling.
         if !matches!(self, Self::ConstExpr(_)) {
             let mut comment = format!("{self}");
-            comment.truncate(70);
+            truncate_chars(&mut comment, 70);
         }
 
         // Compile the expression.
         match self {
             Self::Annotated(expr, metadata) => {
+                // Mark the code with the location of the expression in the source code.
+                let previous_location = env.get_location().cloned();
+                if let Some(location) = metadata.location() {
+                    output.location(location);
+                    env.set_location(Some(location.clone()));
+                }
                 // Compile the expression.
-                expr.compile_expr(env, output)
-                    .map_err(|e| e.annotate(metadata))?;
+                let result = expr.compile_expr(env, output);
+                env.set_location(previous_location);
+                result.map_err(|e| e.annotate(metadata))?;
             }
 
             Self::Match(expr, branches) => {
@@ -101,61 +118,56 @@ impl Compile for Expr {
             }
 
             Self::UnaryOp(unop, expr) => {
+                // The operations are compiled on their operands without the annotations,
+                // so an annotated operand lends its annotation to the whole operation.
+                // This marks the operation with the location of an operand which can trap.
                 if let Expr::Annotated(expr, metadata) = *expr {
-                    return unop
-                        .compile(&expr, env, output)
-                        .map_err(|e| e.annotate(metadata.clone()));
+                    return Self::Annotated(Box::new(Self::UnaryOp(unop, expr)), metadata)
+                        .compile_expr(env, output);
                 }
 
                 // Compile the unary operation on the expression.
                 unop.compile(&expr, env, output)?;
             }
             Self::BinaryOp(binop, lhs, rhs) => {
-                if let Expr::Annotated(lhs, metadata) = &*lhs {
-                    return binop
-                        .compile(lhs, &rhs, env, output)
-                        .map_err(|e| e.annotate(metadata.clone()));
+                if let Expr::Annotated(lhs, metadata) = *lhs {
+                    return Self::Annotated(Box::new(Self::BinaryOp(binop, lhs, rhs)), metadata)
+                        .compile_expr(env, output);
                 }
-                if let Expr::Annotated(rhs, metadata) = &*rhs {
-                    return binop
-                        .compile(&lhs, rhs, env, output)
-                        .map_err(|e| e.annotate(metadata.clone()));
+                if let Expr::Annotated(rhs, metadata) = *rhs {
+                    return Self::Annotated(Box::new(Self::BinaryOp(binop, lhs, rhs)), metadata)
+                        .compile_expr(env, output);
                 }
 
                 // Compile the binary operation on the two expressions.
                 binop.compile(&lhs, &rhs, env, output)?;
             }
             Self::TernaryOp(ternop, a, b, c) => {
-                if let Expr::Annotated(a, metadata) = &*a {
-                    return ternop
-                        .compile(a, &b, &c, env, output)
-                        .map_err(|e| e.annotate(metadata.clone()));
+                if let Expr::Annotated(a, metadata) = *a {
+                    return Self::Annotated(Box::new(Self::TernaryOp(ternop, a, b, c)), metadata)
+                        .compile_expr(env, output);
                 }
-                if let Expr::Annotated(b, metadata) = &*b {
-                    return ternop
-                        .compile(&a, b, &c, env, output)
-                        .map_err(|e| e.annotate(metadata.clone()));
+                if let Expr::Annotated(b, metadata) = *b {
+                    return Self::Annotated(Box::new(Self::TernaryOp(ternop, a, b, c)), metadata)
+                        .compile_expr(env, output);
                 }
-                if let Expr::Annotated(c, metadata) = &*c {
-                    return ternop
-                        .compile(&a, &b, c, env, output)
-                        .map_err(|e| e.annotate(metadata.clone()));
+                if let Expr::Annotated(c, metadata) = *c {
+                    return Self::Annotated(Box::new(Self::TernaryOp(ternop, a, b, c)), metadata)
+                        .compile_expr(env, output);
                 }
 
                 // Compile the ternary operation on the three expressions.
                 ternop.compile(&a, &b, &c, env, output)?;
             }
             Self::AssignOp(op, dst, src) => {
-                if let Expr::Annotated(dst, metadata) = &*dst {
-                    return op
-                        .compile(dst, &src, env, output)
-                        .map_err(|e| e.annotate(metadata.clone()));
+                if let Expr::Annotated(dst, metadata) = *dst {
+                    return Self::Annotated(Box::new(Self::AssignOp(op, dst, src)), metadata)
+                        .compile_expr(env, output);
                 }
 
-                if let Expr::Annotated(src, metadata) = &*src {
-                    return op
-                        .compile(&dst, src, env, output)
-                        .map_err(|e| e.annotate(metadata.clone()));
+                if let Expr::Annotated(src, metadata) = *src {
+                    return Self::Annotated(Box::new(Self::AssignOp(op, dst, src)), metadata)
+                        .compile_expr(env, output);
                 }
                 // Compile the assignment operation on the two expressions.
                 op.compile(&dst, &src, env, output)?;
@@ -165,9 +177,18 @@ impl Compile for Expr {
             Self::ConstExpr(expr) => expr.compile_expr(env, output)?,
             // Compile a block of expressions.
             Self::Many(exprs) => {
-                for expr in exprs {
+                let mut exprs = exprs.into_iter();
+                while let Some(expr) = exprs.next() {
+                    // Get the loops this expression might exit.
+                    let exits = expr.get_loop_exits();
                     // Compile the expression in the block.
                     expr.compile_expr(env, output)?;
+                    if !exits.is_empty() && exprs.len() > 0 {
+                        // Skip the rest of the block if the expression exited a loop.
+                        Self::Many(exprs.collect())
+                            .compile_unless_loop_exited(&exits, env, output)?;
+                        break;
+                    }
                 }
             }
 
@@ -190,10 +211,22 @@ impl Compile for Expr {
                     // If the cast is to a type of the same size,
                     // we will trust the user.
                     (a, b) if a.get_size(env)? == b.get_size(env)? => {}
-                    // Otherwise, the cast is invalid.
-                    (a, b) => {
-                        return Err(Error::InvalidAs(self, a, b));
-                    }
+                    (a, b) => match (
+                        a.simplify_until_concrete(env)?,
+                        b.simplify_until_concrete(env)?,
+                    ) {
+                        // If the cast is a pointer to an array to a slice,
+                        // then pair the pointer with the length of the array.
+                        (Type::Pointer(_, array), Type::Slice(_, _)) => {
+                            if let Type::Array(_, len) = array.simplify_until_concrete(env)? {
+                                output.op(CoreOp::PushConst(vec![len.as_int(env)?]));
+                            } else {
+                                return Err(Error::InvalidAs(self, a, b));
+                            }
+                        }
+                        // Otherwise, the cast is invalid.
+                        _ => return Err(Error::InvalidAs(self, a, b)),
+                    },
                 }
             }
 
@@ -210,6 +243,14 @@ impl Compile for Expr {
                         });
                 }
 
+                // Coerce the arguments to the types of the procedure's parameters.
+                // Method calls are coerced once they're transformed into procedure calls.
+                let args = if self_clone.is_method_call(env)? {
+                    args
+                } else {
+                    Self::coerce_args(&f, args, env)?
+                };
+
                 // if !matches!(*f, Expr::Member(_, _)) {
                 //     // Push the arguments to the procedure on the stack.
                 //     for arg in &args {
@@ -274,10 +315,8 @@ impl Compile for Expr {
                             _ => {
                                 // Push the procedure on the stack.
                                 ConstExpr::Symbol(name).compile_expr(env, output)?;
-                                // Pop the "function pointer" from the stack.
-                                output.op(CoreOp::Pop(Some(A), 1));
                                 // Call the procedure on the arguments.
-                                output.op(CoreOp::Call(A));
+                                Self::compile_call(output);
                             }
                         }
                     }
@@ -298,10 +337,8 @@ impl Compile for Expr {
                             // Push the procedure on the stack.
                             debug!("Method: Monomorphizing {template} with {ty_args:?}");
                             ConstExpr::Monomorphize(template, ty_args).compile_expr(env, output)?;
-                            // Pop the "function pointer" from the stack.
-                            output.op(CoreOp::Pop(Some(A), 1));
                             // Call the procedure on the arguments.
-                            output.op(CoreOp::Call(A));
+                            Self::compile_call(output);
                         }
                     }
 
@@ -321,10 +358,8 @@ impl Compile for Expr {
                             // Compile it normally:
                             // Push the procedure on the stack.
                             val.field(name).compile_expr(env, output)?;
-                            // Pop the "function pointer" from the stack.
-                            output.op(CoreOp::Pop(Some(A), 1));
                             // Call the procedure on the arguments.
-                            output.op(CoreOp::Call(A));
+                            Self::compile_call(output);
                         }
                     }
                     // Otherwise, it must be a procedure.
@@ -336,13 +371,14 @@ impl Compile for Expr {
                         }
                         // Push the procedure on the stack.
                         proc.compile_expr(env, output)?;
-                        // Pop the "function pointer" from the stack.
-                        output.op(CoreOp::Pop(Some(A), 1));
                         // Call the procedure on the arguments.
-                        output.op(CoreOp::Call(A));
+                        Self::compile_call(output);
                     }
                 }
             }
+            // Compile a closure.
+            Self::Closure(closure) => closure.compile_expr(env, output)?,
+
             // Compile a return statement.
             Self::Return(e) => {
                 // Get the size of the arguments and return value.
@@ -381,6 +417,10 @@ impl Compile for Expr {
                 declaration.compile(*body, env, output)?;
             }
 
+            // Compile a while loop which can be exited early.
+            Self::While(cond, body) if !body.get_loop_exits().is_empty() => {
+                Self::compile_loop(None, *cond, *body, Self::NONE, env, output)?;
+            }
             // Compile a while loop.
             Self::While(cond, body) => {
                 // Eval the condition
@@ -397,8 +437,39 @@ impl Compile for Expr {
                 output.op(CoreOp::End);
             }
 
+            // Compile a loop which can be labeled.
+            Self::Loop(label, cond, body, step) => {
+                Self::compile_loop(label, *cond, *body, *step, env, output)?;
+            }
+
+            // Compile a `break` or `continue` by recording it in the state of the loop it exits.
+            // The code following it is skipped by checking the state of the loop.
+            Self::Break(ref label) | Self::Continue(ref label) => {
+                let state = match env.get_loop(label.as_deref()) {
+                    Some((_, Some(offset))) => *offset,
+                    _ => return Err(Error::InvalidLoopExit(self)),
+                };
+                let value = if matches!(self, Self::Break(_)) {
+                    LOOP_BROKEN
+                } else {
+                    LOOP_CONTINUED
+                };
+                output.op(CoreOp::Set(FP.deref().offset(state), value));
+            }
+
             // Compile an if statement.
             Self::If(c, t, e) => {
+                // If a branch exits a loop, it leaves nothing on the stack.
+                // Pad the stack so that it matches the size of the other branch.
+                let (t_padding, e_padding) =
+                    if t.get_loop_exits().is_empty() && e.get_loop_exits().is_empty() {
+                        (0, 0)
+                    } else {
+                        let t_size = t.get_size(env)?;
+                        let e_size = e.get_size(env)?;
+                        (e_size.saturating_sub(t_size), t_size.saturating_sub(e_size))
+                    };
+
                 // Compile the condition
                 c.compile_expr(env, output)?;
                 output.op(CoreOp::Pop(Some(A), 1));
@@ -406,10 +477,16 @@ impl Compile for Expr {
                 output.op(CoreOp::If(A));
                 // Compile the true branch
                 t.compile_expr(env, output)?;
+                if t_padding > 0 {
+                    output.op(CoreOp::Next(SP, Some(t_padding as isize)));
+                }
                 // If the condition is false
                 output.op(CoreOp::Else);
                 // Compile the false branch
                 e.compile_expr(env, output)?;
+                if e_padding > 0 {
+                    output.op(CoreOp::Next(SP, Some(e_padding as isize)));
+                }
                 // Label the end of the if statement
                 output.op(CoreOp::End);
             }
@@ -642,6 +719,21 @@ impl Compile for Expr {
                         // Push the contents of the element onto the stack.
                         output.op(CoreOp::Push(C.deref(), elem_size));
                     }
+                    // If the value being indexed is a string, index its bytes.
+                    Type::Str | Type::String => {
+                        val.field(ConstExpr::Symbol("data".to_string()))
+                            .idx(*idx)
+                            .compile_expr(env, output)?;
+                    }
+                    // If the value being indexed is a slice:
+                    Type::Slice(_, elem) => {
+                        let elem_size = elem.get_size(env)?;
+                        // Push the address of the element, checking the index against the length.
+                        Self::compile_slice_element_address(*val, *idx, elem_size, env, output)?;
+                        // Replace the address with the contents of the element.
+                        output.op(CoreOp::Pop(Some(A), 1));
+                        output.op(CoreOp::Push(A.deref(), elem_size));
+                    }
                     // Otherwise, we can't index this value.
                     _ => unreachable!(),
                 }
@@ -813,8 +905,13 @@ impl Compile for Expr {
 
                     // Push the address of the struct, tuple, or union onto the stack.
                     match val_type.simplify_until_has_members(env)? {
-                        // If the value is a struct, tuple, or union:
-                        Type::Struct(_) | Type::Tuple(_) | Type::Union(_) => {
+                        // If the value is a struct, tuple, union, or string:
+                        Type::Struct(_)
+                        | Type::Tuple(_)
+                        | Type::Union(_)
+                        | Type::Str
+                        | Type::String
+                        | Type::Slice(_, _) => {
                             // Compile a reference to the inner value with the expected mutability.
                             Self::Refer(expected_mutability, val.clone())
                                 .compile_expr(env, output)?;
@@ -930,6 +1027,31 @@ impl Compile for Expr {
                             // Push the address of the element onto the stack.
                             output.op(CoreOp::Push(C, 1));
                         }
+                        // If the value is a string, get the address of one of its bytes.
+                        Type::Str | Type::String => {
+                            Self::Refer(
+                                expected_mutability,
+                                Box::new(
+                                    val.field(ConstExpr::Symbol("data".to_string())).idx(*idx),
+                                ),
+                            )
+                            .compile_expr(env, output)?;
+                        }
+                        // If the value is a slice:
+                        Type::Slice(found_mutability, elem) => {
+                            if !found_mutability.can_decay_to(&expected_mutability) {
+                                return Err(Error::MismatchedMutability {
+                                    found: found_mutability,
+                                    expected: expected_mutability,
+                                    expr: Expr::Index(val, idx),
+                                });
+                            }
+                            let elem_size = elem.get_size(env)?;
+                            // Push the address of the element, checking the index against the length.
+                            Self::compile_slice_element_address(
+                                *val, *idx, elem_size, env, output,
+                            )?;
+                        }
                         // Otherwise, return an error.
                         _ => return Err(Error::InvalidIndex(Expr::Index(val, idx))),
                     }
@@ -944,12 +1066,246 @@ impl Compile for Expr {
     }
 }
 
+/// The value of a loop's state cell after the loop is exited with `break`.
+const LOOP_BROKEN: i64 = 1;
+/// The value of a loop's state cell after the loop is exited with `continue`.
+const LOOP_CONTINUED: i64 = 2;
+
+impl Expr {
+    /// Compile a loop with an optional label, which `break` and `continue` can exit.
+    ///
+    /// The assembly language only has structured control flow, so exits are implemented
+    /// with a state cell allocated on the stack for the loop. A `break` or `continue` sets
+    /// the state, and the code following it checks the state before executing.
+    fn compile_loop(
+        label: Option<String>,
+        cond: Self,
+        body: Self,
+        step: Self,
+        env: &mut Env,
+        output: &mut dyn AssemblyProgram,
+    ) -> Result<(), Error> {
+        let mut exits = body.get_loop_exits();
+        exits.extend(step.get_loop_exits());
+        // Split the exits into those which target this loop, and those which target outer loops.
+        let (own_exits, outer_exits): (Vec<_>, Vec<_>) = exits.into_iter().partition(|exit| {
+            matches!(exit, Self::Break(target) | Self::Continue(target) if target.is_none() || *target == label)
+        });
+        // The state cells of the outer loops which this loop can exit.
+        let outer_states = Self::get_loop_states(&outer_exits, env)?;
+
+        let mut loop_env = env.clone();
+        // Allocate the state cell for this loop, if it's exited by `break` or `continue`.
+        let state = if own_exits.is_empty() {
+            None
+        } else {
+            output.op(CoreOp::PushConst(vec![0]));
+            Some(loop_env.define_var(
+                format!("'{}", label.as_deref().unwrap_or("loop")),
+                Mutability::Mutable,
+                Type::Int,
+            )?)
+        };
+        loop_env.push_loop(label, state);
+
+        // Eval the condition
+        cond.clone().compile_expr(&mut loop_env, output)?;
+        output.op(CoreOp::Pop(Some(A), 1));
+        // While the condition
+        output.op(CoreOp::While(A));
+        // Compile the body
+        body.compile_expr(&mut loop_env, output)?;
+
+        let mut states = outer_states;
+        if let Some(state) = state {
+            // If the body continued, then reset the state to continue looping.
+            output.op(CoreOp::Set(B, LOOP_CONTINUED));
+            output.op(CoreOp::IsEqual {
+                a: FP.deref().offset(state),
+                b: B,
+                dst: B,
+            });
+            output.op(CoreOp::If(B));
+            output.op(CoreOp::Set(FP.deref().offset(state), 0));
+            output.op(CoreOp::End);
+            states.push(state);
+        }
+
+        if states.is_empty() {
+            // Execute the step and eval the condition again
+            step.compile_expr(&mut loop_env, output)?;
+            cond.compile_expr(&mut loop_env, output)?;
+            output.op(CoreOp::Pop(Some(A), 1));
+        } else {
+            // If the loop wasn't exited, then execute the step and eval the condition again.
+            Self::compile_no_loop_exited(&states, output);
+            output.op(CoreOp::If(A));
+            step.compile_expr(&mut loop_env, output)?;
+            cond.compile_expr(&mut loop_env, output)?;
+            output.op(CoreOp::Pop(Some(A), 1));
+            // Otherwise, stop looping.
+            output.op(CoreOp::Else);
+            output.op(CoreOp::Set(A, 0));
+            output.op(CoreOp::End);
+        }
+        // Label the end of the loop
+        output.op(CoreOp::End);
+
+        // Pop the state cell of the loop.
+        if state.is_some() {
+            output.op(CoreOp::Pop(None, 1));
+        }
+        Ok(())
+    }
+
+    /// Compile this expression, but only execute it if none of the loops targeted by
+    /// the given `break` and `continue` expressions were exited. Otherwise, the stack
+    /// is padded to the size of the expression.
+    pub(crate) fn compile_unless_loop_exited(
+        self,
+        exits: &[Self],
+        env: &mut Env,
+        output: &mut dyn AssemblyProgram,
+    ) -> Result<(), Error> {
+        let states = Self::get_loop_states(exits, env)?;
+        let size = self.get_size(env)?;
+
+        Self::compile_no_loop_exited(&states, output);
+        output.op(CoreOp::If(A));
+        self.compile_expr(env, output)?;
+        output.op(CoreOp::Else);
+        if size > 0 {
+            output.op(CoreOp::Next(SP, Some(size as isize)));
+        }
+        output.op(CoreOp::End);
+        Ok(())
+    }
+
+    /// Get the offsets (from the frame pointer) of the state cells of the
+    /// loops which the given `break` and `continue` expressions exit.
+    fn get_loop_states(exits: &[Self], env: &Env) -> Result<Vec<isize>, Error> {
+        let mut states = vec![];
+        for exit in exits {
+            if let Self::Break(label) | Self::Continue(label) = exit {
+                match env.get_loop(label.as_deref()) {
+                    Some((_, Some(state))) => {
+                        if !states.contains(state) {
+                            states.push(*state);
+                        }
+                    }
+                    _ => return Err(Error::InvalidLoopExit(exit.clone())),
+                }
+            }
+        }
+        Ok(states)
+    }
+
+    /// Leave a true value in the `A` register if none of the loops
+    /// with the given state cells have been exited, and false otherwise.
+    fn compile_no_loop_exited(states: &[isize], output: &mut dyn AssemblyProgram) {
+        output.op(CoreOp::Set(A, 0));
+        for state in states {
+            output.op(CoreOp::Or {
+                src: FP.deref().offset(*state),
+                dst: A,
+            });
+        }
+        output.op(CoreOp::Not(A));
+    }
+
+    /// Push the address of an element of a slice onto the stack.
+    /// If the index is out of the slice's bounds, the program panics instead.
+    fn compile_slice_element_address(
+        slice: Self,
+        idx: Self,
+        elem_size: usize,
+        env: &mut Env,
+        output: &mut dyn AssemblyProgram,
+    ) -> Result<(), Error> {
+        // Push the slice's pointer and length, and then the index.
+        slice.compile_expr(env, output)?;
+        idx.compile_expr(env, output)?;
+        // Store the index in `B`, and the length in `A`.
+        output.op(CoreOp::Pop(Some(B), 1));
+        output.op(CoreOp::Pop(Some(A), 1));
+
+        // The index is in bounds if `0 <= index < length`.
+        output.op(CoreOp::IsLess { a: B, b: A, dst: C });
+        output.op(CoreOp::Set(D, 0));
+        output.op(CoreOp::IsGreaterEqual { a: B, b: D, dst: D });
+        output.op(CoreOp::And { src: D, dst: C });
+        output.op(CoreOp::Not(C));
+        output.op(CoreOp::If(C));
+        Self::compile_panic("index out of bounds", env, output);
+        output.op(CoreOp::End);
+
+        // Store the pointer to the first element in `A`.
+        output.op(CoreOp::Pop(Some(A), 1));
+        if elem_size > 1 {
+            // Calculate the offset of the element (the index times the size of the element).
+            output.op(CoreOp::Set(C, elem_size as i64));
+            output.op(CoreOp::Mul { dst: B, src: C });
+        }
+        // Push the address of the element.
+        output.op(CoreOp::Index {
+            src: A,
+            offset: B,
+            dst: C,
+        });
+        output.op(CoreOp::Push(C, 1));
+        Ok(())
+    }
+
+    /// Call the procedure on top of the stack with the arguments under it.
+    /// The procedure's environment is passed to it in a register, so that
+    /// closures can get the variables they captured.
+    fn compile_call(output: &mut dyn AssemblyProgram) {
+        output.op(CoreOp::Pop(Some(A), 1));
+        output.op(CoreOp::Pop(Some(Closure::ENV_REGISTER), 1));
+        output.op(CoreOp::Call(A));
+    }
+
+    /// Stop the program with an error message on stderr, which points
+    /// to the source code being compiled if its location is known.
+    pub(crate) fn compile_panic(message: &str, env: &Env, output: &mut dyn AssemblyProgram) {
+        let message = match env.get_location() {
+            Some(location) => format!("panic at {location}: {message}\n"),
+            None => format!("panic: {message}\n"),
+        };
+        // Push the message onto the stack, and print it with the procedure shared by
+        // every panic, which takes the address of the message in `A` and its length in `B`.
+        let len = message.len();
+        output.op(CoreOp::PushConst(message.bytes().map(i64::from).collect()));
+        output.op(CoreOp::GetAddress {
+            addr: SP.deref().offset(1 - len as isize),
+            dst: A,
+        });
+        output.op(CoreOp::Set(B, len as i64));
+        if !output.is_defined(PANIC_MESSAGE) {
+            output.op(CoreOp::Fn(PANIC_MESSAGE.to_string()));
+            output.op(CoreOp::While(B));
+            output.op(CoreOp::Put(A.deref(), Output::stderr_char()));
+            output.op(CoreOp::Next(A, None));
+            output.op(CoreOp::Dec(B));
+            output.op(CoreOp::End);
+            output.op(CoreOp::End);
+        }
+        output.op(CoreOp::CallLabel(PANIC_MESSAGE.to_string()));
+        // Dereference the panic address to stop the program.
+        output.op(CoreOp::Set(A, PANIC));
+        output.op(CoreOp::Move {
+            src: A.deref(),
+            dst: A,
+        });
+    }
+}
+
 /// Compile a constant expression.
 impl Compile for ConstExpr {
     fn compile_expr(self, env: &mut Env, output: &mut dyn AssemblyProgram) -> Result<(), Error> {
         trace!("Compiling constant expression {self} in environment {env}");
         let mut debug_str = format!("{self}");
-        debug_str.truncate(50);
+        truncate_chars(&mut debug_str, 50);
 
         let current_instruction = output.current_instruction();
         let ty = self.get_type(env)?;
@@ -1064,6 +1420,21 @@ impl Compile for ConstExpr {
                 // output.op(CoreOp::Set(SP.deref(), ch as usize as i64));
                 output.op(CoreOp::PushConst(vec![ch as usize as i64]));
             }
+            // Compile a string slice constant.
+            Self::Str(text) => {
+                // Store the UTF-8 encoded bytes in a static variable, followed by a null
+                // terminator, and push a pointer to them.
+                let bytes = text
+                    .bytes()
+                    .chain(std::iter::once(0))
+                    .map(|b| Self::Char(b as char))
+                    .collect();
+                Expr::ConstExpr(Self::Array(bytes))
+                    .refer(Mutability::Immutable)
+                    .compile_expr(env, output)?;
+                // Push the number of bytes in the string.
+                output.op(CoreOp::PushConst(vec![text.len() as i64]));
+            }
             // Compile a bool constant.
             Self::Bool(x) => {
                 // output.op(CoreOp::Next(SP, None));
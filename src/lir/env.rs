@@ -6,9 +6,12 @@
 
 use super::{
     Compile, ConstExpr, Declaration, Error, Expr, FFIProcedure, GetSize, GetType, Mutability,
-    PolyProcedure, Procedure, Type,
+    PolyProcedure, Procedure, Trait, Type,
+};
+use crate::{
+    asm::{AssemblyProgram, Globals, Location},
+    parse::SourceCodeLocation,
 };
-use crate::asm::{AssemblyProgram, Globals, Location};
 use core::fmt::{Debug, Display, Formatter, Result as FmtResult};
 
 use std::{
@@ -39,6 +42,13 @@ pub struct Env {
     /// Associated constants for types.
     associated_constants: Arc<RwLock<HashMap<Type, HashMap<String, (ConstExpr, Type)>>>>,
     type_checked_consts: Arc<RwLock<HashSet<ConstExpr>>>,
+    /// The traits defined under the environment.
+    traits: Arc<HashMap<String, Trait>>,
+    /// The locations in the source code of the definitions of the types, constants,
+    /// procedures, and traits defined under the environment, if they're known.
+    definitions: Arc<HashMap<String, SourceCodeLocation>>,
+    /// The types implementing each trait, keyed by the name of the trait.
+    trait_impls: Arc<RwLock<HashMap<String, Vec<Type>>>>,
 
     /// The current offset of the frame pointer to assign to the next variable.
     /// This is incremented by the size of each variable as it is defined.
@@ -50,9 +60,17 @@ pub struct Env {
     /// Expected return type of the current function.
     /// This is `None` if we are not currently compiling a function.
     expected_ret: Option<Type>,
+    /// The loops enclosing the current expression, from the outermost to the innermost.
+    /// Each loop has an optional label, and the frame pointer offset of the cell
+    /// which records whether the loop was exited with `break` or `continue`.
+    /// The cell is only allocated if some `break` or `continue` targets the loop.
+    loops: Arc<Vec<(Option<String>, Option<isize>)>>,
 
     /// Memoized type sizes.
     type_sizes: Arc<HashMap<Type, usize>>,
+
+    /// The location in the source code of the innermost expression being compiled, if it's known.
+    location: Option<SourceCodeLocation>,
 }
 
 impl Default for Env {
@@ -60,7 +78,12 @@ impl Default for Env {
         Self {
             // It is important that we use reference counting for the tables because the environment
             // will be copied many times during the compilation process to create new scopes.
-            types: Arc::new(HashMap::new()),
+            types: Arc::new(
+                Type::BUILTIN_TYPES
+                    .into_iter()
+                    .map(|(name, ty)| (name.to_string(), ty))
+                    .collect(),
+            ),
             type_sizes: Arc::new(HashMap::new()),
             consts: Arc::new(HashMap::new()),
             procs: Arc::new(HashMap::new()),
@@ -70,11 +93,16 @@ impl Default for Env {
             associated_constants: Arc::new(RwLock::new(HashMap::new())),
             processed_monomorphizations: Arc::new(RwLock::new(HashMap::new())),
             type_checked_consts: Arc::new(RwLock::new(HashSet::new())),
+            traits: Arc::new(HashMap::new()),
+            trait_impls: Arc::new(RwLock::new(HashMap::new())),
+            definitions: Arc::new(HashMap::new()),
 
             // The last argument is stored at `[FP]`, so our first variable must be at `[FP + 1]`.
             fp_offset: 1,
             args_size: 0,
             expected_ret: None,
+            loops: Arc::new(Vec::new()),
+            location: None,
         }
     }
 }
@@ -107,6 +135,13 @@ impl Env {
                 // Arc::new(RwLock::new(type_checked_consts))
                 self.type_checked_consts.clone()
             },
+            traits: self.traits.clone(),
+            definitions: self.definitions.clone(),
+            trait_impls: {
+                // Copy the data but not the lock, like the associated constants.
+                let trait_impls = self.trait_impls.read().unwrap().clone();
+                Arc::new(RwLock::new(trait_impls))
+            },
 
             // The rest are the same as a new environment.
             ..Env::default()
@@ -695,6 +730,24 @@ impl Env {
                     // })?;
                 }
             }
+            Declaration::Trait(name, trait_) => {
+                self.define_trait(name, trait_.clone());
+            }
+            Declaration::ImplTrait(trait_name, ty, impls) => {
+                let trait_ = self
+                    .get_trait(trait_name)
+                    .ok_or_else(|| Error::TraitNotDefined(trait_name.clone()))?
+                    .clone();
+                // The methods are associated constants of the type, like any other implementation.
+                self.add_compile_time_declaration(&Declaration::Impl(ty.clone(), impls.clone()))?;
+                // Implementations for a template type are registered for the template,
+                // so that every monomorph of the template implements the trait.
+                let implementor = match ty {
+                    Type::Apply(template, _) => *template.clone(),
+                    _ => ty.clone(),
+                };
+                self.add_trait_impl(trait_.get_name(), implementor);
+            }
             Declaration::Var(_, _, Some(_ty), _e) => {
                 // ty.add_monomorphized_associated_consts(self).ok();
                 // if let Ok(ty) = e.get_type(self) {
@@ -759,9 +812,12 @@ impl Env {
             Declaration::StaticVar(_, _, _, _) => {
                 // Static variables are not defined at runtime.
             }
-            Declaration::Impl(_, _) => {
+            Declaration::Impl(_, _) | Declaration::ImplTrait(_, _, _) => {
                 // Implementations are not defined at runtime.
             }
+            Declaration::Trait(_, _) => {
+                // Traits are not defined at runtime.
+            }
             Declaration::Var(name, mutability, ty, expr) => {
                 let ty = match ty {
                     Some(ty) => ty.clone(),
@@ -865,10 +921,51 @@ impl Env {
     }
 
     /// Get a type definition from this environment.
-    pub(super) fn get_type(&self, name: &str) -> Option<&Type> {
+    pub fn get_type(&self, name: &str) -> Option<&Type> {
         self.types.get(name)
     }
 
+    /// Define a trait with a given name under this environment.
+    pub(super) fn define_trait(&mut self, name: impl ToString, trait_: Trait) {
+        let name = name.to_string();
+        trace!("Defining trait {name} as {trait_}");
+        Arc::make_mut(&mut self.traits).insert(name, trait_);
+    }
+
+    /// Get a trait definition from this environment.
+    pub fn get_trait(&self, name: &str) -> Option<&Trait> {
+        self.traits.get(name)
+    }
+
+    /// Record that a type implements the trait with the given name.
+    pub fn add_trait_impl(&self, trait_name: impl ToString, ty: Type) {
+        let trait_name = trait_name.to_string();
+        trace!("Adding implementation of trait {trait_name} for type {ty}");
+        self.trait_impls
+            .write()
+            .unwrap()
+            .entry(trait_name)
+            .or_default()
+            .push(ty);
+    }
+
+    /// Does a type implement the trait with the given name?
+    pub fn implements_trait(&self, ty: &Type, trait_name: &str) -> Result<bool, Error> {
+        let implementors = self
+            .trait_impls
+            .read()
+            .unwrap()
+            .get(trait_name)
+            .cloned()
+            .unwrap_or_default();
+        for implementor in &implementors {
+            if ty.equals(implementor, self)? || ty.is_monomorph_of(implementor, self)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
     /// Define a constant with a given name under this environment.
     pub(super) fn define_const(&mut self, name: impl ToString, e: ConstExpr) {
         let name = name.to_string();
@@ -1022,6 +1119,47 @@ impl Env {
         self.expected_ret = Some(t);
     }
 
+    /// Record the location in the source code where a symbol is defined.
+    pub(super) fn define_location(&mut self, name: impl ToString, location: SourceCodeLocation) {
+        Arc::make_mut(&mut self.definitions).insert(name.to_string(), location);
+    }
+
+    /// Get the location in the source code where a symbol is defined, if it's known.
+    pub fn get_definition_location(&self, name: &str) -> Option<&SourceCodeLocation> {
+        self.definitions.get(name)
+    }
+
+    /// Get the location in the source code of the expression being compiled, if it's known.
+    pub(super) fn get_location(&self) -> Option<&SourceCodeLocation> {
+        self.location.as_ref()
+    }
+
+    /// Set the location in the source code of the expression being compiled.
+    pub(super) fn set_location(&mut self, location: Option<SourceCodeLocation>) {
+        self.location = location;
+    }
+
+    /// Enter a loop with an optional label. The offset is the location of the cell
+    /// (relative to the frame pointer) which records whether the loop was exited,
+    /// if any `break` or `continue` expression targets the loop.
+    pub(super) fn push_loop(&mut self, label: Option<String>, state_offset: Option<isize>) {
+        Arc::make_mut(&mut self.loops).push((label, state_offset));
+    }
+
+    /// Get the loop targeted by a `break` or `continue` with the given label.
+    /// Without a label, this is the innermost loop. This method returns `None`
+    /// if the current scope is not inside of a matching loop.
+    pub(super) fn get_loop(&self, label: Option<&str>) -> Option<&(Option<String>, Option<isize>)> {
+        match label {
+            None => self.loops.last(),
+            Some(label) => self
+                .loops
+                .iter()
+                .rev()
+                .find(|(name, _)| name.as_deref() == Some(label)),
+        }
+    }
+
     /// Does the environment have some precalculated size for the given type?
     /// This helps the compiler memoize the size of types so that it doesn't have to
     /// recalculate the size of the same type multiple times.
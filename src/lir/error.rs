@@ -1,6 +1,6 @@
 use super::{
-    Annotation, AssignOp, BinaryOp, ConstExpr, Expr, Mutability, Pattern, PolyProcedure, TernaryOp,
-    Type, UnaryOp,
+    Annotation, AssignOp, BinaryOp, ConstExpr, Env, Expr, Mutability, Pattern, PolyProcedure,
+    TernaryOp, Type, UnaryOp,
 };
 use core::fmt::{Debug, Display, Formatter, Result as FmtResult};
 
@@ -16,6 +16,11 @@ pub enum Error {
         Annotation,
     ),
 
+    /// Several independent errors found in the same program.
+    /// Type checking keeps going after an error in one declaration or procedure body,
+    /// so that all of these can be reported together.
+    Many(Vec<Self>),
+
     /// An error caused by trying to assemble invalid code generated by the compiler.
     /// This should be taken seriously, unless the error is due to an invalid handwritten builtin.
     AssemblyError(crate::asm::Error),
@@ -122,11 +127,30 @@ pub enum Error {
 
     /// Cannot monomorphize a constant expression.
     InvalidMonomorphize(ConstExpr),
+
+    /// Tried to `break` or `continue` outside of a loop, or out of a loop
+    /// with a label that isn't defined.
+    InvalidLoopExit(Expr),
+
+    /// A trait was used, but not defined.
+    TraitNotDefined(String),
+    /// A type was used where it must implement a trait, but it doesn't.
+    UnimplementedTrait(Type, String),
+    /// An implementation of a trait for a type is missing one of the trait's methods.
+    MissingTraitMethod {
+        trait_name: String,
+        ty: Type,
+        method: String,
+    },
 }
 
 impl Error {
     /// Annotate an error with some metadata.
+    ///
+    /// The definitions a declaration is annotated with aren't passed on to the errors
+    /// in it; an error only has the definitions related to it.
     pub fn annotate(mut self, annotation: Annotation) -> Self {
+        let annotation = annotation.without_definitions();
         match &mut self {
             Self::Annotated(err, previous_annotation) => {
                 let mut result = annotation.clone();
@@ -135,9 +159,106 @@ impl Error {
                 *previous_annotation = result;
                 self
             }
+            Self::Many(errs) => {
+                // Annotate each of the errors individually.
+                *errs = errs
+                    .drain(..)
+                    .map(|err| err.annotate(annotation.clone()))
+                    .collect();
+                self
+            }
             _ => Self::Annotated(Box::new(self), annotation),
         }
     }
+
+    /// Annotate an error with the locations of the definitions related to it, as they're
+    /// defined in the scope where the error occurred. This only applies to errors with a
+    /// location, which haven't been given their related definitions yet.
+    pub(super) fn with_related_definitions(self, env: &Env) -> Self {
+        match self {
+            Self::Many(errs) => Self::Many(
+                errs.into_iter()
+                    .map(|err| err.with_related_definitions(env))
+                    .collect(),
+            ),
+            Self::Annotated(err, mut annotation)
+                if annotation.has_location() && annotation.definitions().is_empty() =>
+            {
+                for name in err.related_symbols() {
+                    if let Some(location) = env.get_definition_location(&name) {
+                        annotation |= Annotation::Definition(name, location.clone());
+                    }
+                }
+                Self::Annotated(err, annotation)
+            }
+            err => err,
+        }
+    }
+
+    /// Combine the results of checking several independent parts of a program.
+    /// If any of them failed, all of their errors are returned together.
+    pub fn combine(results: impl IntoIterator<Item = Result<(), Self>>) -> Result<(), Self> {
+        let mut errs = results
+            .into_iter()
+            .filter_map(Result::err)
+            .flat_map(Self::into_errors)
+            .collect::<Vec<_>>();
+        match errs.len() {
+            0 => Ok(()),
+            1 => Err(errs.remove(0)),
+            _ => Err(Self::Many(errs)),
+        }
+    }
+
+    /// Split this error into each of the individual errors it contains.
+    pub fn into_errors(self) -> Vec<Self> {
+        match self {
+            Self::Many(errs) => errs.into_iter().flat_map(Self::into_errors).collect(),
+            Self::Annotated(err, annotation) if matches!(*err, Self::Many(_)) => {
+                err.annotate(annotation).into_errors()
+            }
+            err => vec![err],
+        }
+    }
+
+    /// Get the error without any of its annotations.
+    pub fn unannotated(&self) -> &Self {
+        match self {
+            Self::Annotated(err, _) => err.unannotated(),
+            err => err,
+        }
+    }
+
+    /// Get the names of the symbols whose definitions are related to this error.
+    /// These are reported alongside the error, so that the user can see
+    /// where the types or procedures involved were originally defined.
+    pub fn related_symbols(&self) -> Vec<String> {
+        let mut symbols = vec![];
+        if let Self::MismatchedTypes {
+            expected,
+            found,
+            expr,
+        } = self.unannotated()
+        {
+            // The procedure being called, whose signature gave the expected types.
+            let mut callee = expr;
+            while let Expr::Annotated(inner, _) | Expr::Apply(inner, _) = callee {
+                callee = inner;
+            }
+            if let Expr::ConstExpr(ConstExpr::Symbol(name)) = callee {
+                symbols.push(name.clone());
+            }
+            // The named types involved.
+            for ty in [expected, found] {
+                if let Type::Symbol(name) = ty {
+                    if !symbols.contains(name) {
+                        symbols.push(name.clone());
+                    }
+                }
+            }
+        }
+        symbols
+    }
 }
 
 /// Create an IR error from an assembly error.
@@ -153,6 +274,15 @@ impl Display for Error {
             Self::Annotated(err, _) => {
                 write!(f, "{err}")
             }
+            Self::Many(errs) => {
+                for (i, err) in errs.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{err}")?;
+                }
+                Ok(())
+            }
             Self::MismatchedTypes {
                 expected,
                 found,
@@ -325,6 +455,26 @@ impl Display for Error {
                     expr
                 )
             }
+            Self::InvalidLoopExit(expr) => {
+                write!(f, "{} is not inside of a matching loop", expr)
+            }
+            Self::TraitNotDefined(name) => {
+                write!(f, "trait {} not defined", name)
+            }
+            Self::UnimplementedTrait(ty, trait_name) => {
+                write!(f, "type {} does not implement trait {}", ty, trait_name)
+            }
+            Self::MissingTraitMethod {
+                trait_name,
+                ty,
+                method,
+            } => {
+                write!(
+                    f,
+                    "implementation of trait {} for type {} is missing method {}",
+                    trait_name, ty, method
+                )
+            }
         }
     }
 }
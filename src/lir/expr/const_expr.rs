@@ -45,6 +45,8 @@ pub enum ConstExpr {
     Float(f64),
     /// A constant chararacter.
     Char(char),
+    /// A constant string slice. Its bytes are stored in static memory.
+    Str(String),
     /// A constant boolean value.
     Bool(bool),
     /// A constant enum variant.
@@ -287,6 +289,7 @@ impl ConstExpr {
                 | Self::Int(_)
                 | Self::Float(_)
                 | Self::Char(_)
+                | Self::Str(_)
                 | Self::Bool(_)
                 | Self::Of(_, _)
                 | Self::CoreBuiltin(_)
@@ -700,6 +703,7 @@ impl GetType for ConstExpr {
             Self::SizeOfType(_) | Self::SizeOfExpr(_) | Self::Int(_) => Type::Int,
             Self::Float(_) => Type::Float,
             Self::Char(_) => Type::Char,
+            Self::Str(_) => Type::Str,
             Self::Cell(_) => Type::Cell,
             Self::Bool(_) => Type::Bool,
             Self::Of(enum_type, _) => enum_type,
@@ -813,6 +817,7 @@ impl GetType for ConstExpr {
             Self::Int(_) => {}
             Self::Float(_) => {}
             Self::Char(_) => {}
+            Self::Str(_) => {}
             Self::Bool(_) => {}
             Self::Of(enum_type, _) => {
                 *enum_type = enum_type.substitute(name, subsitution);
@@ -957,6 +962,7 @@ impl fmt::Display for ConstExpr {
             }
             Self::Bool(x) => write!(f, "{}", if *x { "true" } else { "false" }),
             Self::Char(ch) => write!(f, "{ch:?}"),
+            Self::Str(s) => write!(f, "{s:?}"),
             Self::Cell(n) => write!(f, "{n:x}"),
             Self::Int(n) => write!(f, "{n}"),
             Self::Float(n) => write!(f, "{n}"),
@@ -1064,6 +1070,10 @@ impl Hash for ConstExpr {
                 state.write_u8(19);
                 ch.hash(state);
             }
+            Self::Str(s) => {
+                state.write_u8(29);
+                s.hash(state);
+            }
             Self::Cell(n) => {
                 state.write_u8(20);
                 n.hash(state);
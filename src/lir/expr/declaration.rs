@@ -3,7 +3,7 @@ use crate::{
     asm::{AssemblyProgram, CoreOp, Location, SP},
     lir::{
         Compile, ConstExpr, Env, Error, Expr, FFIProcedure, GetSize, GetType, Mutability, Pattern,
-        Type, TypeCheck,
+        Trait, Type, TypeCheck,
     },
 };
 use core::{
@@ -36,6 +36,10 @@ pub enum Declaration {
     ExternProc(String, FFIProcedure),
     /// Declare associated constants and procedures for a type.
     Impl(Type, Vec<(String, ConstExpr)>),
+    /// A trait declaration.
+    Trait(String, Trait),
+    /// Implement the methods of a trait for a type.
+    ImplTrait(String, Type, Vec<(String, ConstExpr)>),
     /// Many declarations.
     Many(Vec<Declaration>),
 }
@@ -76,6 +80,19 @@ impl Declaration {
         }
     }
 
+    /// Get the `break` and `continue` expressions in the initializers of this
+    /// declaration's variables which exit a loop enclosing the declaration.
+    pub(crate) fn get_loop_exits(&self) -> Vec<Expr> {
+        match self {
+            Self::Var(_, _, _, expr) | Self::VarPat(_, expr) => expr.get_loop_exits(),
+            Self::Many(decls) => decls
+                .iter()
+                .flat_map(|decl| decl.get_loop_exits())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
     /// Is this a compile time declaration?
     pub(crate) fn is_compile_time_declaration(&self) -> bool {
         match self {
@@ -85,6 +102,8 @@ impl Declaration {
             Self::PolyProc(..) => true,
             Self::ExternProc(..) => true,
             Self::Impl(..) => true,
+            Self::Trait(..) => true,
+            Self::ImplTrait(..) => true,
             Self::Many(decls) => decls
                 .par_iter()
                 .all(|decl| decl.is_compile_time_declaration()),
@@ -129,15 +148,18 @@ impl Declaration {
 
                 // Get the type of the variable using its specifier,
                 // or by deducing the type ourselves.
-                let var_ty = if let Some(specified_ty) = specifier {
-                    specified_ty.clone()
+                let (var_ty, expr) = if let Some(specified_ty) = specifier {
+                    (
+                        specified_ty.clone(),
+                        expr.clone().coerce_to(specified_ty, env)?,
+                    )
                 } else {
-                    expr.get_type(env)?
+                    (expr.get_type(env)?, expr.clone())
                 };
                 // Get the size of the variables for the body of the declaration.
                 var_size = var_ty.get_size(env)?;
                 // Compile the expression to leave the value on the stack.
-                expr.clone().compile_expr(env, output)?;
+                expr.compile_expr(env, output)?;
 
                 // Add the variable to the environment, so that it can be used in the body.
                 env.add_local_variable_declaration(self)?;
@@ -203,8 +225,14 @@ impl Declaration {
             let result_type = body.get_type(env)?;
             let result_size = result_type.get_size(env)?;
 
-            // Compile the body under the new scope
-            body.compile_expr(env, output)?;
+            // Compile the body under the new scope, skipping it if
+            // the declaration exited a loop.
+            let exits = self.get_loop_exits();
+            if exits.is_empty() {
+                body.compile_expr(env, output)?;
+            } else {
+                body.compile_unless_loop_exited(&exits, env, output)?;
+            }
             if var_size != 0 {
                 // Copy the return value over where the arguments were stored,
                 // so that when we pop the stack, it's as if we popped the variables
@@ -295,6 +323,15 @@ impl Declaration {
                     expr.substitute(substitution_name, substitution_ty);
                 });
             }
+            Self::Trait(_name, trait_) => {
+                trait_.substitute(substitution_name, substitution_ty);
+            }
+            Self::ImplTrait(_trait_name, ty, impls) => {
+                *ty = ty.substitute(substitution_name, substitution_ty);
+                impls.par_iter_mut().for_each(|(_name, expr)| {
+                    expr.substitute(substitution_name, substitution_ty);
+                });
+            }
             Self::Many(decls) => {
                 // for decl in decls {
                 //     decl.substitute(substitution_name, substitution_ty);
@@ -312,6 +349,11 @@ impl TypeCheck for Declaration {
         match self {
             // Typecheck a variable declaration.
             Self::Var(_name, _mutability, expected_ty, expr) => {
+                // Coerce the expression to the specified type, if there is one.
+                let expr = &match expected_ty {
+                    Some(expected_ty) => expr.clone().coerce_to(expected_ty, env)?,
+                    None => expr.clone(),
+                };
                 // Get the type of the expression.
                 let found_ty = expr.get_type(env)?;
                 // If there is a type specified, then make sure the type of the expression
@@ -482,9 +524,12 @@ impl TypeCheck for Declaration {
                     //     templated_const.type_check(&new_env)?;
                     // }
 
-                    templated_consts
-                        .par_iter()
-                        .try_for_each(|templated_const| templated_const.type_check(&new_env))?;
+                    Error::combine(
+                        templated_consts
+                            .par_iter()
+                            .map(|templated_const| templated_const.type_check(&new_env))
+                            .collect::<Vec<_>>(),
+                    )?;
                 } else {
                     // ty.add_monomorphized_associated_consts(env)?;
                     for (name, associated_const) in impls {
@@ -497,11 +542,26 @@ impl TypeCheck for Declaration {
                     //     associated_const.type_check(&new_env)?;
                     // }
 
-                    impls.par_iter().try_for_each(|(_name, associated_const)| {
-                        associated_const.type_check(&new_env)
-                    })?;
+                    Error::combine(
+                        impls
+                            .par_iter()
+                            .map(|(_name, associated_const)| associated_const.type_check(&new_env))
+                            .collect::<Vec<_>>(),
+                    )?;
                 }
             }
+            Self::Trait(_name, trait_) => {
+                trait_.type_check(env)?;
+            }
+            Self::ImplTrait(trait_name, ty, impls) => {
+                let trait_ = env
+                    .get_trait(trait_name)
+                    .ok_or_else(|| Error::TraitNotDefined(trait_name.clone()))?;
+                // Confirm that the implementation provides all of the trait's methods.
+                trait_.check_impl(ty, impls, env)?;
+                // Then typecheck the methods like any other implementation.
+                Self::Impl(ty.clone(), impls.clone()).type_check(env)?;
+            }
             // Typecheck a multi-declaration.
             Self::Many(decls) => {
                 let mut new_env = env.clone();
@@ -513,12 +573,12 @@ impl TypeCheck for Declaration {
                     .iter()
                     .partition(|decl| decl.is_compile_time_declaration());
 
-                if !comp_time_decls.is_empty() {
-                    // Type check all the compile time declarations in parallel.
-                    comp_time_decls
-                        .par_iter()
-                        .try_for_each(|decl| decl.type_check(&new_env))?;
-                }
+                // Type check all the compile time declarations in parallel.
+                // They're independent of each other, so the errors in all of them are collected.
+                let mut results = comp_time_decls
+                    .par_iter()
+                    .map(|decl| decl.type_check(&new_env))
+                    .collect::<Vec<_>>();
 
                 /*
                 lazy_static! {
@@ -558,13 +618,16 @@ impl TypeCheck for Declaration {
                 }
                  */
 
-                run_time_decls
-                    .iter()
-                    // .map(|decl| decl.type_check(&new_env))
-                    .try_for_each(|decl| {
-                        decl.type_check(&new_env)?;
-                        new_env.add_declaration(decl)
-                    })?;
+                for decl in run_time_decls {
+                    let result = decl.type_check(&new_env);
+                    // Later declarations can't be checked without this one defined.
+                    if let Err(e) = new_env.add_declaration(decl) {
+                        results.push(result.and(Err(e)));
+                        break;
+                    }
+                    results.push(result);
+                }
+                Error::combine(results)?;
 
                 // for decl in decls {
                 //     // Typecheck any variable declarations in the old scope
@@ -620,6 +683,17 @@ impl Display for Declaration {
                 }
                 write!(f, "}}")?;
             }
+            Self::Trait(name, trait_) => {
+                write!(f, "{} = {}", name, trait_)?;
+            }
+            Self::ImplTrait(trait_name, ty, impls) => {
+                write!(f, "impl {} for {}", trait_name, ty)?;
+                write!(f, " {{")?;
+                for (name, expr) in impls {
+                    write!(f, "{} = {}", name, expr)?;
+                }
+                write!(f, "}}")?;
+            }
             Self::Many(decls) => {
                 for decl in decls {
                     writeln!(f, "{}", decl)?;
@@ -844,6 +918,17 @@ impl Hash for Declaration {
                 state.write_u8(9);
                 decls.hash(state);
             }
+            Self::Trait(name, trait_) => {
+                state.write_u8(10);
+                name.hash(state);
+                trait_.hash(state);
+            }
+            Self::ImplTrait(trait_name, ty, impls) => {
+                state.write_u8(11);
+                trait_name.hash(state);
+                ty.hash(state);
+                impls.hash(state);
+            }
         }
     }
 }
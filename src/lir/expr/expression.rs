@@ -7,7 +7,8 @@
 
 use super::ops::*;
 use crate::lir::{
-    Annotation, ConstExpr, Declaration, Env, Error, GetType, Mutability, Pattern, Procedure, Type,
+    Annotation, Closure, ConstExpr, Declaration, Env, Error, GetType, Mutability, Pattern,
+    Procedure, Type,
 };
 use core::fmt;
 use std::collections::BTreeMap;
@@ -35,6 +36,17 @@ pub enum Expr {
 
     /// Create a while loop: while the first expression evaluates to true, evaluate the second expression.
     While(Box<Self>, Box<Self>),
+    /// Create a loop which can be labeled, so that `break` and `continue` expressions
+    /// in nested loops can refer to it.
+    ///
+    /// While the second expression evaluates to true, evaluate the third expression (the body),
+    /// and then the fourth expression (the step). The step is still evaluated after the body
+    /// `continue`s, which is how `for` loops are implemented.
+    Loop(Option<String>, Box<Self>, Box<Self>, Box<Self>),
+    /// Exit the innermost loop, or the enclosing loop with the given label.
+    Break(Option<String>),
+    /// Skip to the next iteration of the innermost loop, or the enclosing loop with the given label.
+    Continue(Option<String>),
     /// An if-then-else expression.
     ///
     /// Evaluate a condition.
@@ -76,6 +88,8 @@ pub enum Expr {
 
     /// Apply a function with some arguments.
     Apply(Box<Self>, Vec<Self>),
+    /// An anonymous procedure which captures variables from the enclosing scope.
+    Closure(Closure),
 
     /// Return a value from a function.
     Return(Box<Self>),
@@ -466,12 +480,21 @@ impl Expr {
         }
     }
 
+    /// Is this expression a declaration, possibly under some annotations?
+    fn is_declare(&self) -> bool {
+        match self {
+            Self::Declare(_, _) => true,
+            Self::Annotated(expr, _) => expr.is_declare(),
+            _ => false,
+        }
+    }
+
     /// Return this expression, but with a given declaration in scope.
     pub fn with(&self, older_decls: impl Into<Declaration>) -> Self {
         match self {
-            // If the expression is an annotated expression, we need to unwrap it.
-            Self::Annotated(expr, annotation) => {
-                // Just unwrap the expression and recurse.
+            // If the expression is annotated declarations, the annotation describes
+            // the declarations, so it's kept on them when they're merged.
+            Self::Annotated(expr, annotation) if expr.is_declare() => {
                 expr.with(older_decls).annotate(annotation.clone())
             }
 
@@ -485,7 +508,7 @@ impl Expr {
                 Self::Declare(Box::new(result), expr.clone())
             }
 
-            // Return the expression with the declaration in scope.
+            // Return the expression, along with its annotations, with the declaration in scope.
             _ => Self::Declare(Box::new(older_decls.into()), Box::new(self.clone())),
         }
     }
@@ -500,6 +523,36 @@ impl Expr {
         Self::As(Box::new(self), t)
     }
 
+    /// Coerce an expression to the type it's expected to have, if its value has to be
+    /// converted first. A pointer to an array becomes a slice of the array's elements.
+    pub(crate) fn coerce_to(self, expected: &Type, env: &Env) -> Result<Self, Error> {
+        if let Type::Slice(_, expected_elem) = expected.simplify_until_concrete(env)? {
+            if let Type::Pointer(mutability, array) =
+                self.get_type(env)?.simplify_until_concrete(env)?
+            {
+                if let Type::Array(elem, _) = array.simplify_until_concrete(env)? {
+                    if elem.equals(&expected_elem, env)? {
+                        // Keep the pointer's mutability, so that it's still checked.
+                        return Ok(self.as_type(Type::Slice(mutability, elem)));
+                    }
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Coerce the arguments of a call to the types of the called procedure's parameters.
+    pub(crate) fn coerce_args(f: &Self, args: Vec<Self>, env: &Env) -> Result<Vec<Self>, Error> {
+        match f.get_type(env)?.simplify_until_concrete(env)? {
+            Type::Proc(params, _) if params.len() == args.len() => args
+                .into_iter()
+                .zip(&params)
+                .map(|(arg, param)| arg.coerce_to(param, env))
+                .collect(),
+            _ => Ok(args),
+        }
+    }
+
     /// Apply a unary operation to this expression.
     pub fn unop(self, op: impl UnaryOp + 'static) -> Self {
         Self::UnaryOp(Box::new(op), Box::new(self))
@@ -741,6 +794,139 @@ impl Expr {
         Expr::While(Box::new(self), Box::new(body.into()))
     }
 
+    /// Create a loop with an optional label, using this expression as the condition.
+    /// The step is evaluated after every iteration of the body.
+    pub fn labeled_loop(
+        self,
+        label: Option<String>,
+        body: impl Into<Self>,
+        step: impl Into<Self>,
+    ) -> Self {
+        Expr::Loop(
+            label,
+            Box::new(self),
+            Box::new(body.into()),
+            Box::new(step.into()),
+        )
+    }
+
+    /// Get the `break` and `continue` expressions in this expression which exit
+    /// a loop that encloses this expression. Exits of loops nested inside of this
+    /// expression are not included, and neither are exits inside of procedures.
+    pub(crate) fn get_loop_exits(&self) -> Vec<Self> {
+        let mut exits = vec![];
+        self.get_loop_exits_helper(&mut vec![], &mut exits);
+        exits
+    }
+
+    /// Can this loop ever finish? This is true if its body can `break` out of it,
+    /// or exit an outer loop. A `continue` to this loop doesn't finish it.
+    /// This always returns false for expressions that aren't loops.
+    pub(crate) fn can_exit_loop(&self) -> bool {
+        let (label, exits) = match self {
+            Self::Annotated(expr, _) => return expr.can_exit_loop(),
+            Self::While(_, body) => (None, body.get_loop_exits()),
+            Self::Loop(label, _, body, step) => {
+                let mut exits = body.get_loop_exits();
+                exits.extend(step.get_loop_exits());
+                (label.clone(), exits)
+            }
+            _ => return false,
+        };
+
+        exits.iter().any(
+            |exit| !matches!(exit, Self::Continue(target) if target.is_none() || *target == label),
+        )
+    }
+
+    /// Collect the loop exits of this expression, given the labels of the loops
+    /// nested inside of the original expression that enclose this expression.
+    fn get_loop_exits_helper(&self, nested: &mut Vec<Option<String>>, exits: &mut Vec<Self>) {
+        match self {
+            Self::Break(label) | Self::Continue(label) => {
+                // An unlabeled exit targets the innermost loop.
+                let escapes = match label {
+                    None => nested.is_empty(),
+                    Some(_) => !nested.contains(label),
+                };
+                if escapes {
+                    exits.push(self.clone());
+                }
+            }
+            Self::While(cond, body) => {
+                cond.get_loop_exits_helper(nested, exits);
+                nested.push(None);
+                body.get_loop_exits_helper(nested, exits);
+                nested.pop();
+            }
+            Self::Loop(label, cond, body, step) => {
+                cond.get_loop_exits_helper(nested, exits);
+                nested.push(label.clone());
+                body.get_loop_exits_helper(nested, exits);
+                step.get_loop_exits_helper(nested, exits);
+                nested.pop();
+            }
+
+            Self::Annotated(expr, _)
+            | Self::Return(expr)
+            | Self::UnaryOp(_, expr)
+            | Self::Refer(_, expr)
+            | Self::Deref(expr)
+            | Self::Union(_, _, expr)
+            | Self::EnumUnion(_, _, expr)
+            | Self::As(expr, _)
+            | Self::Member(expr, _) => expr.get_loop_exits_helper(nested, exits),
+
+            Self::BinaryOp(_, a, b)
+            | Self::AssignOp(_, a, b)
+            | Self::DerefMut(a, b)
+            | Self::Index(a, b)
+            | Self::When(_, a, b) => {
+                a.get_loop_exits_helper(nested, exits);
+                b.get_loop_exits_helper(nested, exits);
+            }
+            Self::If(a, b, c) | Self::TernaryOp(_, a, b, c) | Self::IfLet(_, a, b, c) => {
+                a.get_loop_exits_helper(nested, exits);
+                b.get_loop_exits_helper(nested, exits);
+                c.get_loop_exits_helper(nested, exits);
+            }
+
+            Self::Many(exprs) | Self::Array(exprs) | Self::Tuple(exprs) => {
+                for expr in exprs {
+                    expr.get_loop_exits_helper(nested, exits);
+                }
+            }
+            Self::Struct(fields) => {
+                for expr in fields.values() {
+                    expr.get_loop_exits_helper(nested, exits);
+                }
+            }
+            // Loop exits can't escape a closure's body.
+            Self::Closure(_) => {}
+            Self::Apply(f, args) => {
+                f.get_loop_exits_helper(nested, exits);
+                for arg in args {
+                    arg.get_loop_exits_helper(nested, exits);
+                }
+            }
+            Self::Match(expr, branches) => {
+                expr.get_loop_exits_helper(nested, exits);
+                for (_, branch) in branches {
+                    branch.get_loop_exits_helper(nested, exits);
+                }
+            }
+            Self::Declare(decl, body) => {
+                for expr in decl.get_loop_exits() {
+                    expr.get_loop_exits_helper(nested, exits);
+                }
+                body.get_loop_exits_helper(nested, exits);
+            }
+
+            // Constant expressions (including procedures) cannot exit a loop.
+            Self::ConstExpr(_) => {}
+        }
+    }
+
     /// Reference this expression (i.e. get a pointer to it).
     pub fn refer(self, mutability: impl Into<Mutability>) -> Self {
         Expr::Refer(mutability.into(), Box::new(self))
@@ -810,6 +996,16 @@ impl fmt::Display for Expr {
             Self::While(cond, body) => {
                 write!(f, "while ({cond}) {body}")
             }
+            Self::Loop(label, cond, body, step) => {
+                if let Some(label) = label {
+                    write!(f, "'{label}: ")?;
+                }
+                write!(f, "loop ({cond}) {body} then {step}")
+            }
+            Self::Break(None) => write!(f, "break"),
+            Self::Break(Some(label)) => write!(f, "break '{label}"),
+            Self::Continue(None) => write!(f, "continue"),
+            Self::Continue(Some(label)) => write!(f, "continue '{label}"),
             Self::If(cond, t, e) => {
                 write!(f, "if ({cond}) {t} else {e}")
             }
@@ -876,6 +1072,7 @@ impl fmt::Display for Expr {
                 }
                 write!(f, ")")
             }
+            Self::Closure(closure) => write!(f, "{closure}"),
         }
     }
 }
@@ -894,6 +1091,14 @@ impl PartialEq for Expr {
 
             // Create a while loop: while the first expression evaluates to true, evaluate the second expression.
             (While(cond1, body1), While(cond2, body2)) => cond1 == cond2 && body1 == body2,
+            // Create a loop which can be labeled, and which evaluates a step after every iteration.
+            (Loop(label1, cond1, body1, step1), Loop(label2, cond2, body2, step2)) => {
+                label1 == label2 && cond1 == cond2 && body1 == body2 && step1 == step2
+            }
+            // Exit a loop.
+            (Break(label1), Break(label2)) => label1 == label2,
+            // Skip to the next iteration of a loop.
+            (Continue(label1), Continue(label2)) => label1 == label2,
 
             // An if-then-else expression.
             //
@@ -970,6 +1175,9 @@ impl PartialEq for Expr {
 
             // Index an array or pointer with an expression that evaluates to an `Int` at runtime.
             (Index(val1, idx1), Index(val2, idx2)) => val1 == val2 && idx1 == idx2,
+
+            // An anonymous procedure which captures variables from the enclosing scope.
+            (Closure(closure1), Closure(closure2)) => closure1 == closure2,
             _ => false,
         }
     }
@@ -1132,6 +1340,29 @@ impl Hash for Expr {
                 decl.hash(state);
                 expr.hash(state);
             }
+
+            Loop(label, cond, body, step) => {
+                state.write_u8(23);
+                label.hash(state);
+                cond.hash(state);
+                body.hash(state);
+                step.hash(state);
+            }
+
+            Break(label) => {
+                state.write_u8(24);
+                label.hash(state);
+            }
+
+            Continue(label) => {
+                state.write_u8(25);
+                label.hash(state);
+            }
+
+            Closure(closure) => {
+                state.write_u8(26);
+                closure.hash(state);
+            }
         }
     }
 }
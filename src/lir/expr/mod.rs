@@ -7,6 +7,7 @@ mod expression;
 mod ops;
 mod pattern;
 mod procedure;
+mod scope;
 
 pub use const_expr::*;
 pub use declaration::*;
@@ -8,6 +8,20 @@ use std::collections::BTreeMap;
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub struct Add;
 
+/// Evaluate an expression built only from constants and binary operations.
+fn fold_constant(expr: &Expr, env: &mut Env) -> Option<ConstExpr> {
+    match expr {
+        Expr::Annotated(expr, _) => fold_constant(expr, env),
+        Expr::ConstExpr(constant) => constant.clone().eval(env).ok(),
+        Expr::BinaryOp(op, lhs, rhs) => {
+            let lhs = fold_constant(lhs, env)?;
+            let rhs = fold_constant(rhs, env)?;
+            op.eval(&lhs, &rhs, env).ok()
+        }
+        _ => None,
+    }
+}
+
 impl Add {
     fn return_type_from_types(&self, lhs: &Type, rhs: &Type, env: &Env) -> Result<Type, Error> {
         match (lhs.clone(), rhs.clone()) {
@@ -33,6 +47,8 @@ impl Add {
                     ))
                 }
             }
+            // Constant strings can be concatenated at compile time.
+            (Type::Str, Type::Str) => Ok(Type::Str),
             (Type::Struct(elems1), Type::Struct(elems2)) => {
                 for key in elems1.keys() {
                     if elems2.contains_key(key) {
@@ -83,6 +99,7 @@ impl BinaryOp for Add {
                 a.append(&mut b);
                 ConstExpr::Array(a)
             }
+            (ConstExpr::Str(a), ConstExpr::Str(b)) => ConstExpr::Str(a + &b),
             (ConstExpr::Tuple(mut a), ConstExpr::Tuple(mut b)) => {
                 a.append(&mut b);
                 ConstExpr::Tuple(a)
@@ -241,6 +258,13 @@ impl BinaryOp for Add {
         let lhs_type = lhs.get_type(env)?;
         let rhs_type = rhs.get_type(env)?;
 
+        // Strings can only be concatenated at compile time, so fold chains like `"a" + "b" + "c"`.
+        if let (Type::Str, Type::Str) = (&lhs_type, &rhs_type) {
+            if let (Some(lhs), Some(rhs)) = (fold_constant(lhs, env), fold_constant(rhs, env)) {
+                return self.eval(&lhs, &rhs, env)?.compile_expr(env, output);
+            }
+        }
+
         match (lhs.clone(), rhs.clone()) {
             (Expr::ConstExpr(lhs), Expr::ConstExpr(rhs)) => {
                 if let Ok(constant_result) = self.eval(&lhs, &rhs, env) {
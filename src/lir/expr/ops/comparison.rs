@@ -3,7 +3,7 @@
 //! This module implements comparison operators between two expressions.
 
 use crate::{
-    asm::{AssemblyProgram, CoreOp, StandardOp, SP},
+    asm::{AssemblyProgram, CoreOp, StandardOp, A, B, SP},
     lir::*,
 };
 use ::core::fmt::{Debug, Display, Formatter, Result as FmtResult};
@@ -53,6 +53,10 @@ impl BinaryOp for Comparison {
 
                 self.can_apply(a_type, b_type, env)
             }
+            // Procedures are compared by both their environment and their address.
+            (Type::Proc(_, _), Self::Equal | Self::NotEqual, Type::Proc(_, _)) => {
+                lhs.can_decay_to(rhs, env)
+            }
             (a, Self::Equal, b) | (a, Self::NotEqual, b) => {
                 Ok(a.can_decay_to(b, env)? && a.get_size(env)? == 1)
             }
@@ -224,6 +228,31 @@ impl BinaryOp for Comparison {
                 return self.compile_types(a_type, b_type, env, output);
             }
 
+            (Type::Proc(_, _), Self::Equal | Self::NotEqual, Type::Proc(_, _)) => {
+                // Compare the addresses of the procedures, and then their environments.
+                output.op(CoreOp::IsEqual {
+                    a: SP.deref().offset(-2),
+                    b: SP.deref(),
+                    dst: A,
+                });
+                output.op(CoreOp::IsEqual {
+                    a: SP.deref().offset(-3),
+                    b: SP.deref().offset(-1),
+                    dst: B,
+                });
+                output.op(CoreOp::And { src: B, dst: A });
+                if let Self::NotEqual = self {
+                    output.op(CoreOp::Not(A));
+                }
+                // Replace both procedures with the result.
+                output.op(CoreOp::Pop(None, 3));
+                output.op(CoreOp::Move {
+                    src: A,
+                    dst: SP.deref(),
+                });
+                return Ok(());
+            }
+
             (a, Self::Equal, b) if a.can_decay_to(b, env)? => {
                 output.op(CoreOp::Move { src: dst, dst: tmp });
                 output.op(core_op);
@@ -1,7 +1,7 @@
 use super::*;
 
 use crate::{
-    asm::{AssemblyProgram, CoreOp, Location, A, B, C, SP},
+    asm::{AssemblyProgram, CoreOp, Location, A, B, C, D, SP},
     lir::*,
     side_effects::*,
 };
@@ -94,6 +94,103 @@ pub enum Put {
 }
 
 impl Put {
+    /// Print text known at compile time from the stack, leaving the registers untouched.
+    fn put_bytes(text: &str, output: &mut dyn AssemblyProgram) {
+        if text.is_empty() {
+            return;
+        }
+        let bytes: Vec<i64> = text.bytes().map(|byte| byte as i64).collect();
+        let size = bytes.len();
+        output.op(CoreOp::PushConst(bytes));
+        for i in 0..size {
+            output.op(CoreOp::Put(
+                SP.deref().offset(1 - (size - i) as isize),
+                Output::stdout_char(),
+            ));
+        }
+        output.op(CoreOp::Pop(None, size));
+    }
+
+    /// Print a character as its UTF-8 encoded bytes.
+    fn put_char(addr: Location, output: &mut dyn AssemblyProgram) {
+        use CoreOp::*;
+        // Print the bytes of an `n` byte encoding of the character in `A`.
+        let encode = |n: i64| {
+            let mut ops = vec![];
+            for k in (0..n).rev() {
+                ops.push(Move { src: A, dst: D });
+                ops.push(Set(B, 6 * k));
+                ops.push(LogicalRightShift { src: B, dst: D });
+                if k == n - 1 {
+                    // The leading byte is marked with the length of the encoding.
+                    ops.push(Set(B, [0, 0, 0xC0, 0xE0, 0xF0][n as usize]));
+                } else {
+                    // Every other byte holds six bits of the character.
+                    ops.push(Set(B, 0x3F));
+                    ops.push(BitwiseAnd { src: B, dst: D });
+                    ops.push(Set(B, 0x80));
+                }
+                ops.push(BitwiseOr { src: B, dst: D });
+                ops.push(Put(D, Output::stdout_char()));
+            }
+            Many(ops)
+        };
+
+        output.op(Many(vec![
+            Move { src: addr, dst: A },
+            Set(B, 0x80),
+            IsLess { a: A, b: B, dst: C },
+            If(C),
+            // ASCII characters are a single byte.
+            Put(A, Output::stdout_char()),
+            Else,
+            Set(B, 0x800),
+            IsLess { a: A, b: B, dst: C },
+            If(C),
+            encode(2),
+            Else,
+            Set(B, 0x10000),
+            IsLess { a: A, b: B, dst: C },
+            If(C),
+            encode(3),
+            Else,
+            encode(4),
+            End,
+            End,
+            End,
+        ]));
+    }
+
+    /// Print the bytes of a string, given the address of the string value.
+    fn put_str(
+        addr: Location,
+        t: &Type,
+        env: &Env,
+        output: &mut dyn AssemblyProgram,
+    ) -> Result<(), Error> {
+        let member = |name: &str| {
+            t.get_member_offset(&ConstExpr::Symbol(name.to_string()), &Expr::NONE, env)
+                .map(|(_, offset)| offset as isize)
+        };
+        use CoreOp::*;
+        output.op(Many(vec![
+            Move {
+                src: addr.offset(member("data")?),
+                dst: A,
+            },
+            Move {
+                src: addr.offset(member("length")?),
+                dst: B,
+            },
+            While(B),
+            Put(A.deref(), Output::stdout_char()),
+            Next(A, None),
+            Dec(B),
+            End,
+        ]));
+        Ok(())
+    }
+
     pub fn debug(
         addr: Location,
         t: &Type,
@@ -171,10 +268,17 @@ impl Put {
             Type::Char => {
                 output.op(CoreOp::Set(A, b'\'' as i64));
                 output.op(CoreOp::Put(A, Output::stdout_char()));
-                output.op(CoreOp::Put(addr, Output::stdout_char()));
+                Self::put_char(addr, output);
                 output.op(CoreOp::Set(A, b'\'' as i64));
                 output.op(CoreOp::Put(A, Output::stdout_char()));
             }
+            Type::Str | Type::String => {
+                output.op(CoreOp::Set(A, b'"' as i64));
+                output.op(CoreOp::Put(A, Output::stdout_char()));
+                Self::put_str(addr, t, env, output)?;
+                output.op(CoreOp::Set(A, b'"' as i64));
+                output.op(CoreOp::Put(A, Output::stdout_char()));
+            }
             Type::Never => {
                 for c in "Never".to_string().chars() {
                     output.op(CoreOp::Set(A, c as u8 as i64));
@@ -264,6 +368,56 @@ impl Put {
                 }
             }
 
+            Type::Slice(_, ty) => {
+                use CoreOp::*;
+                let ty_size = ty.get_size(env)?;
+                // Keep the pointer to the next element and the number of elements left on the stack,
+                // so that printing the elements can use the registers.
+                let ptr = SP.deref().offset(-1);
+                let remaining = SP.deref();
+                output.op(Many(vec![
+                    Move {
+                        src: addr.clone(),
+                        dst: A,
+                    },
+                    Move {
+                        src: addr.offset(1),
+                        dst: B,
+                    },
+                    Push(A, 1),
+                    Push(B, 1),
+                    Set(A, b'[' as i64),
+                    Put(A, Output::stdout_char()),
+                    While(remaining.clone()),
+                    Move {
+                        src: ptr.clone(),
+                        dst: A,
+                    },
+                    Push(A.deref(), ty_size),
+                ]));
+                Self::debug(SP.deref().offset(1 - ty_size as isize), ty, env, output)?;
+                output.op(Many(vec![
+                    Pop(None, ty_size),
+                    Move {
+                        src: ptr.clone(),
+                        dst: A,
+                    },
+                    Next(A, Some(ty_size as isize)),
+                    Move { src: A, dst: ptr },
+                    Dec(remaining.clone()),
+                    If(remaining),
+                    Set(A, b',' as i64),
+                    Put(A, Output::stdout_char()),
+                    Set(A, b' ' as i64),
+                    Put(A, Output::stdout_char()),
+                    End,
+                    End,
+                    Set(A, b']' as i64),
+                    Put(A, Output::stdout_char()),
+                    Pop(None, 2),
+                ]));
+            }
+
             Type::Struct(fields) => {
                 for c in "{".chars() {
                     output.op(CoreOp::Set(A, c as u8 as i64));
@@ -435,9 +589,8 @@ impl Put {
             Type::Cell => {
                 output.op(CoreOp::Put(addr, Output::stdout_int()));
             }
-            Type::Char => {
-                output.op(CoreOp::Put(addr, Output::stdout_char()));
-            }
+            Type::Char => Self::put_char(addr, output),
+            Type::Str | Type::String => Self::put_str(addr, t, env, output)?,
             // Char pointer is a string
             Type::Pointer(_, inner) => {
                 if inner.equals(&Type::Char, env)? {
@@ -580,6 +733,42 @@ impl UnaryOp for Put {
         Ok(ConstExpr::None)
     }
 
+    /// Compile the unary operation on the given expression.
+    fn compile(
+        &self,
+        expr: &Expr,
+        env: &mut Env,
+        output: &mut dyn AssemblyProgram,
+    ) -> Result<(), Error> {
+        match expr {
+            Expr::Annotated(expr, metadata) => self
+                .compile(expr, env, output)
+                .map_err(|err| err.annotate(metadata.clone())),
+            // Characters and strings known at compile time are printed as their UTF-8
+            // encoded bytes directly, without storing them in memory first.
+            Expr::ConstExpr(ConstExpr::Char(ch)) if matches!(self, Self::Display) => {
+                Self::put_bytes(&ch.to_string(), output);
+                Ok(())
+            }
+            Expr::ConstExpr(ConstExpr::Str(text)) => {
+                match self {
+                    Self::Display => Self::put_bytes(text, output),
+                    Self::Debug => Self::put_bytes(&format!("\"{text}\""), output),
+                }
+                Ok(())
+            }
+            _ => {
+                let current_instruction = output.current_instruction();
+                expr.clone().compile_expr(env, output)?;
+                self.compile_types(&expr.get_type(env)?, env, output)?;
+                let message =
+                    format!("Compiled unary op: {self} '{expr}' (with operator {self:?})");
+                output.log_instructions_after(&self.display(expr), &message, current_instruction);
+                Ok(())
+            }
+        }
+    }
+
     /// Compile the unary operation.
     fn compile_types(
         &self,
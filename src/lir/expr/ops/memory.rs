@@ -1,5 +1,6 @@
 use super::*;
 use crate::asm::{CoreOp, StandardOp, A, SP};
+use crate::NULL;
 use ::core::fmt::{Debug, Display, Formatter, Result as FmtResult};
 
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
@@ -75,7 +76,11 @@ pub struct Delete;
 impl UnaryOp for Delete {
     /// Can this unary operation be applied to the given type?
     fn can_apply(&self, ty: &Type, env: &Env) -> Result<bool, Error> {
-        ty.equals(&Type::Pointer(Mutability::Any, Box::new(Type::Any)), env)
+        // Deleting a procedure frees the environment it captured, if it's a closure.
+        Ok(
+            ty.equals(&Type::Pointer(Mutability::Any, Box::new(Type::Any)), env)?
+                || matches!(ty.simplify_until_concrete(env)?, Type::Proc(_, _)),
+        )
     }
 
     /// Get the type of the result of applying this unary operation to the given type.
@@ -91,16 +96,36 @@ impl UnaryOp for Delete {
     /// Compile the unary operation.
     fn compile_types(
         &self,
-        _ty: &Type,
-        _env: &mut Env,
+        ty: &Type,
+        env: &mut Env,
         output: &mut dyn AssemblyProgram,
     ) -> Result<(), Error> {
-        output.std_op(StandardOp::Free(SP.deref())).map_err(|_| {
+        let unsupported = |_| {
             Error::UnsupportedOperation(Expr::UnaryOp(
                 self.clone_box(),
                 Box::new(Expr::ConstExpr(ConstExpr::None)),
             ))
-        })?;
+        };
+        if let Type::Proc(_, _) = ty.simplify_until_concrete(env)? {
+            // Pop the procedure's address, leaving its environment.
+            output.op(CoreOp::Pop(None, 1));
+            // Only closures have an environment to free.
+            output.op(CoreOp::Set(A, NULL));
+            output.op(CoreOp::IsNotEqual {
+                a: SP.deref(),
+                b: A,
+                dst: A,
+            });
+            output.op(CoreOp::If(A));
+            output
+                .std_op(StandardOp::Free(SP.deref()))
+                .map_err(unsupported)?;
+            output.op(CoreOp::End);
+        } else {
+            output
+                .std_op(StandardOp::Free(SP.deref()))
+                .map_err(unsupported)?;
+        }
         output.op(CoreOp::Pop(None, 1));
         Ok(())
     }
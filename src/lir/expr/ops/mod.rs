@@ -9,6 +9,7 @@ mod comparison;
 mod io;
 mod logic;
 mod memory;
+mod slice;
 mod tagged_union;
 
 pub use arithmetic::*;
@@ -18,6 +19,7 @@ pub use comparison::*;
 pub use io::*;
 pub use logic::*;
 pub use memory::*;
+pub use slice::*;
 pub use tagged_union::*;
 
 use crate::{asm::AssemblyProgram, lir::*};
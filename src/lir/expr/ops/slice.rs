@@ -0,0 +1,137 @@
+//! # Slice Operations
+//!
+//! This module implements taking a subslice of a slice, like `xs[1..3]`.
+
+use crate::{
+    asm::{AssemblyProgram, CoreOp, A, B, C, D, E},
+    lir::*,
+};
+use ::core::fmt::{Debug, Display, Formatter, Result as FmtResult};
+
+/// Take the elements of a slice between a start index and an end index.
+///
+/// The operands are the slice, the start, and the end. If the end is `None`,
+/// the subslice runs to the end of the slice.
+#[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct Subslice;
+
+impl TernaryOp for Subslice {
+    /// Get the type of the result of applying this ternary operation to the given expressions.
+    fn return_type(&self, a: &Expr, b: &Expr, c: &Expr, env: &Env) -> Result<Type, Error> {
+        if self.can_apply_exprs(a, b, c, env)? {
+            a.get_type(env)
+        } else {
+            Err(Error::InvalidTernaryOp(
+                self.clone_box(),
+                a.clone(),
+                b.clone(),
+                c.clone(),
+            ))
+        }
+    }
+
+    /// Clone this operation into a box.
+    fn clone_box(&self) -> Box<dyn TernaryOp> {
+        Box::new(*self)
+    }
+
+    fn display(&self, a: &Expr, b: &Expr, c: &Expr) -> String {
+        match c {
+            Expr::ConstExpr(ConstExpr::None) => format!("{a}[{b}..]"),
+            _ => format!("{a}[{b}..{c}]"),
+        }
+    }
+
+    /// Can this ternary operation be applied to the given types?
+    fn can_apply(&self, a: &Type, b: &Type, c: &Type, env: &Env) -> Result<bool, Error> {
+        Ok(matches!(a.simplify_until_concrete(env)?, Type::Slice(_, _))
+            && b.equals(&Type::Int, env)?
+            && (c.equals(&Type::Int, env)? || c.equals(&Type::None, env)?))
+    }
+
+    /// Evaluate this ternary operation on the given constant values.
+    fn eval(
+        &self,
+        a: &ConstExpr,
+        _b: &ConstExpr,
+        _c: &ConstExpr,
+        _env: &mut Env,
+    ) -> Result<ConstExpr, Error> {
+        Err(Error::InvalidConstExpr(a.clone()))
+    }
+
+    /// Compile the ternary operation.
+    fn compile_types(
+        &self,
+        a: &Type,
+        _b: &Type,
+        c: &Type,
+        env: &mut Env,
+        output: &mut dyn AssemblyProgram,
+    ) -> Result<(), Error> {
+        let elem_size = match a.simplify_until_concrete(env)? {
+            Type::Slice(_, elem) => elem.get_size(env)?,
+            _ => {
+                return Err(Error::InvalidTernaryOpTypes(
+                    self.clone_box(),
+                    a.clone(),
+                    Type::Int,
+                    c.clone(),
+                ))
+            }
+        };
+
+        // Store the length in `A`, the start in `B`, and the end in `C`.
+        if c.equals(&Type::None, env)? {
+            output.op(CoreOp::Pop(Some(B), 1));
+            output.op(CoreOp::Pop(Some(A), 1));
+            output.op(CoreOp::Move { src: A, dst: C });
+        } else {
+            output.op(CoreOp::Pop(Some(C), 1));
+            output.op(CoreOp::Pop(Some(B), 1));
+            output.op(CoreOp::Pop(Some(A), 1));
+        }
+
+        // The bounds are valid if `0 <= start <= end <= length`.
+        output.op(CoreOp::Set(D, 0));
+        output.op(CoreOp::IsGreaterEqual { a: B, b: D, dst: D });
+        output.op(CoreOp::IsLessEqual { a: B, b: C, dst: E });
+        output.op(CoreOp::And { src: E, dst: D });
+        output.op(CoreOp::IsLessEqual { a: C, b: A, dst: E });
+        output.op(CoreOp::And { src: E, dst: D });
+        output.op(CoreOp::Not(D));
+        output.op(CoreOp::If(D));
+        Expr::compile_panic("slice out of bounds", env, output);
+        output.op(CoreOp::End);
+
+        // The length of the subslice is the end minus the start.
+        output.op(CoreOp::Move { src: C, dst: A });
+        output.op(CoreOp::Sub { dst: A, src: B });
+        // Offset the pointer to the first element by the start.
+        if elem_size > 1 {
+            output.op(CoreOp::Set(D, elem_size as i64));
+            output.op(CoreOp::Mul { dst: B, src: D });
+        }
+        output.op(CoreOp::Pop(Some(D), 1));
+        output.op(CoreOp::Index {
+            src: D,
+            offset: B,
+            dst: D,
+        });
+        output.op(CoreOp::Push(D, 1));
+        output.op(CoreOp::Push(A, 1));
+        Ok(())
+    }
+}
+
+impl Debug for Subslice {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "..")
+    }
+}
+
+impl Display for Subslice {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "..")
+    }
+}
@@ -729,13 +729,18 @@ impl Pattern {
                 }
                 // If no error was thrown, the variant is an option which can be matched.
                 // Now, check if the tag matches the variant.
+                //
+                // The data is only matched against the pattern if the tag matches, because
+                // the data of another variant may not be valid for this one (a pointer
+                // pattern would dereference whatever is in the pointer's place).
+                // `And` evaluates both of its operands, so it can't be used here.
                 expr.clone()
                     .unop(super::ops::Tag)
                     .eq(ConstExpr::Of(
                         Type::Enum(variants.clone().into_keys().collect()),
                         name.clone(),
                     ))
-                    .and(
+                    .if_then(
                         pattern.matches(
                             &expr
                                 .clone()
@@ -744,6 +749,7 @@ impl Pattern {
                             &variants[name].clone().simplify(env)?,
                             env,
                         )?,
+                        ConstExpr::Bool(false),
                     )
             }
 
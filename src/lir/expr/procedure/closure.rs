@@ -0,0 +1,248 @@
+//! # Closure
+//!
+//! A closure is an anonymous procedure which captures variables from the
+//! scope it is created in.
+//!
+//! Closures are lowered to an environment structure holding the captured
+//! variables, paired with a regular procedure. The environment is allocated on the
+//! heap, so that the closure can outlive the scope that created it, and it's freed
+//! when the closure is deleted with `del`.
+//!
+//! A closure's value is the pointer to its environment, followed by the
+//! procedure's address, just like every other procedure, whose environment is
+//! `NULL`. When a procedure is called, its environment is passed to it in a
+//! register, so closures have the same type as procedures and are called the same way.
+use crate::asm::{AssemblyProgram, CoreOp, Location, A, B};
+use crate::lir::{
+    Compile, ConstExpr, CoreBuiltin, Env, Error, Expr, GetType, Mutability, New, Procedure, Type,
+    TypeCheck,
+};
+use core::fmt;
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+use log::trace;
+
+/// How a closure captures a variable from its enclosing scope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Capture {
+    /// Copy the variable's value into the closure's environment.
+    /// Inside the closure, the variable is bound immutably to the copy.
+    Value,
+    /// Store a pointer to the variable in the closure's environment.
+    /// Inside the closure, the variable is bound to the pointer.
+    Reference(Mutability),
+}
+
+/// An anonymous procedure which captures variables from its enclosing scope.
+#[derive(Clone, Debug, PartialEq, Hash)]
+pub struct Closure {
+    /// The variables captured from the enclosing scope, and how they're captured.
+    captures: Vec<(String, Capture)>,
+    /// The arguments of the closure, and their types.
+    args: Vec<(String, Mutability, Type)>,
+    /// The return type of the closure.
+    ret: Type,
+    /// The closure's body expression.
+    body: Box<Expr>,
+}
+
+impl Closure {
+    /// The name of the variable which points to the closure's environment.
+    pub const ENV_VAR: &'static str = "__closure_env";
+    /// The register which holds the environment of a procedure when it's called.
+    pub(crate) const ENV_REGISTER: Location = B;
+
+    /// Construct a new closure with a list of captured variables, a list of
+    /// arguments and their types, a return type, and the body of the closure.
+    pub fn new(
+        captures: Vec<(String, Capture)>,
+        args: Vec<(String, Mutability, Type)>,
+        ret: Type,
+        body: impl Into<Expr>,
+    ) -> Self {
+        Self {
+            captures,
+            args,
+            ret,
+            body: Box::new(body.into()),
+        }
+    }
+
+    /// Get the variables captured by the closure.
+    pub fn get_captures(&self) -> &[(String, Capture)] {
+        &self.captures
+    }
+
+    /// Get the arguments of the closure.
+    pub fn get_args(&self) -> &[(String, Mutability, Type)] {
+        &self.args
+    }
+
+    /// Get the return type of the closure.
+    pub fn get_ret(&self) -> &Type {
+        &self.ret
+    }
+
+    /// Get the body of the closure.
+    pub fn get_body(&self) -> &Expr {
+        &self.body
+    }
+
+    /// Get the type of the environment structure for this closure in the given scope.
+    fn get_env_type(&self, env: &Env) -> Result<Type, Error> {
+        let mut fields = BTreeMap::new();
+        for (name, capture) in &self.captures {
+            // Only local variables need to be captured.
+            let ty = match env.get_var(name) {
+                Some((_, ty, _)) => ty.clone(),
+                None => return Err(Error::SymbolNotDefined(name.clone())),
+            };
+            fields.insert(
+                name.clone(),
+                match capture {
+                    Capture::Value => ty,
+                    Capture::Reference(mutability) => Type::Pointer(*mutability, Box::new(ty)),
+                },
+            );
+        }
+        Ok(Type::Struct(fields))
+    }
+
+    /// Lower the closure into the expression which allocates its environment,
+    /// and the procedure which is called with the environment.
+    ///
+    /// A new procedure is created every time the closure is lowered, so that closures
+    /// inside of polymorphic procedures are not shared between monomorphs.
+    pub fn lower(&self, env: &Env) -> Result<(Expr, Procedure), Error> {
+        trace!("lowering closure: {}", self);
+        let env_ty = self.get_env_type(env)?;
+
+        // Bind each captured variable to its copy in the environment.
+        let env_ptr = Expr::var(Self::ENV_VAR).deref();
+        let mut body = self
+            .captures
+            .iter()
+            .rev()
+            .fold(*self.body.clone(), |body, (name, _)| {
+                Expr::let_var(
+                    name,
+                    Mutability::Immutable,
+                    None,
+                    env_ptr.clone().field(ConstExpr::Symbol(name.clone())),
+                    body,
+                )
+            });
+
+        // Only allocate an environment if there's something to capture.
+        let env_val = if self.captures.is_empty() {
+            ConstExpr::Null.into()
+        } else {
+            // The environment is passed to the procedure in a register,
+            // so the procedure saves it before anything else.
+            let get_env = CoreBuiltin {
+                name: Self::ENV_VAR.to_string(),
+                args: vec![],
+                ret: Type::Pointer(Mutability::Immutable, Box::new(env_ty)),
+                body: vec![CoreOp::Push(Self::ENV_REGISTER, 1)],
+            };
+            body = Expr::let_var(
+                Self::ENV_VAR,
+                Mutability::Immutable,
+                None,
+                Expr::from(ConstExpr::CoreBuiltin(get_env)).app(vec![]),
+                body,
+            );
+
+            Expr::Struct(
+                self.captures
+                    .iter()
+                    .map(|(name, capture)| {
+                        (
+                            name.clone(),
+                            match capture {
+                                Capture::Value => Expr::var(name),
+                                Capture::Reference(mutability) => {
+                                    Expr::var(name).refer(*mutability)
+                                }
+                            },
+                        )
+                    })
+                    .collect(),
+            )
+            .unop(New)
+        };
+
+        let proc = Procedure::new(None, self.args.clone(), self.ret.clone(), body);
+        Ok((env_val, proc))
+    }
+}
+
+impl TypeCheck for Closure {
+    fn type_check(&self, env: &Env) -> Result<(), Error> {
+        trace!("type checking closure: {}", self);
+        let (env_val, proc) = self.lower(env)?;
+        env_val.type_check(env)?;
+        proc.type_check(env)
+    }
+}
+
+impl GetType for Closure {
+    fn get_type_checked(&self, _env: &Env, _i: usize) -> Result<Type, Error> {
+        Ok(Type::Proc(
+            self.args.iter().map(|(_, _, t)| t.clone()).collect(),
+            Box::new(self.ret.clone()),
+        ))
+    }
+
+    fn substitute(&mut self, name: &str, ty: &Type) {
+        for (_, _, t) in &mut self.args {
+            *t = t.substitute(name, ty);
+        }
+        self.ret = self.ret.substitute(name, ty);
+
+        self.body.substitute(name, ty);
+    }
+}
+
+impl Compile for Closure {
+    fn compile_expr(self, env: &mut Env, output: &mut dyn AssemblyProgram) -> Result<(), Error> {
+        let (env_val, proc) = self.lower(env)?;
+        // Push the environment, and then the procedure with its empty environment.
+        env_val.compile_expr(env, output)?;
+        ConstExpr::Proc(proc).compile_expr(env, output)?;
+        // Replace the procedure's empty environment with the closure's.
+        output.op(CoreOp::Pop(Some(A), 1));
+        output.op(CoreOp::Pop(None, 1));
+        output.op(CoreOp::Push(A, 1));
+        Ok(())
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "closure[")?;
+        for (i, (name, capture)) in self.captures.iter().enumerate() {
+            match capture {
+                Capture::Value => {}
+                Capture::Reference(Mutability::Mutable) => write!(f, "&mut ")?,
+                Capture::Reference(_) => write!(f, "&")?,
+            }
+            write!(f, "{name}")?;
+            if i < self.captures.len() - 1 {
+                write!(f, ", ")?
+            }
+        }
+        write!(f, "](")?;
+        for (i, (name, mutability, ty)) in self.args.iter().enumerate() {
+            if mutability.is_mutable() {
+                write!(f, "mut ")?;
+            }
+            write!(f, "{name}: {ty}")?;
+            if i < self.args.len() - 1 {
+                write!(f, ", ")?
+            }
+        }
+        write!(f, ") -> {} = {}", self.ret, self.body)
+    }
+}
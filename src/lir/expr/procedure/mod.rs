@@ -15,12 +15,19 @@
 //!
 //! Polymorphic procedures take a list of type arguments, and produce a monomorphized
 //! version of the procedure. This can then be compiled directly to assembly.
+//!
+//! ## Closure
+//!
+//! Closures are anonymous procedures which capture variables from their enclosing scope.
+//! They are lowered to a pointer to an environment structure paired with a mono procedure.
 mod builtin;
+mod closure;
 mod ffi;
 mod mono;
 mod poly;
 
 pub use builtin::*;
+pub use closure::*;
 pub use ffi::*;
 pub use mono::*;
 pub use poly::*;
@@ -7,11 +7,16 @@
 //! only compiled once, and referenced by their label every other time
 //! they are used.
 //!
+//! A procedure's value is its label paired with the environment it captured.
+//! Procedures created by the `proc` keyword don't capture anything, so their
+//! environment is `NULL`.
+//!
 //! Procedures are created by the `proc` keyword.
 use crate::asm::{AssemblyProgram, CoreOp, A, FP, SP};
 use crate::lir::{
     Compile, ConstExpr, Env, Error, Expr, GetSize, GetType, Mutability, Type, TypeCheck,
 };
+use crate::NULL;
 use core::fmt;
 use std::hash::Hash;
 use std::sync::{Arc, Mutex, RwLock};
@@ -89,6 +94,14 @@ impl Procedure {
         &self.body
     }
 
+    /// Get the environment in scope for the procedure's body, with its arguments defined.
+    pub fn get_body_scope(&self, env: &Env) -> Result<Env, Error> {
+        let mut new_env = env.new_scope();
+        new_env.define_args(self.args.clone())?;
+        new_env.set_expected_return_type(self.ret.clone());
+        Ok(new_env)
+    }
+
     /// Get the mangled name of the procedure.
     /// The procedure's mangled name is used to store the procedure in the environment.
     pub fn get_mangled_name(&self) -> &str {
@@ -107,8 +120,10 @@ impl Procedure {
         self.common_name = Some(name.to_string());
     }
 
-    /// Push this procedure's label to the stack.
+    /// Push this procedure's label to the stack, after its `NULL` environment.
     pub fn push_label(&self, output: &mut dyn AssemblyProgram) {
+        // The procedure doesn't capture an environment.
+        output.op(CoreOp::PushConst(vec![NULL]));
         // Set a register to the address of the procedure's label.
         output.op(CoreOp::SetLabel(A, self.mangled_name.clone()));
         // Push the register to the stack.
@@ -136,18 +151,23 @@ impl TypeCheck for Procedure {
         self.ret.type_check(env)?;
 
         // Create a new scope for the procedure's body, and define the arguments for the scope.
-        let mut new_env = env.new_scope();
-        new_env.define_args(self.args.clone())?;
-        new_env.set_expected_return_type(self.ret.clone());
+        let new_env = self.get_body_scope(env)?;
+
+        // Errors with the procedure as a whole are reported at its definition,
+        // which its body is annotated with.
+        let annotate = |err: Error| match &*self.body {
+            Expr::Annotated(_, annotation) => err.annotate(annotation.clone()),
+            _ => err,
+        };
 
         // Get the type of the procedure's body, and confirm that it matches the return type.
-        let body_type = self.body.get_type(&new_env)?;
+        let body_type = self.body.get_type(&new_env).map_err(annotate)?;
         if !body_type.can_decay_to(&self.ret, env)? {
-            Err(Error::MismatchedTypes {
+            Err(annotate(Error::MismatchedTypes {
                 expected: self.ret.clone(),
                 found: body_type,
                 expr: ConstExpr::Proc(self.clone()).into(),
-            })
+            }))
         } else {
             // Typecheck the procedure's body.
             self.body.type_check(&new_env)
@@ -175,24 +195,23 @@ impl GetType for Procedure {
 
 impl Compile for Procedure {
     fn compile_expr(self, env: &mut Env, output: &mut dyn AssemblyProgram) -> Result<(), Error> {
-        // Compile the contents of the procedure under a new environment
-        let mut new_env = env.new_scope();
-
-        // Declare the arguments and get their size
-        let args_size = new_env.define_args(self.args)?;
-        // Get the size of the return value to leave on the stack
-        let ret_size = self.ret.get_size(env)?;
-
         // Check to see if the procedure has already been defined
         if output.is_defined(&self.mangled_name) {
             // If the procedure has already been defined, then we don't need to
             // compile it again.
             // Push the procedure label address onto the stack
-            output.op(CoreOp::Next(SP, None));
-            output.op(CoreOp::SetLabel(SP.deref(), self.mangled_name));
+            self.push_label(output);
             return Ok(());
         }
 
+        // Compile the contents of the procedure under a new environment
+        let mut new_env = env.new_scope();
+
+        // Declare the arguments and get their size
+        let args_size = new_env.define_args(self.args)?;
+        // Get the size of the return value to leave on the stack
+        let ret_size = self.ret.get_size(env)?;
+
         // Declare the function body
         output.op(CoreOp::Fn(self.mangled_name.clone()));
         if let Some(common_name) = &self.common_name {
@@ -216,7 +235,8 @@ impl Compile for Procedure {
         output.op(CoreOp::End);
 
         output.comment(format!("push {} onto the stack", self.mangled_name));
-        // Push the procedure label address onto the stack
+        // Push the procedure label address onto the stack, after its empty environment
+        output.op(CoreOp::PushConst(vec![NULL]));
         output.op(CoreOp::SetLabel(A, self.mangled_name.clone()));
         output.op(CoreOp::Push(A, 1));
 
@@ -3,7 +3,7 @@
 //! A polymorphic procedure of LIR code which can be applied to a list of arguments with type arguments.
 //! This is mono-morphed into a `Procedure` when it is called with a list of type arguments.
 //! A procedure is compiled down to a label in the assembly code.
-use crate::lir::{ConstExpr, Env, Error, Expr, GetType, Mutability, Type, TypeCheck};
+use crate::lir::{ConstExpr, Env, Error, Expr, FFIProcedure, GetType, Mutability, Type, TypeCheck};
 use core::fmt;
 use log::{debug, error, trace};
 use std::{
@@ -23,6 +23,8 @@ pub struct PolyProcedure {
     name: String,
     /// The type parameters of the procedure.
     ty_params: Vec<String>,
+    /// The trait bounds of the type parameters, as pairs of a type parameter and a trait name.
+    bounds: Vec<(String, String)>,
     /// The arguments of the procedure.
     args: Vec<(String, Mutability, Type)>,
     /// The return type of the procedure.
@@ -38,6 +40,7 @@ impl PartialEq for PolyProcedure {
     fn eq(&self, other: &Self) -> bool {
         self.name == other.name
             && self.ty_params == other.ty_params
+            && self.bounds == other.bounds
             && self.args == other.args
             && self.ret == other.ret
             && self.body == other.body
@@ -57,6 +60,7 @@ impl PolyProcedure {
         Self {
             name,
             ty_params,
+            bounds: vec![],
             args,
             ret,
             body: Box::new(body.into()),
@@ -75,6 +79,7 @@ impl PolyProcedure {
         Self {
             name,
             ty_params,
+            bounds: vec![],
             args: mono.get_args().to_vec(),
             ret: mono.get_ret().clone(),
             body: mono.get_body().clone().into(),
@@ -83,6 +88,18 @@ impl PolyProcedure {
         }
     }
 
+    /// Require the type arguments of some of the type parameters to implement traits.
+    /// Each bound is a pair of a type parameter and the name of a trait.
+    pub fn with_bounds(mut self, bounds: Vec<(String, String)>) -> Self {
+        self.bounds = bounds;
+        self
+    }
+
+    /// Get the trait bounds of the type parameters of this polymorphic procedure.
+    pub fn get_bounds(&self) -> &[(String, String)] {
+        &self.bounds
+    }
+
     /// Get the name of this polymorphic procedure.
     /// This is not the mangled name, but the name known to the LIR front-end.
     /// The mangled name is unique for each monomorph of the procedure.
@@ -90,6 +107,54 @@ impl PolyProcedure {
         &self.name
     }
 
+    /// Get the arguments of this polymorphic procedure.
+    pub fn get_args(&self) -> &[(String, Mutability, Type)] {
+        &self.args
+    }
+
+    /// Get the body of this polymorphic procedure.
+    pub fn get_body(&self) -> &Expr {
+        &self.body
+    }
+
+    /// Get the environment in scope for the procedure's body, with its type
+    /// parameters and arguments defined.
+    pub fn get_body_scope(&self, env: &Env) -> Result<Env, Error> {
+        let mut new_env = env.new_scope();
+        // Define the type parameters of the procedure.
+        new_env.define_types(
+            self.ty_params
+                .clone()
+                .into_iter()
+                .map(|ty_param| (ty_param.clone(), Type::Unit(ty_param, Box::new(Type::None))))
+                .collect(),
+        );
+        // Give the bounded type parameters the methods of their traits, so that
+        // the body can call them before the procedure is monomorphized.
+        for (ty_param, trait_name) in &self.bounds {
+            let trait_ = new_env
+                .get_trait(trait_name)
+                .ok_or_else(|| Error::TraitNotDefined(trait_name.clone()))?
+                .clone();
+            let param_ty = Type::Unit(ty_param.clone(), Box::new(Type::None));
+            for (name, method_ty) in trait_.get_methods_for(&Type::Symbol(ty_param.clone())) {
+                if let Type::Proc(args, ret) = method_ty {
+                    let method = FFIProcedure::new(name.clone(), args, *ret);
+                    new_env.add_associated_const(
+                        param_ty.clone(),
+                        name,
+                        ConstExpr::FFIProcedure(method),
+                    )?;
+                }
+            }
+            new_env.add_trait_impl(trait_.get_name(), param_ty);
+        }
+        // Define the arguments of the procedure.
+        new_env.define_args(self.args.clone())?;
+        new_env.set_expected_return_type(self.ret.clone());
+        Ok(new_env)
+    }
+
     /// Take some type arguments and produce a monomorphized version of the procedure.
     /// This monomorphized version can then be compiled directly. Additionally, the
     /// mono version of the procedure is memoized, so that it is only compiled once.
@@ -112,6 +177,26 @@ impl PolyProcedure {
             .collect::<Result<Vec<_>, Error>>()?;
 
         debug!(target: "mono", "Simplified type arguments: {:?}", simplified_ty_args);
+        // Confirm that the type arguments implement the traits their parameters are bounded by.
+        for (ty_param, trait_name) in &self.bounds {
+            let trait_ = env
+                .get_trait(trait_name)
+                .ok_or_else(|| Error::TraitNotDefined(trait_name.clone()))?;
+            let ty_arg = self
+                .ty_params
+                .iter()
+                .position(|param| param == ty_param)
+                .and_then(|i| simplified_ty_args.get(i));
+            if let Some(ty_arg) = ty_arg {
+                if !env.implements_trait(ty_arg, trait_.get_name())? {
+                    error!("Type {ty_arg} does not implement trait {trait_name}");
+                    return Err(Error::UnimplementedTrait(
+                        ty_arg.clone(),
+                        trait_name.clone(),
+                    ));
+                }
+            }
+        }
         // This is a helper function to bind the type arguments to the type parameters.
         let bind_type_args = |ty: Type| -> Result<Type, Error> {
             // Add the type parameters to the given type,
@@ -213,18 +298,7 @@ impl TypeCheck for PolyProcedure {
         *self.has_type_checked.write().unwrap() = true;
         trace!("Type checking {self}");
         // Create a new scope for the procedure's body, and define the arguments for the scope.
-        let mut new_env = env.new_scope();
-        // Define the type parameters of the procedure.
-        new_env.define_types(
-            self.ty_params
-                .clone()
-                .into_iter()
-                .map(|ty_param| (ty_param.clone(), Type::Unit(ty_param, Box::new(Type::None))))
-                .collect(),
-        );
-        // Define the arguments of the procedure.
-        new_env.define_args(self.args.clone())?;
-        new_env.set_expected_return_type(self.ret.clone());
+        let new_env = self.get_body_scope(env)?;
 
         // Typecheck the types of the arguments and return value
         for (_, _, t) in &self.args {
@@ -258,6 +332,14 @@ impl fmt::Display for PolyProcedure {
         write!(f, "proc[")?;
         for (i, ty_param) in self.ty_params.iter().enumerate() {
             write!(f, "{}", ty_param)?;
+            for (j, (_, trait_name)) in self
+                .bounds
+                .iter()
+                .filter(|(bounded, _)| bounded == ty_param)
+                .enumerate()
+            {
+                write!(f, "{}{}", if j == 0 { ": " } else { " + " }, trait_name)?;
+            }
             if i < self.ty_params.len() - 1 {
                 write!(f, ", ")?;
             }
@@ -282,6 +364,7 @@ impl Hash for PolyProcedure {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.name.hash(state);
         self.ty_params.hash(state);
+        self.bounds.hash(state);
         self.args.hash(state);
         self.ret.hash(state);
         self.body.hash(state);
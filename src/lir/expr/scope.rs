@@ -0,0 +1,210 @@
+//! # Scope
+//!
+//! Editor tooling needs to know which symbols are defined at a given location
+//! in the source code, so that it can look up their types. This module finds
+//! the environment in scope at a location, using the source code locations
+//! annotated on an expression.
+use crate::lir::{ConstExpr, Declaration, Env, Expr, GetType};
+
+impl Expr {
+    /// Get the environment in scope at the given byte offset into a source file.
+    ///
+    /// This is the scope of the smallest annotated expression which contains the offset.
+    /// If no annotated expression contains the offset, this is the scope of the
+    /// top-level declarations.
+    pub fn scope_at(&self, env: &Env, filename: Option<&str>, offset: usize) -> Env {
+        let mut finder = ScopeFinder {
+            filename,
+            offset,
+            best: None,
+            top_level: env.clone(),
+        };
+        finder.visit_expr(self, env, true);
+        match finder.best {
+            Some((_, env)) => env,
+            None => finder.top_level,
+        }
+    }
+}
+
+/// Searches an expression for the scope at an offset into a source file.
+struct ScopeFinder<'a> {
+    /// The file containing the offset.
+    filename: Option<&'a str>,
+    /// The byte offset into the file.
+    offset: usize,
+    /// The length of the smallest annotated expression found containing the offset,
+    /// and the scope of that expression.
+    best: Option<(usize, Env)>,
+    /// The scope of the top-level declarations.
+    top_level: Env,
+}
+
+impl ScopeFinder<'_> {
+    fn visit_expr(&mut self, expr: &Expr, env: &Env, top_level: bool) {
+        match expr {
+            Expr::Annotated(expr, annotation) => {
+                if let Some(loc) = annotation.location() {
+                    let length = loc.length.unwrap_or(0);
+                    let contains_offset = loc.filename.as_deref() == self.filename
+                        && (loc.offset..=loc.offset + length).contains(&self.offset);
+                    if contains_offset && !matches!(self.best, Some((best, _)) if best < length) {
+                        // A statement's annotation is on the declarations it's in the scope of.
+                        let scope = match &**expr {
+                            Expr::Declare(decl, _) => declare(env, decl),
+                            _ => env.clone(),
+                        };
+                        self.best = Some((length, scope));
+                    }
+                }
+                self.visit_expr(expr, env, top_level)
+            }
+            Expr::Declare(decl, body) => {
+                // Procedures can refer to themselves, so their bodies are visited with the
+                // declarations defined.
+                let new_env = declare(env, decl);
+                self.visit_decl(decl, &new_env);
+                if top_level {
+                    self.top_level = new_env.clone();
+                }
+                self.visit_expr(body, &new_env, top_level)
+            }
+            Expr::Many(exprs) => {
+                for expr in exprs {
+                    self.visit_expr(expr, env, top_level);
+                }
+            }
+            Expr::ConstExpr(expr) => self.visit_const(expr, env),
+            Expr::Closure(closure) => {
+                if let Ok((env_val, proc)) = closure.lower(env) {
+                    self.visit_expr(&env_val, env, false);
+                    self.visit_const(&ConstExpr::Proc(proc), env);
+                }
+            }
+
+            Expr::Return(expr)
+            | Expr::UnaryOp(_, expr)
+            | Expr::Refer(_, expr)
+            | Expr::Deref(expr)
+            | Expr::Union(_, _, expr)
+            | Expr::EnumUnion(_, _, expr)
+            | Expr::As(expr, _)
+            | Expr::Member(expr, _) => self.visit_expr(expr, env, false),
+
+            Expr::While(a, b)
+            | Expr::BinaryOp(_, a, b)
+            | Expr::AssignOp(_, a, b)
+            | Expr::DerefMut(a, b)
+            | Expr::Index(a, b)
+            | Expr::When(_, a, b) => {
+                self.visit_expr(a, env, false);
+                self.visit_expr(b, env, false);
+            }
+            Expr::Loop(_, a, b, c) | Expr::If(a, b, c) | Expr::TernaryOp(_, a, b, c) => {
+                self.visit_expr(a, env, false);
+                self.visit_expr(b, env, false);
+                self.visit_expr(c, env, false);
+            }
+            Expr::IfLet(pat, expr, then, otherwise) => {
+                self.visit_expr(expr, env, false);
+                // The pattern's bindings are only defined in the body of the `if let`.
+                let mut new_env = env.clone();
+                if let Ok(ty) = expr.get_type(env) {
+                    let _ = pat.declare_let_bind(expr, &ty, &mut new_env);
+                }
+                self.visit_expr(then, &new_env, false);
+                self.visit_expr(otherwise, env, false);
+            }
+            Expr::Match(expr, branches) => {
+                self.visit_expr(expr, env, false);
+                let ty = expr.get_type(env);
+                for (pat, branch) in branches {
+                    let mut new_env = env.clone();
+                    if let Ok(ty) = &ty {
+                        let _ = pat.declare_let_bind(expr, ty, &mut new_env);
+                    }
+                    self.visit_expr(branch, &new_env, false);
+                }
+            }
+
+            Expr::Array(exprs) | Expr::Tuple(exprs) => {
+                for expr in exprs {
+                    self.visit_expr(expr, env, false);
+                }
+            }
+            Expr::Struct(fields) => {
+                for expr in fields.values() {
+                    self.visit_expr(expr, env, false);
+                }
+            }
+            Expr::Apply(f, args) => {
+                self.visit_expr(f, env, false);
+                for arg in args {
+                    self.visit_expr(arg, env, false);
+                }
+            }
+
+            Expr::Break(_) | Expr::Continue(_) => {}
+        }
+    }
+
+    fn visit_decl(&mut self, decl: &Declaration, env: &Env) {
+        match decl {
+            Declaration::Var(_, _, _, expr) | Declaration::VarPat(_, expr) => {
+                self.visit_expr(expr, env, false)
+            }
+            Declaration::Proc(_, proc) => self.visit_const(&ConstExpr::Proc(proc.clone()), env),
+            Declaration::PolyProc(_, proc) => {
+                self.visit_const(&ConstExpr::PolyProc(proc.clone()), env)
+            }
+            Declaration::Const(_, expr) | Declaration::StaticVar(_, _, _, expr) => {
+                self.visit_const(expr, env)
+            }
+            Declaration::Impl(_, consts) | Declaration::ImplTrait(_, _, consts) => {
+                for (_, expr) in consts {
+                    self.visit_const(expr, env);
+                }
+            }
+            Declaration::Many(decls) => {
+                for decl in decls {
+                    self.visit_decl(decl, env);
+                }
+            }
+            Declaration::Type(..) | Declaration::ExternProc(..) | Declaration::Trait(..) => {}
+        }
+    }
+
+    fn visit_const(&mut self, expr: &ConstExpr, env: &Env) {
+        match expr {
+            ConstExpr::Annotated(expr, _) => self.visit_const(expr, env),
+            ConstExpr::Proc(proc) => {
+                if let Ok(scope) = proc.get_body_scope(env) {
+                    self.visit_expr(proc.get_body(), &scope, false);
+                }
+            }
+            ConstExpr::PolyProc(proc) => {
+                if let Ok(scope) = proc.get_body_scope(env) {
+                    self.visit_expr(proc.get_body(), &scope, false);
+                }
+            }
+            ConstExpr::Declare(decl, body) => {
+                let mut new_env = env.clone();
+                let _ = new_env.add_compile_time_declaration(decl);
+                self.visit_decl(decl, &new_env);
+                self.visit_const(body, &new_env);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Get the scope with a declaration defined in it. If the variables can't be
+/// defined, then only the compile-time declarations are added.
+fn declare(env: &Env, decl: &Declaration) -> Env {
+    let mut new_env = env.clone();
+    if new_env.add_declaration(decl).is_err() {
+        new_env = env.clone();
+        let _ = new_env.add_compile_time_declaration(decl);
+    }
+    new_env
+}
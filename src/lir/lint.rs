@@ -0,0 +1,448 @@
+//! # Lints
+//!
+//! Lints are problems with a program which don't stop it from compiling,
+//! but which are probably mistakes. This module finds unused variables and
+//! parameters, shadowed bindings, and unreachable code in an expression,
+//! using the source code locations annotated on it to report them.
+use super::{Annotation, ConstExpr, Declaration, Env, Expr, GetType, Mutability, Pattern, Type};
+use crate::parse::SourceCodeLocation;
+use core::fmt::{Display, Formatter, Result as FmtResult};
+
+/// A warning about some code which compiles, but is probably a mistake.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Warning {
+    /// A warning with some annotation about the source code that caused it.
+    Annotated(Box<Self>, Annotation),
+
+    /// A local variable was declared, but never used.
+    UnusedVariable(String),
+    /// A parameter of a procedure was never used in its body.
+    UnusedParameter(String),
+    /// A variable was declared with the same name as another in scope, hiding it.
+    ShadowedBinding(String),
+    /// Code comes after a `return`, `break`, `continue`, or a call that never returns.
+    UnreachableCode,
+    /// A module was imported, but none of its names were used.
+    UnusedImport(String),
+}
+
+impl Warning {
+    /// Annotate a warning with some metadata.
+    pub fn annotate(self, annotation: Annotation) -> Self {
+        match self {
+            Self::Annotated(warning, previous_annotation) => {
+                let mut result = annotation;
+                result |= previous_annotation;
+                Self::Annotated(warning, result)
+            }
+            _ => Self::Annotated(Box::new(self), annotation),
+        }
+    }
+
+    /// Get the source code location of the warning, if it has one.
+    pub fn location(&self) -> Option<&SourceCodeLocation> {
+        match self {
+            Self::Annotated(_, annotation) => annotation.location(),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Annotated(warning, _) => write!(f, "{warning}"),
+            Self::UnusedVariable(name) => write!(f, "unused variable `{name}`"),
+            Self::UnusedParameter(name) => write!(f, "unused parameter `{name}`"),
+            Self::ShadowedBinding(name) => {
+                write!(f, "`{name}` shadows an earlier binding with the same name")
+            }
+            Self::UnreachableCode => write!(f, "unreachable code"),
+            Self::UnusedImport(name) => write!(f, "unused import `{name}`"),
+        }
+    }
+}
+
+impl Expr {
+    /// Find the warnings for the code in the given source file.
+    ///
+    /// Warnings for code in other files, like imported modules, aren't reported.
+    /// Bindings whose names start with an underscore are never reported as unused
+    /// or shadowing.
+    pub fn lint(&self, env: &Env, filename: Option<&str>) -> Vec<Warning> {
+        let mut linter = Linter {
+            filename,
+            loc: None,
+            bindings: vec![],
+            warnings: vec![],
+            unreachable_found: 0,
+        };
+        linter.visit_expr(self, env);
+        linter.end_scope(0);
+        linter.warnings
+    }
+}
+
+/// A variable or parameter in scope, and whether it's been used.
+struct Binding {
+    /// The name of the binding.
+    name: String,
+    /// Where the binding was declared.
+    loc: Option<SourceCodeLocation>,
+    /// Is the binding a parameter of a procedure?
+    is_parameter: bool,
+    /// Has the binding been used, or is it never reported as unused?
+    used: bool,
+}
+
+/// Searches an expression for the code that deserves a warning.
+struct Linter<'a> {
+    /// The file whose warnings are reported.
+    filename: Option<&'a str>,
+    /// The location of the innermost annotated expression being visited.
+    loc: Option<SourceCodeLocation>,
+    /// The variables in scope in the procedure being visited, innermost last.
+    bindings: Vec<Binding>,
+    /// The warnings found so far.
+    warnings: Vec<Warning>,
+    /// How much unreachable code has been found so far, in any file.
+    unreachable_found: usize,
+}
+
+impl Linter<'_> {
+    /// Report a warning at the given location, if it's in the file being linted.
+    fn warn(&mut self, warning: Warning, loc: Option<SourceCodeLocation>) {
+        if warning == Warning::UnreachableCode {
+            self.unreachable_found += 1;
+        }
+        if let Some(loc) = loc {
+            if loc.filename.as_deref() == self.filename {
+                self.warnings
+                    .push(warning.annotate(Annotation::Location(loc)));
+            }
+        }
+    }
+
+    /// Get the location of an expression, or the location of the code around it.
+    fn location_of(&self, expr: &Expr) -> Option<SourceCodeLocation> {
+        match expr {
+            Expr::Annotated(_, annotation) => annotation.location().cloned(),
+            _ => self.loc.clone(),
+        }
+    }
+
+    /// Bring a variable into scope, warning if it shadows another.
+    fn bind(&mut self, name: &str, loc: Option<SourceCodeLocation>, is_parameter: bool) {
+        let ignored = name.starts_with('_');
+        if !ignored && self.bindings.iter().any(|binding| binding.name == name) {
+            self.warn(Warning::ShadowedBinding(name.to_string()), loc.clone());
+        }
+        self.bindings.push(Binding {
+            name: name.to_string(),
+            loc,
+            is_parameter,
+            used: ignored,
+        });
+    }
+
+    /// Mark the innermost variable with the given name as used.
+    fn use_symbol(&mut self, name: &str) {
+        if let Some(binding) = self.bindings.iter_mut().rev().find(|b| b.name == name) {
+            binding.used = true;
+        }
+    }
+
+    /// Take the variables declared since the scope started out of scope,
+    /// and warn about the ones which were never used.
+    fn end_scope(&mut self, start: usize) {
+        for binding in self.bindings.split_off(start) {
+            if !binding.used {
+                let warning = if binding.is_parameter {
+                    Warning::UnusedParameter(binding.name)
+                } else {
+                    Warning::UnusedVariable(binding.name)
+                };
+                self.warn(warning, binding.loc);
+            }
+        }
+    }
+
+    /// Visit the body of a procedure with its parameters in scope.
+    /// The variables in scope outside of the procedure can't be used inside it.
+    fn visit_proc(&mut self, args: &[(String, Mutability, Type)], body: &Expr, env: &Env) {
+        let outer_bindings = std::mem::take(&mut self.bindings);
+        // The body is annotated with the location of the procedure's signature.
+        let loc = self.location_of(body);
+        for (name, _, _) in args {
+            self.bind(name, loc.clone(), true);
+        }
+        self.visit_expr(body, env);
+        self.end_scope(0);
+        self.bindings = outer_bindings;
+    }
+
+    /// Does evaluating the expression always leave the enclosing block?
+    fn diverges(&self, expr: &Expr, env: &Env) -> bool {
+        match expr {
+            Expr::Annotated(expr, _) => self.diverges(expr, env),
+            Expr::Return(_) | Expr::Break(_) | Expr::Continue(_) => true,
+            Expr::Many(exprs) => exprs.iter().any(|expr| self.diverges(expr, env)),
+            Expr::If(_, then, otherwise) => {
+                self.diverges(then, env) && self.diverges(otherwise, env)
+            }
+            // Calls to procedures which never return, like `exit` or `panic`.
+            Expr::Apply(..) => matches!(expr.get_type(env), Ok(Type::Never)),
+            _ => false,
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr, env: &Env) {
+        match expr {
+            Expr::Annotated(inner, annotation) => {
+                let outer_loc = self.loc.clone();
+                if let Some(loc) = annotation.location() {
+                    self.loc = Some(loc.clone());
+                }
+                self.visit_expr(inner, env);
+                self.loc = outer_loc;
+            }
+            Expr::Declare(decl, body) => {
+                let mut new_env = env.clone();
+                if new_env.add_declaration(decl).is_err() {
+                    new_env = env.clone();
+                    let _ = new_env.add_compile_time_declaration(decl);
+                }
+                let start = self.bindings.len();
+                self.visit_decl(decl, &new_env);
+                self.visit_expr(body, &new_env);
+                self.end_scope(start);
+            }
+            Expr::Many(exprs) => {
+                for (i, expr) in exprs.iter().enumerate() {
+                    let found = self.unreachable_found;
+                    self.visit_expr(expr, env);
+                    // Code which is unreachable inside of the expression is already
+                    // reported, so it isn't reported again for the code after it.
+                    if self.diverges(expr, env) && self.unreachable_found == found {
+                        // Warn about the first piece of code after this which
+                        // came from the source, but not every piece of code.
+                        if let Some(loc) = exprs[i + 1..].iter().find_map(|expr| match expr {
+                            Expr::Annotated(_, annotation) => annotation.location().cloned(),
+                            _ => None,
+                        }) {
+                            self.warn(Warning::UnreachableCode, Some(loc));
+                        }
+                        for expr in &exprs[i + 1..] {
+                            self.visit_expr(expr, env);
+                        }
+                        break;
+                    }
+                }
+            }
+            Expr::ConstExpr(expr) => self.visit_const(expr, env),
+            Expr::Closure(closure) => {
+                for (name, _) in closure.get_captures() {
+                    self.use_symbol(name);
+                }
+                let mut new_env = env.clone();
+                for (name, mutability, ty) in closure.get_args() {
+                    let _ = new_env.define_var(name, *mutability, ty.clone());
+                }
+                let outer_bindings = std::mem::take(&mut self.bindings);
+                // The captured variables are already reported in the enclosing scope.
+                for (name, _) in closure.get_captures() {
+                    self.bind(name, None, false);
+                    self.use_symbol(name);
+                }
+                let loc = self.location_of(closure.get_body());
+                for (name, _, _) in closure.get_args() {
+                    self.bind(name, loc.clone(), true);
+                }
+                self.visit_expr(closure.get_body(), &new_env);
+                self.end_scope(0);
+                self.bindings = outer_bindings;
+            }
+
+            // Only the value a member is accessed on can use a variable, not the member's name.
+            Expr::Member(expr, _)
+            | Expr::Return(expr)
+            | Expr::UnaryOp(_, expr)
+            | Expr::Refer(_, expr)
+            | Expr::Deref(expr)
+            | Expr::Union(_, _, expr)
+            | Expr::EnumUnion(_, _, expr)
+            | Expr::As(expr, _) => self.visit_expr(expr, env),
+
+            Expr::While(a, b)
+            | Expr::BinaryOp(_, a, b)
+            | Expr::AssignOp(_, a, b)
+            | Expr::DerefMut(a, b)
+            | Expr::Index(a, b) => {
+                self.visit_expr(a, env);
+                self.visit_expr(b, env);
+            }
+            Expr::When(cond, a, b) => {
+                self.visit_const(cond, env);
+                self.visit_expr(a, env);
+                self.visit_expr(b, env);
+            }
+            Expr::Loop(_, a, b, c) | Expr::If(a, b, c) | Expr::TernaryOp(_, a, b, c) => {
+                self.visit_expr(a, env);
+                self.visit_expr(b, env);
+                self.visit_expr(c, env);
+            }
+            Expr::IfLet(pat, expr, then, otherwise) => {
+                self.visit_expr(expr, env);
+                // The pattern's bindings are only defined in the body of the `if let`.
+                let mut new_env = env.clone();
+                if let Ok(ty) = expr.get_type(env) {
+                    let _ = pat.declare_let_bind(expr, &ty, &mut new_env);
+                }
+                self.visit_pattern(pat, then, &new_env);
+                self.visit_expr(otherwise, env);
+            }
+            Expr::Match(expr, branches) => {
+                self.visit_expr(expr, env);
+                let ty = expr.get_type(env);
+                for (pat, branch) in branches {
+                    let mut new_env = env.clone();
+                    if let Ok(ty) = &ty {
+                        let _ = pat.declare_let_bind(expr, ty, &mut new_env);
+                    }
+                    self.visit_pattern(pat, branch, &new_env);
+                }
+            }
+
+            Expr::Array(exprs) | Expr::Tuple(exprs) => {
+                for expr in exprs {
+                    self.visit_expr(expr, env);
+                }
+            }
+            Expr::Struct(fields) => {
+                for expr in fields.values() {
+                    self.visit_expr(expr, env);
+                }
+            }
+            Expr::Apply(f, args) => {
+                self.visit_expr(f, env);
+                for arg in args {
+                    self.visit_expr(arg, env);
+                }
+            }
+
+            Expr::Break(_) | Expr::Continue(_) => {}
+        }
+    }
+
+    /// Visit an expression with the bindings of a pattern in scope.
+    /// Patterns often bind more than they need to, so their bindings are never reported.
+    fn visit_pattern(&mut self, pat: &Pattern, expr: &Expr, env: &Env) {
+        let start = self.bindings.len();
+        for name in pattern_names(pat) {
+            self.bindings.push(Binding {
+                name,
+                loc: None,
+                is_parameter: false,
+                used: true,
+            });
+        }
+        self.visit_expr(expr, env);
+        self.end_scope(start);
+    }
+
+    fn visit_decl(&mut self, decl: &Declaration, env: &Env) {
+        match decl {
+            Declaration::Var(name, _, _, expr) => {
+                self.visit_expr(expr, env);
+                let loc = self.location_of(expr);
+                self.bind(name, loc, false);
+            }
+            Declaration::VarPat(pat, expr) => {
+                self.visit_expr(expr, env);
+                let loc = self.location_of(expr);
+                for name in pattern_names(pat) {
+                    self.bind(&name, loc.clone(), false);
+                }
+            }
+            Declaration::Proc(_, proc) => self.visit_const(&ConstExpr::Proc(proc.clone()), env),
+            Declaration::PolyProc(_, proc) => {
+                self.visit_const(&ConstExpr::PolyProc(proc.clone()), env)
+            }
+            Declaration::Const(_, expr) | Declaration::StaticVar(_, _, _, expr) => {
+                self.visit_const(expr, env)
+            }
+            Declaration::Impl(_, consts) | Declaration::ImplTrait(_, _, consts) => {
+                for (_, expr) in consts {
+                    self.visit_const(expr, env);
+                }
+            }
+            Declaration::Many(decls) => {
+                // Variables declared together are defined in order, so
+                // each can use the ones before it.
+                for decl in decls.iter() {
+                    self.visit_decl(decl, env);
+                }
+            }
+            Declaration::Type(..) | Declaration::ExternProc(..) | Declaration::Trait(..) => {}
+        }
+    }
+
+    fn visit_const(&mut self, expr: &ConstExpr, env: &Env) {
+        match expr {
+            ConstExpr::Annotated(expr, annotation) => {
+                let outer_loc = self.loc.clone();
+                if let Some(loc) = annotation.location() {
+                    self.loc = Some(loc.clone());
+                }
+                self.visit_const(expr, env);
+                self.loc = outer_loc;
+            }
+            ConstExpr::Symbol(name) => self.use_symbol(name),
+            ConstExpr::Proc(proc) => {
+                if let Ok(scope) = proc.get_body_scope(env) {
+                    self.visit_proc(proc.get_args(), proc.get_body(), &scope);
+                }
+            }
+            ConstExpr::PolyProc(proc) => {
+                if let Ok(scope) = proc.get_body_scope(env) {
+                    self.visit_proc(proc.get_args(), proc.get_body(), &scope);
+                }
+            }
+            ConstExpr::Declare(decl, body) => {
+                let mut new_env = env.clone();
+                let _ = new_env.add_compile_time_declaration(decl);
+                self.visit_decl(decl, &new_env);
+                self.visit_const(body, &new_env);
+            }
+            ConstExpr::TypeOf(expr) | ConstExpr::SizeOfExpr(expr) => self.visit_expr(expr, env),
+            ConstExpr::Tuple(exprs) | ConstExpr::Array(exprs) => {
+                for expr in exprs {
+                    self.visit_const(expr, env);
+                }
+            }
+            ConstExpr::Struct(fields) => {
+                for expr in fields.values() {
+                    self.visit_const(expr, env);
+                }
+            }
+            ConstExpr::Union(_, _, expr)
+            | ConstExpr::EnumUnion(_, _, expr)
+            | ConstExpr::Member(expr, _)
+            | ConstExpr::As(expr, _)
+            | ConstExpr::Monomorphize(expr, _)
+            | ConstExpr::Template(_, expr) => self.visit_const(expr, env),
+            _ => {}
+        }
+    }
+}
+
+/// Get the names of the variables bound by a pattern.
+fn pattern_names(pat: &Pattern) -> Vec<String> {
+    match pat {
+        Pattern::Symbol(_, name) => vec![name.clone()],
+        Pattern::Tuple(pats) | Pattern::Alt(pats) => pats.iter().flat_map(pattern_names).collect(),
+        Pattern::Struct(fields) => fields.values().flat_map(pattern_names).collect(),
+        Pattern::Variant(_, Some(pat)) | Pattern::Pointer(pat) => pattern_names(pat),
+        Pattern::Variant(_, None) | Pattern::ConstExpr(_) | Pattern::Wildcard => vec![],
+    }
+}
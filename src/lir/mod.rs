@@ -70,6 +70,7 @@ mod compile;
 mod env;
 mod error;
 mod expr;
+mod lint;
 mod types;
 
 pub use annotate::*;
@@ -77,6 +78,7 @@ pub use compile::*;
 pub use env::*;
 pub use error::*;
 pub use expr::*;
+pub use lint::*;
 pub use types::*;
 
 /// Simplify an expression while maintaining structural equality.
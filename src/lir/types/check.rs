@@ -38,6 +38,8 @@ impl TypeCheck for Type {
             | Self::Float
             | Self::Bool
             | Self::Char
+            | Self::Str
+            | Self::String
             | Self::Enum(_) => Ok(()),
 
             Self::Type(t) => t.type_check(env),
@@ -189,8 +191,8 @@ impl TypeCheck for Type {
                 Ok(())
             }
 
-            // Pointers are sound if their inner type is sound.
-            Self::Pointer(_, t) => t.type_check(env),
+            // Pointers and slices are sound if their element type is sound.
+            Self::Pointer(_, t) | Self::Slice(_, t) => t.type_check(env),
         }
     }
 }
@@ -204,20 +206,37 @@ impl TypeCheck for Expr {
 
         match self {
             Self::Annotated(expr, metadata) => {
+                // Declarations are annotated with the locations of the symbols they define.
+                let definitions = metadata.definitions();
+                let mut new_env;
+                let env = if definitions.is_empty() {
+                    env
+                } else {
+                    new_env = env.clone();
+                    for (name, location) in definitions {
+                        new_env.define_location(name, location.clone());
+                    }
+                    &new_env
+                };
                 // Check the inner expression.
                 expr.type_check(env)
-                    .map_err(|e| e.annotate(metadata.clone()))
+                    .map_err(|e| e.annotate(metadata.clone()).with_related_definitions(env))
             }
 
             Self::Declare(declaration, body) => {
                 // Create a new environment with the declarations defined.
                 let mut new_env = env.clone();
                 // Check the declaration.
-                declaration.type_check(&new_env)?;
+                let declaration_result = declaration.type_check(&new_env);
                 // Add the declarations to the environment.
-                new_env.add_declaration(declaration)?;
-                // Check the body with the declarations defined.
-                body.type_check(&new_env)
+                if let Err(e) = new_env.add_declaration(declaration) {
+                    // The body can't be checked without the declarations,
+                    // so report whatever went wrong with them.
+                    return declaration_result.and(Err(e));
+                }
+                // Check the body with the declarations defined, even if the
+                // declarations had errors, so that those are reported too.
+                Error::combine([declaration_result, body.type_check(&new_env)])
             }
 
             Self::UnaryOp(unop, expr) => {
@@ -388,9 +407,9 @@ impl TypeCheck for Expr {
                 */
 
                 let count = exprs.len();
-                exprs.into_par_iter()
+                let results = exprs.into_par_iter()
                     .enumerate()
-                    .try_for_each(|(i, expr)| {
+                    .map(|(i, expr)| {
                         expr.type_check(env)?;
                         if i < count - 1 {
                             // If it's not the last expression, confirm that it's of type `None`.
@@ -403,17 +422,39 @@ impl TypeCheck for Expr {
                             }
                         }
                         Ok(())
-                    })?;
+                    })
+                    .collect::<Vec<_>>();
 
                 // Return success if all the expressions are sound.
-                Ok(())
+                // Otherwise, report the errors in each of them.
+                Error::combine(results)
             }
 
             Self::While(cond, body) => {
                 // Typecheck the condition.
                 cond.type_check(env)?;
-                // Typecheck the body.
-                body.type_check(env)
+                // Typecheck the body inside of the loop.
+                let mut loop_env = env.clone();
+                loop_env.push_loop(None, None);
+                body.type_check(&loop_env)
+            }
+
+            Self::Loop(label, cond, body, step) => {
+                // Typecheck the condition.
+                cond.type_check(env)?;
+                // Typecheck the body and the step inside of the loop.
+                let mut loop_env = env.clone();
+                loop_env.push_loop(label.clone(), None);
+                body.type_check(&loop_env)?;
+                step.type_check(&loop_env)
+            }
+
+            // Make sure that a `break` or `continue` has a loop to exit.
+            Self::Break(label) | Self::Continue(label) => {
+                if env.get_loop(label.as_deref()).is_none() {
+                    return Err(Error::InvalidLoopExit(self.clone()));
+                }
+                Ok(())
             }
 
             Self::If(cond, t, e) => {
@@ -601,6 +642,8 @@ impl TypeCheck for Expr {
                     debug!("Transformed method call: {method_call}");
 
                     if let Self::Apply(f, args) = method_call.clone() {
+                        // Coerce the supplied arguments to the types of the parameters.
+                        let args = Self::coerce_args(&f, args, env)?;
                         // Typecheck the supplied arguments.
                         for arg in &args {
                             arg.type_check(env)?;
@@ -655,6 +698,8 @@ impl TypeCheck for Expr {
 
                 // Typecheck the expression we want to call as a procedure.
                 f.type_check(env)?;
+                // Coerce the supplied arguments to the types of the parameters.
+                let args = &Self::coerce_args(f, args.clone(), env)?;
                 // Typecheck the supplied arguments.
                 for arg in args {
                     arg.type_check(env)?;
@@ -702,6 +747,9 @@ impl TypeCheck for Expr {
                 }
             }
 
+            // Typecheck a closure.
+            Self::Closure(closure) => closure.type_check(env),
+
             // Typecheck a return statement.
             Self::Return(e) => {
                 e.type_check(env)?;
@@ -872,9 +920,13 @@ impl TypeCheck for Expr {
                 let val_type = val.get_type(env)?;
                 // Get the type of the index.
                 let idx_type = idx.get_type(env)?;
-                // Confirm that the type is an array or pointer.
+                // Confirm that the type is an array, pointer, slice, or string.
                 match val_type {
-                    Type::Array(_, _) | Type::Pointer(_, _) => {}
+                    Type::Array(_, _)
+                    | Type::Pointer(_, _)
+                    | Type::Slice(_, _)
+                    | Type::Str
+                    | Type::String => {}
                     // If it isn't, return an error.
                     _ => return Err(Error::InvalidIndex(self.clone())),
                 }
@@ -955,6 +1007,7 @@ impl TypeCheck for ConstExpr {
             | Self::Int(_)
             | Self::Float(_)
             | Self::Char(_)
+            | Self::Str(_)
             | Self::Bool(_) => Ok(()),
 
             Self::SizeOfType(t) => t.type_check(env),
@@ -1276,3 +1329,78 @@ impl TypeCheck for ConstExpr {
         Ok(())
     }
 }
+
+/// Check the method signatures of a trait declaration.
+impl TypeCheck for Trait {
+    fn type_check(&self, env: &Env) -> Result<(), Error> {
+        trace!("Type checking trait: {self}");
+        // The implementing type is unknown, so `Self` is an opaque type in the signatures.
+        let mut new_env = env.clone();
+        new_env.define_type(
+            Trait::SELF,
+            Type::Unit(Trait::SELF.to_string(), Box::new(Type::None)),
+        );
+
+        for (_name, method_ty) in self.get_methods() {
+            if !matches!(method_ty, Type::Proc(..)) {
+                return Err(Error::MismatchedTypes {
+                    expected: Type::Proc(vec![], Box::new(Type::Any)),
+                    found: method_ty.clone(),
+                    expr: Expr::NONE,
+                });
+            }
+            method_ty.type_check(&new_env)?;
+        }
+        Ok(())
+    }
+}
+
+impl Trait {
+    /// Check that an implementation of this trait for a type defines
+    /// every method of the trait, with the signature the trait expects.
+    pub fn check_impl(
+        &self,
+        ty: &Type,
+        impls: &[(String, ConstExpr)],
+        env: &Env,
+    ) -> Result<(), Error> {
+        let mut new_env = env.clone();
+        if let Type::Apply(_template, supplied_params) = ty {
+            // The parameters of a template implementation are opaque in the signatures.
+            new_env.define_types(
+                supplied_params
+                    .iter()
+                    .filter_map(|param| match param {
+                        Type::Symbol(sym) => {
+                            Some((sym.clone(), Type::Unit(sym.clone(), Box::new(Type::None))))
+                        }
+                        _ => None,
+                    })
+                    .collect(),
+            );
+        }
+
+        for (name, expected) in self.get_methods_for(ty) {
+            let method = impls
+                .iter()
+                .find(|(method_name, _)| method_name == &name)
+                .map(|(_, method)| method)
+                .ok_or_else(|| Error::MissingTraitMethod {
+                    trait_name: self.get_name().to_string(),
+                    ty: ty.clone(),
+                    method: name.clone(),
+                })?;
+
+            let found = method.get_type(&new_env)?;
+            if !found.equals(&expected, &new_env)? {
+                error!("Mismatched types: expected {expected}, found {found} for method {name} of trait {}", self.get_name());
+                return Err(Error::MismatchedTypes {
+                    expected,
+                    found,
+                    expr: Expr::ConstExpr(method.clone()),
+                });
+            }
+        }
+        Ok(())
+    }
+}
@@ -164,25 +164,32 @@ impl GetType for Expr {
             Self::As(_, t) => t.clone(),
 
             // A while loop returns the None value.
-            Self::While(cond, _) => {
+            Self::While(cond, _) | Self::Loop(_, cond, _, _) => {
                 let mut cond = *cond.clone();
                 while let Expr::Annotated(expr, _) = cond {
                     cond = *expr;
                 }
 
-                match cond {
-                    Self::ConstExpr(ConstExpr::Bool(true)) => Type::Never,
+                let is_infinite = match cond {
+                    Self::ConstExpr(ConstExpr::Bool(true)) => true,
                     Self::ConstExpr(ref c) => {
-                        if let Ok(true) = c.clone().simplify(env)?.as_bool(env) {
-                            Type::Never
-                        } else {
-                            Type::None
-                        }
+                        matches!(c.clone().simplify(env)?.as_bool(env), Ok(true))
                     }
-                    _ => Type::None,
+                    _ => false,
+                };
+
+                // An infinite loop never finishes, unless it's exited with a `break`
+                // (or with a `continue` to an outer loop).
+                if is_infinite && !self.can_exit_loop() {
+                    Type::Never
+                } else {
+                    Type::None
                 }
             }
 
+            // Exiting a loop never returns a value.
+            Self::Break(_) | Self::Continue(_) => Type::Never,
+
             // An if statement returns the type of the expression
             // that is evaluated if the condition is true (which must
             // be type-equal with the else branch).
@@ -227,6 +234,8 @@ impl GetType for Expr {
             // The type of a return is "Never" - it never allows
             // the following instructions to be executed.
             Self::Return(_) => Type::Never,
+            // Get the type of a closure.
+            Self::Closure(closure) => closure.get_type_checked(env, i)?,
             // Get the type of a procedure call.
             Self::Apply(func, _) => {
                 // Get the type of the function.
@@ -302,10 +311,14 @@ impl GetType for Expr {
                         env.get_type_of_associated_const(&ty, &as_symbol?)
                             .ok_or(Error::MemberNotFound(*val.clone(), field.clone()))?
                     }
-                    Type::Unit(_unit_name, inner_ty) => {
+                    Type::Unit(unit_name, inner_ty) => {
                         // Get the associated constant expression's type.
-                        env.get_type_of_associated_const(&inner_ty, &as_symbol?)
-                            .ok_or(Error::MemberNotFound(*val.clone(), field.clone()))?
+                        // This falls back on the inner type if the unit type doesn't define it.
+                        env.get_type_of_associated_const(
+                            &Type::Unit(unit_name, inner_ty),
+                            &as_symbol?,
+                        )
+                        .ok_or(Error::MemberNotFound(*val.clone(), field.clone()))?
                     }
                     Type::Pointer(found_mutability, t) => {
                         match t.get_member_offset(field, val, env) {
@@ -397,9 +410,11 @@ impl GetType for Expr {
 
             // Get the type of an index access.
             Self::Index(val, _) => match val.get_type_checked(env, i)?.simplify(env)? {
-                // Only arrays and pointers can be indexed.
+                // Only arrays, pointers, slices, and strings can be indexed.
                 Type::Array(item, _) => *item,
-                Type::Pointer(_, item) => *item,
+                Type::Pointer(_, item) | Type::Slice(_, item) => *item,
+                // Indexing a string gets one of its bytes.
+                Type::Str | Type::String => Type::Char,
 
                 // If we're accessing an index of a type that is not an array or pointer,
                 // we cannot access an index.
@@ -432,6 +447,12 @@ impl GetType for Expr {
                 cond.substitute(name, ty);
                 body.substitute(name, ty)
             }
+            Self::Loop(_, cond, body, step) => {
+                cond.substitute(name, ty);
+                body.substitute(name, ty);
+                step.substitute(name, ty)
+            }
+            Self::Break(_) | Self::Continue(_) => {}
 
             Self::Many(exprs) => {
                 // for expr in exprs.iter_mut() {
@@ -499,6 +520,8 @@ impl GetType for Expr {
 
             Self::Return(expr) => expr.substitute(name, ty),
 
+            Self::Closure(closure) => closure.substitute(name, ty),
+
             Self::Array(exprs) | Self::Tuple(exprs) => {
                 // for expr in exprs.iter_mut() {
                 //     expr.substitute(name, ty);
@@ -12,9 +12,11 @@ use std::sync::Mutex;
 mod check;
 mod inference;
 mod size;
+mod traits;
 pub use check::*;
 pub use inference::*;
 pub use size::*;
+pub use traits::*;
 
 use log::*;
 
@@ -144,6 +146,11 @@ pub enum Type {
     Char,
     /// The type of a boolean value.
     Bool,
+    /// A string slice: a pointer to UTF-8 encoded bytes, and the number of bytes.
+    /// String literals have this type.
+    Str,
+    /// An owned, growable string of UTF-8 encoded bytes stored on the heap.
+    String,
     /// An enumeration of a list of possible named values.
     /// A boolean could be considered an enumeration of `true` and `false`.
     Enum(Vec<String>),
@@ -175,9 +182,14 @@ pub enum Type {
     /// This is a sum type.
     Union(BTreeMap<String, Self>),
     /// A procedure with a list of parameters and a return type.
+    /// A procedure's value is a pointer to the environment captured by the procedure,
+    /// which is `NULL` unless it's a closure, paired with the procedure's address.
     Proc(Vec<Type>, Box<Type>),
     /// A pointer to another type.
     Pointer(Mutability, Box<Self>),
+    /// A slice of elements of another type.
+    /// A slice's value is a pointer to its first element paired with its number of elements.
+    Slice(Mutability, Box<Self>),
     /// A type reserved by the compiler.
     /// This type is equal to any other type.
     /// The NULL pointer, for example, is of type `Pointer(Any)`.
@@ -210,6 +222,57 @@ impl Type {
     /// This is the maximum number of times a type will be simplified recursively.
     pub const SIMPLIFY_RECURSION_LIMIT: usize = 30;
 
+    /// The names of the built-in types which are defined in every environment.
+    pub const BUILTIN_TYPES: [(&'static str, Self); 2] =
+        [("Str", Self::Str), ("String", Self::String)];
+
+    /// The memory layout of a string slice.
+    ///
+    /// The bytes of a string slice are always followed by a null terminator,
+    /// so `data` can also be used as a C-style string.
+    pub fn str_layout() -> Self {
+        Self::Struct(BTreeMap::from([
+            (
+                "data".to_string(),
+                Self::Pointer(Mutability::Immutable, Box::new(Self::Char)),
+            ),
+            ("length".to_string(), Self::Int),
+        ]))
+    }
+
+    /// The memory layout of an owned string.
+    pub fn string_layout() -> Self {
+        Self::Struct(BTreeMap::from([
+            ("capacity".to_string(), Self::Int),
+            (
+                "data".to_string(),
+                Self::Pointer(Mutability::Mutable, Box::new(Self::Char)),
+            ),
+            ("length".to_string(), Self::Int),
+        ]))
+    }
+
+    /// The memory layout of a slice of elements.
+    pub fn slice_layout(mutability: Mutability, elem: Self) -> Self {
+        Self::Struct(BTreeMap::from([
+            (
+                "data".to_string(),
+                Self::Pointer(mutability, Box::new(elem)),
+            ),
+            ("len".to_string(), Self::Int),
+        ]))
+    }
+
+    /// Get the struct which describes the members of a built-in type, if it has any.
+    pub fn builtin_layout(&self) -> Option<Self> {
+        match self {
+            Self::Str => Some(Self::str_layout()),
+            Self::String => Some(Self::string_layout()),
+            Self::Slice(mutability, elem) => Some(Self::slice_layout(*mutability, *elem.clone())),
+            _ => None,
+        }
+    }
+
     pub fn is_recursive(&self, env: &Env) -> Result<bool, Error> {
         let mut symbols = HashSet::new();
         self.is_recursive_helper(&mut symbols, env)
@@ -279,7 +342,7 @@ impl Type {
                 ret.is_recursive_helper(symbols, env)
             }
 
-            Self::Pointer(_, t) => t.is_recursive_helper(symbols, env),
+            Self::Pointer(_, t) | Self::Slice(_, t) => t.is_recursive_helper(symbols, env),
             Self::Unit(_, t) => t.is_recursive_helper(symbols, env),
             Self::Type(t) => t.is_recursive_helper(symbols, env),
             Self::Array(t, _) => t.is_recursive_helper(symbols, env),
@@ -322,6 +385,8 @@ impl Type {
             | Self::Cell
             | Self::Char
             | Self::Bool
+            | Self::Str
+            | Self::String
             | Self::Any
             | Self::Never => Ok(false),
         };
@@ -425,7 +490,8 @@ impl Type {
                 inner1.get_monomorph_template_args(inner2, matched_symbols, param_symbols, env)?;
             }
 
-            (Self::Pointer(_, inner1), Self::Pointer(_, inner2)) => {
+            (Self::Pointer(_, inner1), Self::Pointer(_, inner2))
+            | (Self::Slice(_, inner1), Self::Slice(_, inner2)) => {
                 inner1.get_monomorph_template_args(inner2, matched_symbols, param_symbols, env)?;
             }
 
@@ -571,6 +637,7 @@ impl Type {
 
             Self::Array(inner, _)
             | Self::Pointer(_, inner)
+            | Self::Slice(_, inner)
             | Self::Type(inner)
             | Self::Unit(_, inner) => {
                 inner.add_monomorphized_associated_consts(env)?;
@@ -601,6 +668,8 @@ impl Type {
             | Self::Cell
             | Self::Char
             | Self::Bool
+            | Self::Str
+            | Self::String
             | Self::Enum(_) => {}
         }
         Ok(())
@@ -661,6 +730,8 @@ impl Type {
             | Self::Cell
             | Self::Char
             | Self::Bool
+            | Self::Str
+            | Self::String
             | Self::Any
             | Self::Never
             | Self::Enum(_)
@@ -669,7 +740,7 @@ impl Type {
             Self::Tuple(inner) => inner.iter().all(|t| t.is_simple()),
             Self::Array(inner, expr) => inner.is_simple() && matches!(**expr, ConstExpr::Int(_)),
             Self::Proc(args, ret) => args.iter().all(|t| t.is_simple()) && ret.is_simple(),
-            Self::Pointer(_, inner) => inner.is_simple(),
+            Self::Pointer(_, inner) | Self::Slice(_, inner) => inner.is_simple(),
             Self::Struct(inner) | Self::Union(inner) | Self::EnumUnion(inner) => {
                 inner.iter().all(|(_, t)| t.is_simple())
             }
@@ -708,6 +779,8 @@ impl Type {
             | Self::Cell
             | Self::Char
             | Self::Bool
+            | Self::Str
+            | Self::String
             | Self::Any
             | Self::Never
             | Self::Enum(_)
@@ -719,7 +792,8 @@ impl Type {
             | Self::Unit(_, _)
             | Self::Type(_)
             | Self::Array(_, _)
-            | Self::Pointer(_, _) => true,
+            | Self::Pointer(_, _)
+            | Self::Slice(_, _) => true,
         }
     }
 
@@ -732,6 +806,8 @@ impl Type {
             | Self::Cell
             | Self::Char
             | Self::Bool
+            | Self::Str
+            | Self::String
             | Self::Any
             | Self::Never
             | Self::Enum(_)
@@ -740,7 +816,7 @@ impl Type {
             Self::Tuple(inner) => inner.iter().all(|t| t.is_atomic()),
             Self::Array(inner, expr) => inner.is_atomic() && matches!(**expr, ConstExpr::Int(_)),
             Self::Proc(args, ret) => args.iter().all(|t| t.is_atomic()) && ret.is_atomic(),
-            Self::Pointer(_, inner) => inner.is_atomic(),
+            Self::Pointer(_, inner) | Self::Slice(_, inner) => inner.is_atomic(),
             Self::Struct(inner) => inner.iter().all(|(_, t)| t.is_atomic()),
             Self::EnumUnion(inner) => inner.iter().all(|(_, t)| t.is_atomic()),
             // Self::Poly(_, _) | Self::Symbol(_) | Self::Apply(_, _) => false,
@@ -796,7 +872,14 @@ impl Type {
     fn possibly_has_members(&self) -> bool {
         if matches!(
             self,
-            Self::Tuple(_) | Self::Struct(_) | Self::Union(_) | Self::Pointer(_, _) | Self::Type(_)
+            Self::Tuple(_)
+                | Self::Struct(_)
+                | Self::Union(_)
+                | Self::Pointer(_, _)
+                | Self::Slice(_, _)
+                | Self::Type(_)
+                | Self::Str
+                | Self::String
         ) {
             return true;
         }
@@ -998,6 +1081,8 @@ impl Type {
             | Self::Cell
             | Self::Char
             | Self::Bool
+            | Self::Str
+            | Self::String
             | Self::Enum(_) => false,
 
             Self::Tuple(items) => items.iter().any(|t| t.contains_symbol(name)),
@@ -1009,7 +1094,7 @@ impl Type {
             Self::Proc(params, ret) => {
                 params.iter().any(|t| t.contains_symbol(name)) || ret.contains_symbol(name)
             }
-            Self::Pointer(_, t) => t.contains_symbol(name),
+            Self::Pointer(_, t) | Self::Slice(_, t) => t.contains_symbol(name),
         }
     }
 
@@ -1072,6 +1157,8 @@ impl Type {
             | Self::Cell
             | Self::Char
             | Self::Bool
+            | Self::Str
+            | Self::String
             | Self::Enum(_) => self.clone(),
             Self::Tuple(items) => Self::Tuple(
                 items
@@ -1116,6 +1203,9 @@ impl Type {
             Self::Pointer(mutability, ptr) => {
                 Self::Pointer(*mutability, Box::new(ptr.substitute(name, substitution)))
             }
+            Self::Slice(mutability, elem) => {
+                Self::Slice(*mutability, Box::new(elem.substitute(name, substitution)))
+            }
         }
     }
 
@@ -1196,6 +1286,13 @@ impl Type {
                 Ok(false)
             }
 
+            // Can we decay a slice to a slice?
+            (
+                Self::Slice(found_mutability, found_elem_ty),
+                Self::Slice(desired_mutability, desired_elem_ty),
+            ) => Ok(found_mutability.can_decay_to(desired_mutability)
+                && found_elem_ty.equals(desired_elem_ty, env)?),
+
             // Can a tuple decay to another tuple?
             (Self::Tuple(found_fields), Self::Tuple(desired_fields)) => {
                 // If the tuples have different numbers of fields, then we can't decay.
@@ -1348,6 +1445,25 @@ impl Type {
             (Self::Cell, Self::Char) | (Self::Char, Self::Cell) => Ok(true),
             (Self::Cell, Self::Bool) | (Self::Bool, Self::Cell) => Ok(true),
 
+            // Strings and slices can be cast to and from the structs describing their members.
+            (Self::Str | Self::String | Self::Slice(_, _), Self::Struct(_)) => {
+                other.equals(&self.builtin_layout().unwrap(), env)
+            }
+            (Self::Struct(_), Self::Str | Self::String | Self::Slice(_, _)) => {
+                self.equals(&other.builtin_layout().unwrap(), env)
+            }
+
+            // A pointer to an array can be cast to a slice of the whole array.
+            (Self::Pointer(found, array), Self::Slice(desired, elem)) => {
+                match array.clone().simplify_until_concrete(env)? {
+                    Self::Array(array_elem, _) => {
+                        Ok(found.can_decay_to(desired) && array_elem.equals(elem, env)?)
+                    }
+                    _ => Ok(false),
+                }
+            }
+            (Self::Slice(found, _), Self::Slice(desired, _)) => Ok(found.can_decay_to(desired)),
+
             (Self::Pointer(_, _), Self::Cell) => Ok(true),
             (Self::Pointer(found, _), Self::Pointer(desired, _)) => Ok(found.can_decay_to(desired)),
 
@@ -1478,6 +1594,10 @@ impl Type {
                 mutability,
                 Box::new(inner.perform_template_applications(env, previous_applications)?),
             ),
+            Self::Slice(mutability, elem) => Self::Slice(
+                mutability,
+                Box::new(elem.perform_template_applications(env, previous_applications)?),
+            ),
             Self::Proc(args, ret) => Self::Proc(
                 args.into_iter()
                     .map(|t| t.perform_template_applications(env, previous_applications))
@@ -1567,6 +1687,8 @@ impl Type {
             | (Self::None, Self::None)
             | (Self::Bool, Self::Bool)
             | (Self::Char, Self::Char)
+            | (Self::Str, Self::Str)
+            | (Self::String, Self::String)
             | (Self::Int, Self::Int)
             | (Self::Float, Self::Float)
             | (Self::Cell, Self::Cell) => true,
@@ -1735,7 +1857,8 @@ impl Type {
                 ret1.equals_checked(ret2, compared_symbols, env, i)?
             }
 
-            (Self::Pointer(m1, t1), Self::Pointer(m2, t2)) => {
+            (Self::Pointer(m1, t1), Self::Pointer(m2, t2))
+            | (Self::Slice(m1, t1), Self::Slice(m2, t2)) => {
                 m1 == m2 && t1.equals_checked(t2, compared_symbols, env, i)?
             }
 
@@ -1885,6 +2008,10 @@ impl Type {
             }
 
             Type::Unit(_unit_name, t) => t.get_member_offset(member, expr, env),
+            Type::Str | Type::String | Type::Slice(_, _) => self
+                .builtin_layout()
+                .unwrap()
+                .get_member_offset(member, expr, env),
 
             Type::Apply(_, _) | Type::Poly(_, _) => {
                 let t = self.simplify_until_concrete(env)?;
@@ -1983,6 +2110,24 @@ impl Type {
 
             Type::Unit(_unit_name, t) => t.type_check_member(member, expr, env),
 
+            Type::Str | Type::String | Type::Slice(_, _) => {
+                // Strings and slices have the members of their layout, and any associated constants.
+                if let Some(Type::Struct(members)) = self.builtin_layout() {
+                    if members
+                        .keys()
+                        .any(|k| &ConstExpr::Symbol(k.clone()) == member)
+                    {
+                        return Ok(());
+                    }
+                }
+                let name = member.clone().as_symbol(env)?;
+                if env.has_associated_const(self, &name) {
+                    Ok(())
+                } else {
+                    Err(Error::MemberNotFound(expr.clone(), member.clone()))
+                }
+            }
+
             Type::Symbol(name) => {
                 if let Some(t) = env.get_type(name) {
                     t.type_check_member(member, expr, env)
@@ -2042,12 +2187,17 @@ impl Simplify for Type {
             | Self::Float
             | Self::Char
             | Self::Bool
+            | Self::Str
+            | Self::String
             | Self::Cell
             | Self::Enum(_)
             | Self::Poly(_, _) => self.clone(),
             Self::Pointer(mutability, inner) => {
                 Self::Pointer(mutability, Box::new(inner.simplify_checked(env, i)?))
             }
+            Self::Slice(mutability, elem) => {
+                Self::Slice(mutability, Box::new(elem.simplify_checked(env, i)?))
+            }
 
             Self::Let(name, t, ret) => {
                 // Is the bound type recursive?
@@ -2159,8 +2309,17 @@ impl fmt::Display for Type {
                 }
                 write!(f, "{ty}")
             }
+            Self::Slice(mutability, elem) => {
+                write!(f, "&")?;
+                if mutability.is_mutable() {
+                    write!(f, "mut ")?;
+                }
+                write!(f, "[{elem}]")
+            }
             Self::Bool => write!(f, "Bool"),
             Self::Char => write!(f, "Char"),
+            Self::Str => write!(f, "Str"),
+            Self::String => write!(f, "String"),
             Self::Cell => write!(f, "Cell"),
             Self::Int => write!(f, "Int"),
             Self::Float => write!(f, "Float"),
@@ -2354,6 +2513,17 @@ impl std::hash::Hash for Type {
                 state.write_u8(21);
                 t.hash(state);
             }
+            Self::Str => {
+                state.write_u8(23);
+            }
+            Self::String => {
+                state.write_u8(24);
+            }
+            Self::Slice(m, elem) => {
+                state.write_u8(25);
+                m.hash(state);
+                elem.hash(state);
+            }
         }
     }
 }
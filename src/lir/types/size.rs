@@ -102,8 +102,15 @@ impl GetSize for Type {
             | Self::Bool
             | Self::Cell
             | Self::Enum(_)
-            | Self::Pointer(_, _)
-            | Self::Proc(_, _) => 1,
+            | Self::Pointer(_, _) => 1,
+
+            // Procedures are a pointer to the environment they captured, paired with their address.
+            Self::Proc(_, _) => 2,
+
+            // Strings and slices are laid out like the structs describing their members.
+            Self::Str | Self::String | Self::Slice(_, _) => {
+                self.builtin_layout().unwrap().get_size_checked(env, i)?
+            }
 
             // Tuple types are the sum of the sizes of their elements.
             Self::Tuple(items) => items
@@ -0,0 +1,73 @@
+//! # Traits
+//!
+//! A trait is a named collection of method signatures which types can implement.
+//! Type parameters of polymorphic procedures can be bounded by traits, so that
+//! the procedure can call the trait's methods on values of the parameter's type.
+use super::Type;
+use core::fmt;
+
+/// A trait declaration: a list of methods that an implementing type must provide.
+///
+/// The method signatures refer to the implementing type as `Self`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Trait {
+    /// The name of the trait. Implementations are registered under this name,
+    /// even when the trait is bound under another name in the environment.
+    name: String,
+    /// The names and types of the methods of the trait.
+    methods: Vec<(String, Type)>,
+}
+
+impl Trait {
+    /// The name used for the implementing type in the method signatures.
+    pub const SELF: &'static str = "Self";
+
+    /// Create a new trait with the given name and method signatures.
+    pub fn new(name: impl ToString, methods: Vec<(String, Type)>) -> Self {
+        Self {
+            name: name.to_string(),
+            methods,
+        }
+    }
+
+    /// Get the name of the trait.
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the method signatures of the trait, with `Self` unsubstituted.
+    pub fn get_methods(&self) -> &[(String, Type)] {
+        &self.methods
+    }
+
+    /// Get the method signatures of the trait for a given implementing type.
+    pub fn get_methods_for(&self, ty: &Type) -> Vec<(String, Type)> {
+        self.methods
+            .iter()
+            .map(|(name, method_ty)| (name.clone(), method_ty.substitute(Self::SELF, ty)))
+            .collect()
+    }
+
+    /// Substitute a type for a symbol in the method signatures of the trait.
+    pub fn substitute(&mut self, name: &str, ty: &Type) {
+        if name == Self::SELF {
+            return;
+        }
+        for (_, method_ty) in &mut self.methods {
+            *method_ty = method_ty.substitute(name, ty);
+        }
+    }
+}
+
+impl fmt::Display for Trait {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "trait {} {{", self.name)?;
+        for (i, (name, ty)) in self.methods.iter().enumerate() {
+            write!(f, "{name}: {ty}")?;
+            if i < self.methods.len() - 1 {
+                write!(f, ", ")?;
+            }
+        }
+        write!(f, "}}")
+    }
+}
@@ -0,0 +1,299 @@
+//! # Document
+//!
+//! A document is a frontend source file opened in the editor. Every time the
+//! document changes, it's parsed and type checked again, and the results are
+//! kept around to answer the editor's requests about the document.
+//!
+//! The comments in a document are blanked out with spaces before it's analyzed,
+//! rather than removed, so the locations the compiler reports for the code are
+//! the same as the positions in the editor.
+use super::protocol::{
+    column_to_position, location_to_range, offset_to_position, position_to_offset,
+};
+use crate::frontend::{self, SymbolDefinition, SymbolKind};
+use crate::lir::{Env, Expr, GetType, Type, TypeCheck};
+use crate::parse::blank_comments;
+use serde_json::{json, Value};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// The severity of an error in an LSP diagnostic.
+const SEVERITY_ERROR: i64 = 1;
+/// The severity of a warning in an LSP diagnostic.
+const SEVERITY_WARNING: i64 = 2;
+
+/// An open frontend source file, and the results of analyzing it.
+pub struct Document {
+    /// The URI of the document.
+    uri: String,
+    /// The path of the document, used as the filename for source code locations.
+    path: Option<String>,
+    /// The text of the document, as the editor has it.
+    text: String,
+    /// The code of the document, with comments blanked out.
+    code: String,
+    /// The parsed program, if the document could be parsed.
+    expr: Option<Expr>,
+    /// The symbols defined in the document.
+    definitions: Vec<SymbolDefinition>,
+    /// The diagnostics for the document.
+    diagnostics: Vec<Value>,
+}
+
+impl Document {
+    /// Open a document with the given URI and text, and analyze it.
+    pub fn new(uri: impl ToString, path: Option<String>, text: &str) -> Self {
+        let mut doc = Self {
+            uri: uri.to_string(),
+            path,
+            text: String::new(),
+            code: String::new(),
+            expr: None,
+            definitions: vec![],
+            diagnostics: vec![],
+        };
+        doc.update(text);
+        doc
+    }
+
+    /// Replace the text of the document, and analyze it again.
+    pub fn update(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.code = blank_comments(text);
+        self.expr = None;
+        self.definitions =
+            frontend::definitions(&self.code, self.path.as_deref()).unwrap_or_default();
+
+        // The compiler may panic on code it doesn't expect, which shouldn't bring down
+        // the whole server.
+        let result = catch_unwind(AssertUnwindSafe(|| self.analyze()));
+        self.diagnostics = match result {
+            Ok(diagnostics) => diagnostics,
+            Err(_) => vec![self.diagnostic(None, "internal compiler error".to_string())],
+        };
+    }
+
+    /// Parse and type check the document, returning the diagnostics for it.
+    fn analyze(&mut self) -> Vec<Value> {
+        let expr = match frontend::parse(&self.code, self.path.as_deref()) {
+            Ok(expr) => expr,
+            Err(e) => return vec![self.parse_diagnostic(&e)],
+        };
+
+        let result = expr.type_check(&Env::default());
+        let mut warnings =
+            frontend::unused_imports(&self.code, self.path.as_deref()).unwrap_or_default();
+        warnings.extend(expr.lint(&Env::default(), self.path.as_deref()));
+        self.expr = Some(expr);
+
+        let mut diagnostics = match result {
+            Ok(()) => vec![],
+            // Report every error found by the type checker.
+            Err(e) => e
+                .into_errors()
+                .into_iter()
+                .map(|e| self.type_diagnostic(&e))
+                .collect(),
+        };
+        for warning in warnings {
+            if let Some(loc) = warning.location() {
+                let mut diagnostic = self.diagnostic(
+                    Some(location_to_range(&self.text, &self.code, loc)),
+                    warning.to_string(),
+                );
+                diagnostic["severity"] = json!(SEVERITY_WARNING);
+                diagnostics.push(diagnostic);
+            }
+        }
+        diagnostics
+    }
+
+    /// Create a diagnostic from a type checking error.
+    fn type_diagnostic(&self, err: &crate::lir::Error) -> Value {
+        let loc = match err {
+            crate::lir::Error::Annotated(_, annotation) => annotation.location().cloned(),
+            _ => None,
+        };
+        match loc {
+            // The error is in this document.
+            Some(loc) if loc.filename == self.path => self.diagnostic(
+                Some(location_to_range(&self.text, &self.code, &loc)),
+                err.to_string(),
+            ),
+            // The error is in an imported module, so report it at the start of the document.
+            Some(loc) => self.diagnostic(None, format!("{loc}: {err}")),
+            None => self.diagnostic(None, err.to_string()),
+        }
+    }
+
+    /// Create a diagnostic from a parse error.
+    fn parse_diagnostic(&self, err: &str) -> Value {
+        // The parser reports the position of the error as ` --> line:column`.
+        let position = err.lines().find_map(|line| {
+            let position = line.trim().strip_prefix("-->")?;
+            let mut parts = position.trim().rsplitn(3, ':');
+            let column = parts.next()?.parse::<usize>().ok()?;
+            let line = parts.next()?.parse::<usize>().ok()?;
+            Some(column_to_position(
+                &self.text,
+                line.saturating_sub(1),
+                column.saturating_sub(1),
+            ))
+        });
+        // The message follows the `=` in the report.
+        let message = err
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("= "))
+            .unwrap_or(err);
+        self.diagnostic(
+            position.map(|position| json!({ "start": position, "end": position })),
+            format!("parse error: {message}"),
+        )
+    }
+
+    /// Create an error diagnostic for a range of the document.
+    /// If there's no range, the diagnostic is placed at the start of the document.
+    fn diagnostic(&self, range: Option<Value>, message: String) -> Value {
+        let start = json!({ "line": 0, "character": 0 });
+        json!({
+            "range": range.unwrap_or_else(|| json!({ "start": start, "end": start })),
+            "severity": SEVERITY_ERROR,
+            "source": "sage",
+            "message": message,
+        })
+    }
+
+    /// Get the notification which publishes the diagnostics for the document.
+    pub fn publish_diagnostics(&self) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {
+                "uri": self.uri,
+                "diagnostics": self.diagnostics,
+            },
+        })
+    }
+
+    /// Get the word at a position in the document, with the byte offsets of its start and end.
+    fn word_at(&self, line: usize, character: usize) -> Option<(&str, usize, usize)> {
+        let offset = position_to_offset(&self.text, &self.code, line, character);
+        let start = ident_start(&self.code[..offset]);
+        let end = self.code[offset..]
+            .find(|c| !is_ident(c))
+            .map(|i| offset + i)
+            .unwrap_or(self.code.len());
+        if start == end {
+            return None;
+        }
+        Some((&self.code[start..end], start, end))
+    }
+
+    /// Get the name of the type or variable before a `.` which precedes the given offset, if any.
+    fn owner_before(&self, start: usize) -> Option<&str> {
+        let before = self.code[..start].trim_end().strip_suffix('.')?.trim_end();
+        let owner = &before[ident_start(before)..];
+        (!owner.is_empty()).then_some(owner)
+    }
+
+    /// Get the hover information for the symbol at a position in the document.
+    pub fn hover(&self, line: usize, character: usize) -> Value {
+        let Some((word, start, end)) = self.word_at(line, character) else {
+            return Value::Null;
+        };
+        let Some(expr) = &self.expr else {
+            return Value::Null;
+        };
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let env = expr.scope_at(&Env::default(), self.path.as_deref(), start);
+            if let Some(owner) = self.owner_before(start) {
+                // An associated constant of a type, or a method called on a variable.
+                let ty = match env.get_type(owner) {
+                    Some(_) => Type::Symbol(owner.to_string()),
+                    None => {
+                        let mut ty = Expr::var(owner).get_type(&env).ok()?;
+                        while let Type::Pointer(_, inner) = ty {
+                            ty = *inner;
+                        }
+                        ty
+                    }
+                };
+                let ty = env.get_type_of_associated_const(&ty, word)?;
+                Some(format!("{word}: {ty}"))
+            } else if let Some(ty) = env.get_type(word) {
+                // A type.
+                Some(format!("type {word} = {ty}"))
+            } else {
+                // A variable, constant, or procedure.
+                let ty = Expr::var(word).get_type(&env).ok()?;
+                Some(format!("{word}: {ty}"))
+            }
+        }));
+
+        match result {
+            Ok(Some(contents)) => json!({
+                "contents": {
+                    "kind": "markdown",
+                    "value": format!("```sage\n{contents}\n```"),
+                },
+                "range": {
+                    "start": offset_to_position(&self.text, &self.code, start),
+                    "end": offset_to_position(&self.text, &self.code, end),
+                },
+            }),
+            _ => Value::Null,
+        }
+    }
+
+    /// Get the locations of the definitions of the symbol at a position in the document.
+    pub fn definition(&self, line: usize, character: usize) -> Value {
+        let Some((word, start, _)) = self.word_at(line, character) else {
+            return Value::Null;
+        };
+
+        let named = self.definitions.iter().filter(|def| def.name == word);
+        let owner = self.owner_before(start);
+        let is_type = |name: &str| {
+            self.definitions
+                .iter()
+                .any(|def| def.name == name && def.kind == SymbolKind::Type)
+        };
+        let matches_context = |def: &&SymbolDefinition| match (&def.kind, owner) {
+            // `Type.name` refers to a constant associated with that type.
+            (SymbolKind::AssociatedConst(ty), Some(owner)) if is_type(owner) => ty == owner,
+            // `value.name` refers to a method of some type.
+            (SymbolKind::AssociatedConst(_), owner) => owner.is_some(),
+            (_, owner) => owner.is_none(),
+        };
+        let mut defs = named.clone().filter(matches_context).collect::<Vec<_>>();
+        if defs.is_empty() {
+            // Fall back to any symbol with the same name.
+            defs = named.collect();
+        }
+
+        Value::Array(
+            defs.into_iter()
+                .map(|def| {
+                    json!({
+                        "uri": self.uri,
+                        "range": location_to_range(&self.text, &self.code, &def.loc),
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Can the character be part of an identifier?
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Get the byte offset of the identifier at the end of the text.
+fn ident_start(text: &str) -> usize {
+    text.char_indices()
+        .rev()
+        .find(|(_, c)| !is_ident(*c))
+        .map(|(i, c)| i + c.len_utf8())
+        .unwrap_or(0)
+}
@@ -0,0 +1,173 @@
+//! # Language Server Module
+//!
+//! This module implements a language server for the Sage frontend, which editors
+//! can use to check code as it's written. The server speaks the Language Server
+//! Protocol over any reader and writer, and the `sage-lsp` binary runs it over stdio.
+//!
+//! The server supports:
+//! - Publishing the parse and type errors in a document as diagnostics.
+//! - Jumping to the definitions of procedures, types, and associated constants.
+//! - Showing the types of symbols when hovering over them.
+//!
+//! Documents are synchronized by sending their full text on every change.
+mod document;
+mod protocol;
+
+pub use document::Document;
+pub use protocol::{path_to_uri, read_message, uri_to_path, write_message};
+
+use log::{debug, warn};
+use protocol::{INVALID_REQUEST, METHOD_NOT_FOUND};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+/// The document synchronization kind where the editor sends the full text on every change.
+const SYNC_FULL: i64 = 1;
+
+/// A language server for Sage frontend code.
+#[derive(Default)]
+pub struct LanguageServer {
+    /// The open documents, by their URIs.
+    documents: HashMap<String, Document>,
+    /// Has the editor asked the server to shut down?
+    shutdown: bool,
+    /// Has the editor told the server to exit?
+    exit: bool,
+}
+
+impl LanguageServer {
+    /// Create a new language server with no open documents.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the server, reading messages from the input and writing responses to the output,
+    /// until the editor tells the server to exit or the input ends.
+    pub fn run(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        while !self.exit {
+            let Some(message) = read_message(&mut input)? else {
+                break;
+            };
+            for response in self.handle(&message) {
+                write_message(&mut output, &response)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a message from the editor, returning the messages to send back.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        debug!("received {method}");
+
+        // Notifications have no ID, and don't get a response.
+        let Some(id) = message.get("id").cloned() else {
+            return self.notify(method, params);
+        };
+
+        if self.shutdown {
+            return vec![error(id, INVALID_REQUEST, "the server is shutting down")];
+        }
+
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": SYNC_FULL,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": {
+                    "name": "sage-lsp",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            }),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/hover" => match self.position(params) {
+                Some((doc, line, character)) => doc.hover(line, character),
+                None => Value::Null,
+            },
+            "textDocument/definition" => match self.position(params) {
+                Some((doc, line, character)) => doc.definition(line, character),
+                None => Value::Null,
+            },
+            _ => {
+                warn!("unsupported request {method}");
+                return vec![error(
+                    id,
+                    METHOD_NOT_FOUND,
+                    &format!("unknown method {method}"),
+                )];
+            }
+        };
+        vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })]
+    }
+
+    /// Handle a notification from the editor, returning the messages to send back.
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        match method {
+            "exit" => {
+                self.exit = true;
+                vec![]
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                let doc = Document::new(&uri, uri_to_path(&uri), text);
+                let diagnostics = doc.publish_diagnostics();
+                self.documents.insert(uri, doc);
+                vec![diagnostics]
+            }
+            "textDocument/didChange" => {
+                // With full synchronization, the last change holds the whole text.
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                match (self.documents.get_mut(&uri), text) {
+                    (Some(doc), Some(text)) => {
+                        doc.update(text);
+                        vec![doc.publish_diagnostics()]
+                    }
+                    _ => vec![],
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                // Clear the diagnostics of the closed document.
+                vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                })]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Get the document and the position in it for a request.
+    fn position(&self, params: &Value) -> Option<(&Document, usize, usize)> {
+        let doc = self
+            .documents
+            .get(params["textDocument"]["uri"].as_str()?)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        Some((doc, line, character))
+    }
+}
+
+/// Create an error response to a request.
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
@@ -0,0 +1,159 @@
+//! # Protocol
+//!
+//! The language server talks to the editor with JSON-RPC messages over stdio.
+//! Every message starts with a `Content-Length` header, followed by a blank line
+//! and then the JSON content of the message.
+//!
+//! This module also converts between the positions used by the protocol (zero-based
+//! lines and characters) and the byte offsets used by the compiler.
+use crate::parse::SourceCodeLocation;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+/// The error code for a request with a method the server doesn't support.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// The error code for a request which isn't valid in the server's state.
+pub const INVALID_REQUEST: i64 = -32600;
+
+/// Read a message from the editor. This returns `None` at the end of the input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        // The headers end with an empty line.
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>().map_err(invalid_data)?);
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| invalid_data("missing Content-Length header"))?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(invalid_data)
+}
+
+/// Write a message to the editor.
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{content}", content.len())?;
+    output.flush()
+}
+
+fn invalid_data(err: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+/// Get the path of the file with the given `file://` URI.
+pub fn uri_to_path(uri: &str) -> Option<String> {
+    let path = uri.strip_prefix("file://")?;
+    // Decode the percent-encoded bytes of the path.
+    let mut bytes = vec![];
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        match (byte, tail) {
+            (b'%', [hi, lo, tail @ ..]) => {
+                let hex = std::str::from_utf8(&[*hi, *lo]).ok()?.to_string();
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+                rest = tail;
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Get the `file://` URI of the file with the given path.
+pub fn path_to_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    uri
+}
+
+/// Get the byte offset into the code of a zero-based line and character.
+/// The code is the text of a document with its comments blanked out, and the
+/// characters are counted in the text, in UTF-16 code units as the protocol requires.
+/// Positions past the end of a line are clamped to the end of the line.
+pub fn position_to_offset(text: &str, code: &str, line: usize, character: usize) -> usize {
+    let mut units = 0;
+    let column = nth_line(text, line)
+        .chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= character
+        })
+        .count();
+    let mut offset = 0;
+    for (i, code_line) in code.split('\n').enumerate() {
+        if i == line {
+            return offset
+                + code_line
+                    .char_indices()
+                    .nth(column)
+                    .map(|(i, _)| i)
+                    .unwrap_or(code_line.len());
+        }
+        offset += code_line.len() + 1;
+    }
+    code.len()
+}
+
+/// Get the zero-based line and character of a byte offset into the code, which
+/// is the text of a document with its comments blanked out.
+pub fn offset_to_position(text: &str, code: &str, offset: usize) -> Value {
+    let before = &code[..offset.min(code.len())];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    column_to_position(text, line, before[line_start..].chars().count())
+}
+
+/// Get the range of the text at a source code location in the code, which
+/// is the text of a document with its comments blanked out.
+pub fn location_to_range(text: &str, code: &str, loc: &SourceCodeLocation) -> Value {
+    let start = column_to_position(
+        text,
+        loc.line.saturating_sub(1),
+        loc.column.saturating_sub(1),
+    );
+    let end = match loc.length {
+        Some(length) => offset_to_position(text, code, loc.offset + length),
+        None => start.clone(),
+    };
+    json!({ "start": start, "end": end })
+}
+
+/// Get the position of a zero-based line and column of the text, where the column
+/// counts characters. The protocol counts them in UTF-16 code units instead.
+pub fn column_to_position(text: &str, line: usize, column: usize) -> Value {
+    let character: usize = nth_line(text, line)
+        .chars()
+        .take(column)
+        .map(char::len_utf16)
+        .sum();
+    json!({ "line": line, "character": character })
+}
+
+/// Get a zero-based line of the text, or nothing if the text is too short.
+fn nth_line(text: &str, line: usize) -> &str {
+    text.split('\n').nth(line).unwrap_or_default()
+}
@@ -0,0 +1,44 @@
+//! # The Sage Language Server
+//!
+//! This program runs a language server for the sage frontend over stdio,
+//! so that editors can report errors, jump to definitions, and show the
+//! types of symbols in sage code as it's written.
+//!
+//! Logs are written to stderr, since stdout is used to talk to the editor.
+use sage::lsp::LanguageServer;
+use std::io::{stdin, stdout};
+
+use log::error;
+
+// The stack size of the thread used to analyze the code.
+const STACK_SIZE_MB: usize = 2048;
+
+/// Run the language server.
+fn server() {
+    env_logger::Builder::new()
+        .format_timestamp(None)
+        .filter_level(log::LevelFilter::Warn)
+        .parse_default_env()
+        .init();
+
+    if let Err(e) = LanguageServer::new().run(stdin().lock(), stdout().lock()) {
+        error!("Error communicating with the editor: {e}");
+    }
+}
+
+fn main() {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(16)
+        .stack_size(STACK_SIZE_MB * 1024 * 1024)
+        .build_global()
+        .unwrap();
+
+    // Analyze the code in a separate thread, to allow the process to have more stack space.
+    let child = std::thread::Builder::new()
+        .stack_size(STACK_SIZE_MB * 1024 * 1024)
+        .spawn(server)
+        .unwrap();
+
+    // Wait for the thread to finish.
+    child.join().unwrap()
+}
@@ -9,6 +9,7 @@
 //! - Standard Assembly source
 //! - Core Virtual Machine code source
 //! - Standard Virtual Machine code source
+//! - Virtual Machine bytecode
 //!
 //! ## Stability
 //!
@@ -30,10 +31,10 @@ use super::frontend;
 use super::lir::Expr;
 use super::vm;
 
+use core::fmt;
 use log::trace;
 
 use lalrpop_util::lalrpop_mod;
-use no_comment::{languages, IntoWithoutComments};
 
 /// A struct representing a location in the source code.
 /// This is used to format errors properly.
@@ -71,6 +72,63 @@ impl SourceCodeLocation {
 
         code
     }
+
+    /// Create a comment to insert into compiled code, which marks
+    /// the instructions that follow it as coming from this location.
+    pub fn to_comment(&self) -> String {
+        format!("{LOCATION_COMMENT_PREFIX}{self}")
+    }
+}
+
+/// The prefix of the comments which mark source code locations in compiled code.
+const LOCATION_COMMENT_PREFIX: &str = "@ ";
+
+impl fmt::Display for SourceCodeLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(filename) = &self.filename {
+            write!(f, "{filename}:")?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Replace the comments in some code with spaces, keeping their newlines, so that every
+/// line and column in the code stays where it was. This lets the source code locations
+/// found by the parsers point into the original file. `//` comments run to the end of
+/// the line, and `/* */` comments can be nested.
+pub fn blank_comments(code: &str) -> String {
+    let mut result = String::with_capacity(code.len());
+    let mut chars = code.chars().peekable();
+    // The nesting depth of the block comments around the current character.
+    let mut depth = 0;
+    let mut in_line_comment = false;
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\n', _) => {
+                in_line_comment = false;
+                result.push(c);
+            }
+            _ if in_line_comment => result.push(' '),
+            ('/', Some('*')) => {
+                depth += 1;
+                chars.next();
+                result.push_str("  ");
+            }
+            ('*', Some('/')) if depth > 0 => {
+                depth -= 1;
+                chars.next();
+                result.push_str("  ");
+            }
+            ('/', Some('/')) if depth == 0 => {
+                in_line_comment = true;
+                chars.next();
+                result.push_str("  ");
+            }
+            _ if depth > 0 => result.push(' '),
+            _ => result.push(c),
+        }
+    }
+    result
 }
 
 lalrpop_mod!(
@@ -94,11 +152,7 @@ lalrpop_mod!(
 pub fn parse_vm(
     input: impl ToString,
 ) -> Result<Result<vm::CoreProgram, vm::StandardProgram>, String> {
-    let code = input
-        .to_string()
-        .chars()
-        .without_comments(languages::rust())
-        .collect::<String>();
+    let code = blank_comments(&input.to_string());
 
     let code = code.trim();
 
@@ -111,14 +165,19 @@ pub fn parse_vm(
     }
 }
 
+/// Load Core and Standard variants of virtual machine bytecode, which is
+/// produced by the `to_bytecode` methods of the virtual machine programs.
+/// This returns core code if the bytecode was encoded from a core program.
+pub fn parse_vm_bytecode(
+    input: &[u8],
+) -> Result<Result<vm::CoreProgram, vm::StandardProgram>, String> {
+    vm::from_bytecode(input).map_err(|e| e.to_string())
+}
+
 /// Parse Core and Standard variants of assembly source code.
 /// This will return core code by default, but will fallback on standard.
 pub fn parse_asm(input: impl ToString) -> Result<Result<CoreProgram, StandardProgram>, String> {
-    let code = input
-        .to_string()
-        .chars()
-        .without_comments(languages::rust())
-        .collect::<String>();
+    let code = blank_comments(&input.to_string());
 
     let code = code.trim();
 
@@ -133,11 +192,7 @@ pub fn parse_asm(input: impl ToString) -> Result<Result<CoreProgram, StandardPro
 
 /// Parse LIR code as an LIR expression.
 pub fn parse_lir(input: impl ToString) -> Result<Expr, String> {
-    let code = input
-        .to_string()
-        .chars()
-        .without_comments(languages::rust())
-        .collect::<String>();
+    let code = blank_comments(&input.to_string());
 
     let code = code.trim();
     match lir_parser::ExprParser::new().parse(code) {
@@ -0,0 +1,544 @@
+//! # Runtime Module
+//!
+//! This module implements a high level API for embedding Sage in Rust programs.
+//! A `Runtime` compiles source code in any of Sage's languages to virtual machine
+//! code, and runs it in the right interpreter with a device and any foreign
+//! functions registered by the host.
+//!
+//! ```
+//! use sage::{side_effects::FFIBinding, vm::TestingDevice, Runtime};
+//!
+//! let device = Runtime::with_device(TestingDevice::new(""))
+//!     .with_ffi(FFIBinding::new("double".to_string(), 1, 1), |call| {
+//!         let n = call.int_arg()?;
+//!         call.ret_int(n * 2)
+//!     })
+//!     .run("extern def double(n: Int): Int; println(double(21));")
+//!     .unwrap();
+//! assert_eq!(device.output_str(), "42\n");
+//! ```
+//!
+//! Type checking deeply nested programs is recursive, so a runtime compiles code
+//! on threads with a larger stack than the default. The CLI compiles everything
+//! through a runtime too.
+use crate::{
+    asm,
+    lir::{self, Compile, Env, Warning},
+    parse::{parse_asm, parse_frontend, parse_lir, parse_vm},
+    side_effects::{FFIBinding, FFICall, FFIFunction, Input, Output},
+    vm::{
+        self, BytecodeError, CoreInterpreter, CoreProgram, Device, Limits, Profile, SourceMap,
+        StandardDevice, StandardInterpreter, StandardProgram, Trap,
+    },
+};
+use ::std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Display, Formatter, Result as FmtResult},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+/// The default number of cells allocated for the call stack, the same as the CLI's.
+pub const DEFAULT_CALL_STACK_SIZE: usize = 8192;
+
+/// The default size in bytes of the stacks of the threads which compile source code.
+pub const DEFAULT_COMPILE_STACK_SIZE: usize = 512 * 1024 * 1024;
+
+/// The languages which a runtime can compile and run.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SourceType {
+    /// Sage frontend code.
+    #[default]
+    Sage,
+    /// LIR code.
+    LowIR,
+    /// Core variant assembly code.
+    CoreASM,
+    /// Standard variant assembly code.
+    StdASM,
+    /// Core variant virtual machine code.
+    CoreVM,
+    /// Standard variant virtual machine code.
+    StdVM,
+}
+
+/// A compiled assembly program, which uses either the core or the standard variant.
+pub type AsmProgram = Result<asm::CoreProgram, asm::StandardProgram>;
+
+/// A compiled virtual machine program, which uses either the core or the standard variant,
+/// along with its source map.
+#[derive(Clone, PartialEq)]
+pub enum Program {
+    /// A program which only uses core instructions, and runs on every target.
+    Core(CoreProgram, SourceMap),
+    /// A program which uses standard instructions.
+    Standard(StandardProgram, SourceMap),
+}
+
+impl Program {
+    /// Does the program only use the core variant of the virtual machine?
+    pub fn is_core(&self) -> bool {
+        matches!(self, Self::Core(..))
+    }
+
+    /// Get the source map from the program's instructions to its source code.
+    pub fn source_map(&self) -> &SourceMap {
+        match self {
+            Self::Core(_, map) | Self::Standard(_, map) => map,
+        }
+    }
+
+    /// Optimize the program with the given optimization level.
+    pub fn optimize(self, level: usize) -> Self {
+        match self {
+            Self::Core(program, map) => {
+                let (program, map) = program.optimize_with_source_map(level, &map);
+                Self::Core(program, map)
+            }
+            Self::Standard(program, map) => {
+                let (program, map) = program.optimize_with_source_map(level, &map);
+                Self::Standard(program, map)
+            }
+        }
+    }
+
+    /// Flatten the program, so that all of its functions are defined at the beginning.
+    pub fn flatten(self) -> Self {
+        match self {
+            Self::Core(program, map) => {
+                let (program, map) = program.flatten_with_source_map(&map);
+                Self::Core(program, map)
+            }
+            Self::Standard(program, map) => {
+                let (program, map) = program.flatten_with_source_map(&map);
+                Self::Standard(program, map)
+            }
+        }
+    }
+
+    /// Encode the program as bytecode, which can be loaded with `Runtime::load`.
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        match self {
+            Self::Core(program, map) => program.to_bytecode_with_source_map(map),
+            Self::Standard(program, map) => program.to_bytecode_with_source_map(map),
+        }
+    }
+}
+
+/// A program without a source map, like one parsed from virtual machine code.
+impl From<Result<CoreProgram, StandardProgram>> for Program {
+    fn from(program: Result<CoreProgram, StandardProgram>) -> Self {
+        match program {
+            Ok(program) => {
+                let map = SourceMap::from(&program);
+                Self::Core(program, map)
+            }
+            Err(program) => {
+                let map = SourceMap::from(&program);
+                Self::Standard(program, map)
+            }
+        }
+    }
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Core(program, _) => program.fmt(f),
+            Self::Standard(program, _) => program.fmt(f),
+        }
+    }
+}
+
+/// An error from compiling or running a program with a runtime.
+#[derive(Clone, Debug)]
+pub enum RuntimeError {
+    /// The source code couldn't be parsed.
+    Parse(String),
+    /// The LIR code couldn't be compiled.
+    Lir(Box<lir::Error>),
+    /// The assembly code couldn't be assembled.
+    Asm(asm::Error),
+    /// The bytecode couldn't be loaded.
+    Bytecode(BytecodeError),
+    /// The source code was a standard variant program, but a core variant program was expected.
+    InvalidSource(String),
+    /// The program stopped with an error while it was running.
+    Trap(Trap),
+    /// The threads which compile the source code couldn't be created.
+    CompileThreads(String),
+}
+
+impl From<lir::Error> for RuntimeError {
+    fn from(e: lir::Error) -> Self {
+        Self::Lir(Box::new(e))
+    }
+}
+
+impl From<asm::Error> for RuntimeError {
+    fn from(e: asm::Error) -> Self {
+        Self::Asm(e)
+    }
+}
+
+impl From<BytecodeError> for RuntimeError {
+    fn from(e: BytecodeError) -> Self {
+        Self::Bytecode(e)
+    }
+}
+
+impl From<Trap> for RuntimeError {
+    fn from(trap: Trap) -> Self {
+        Self::Trap(trap)
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Parse(e) => write!(f, "parse error: {e}"),
+            Self::Lir(e) => write!(f, "compile error: {e}"),
+            Self::Asm(e) => write!(f, "assembly error: {e}"),
+            Self::Bytecode(e) => write!(f, "bytecode error: {e}"),
+            Self::InvalidSource(e) => write!(f, "invalid source: {e}"),
+            Self::Trap(trap) => write!(f, "{trap}"),
+            Self::CompileThreads(e) => write!(f, "couldn't create the compile threads: {e}"),
+        }
+    }
+}
+
+impl ::std::error::Error for RuntimeError {}
+
+/// Compiles and runs Sage programs with a device, and the foreign functions
+/// registered by the host.
+pub struct Runtime<D: Device = StandardDevice> {
+    /// The device which the programs interact with.
+    device: D,
+    /// The foreign functions registered with the runtime. These are called
+    /// before the bindings of the device.
+    ffi: HashMap<FFIBinding, FFIFunction>,
+    /// The language of the source code.
+    source_type: SourceType,
+    /// The name of the file the source code is from, used in source locations.
+    filename: Option<String>,
+    /// The number of cells allocated for the call stack.
+    call_stack_size: usize,
+    /// The optimization level for the virtual machine code.
+    opt_level: usize,
+    /// The limits on the resources the programs can use.
+    limits: Limits,
+    /// The size in bytes of the stacks of the threads which compile source code,
+    /// or `None` to compile on the calling thread and the global thread pool.
+    compile_stack_size: Option<usize>,
+}
+
+impl Runtime {
+    /// Create a runtime which runs Sage frontend code with the standard device.
+    pub fn new() -> Self {
+        Self::with_device(StandardDevice::default())
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Device> Runtime<D> {
+    /// Create a runtime which runs Sage frontend code with the given device.
+    pub fn with_device(device: D) -> Self {
+        Self {
+            device,
+            ffi: HashMap::new(),
+            source_type: SourceType::default(),
+            filename: None,
+            call_stack_size: DEFAULT_CALL_STACK_SIZE,
+            opt_level: 0,
+            limits: Limits::default(),
+            compile_stack_size: Some(DEFAULT_COMPILE_STACK_SIZE),
+        }
+    }
+
+    /// Set the language of the source code.
+    pub fn with_source_type(mut self, source_type: SourceType) -> Self {
+        self.source_type = source_type;
+        self
+    }
+
+    /// Set the name of the file the source code is from.
+    pub fn with_filename(mut self, filename: impl ToString) -> Self {
+        self.filename = Some(filename.to_string());
+        self
+    }
+
+    /// Set the number of cells allocated for the call stack.
+    pub fn with_call_stack_size(mut self, call_stack_size: usize) -> Self {
+        self.call_stack_size = call_stack_size;
+        self
+    }
+
+    /// Set the optimization level for the virtual machine code.
+    pub fn with_opt_level(mut self, opt_level: usize) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Limit the resources the programs can use, for running untrusted code.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set the size in bytes of the stacks of the threads which compile source code.
+    /// With `None`, the code is compiled on the calling thread and the global thread
+    /// pool instead, for hosts which already give them large enough stacks.
+    pub fn with_compile_stack_size(mut self, compile_stack_size: Option<usize>) -> Self {
+        self.compile_stack_size = compile_stack_size;
+        self
+    }
+
+    /// Bind a host function to a foreign function. This takes priority over
+    /// any binding for the same foreign function on the device.
+    pub fn with_ffi(
+        mut self,
+        ffi: FFIBinding,
+        f: impl FnMut(&mut FFICall) -> Result<(), String> + Send + 'static,
+    ) -> Self {
+        self.ffi.insert(ffi, Box::new(f));
+        self
+    }
+
+    /// Run part of the compiler with the runtime's compile stack size.
+    fn on_compile_stack<T: Send>(&self, f: impl FnOnce() -> T + Send) -> Result<T, RuntimeError> {
+        Ok(match self.compile_stack_size {
+            Some(size) => compile_pool(size)?.install(f),
+            None => f(),
+        })
+    }
+
+    /// Find the warnings about frontend source code, like unused variables and imports.
+    /// Code in the other languages doesn't have any warnings.
+    pub fn warnings(&self, src: &str) -> Result<Vec<Warning>, RuntimeError> {
+        if self.source_type != SourceType::Sage {
+            return Ok(vec![]);
+        }
+        let filename = self.filename.as_deref();
+        self.on_compile_stack(move || {
+            let expr = parse_frontend(src, filename).map_err(RuntimeError::Parse)?;
+            let mut warnings =
+                crate::frontend::unused_imports(src, filename).map_err(RuntimeError::Parse)?;
+            warnings.extend(expr.lint(&Env::default(), filename));
+            warnings.sort_by_key(|warning| warning.location().map(|loc| loc.offset));
+            Ok(warnings)
+        })?
+    }
+
+    /// Compile source code to an assembly program. Virtual machine code can't be
+    /// turned back into assembly code.
+    pub fn compile_to_asm(&self, src: &str) -> Result<AsmProgram, RuntimeError> {
+        let (source_type, filename) = (self.source_type, self.filename.as_deref());
+        self.on_compile_stack(move || compile_to_asm(src, source_type, filename))?
+    }
+
+    /// Compile source code to an optimized virtual machine program.
+    pub fn compile(&self, src: &str) -> Result<Program, RuntimeError> {
+        let (source_type, filename) = (self.source_type, self.filename.as_deref());
+        let call_stack_size = self.call_stack_size;
+        let program = self.on_compile_stack(move || {
+            Ok(match source_type {
+                SourceType::CoreVM | SourceType::StdVM => {
+                    match parse_vm(src).map_err(RuntimeError::Parse)? {
+                        Err(_) if source_type == SourceType::CoreVM => {
+                            return Err(RuntimeError::InvalidSource(
+                                "expected core VM program, got standard VM program".to_string(),
+                            ))
+                        }
+                        vm_code => Program::from(vm_code),
+                    }
+                }
+                _ => match compile_to_asm(src, source_type, filename)? {
+                    Ok(asm_code) => {
+                        let (program, map) = asm_code.assemble_with_source_map(call_stack_size)?;
+                        Program::Core(program, map)
+                    }
+                    Err(asm_code) => {
+                        let (program, map) = asm_code.assemble_with_source_map(call_stack_size)?;
+                        Program::Standard(program, map)
+                    }
+                },
+            })
+        })??;
+        Ok(program.optimize(self.opt_level))
+    }
+
+    /// Load a program from bytecode, and optimize it. Core VM source programs
+    /// must be encoded from core variant programs.
+    pub fn load(&self, bytecode: &[u8]) -> Result<Program, RuntimeError> {
+        let program = match vm::from_bytecode_with_source_map(bytecode)? {
+            (Ok(program), map) => Program::Core(program, map),
+            (Err(program), map) => Program::Standard(program, map),
+        };
+        if self.source_type == SourceType::CoreVM && !program.is_core() {
+            return Err(RuntimeError::InvalidSource(
+                "expected core VM program, got standard VM program".to_string(),
+            ));
+        }
+        Ok(program.optimize(self.opt_level))
+    }
+
+    /// Compile source code and run it, and return the device when it's finished.
+    pub fn run(self, src: &str) -> Result<D, RuntimeError> {
+        let program = self.compile(src)?;
+        self.run_program(&program)
+    }
+
+    /// Load a program from bytecode and run it, and return the device when it's finished.
+    pub fn run_bytecode(self, bytecode: &[u8]) -> Result<D, RuntimeError> {
+        let program = self.load(bytecode)?;
+        self.run_program(&program)
+    }
+
+    /// Run a compiled program, and return the device when it's finished.
+    pub fn run_program(self, program: &Program) -> Result<D, RuntimeError> {
+        let limits = self.limits;
+        let host = match program {
+            Program::Core(program, map) => CoreInterpreter::new(self.host())
+                .with_limits(limits)
+                .with_source_map(map.clone())
+                .run(program)?,
+            Program::Standard(program, map) => StandardInterpreter::new(self.host())
+                .with_limits(limits)
+                .with_source_map(map.clone())
+                .run(program)?,
+        };
+        Ok(host.device)
+    }
+
+    /// Run a compiled program, and count the instructions it executes in the profile.
+    /// The profile is recorded even if the program stops with an error.
+    pub fn run_program_with_profile(
+        self,
+        program: &Program,
+        profile: &mut Profile,
+    ) -> Result<D, RuntimeError> {
+        let limits = self.limits;
+        let host = match program {
+            Program::Core(program, map) => CoreInterpreter::new(self.host())
+                .with_limits(limits)
+                .with_source_map(map.clone())
+                .run_with_profile(program, profile)?,
+            Program::Standard(program, map) => StandardInterpreter::new(self.host())
+                .with_limits(limits)
+                .with_source_map(map.clone())
+                .run_with_profile(program, profile)?,
+        };
+        Ok(host.device)
+    }
+
+    /// The device which runs the programs, with the runtime's foreign functions.
+    fn host(self) -> Host<D> {
+        Host {
+            device: self.device,
+            ffi: self.ffi,
+            channel: VecDeque::new(),
+        }
+    }
+}
+
+/// The threads which compile source code with the given stack size. These are created
+/// the first time they're needed, and shared by every runtime in the process after.
+fn compile_pool(stack_size: usize) -> Result<Arc<rayon::ThreadPool>, RuntimeError> {
+    static POOLS: OnceLock<Mutex<HashMap<usize, Arc<rayon::ThreadPool>>>> = OnceLock::new();
+    let mut pools = POOLS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(pool) = pools.get(&stack_size) {
+        return Ok(pool.clone());
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .stack_size(stack_size)
+        .build()
+        .map_err(|e| RuntimeError::CompileThreads(e.to_string()))?;
+    Ok(pools.entry(stack_size).or_insert(Arc::new(pool)).clone())
+}
+
+/// Compile source code in any language but virtual machine code to an assembly program.
+fn compile_to_asm(
+    src: &str,
+    source_type: SourceType,
+    filename: Option<&str>,
+) -> Result<AsmProgram, RuntimeError> {
+    Ok(match source_type {
+        SourceType::Sage => parse_frontend(src, filename)
+            .map_err(RuntimeError::Parse)?
+            .compile()?,
+        SourceType::LowIR => parse_lir(src).map_err(RuntimeError::Parse)?.compile()?,
+        SourceType::CoreASM | SourceType::StdASM => {
+            match parse_asm(src).map_err(RuntimeError::Parse)? {
+                Err(_) if source_type == SourceType::CoreASM => {
+                    return Err(RuntimeError::InvalidSource(
+                        "expected core assembly program, got standard assembly program".to_string(),
+                    ))
+                }
+                asm_code => asm_code,
+            }
+        }
+        SourceType::CoreVM | SourceType::StdVM => {
+            return Err(RuntimeError::InvalidSource(
+                "cannot compile a VM program to assembly".to_string(),
+            ))
+        }
+    })
+}
+
+/// The device used by a runtime, which calls the foreign functions registered with
+/// the runtime, and passes everything else through to the runtime's device.
+struct Host<D: Device> {
+    /// The runtime's device.
+    device: D,
+    /// The foreign functions registered with the runtime.
+    ffi: HashMap<FFIBinding, FFIFunction>,
+    /// The FFI channel for the foreign functions registered with the runtime.
+    channel: VecDeque<i64>,
+}
+
+impl<D: Device> Device for Host<D> {
+    fn get(&mut self, src: Input) -> Result<i64, String> {
+        self.device.get(src)
+    }
+
+    fn put(&mut self, val: i64, dst: Output) -> Result<(), String> {
+        self.device.put(val, dst)
+    }
+
+    fn peek(&mut self) -> Result<i64, String> {
+        self.channel
+            .pop_front()
+            .ok_or_else(|| "ffi channel is empty".to_string())
+    }
+
+    fn poke(&mut self, val: i64) -> Result<(), String> {
+        self.channel.push_back(val);
+        Ok(())
+    }
+
+    fn ffi_call(&mut self, ffi: &FFIBinding, tape: Option<&mut [i64]>) -> Result<(), String> {
+        if let Some(f) = self.ffi.get_mut(ffi) {
+            let mut call = FFICall::new(ffi, &mut self.channel, tape);
+            f(&mut call)?;
+            return call.finish();
+        }
+        // Pass the arguments through to the device's binding, and get its results back.
+        for _ in 0..ffi.input_cells {
+            let val = self.peek()?;
+            self.device.poke(val)?;
+        }
+        self.device.ffi_call(ffi, tape)?;
+        for _ in 0..ffi.output_cells {
+            let val = self.device.peek()?;
+            self.channel.push_back(val);
+        }
+        Ok(())
+    }
+}
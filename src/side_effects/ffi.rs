@@ -2,8 +2,15 @@
 //!
 //! This module contains the definition of the foreign function interface (FFI) bindings, which
 //! are used in the various stages of IR to represent calls to foreign functions.
+//!
+//! It also contains the `FFICall` type, which the interpreters' devices give to the host
+//! functions bound to foreign functions, to read their arguments and write their results.
 
-use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use crate::vm::{as_float, as_int};
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+};
 
 /// This is an FFI binding, which is used to call a foreign function in the virtual machine code.
 ///
@@ -43,3 +50,225 @@ impl Debug for FFIBinding {
         )
     }
 }
+
+/// A host function bound to a foreign function, which is called by an interpreter's device.
+///
+/// The function can own any state it needs, like a table of open files or a connection
+/// to the host application, and it's kept between calls. It reads its arguments from
+/// the call and writes its results back to it, and returns an error to stop the program.
+///
+/// The function must be `Send`, so devices and runtimes with bindings can be moved to
+/// other threads.
+pub type FFIFunction = Box<dyn FnMut(&mut FFICall) -> Result<(), String> + Send>;
+
+/// A call to a foreign function, given to the host function bound to it.
+///
+/// The arguments are read in the order they're declared, and the results are written
+/// in the same way. Each argument and result is a single cell, so the typed helpers
+/// check that the call doesn't read or write more cells than the binding declares,
+/// and [`FFICall::finish`] checks that it doesn't read or write fewer.
+pub struct FFICall<'a> {
+    /// The binding of the foreign function being called.
+    binding: &'a FFIBinding,
+    /// The FFI channel, which holds the arguments and receives the results.
+    channel: &'a mut VecDeque<i64>,
+    /// The tape of the program, if the device gives the foreign function access to it.
+    /// The foreign function can change the cells on the tape, but it can't grow the tape
+    /// past the interpreter's limits.
+    tape: Option<&'a mut [i64]>,
+    /// The number of argument cells read so far.
+    args_read: usize,
+    /// The number of result cells written so far.
+    results_written: usize,
+    /// The number of cells in the channel when the call started.
+    channel_len: usize,
+    /// Did the foreign function use the raw channel instead of the typed helpers?
+    raw: bool,
+}
+
+impl<'a> FFICall<'a> {
+    /// Create a call to a foreign function, with the arguments in the FFI channel.
+    pub fn new(
+        binding: &'a FFIBinding,
+        channel: &'a mut VecDeque<i64>,
+        tape: Option<&'a mut [i64]>,
+    ) -> Self {
+        Self {
+            binding,
+            channel_len: channel.len(),
+            channel,
+            tape,
+            args_read: 0,
+            results_written: 0,
+            raw: false,
+        }
+    }
+
+    /// The binding of the foreign function being called.
+    pub fn binding(&self) -> &FFIBinding {
+        self.binding
+    }
+
+    /// The raw FFI channel, for foreign functions which don't use the typed helpers.
+    pub fn channel(&mut self) -> &mut VecDeque<i64> {
+        self.raw = true;
+        self.channel
+    }
+
+    /// The cells on the tape of the program, if the foreign function has access to it.
+    pub fn tape(&mut self) -> Option<&mut [i64]> {
+        self.tape.as_deref_mut()
+    }
+
+    /// Read the next argument cell.
+    pub fn arg(&mut self) -> Result<i64, String> {
+        if self.args_read >= self.binding.input_cells {
+            return Err(format!(
+                "{:?} read more than its {} argument cells",
+                self.binding, self.binding.input_cells
+            ));
+        }
+        self.args_read += 1;
+        self.channel
+            .pop_front()
+            .ok_or_else(|| format!("{:?} is missing arguments", self.binding))
+    }
+
+    /// Read the next argument as an `Int`.
+    pub fn int_arg(&mut self) -> Result<i64, String> {
+        self.arg()
+    }
+
+    /// Read the next argument as a `Float`.
+    pub fn float_arg(&mut self) -> Result<f64, String> {
+        self.arg().map(as_float)
+    }
+
+    /// Read the next argument as a `Char`.
+    pub fn char_arg(&mut self) -> Result<char, String> {
+        let n = self.arg()?;
+        u32::try_from(n)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("{:?} got invalid character {n}", self.binding))
+    }
+
+    /// Read the next argument as a pointer, which is an address on the tape.
+    pub fn pointer_arg(&mut self) -> Result<usize, String> {
+        let n = self.arg()?;
+        usize::try_from(n).map_err(|_| format!("{:?} got invalid pointer {n}", self.binding))
+    }
+
+    /// Write the next result cell.
+    pub fn ret(&mut self, val: i64) -> Result<(), String> {
+        if self.results_written >= self.binding.output_cells {
+            return Err(format!(
+                "{:?} wrote more than its {} result cells",
+                self.binding, self.binding.output_cells
+            ));
+        }
+        self.results_written += 1;
+        self.channel.push_back(val);
+        Ok(())
+    }
+
+    /// Write the next result as an `Int`.
+    pub fn ret_int(&mut self, val: i64) -> Result<(), String> {
+        self.ret(val)
+    }
+
+    /// Write the next result as a `Float`.
+    pub fn ret_float(&mut self, val: f64) -> Result<(), String> {
+        self.ret(as_int(val))
+    }
+
+    /// Write the next result as a `Char`.
+    pub fn ret_char(&mut self, val: char) -> Result<(), String> {
+        self.ret(val as i64)
+    }
+
+    /// Write the next result as a pointer to an address on the tape.
+    pub fn ret_pointer(&mut self, addr: usize) -> Result<(), String> {
+        self.ret(addr as i64)
+    }
+
+    /// Read the cells on the tape starting at the given address.
+    pub fn read(&self, addr: usize, len: usize) -> Result<&[i64], String> {
+        let tape = self.tape.as_deref().ok_or_else(|| no_tape(self.binding))?;
+        addr.checked_add(len)
+            .and_then(|end| tape.get(addr..end))
+            .ok_or_else(|| format!("{:?} read outside of the tape", self.binding))
+    }
+
+    /// Write cells to the tape starting at the given address.
+    pub fn write(&mut self, addr: usize, cells: &[i64]) -> Result<(), String> {
+        let binding = self.binding;
+        let tape = self.tape.as_deref_mut().ok_or_else(|| no_tape(binding))?;
+        match addr
+            .checked_add(cells.len())
+            .and_then(|end| tape.get_mut(addr..end))
+        {
+            Some(dst) => {
+                dst.copy_from_slice(cells);
+                Ok(())
+            }
+            None => Err(format!("{binding:?} wrote outside of the tape")),
+        }
+    }
+
+    /// Read a null-terminated string of UTF-8 bytes from the tape, like a `&Char`.
+    pub fn read_str(&self, addr: usize) -> Result<String, String> {
+        let tape = self.tape.as_deref().ok_or_else(|| no_tape(self.binding))?;
+        let bytes = tape
+            .get(addr..)
+            .and_then(|cells| {
+                let len = cells.iter().position(|&cell| cell == 0)?;
+                Some(cells[..len].iter().map(|&cell| cell as u8).collect())
+            })
+            .ok_or_else(|| format!("{:?} read an unterminated string", self.binding))?;
+        String::from_utf8(bytes).map_err(|e| e.to_string())
+    }
+
+    /// Finish the call, after the foreign function returns.
+    ///
+    /// This checks that the foreign function read all of its arguments and wrote all
+    /// of its results, so no stale cells are left in the FFI channel. Any arguments it
+    /// didn't read are removed from the channel. Foreign functions which use the raw
+    /// channel must leave it with the right number of cells.
+    pub fn finish(self) -> Result<(), String> {
+        let (input_cells, output_cells) = (self.binding.input_cells, self.binding.output_cells);
+        if self.raw {
+            let expected = (self.channel_len + output_cells).saturating_sub(input_cells);
+            if self.channel.len() != expected {
+                return Err(format!(
+                    "{:?} left {} cells in the ffi channel instead of {expected}",
+                    self.binding,
+                    self.channel.len()
+                ));
+            }
+            return Ok(());
+        }
+
+        if self.args_read < input_cells {
+            // The unread arguments are still in front of the results.
+            let unread = (input_cells - self.args_read).min(self.channel.len());
+            self.channel.drain(..unread);
+            return Err(format!(
+                "{:?} only read {} of its {input_cells} argument cells",
+                self.binding, self.args_read
+            ));
+        }
+        if self.results_written < output_cells {
+            return Err(format!(
+                "{:?} only wrote {} of its {output_cells} result cells",
+                self.binding, self.results_written
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The error for a foreign function which needs the tape, when it isn't available.
+fn no_tape(binding: &FFIBinding) -> String {
+    format!("{binding:?} needs access to the tape")
+}
@@ -3,6 +3,10 @@
 //! An implementation of the virtual machine for the C language.
 //!
 //! This allows the virtual machine to target C programs.
+//!
+//! Every procedure is emitted as a function at file scope, and the
+//! generated code is strictly conforming C99. It compiles cleanly with
+//! both GCC and Clang using `-std=c99 -Wall -Werror`.
 use super::{Architecture, CompiledTarget};
 use crate::{
     side_effects::{Input, InputMode, Output, OutputMode},
@@ -26,7 +30,10 @@ impl Architecture for C {
     fn supports_output(&self, o: &Output) -> bool {
         matches!(
             o.mode,
-            OutputMode::StdoutChar | OutputMode::StdoutFloat | OutputMode::StdoutInt
+            OutputMode::StdoutChar
+                | OutputMode::StdoutFloat
+                | OutputMode::StdoutInt
+                | OutputMode::StderrChar
         )
     }
 
@@ -158,7 +165,7 @@ impl Architecture for C {
 
     fn std_op(&mut self, op: &StandardOp) -> Result<String, String> {
         Ok(match op {
-            StandardOp::Call(ffi) => format!("__{}();", ffi.name),
+            StandardOp::Call(ffi) => format!("{{ extern void __{0}(void); __{0}(); }}", ffi.name),
             StandardOp::Peek => self.peek()?,
             StandardOp::Poke => self.poke()?,
             StandardOp::Set(n) => {
@@ -260,7 +267,7 @@ impl Architecture for C {
     }
 
     fn declare_proc(&mut self, label_id: usize) -> String {
-        format!("void f{label_id}() {{")
+        format!("void f{label_id}(void) {{")
     }
 
     fn name(&self) -> &str {
@@ -278,8 +285,8 @@ impl Architecture for C {
         let ch = src.channel.0;
         match src.mode {
             InputMode::StdinChar => Ok("tmp = getchar(); scalar_reg.i = tmp == EOF? 0 : tmp;".to_string()),
-            InputMode::StdinInt => Ok("scanf(\"%ld\", &tmp_scalar_reg.i); reg = tmp_reg;".to_string()),
-            InputMode::StdinFloat => Ok("scanf(\"%lf\", &tmp_scalar_reg.f); reg = tmp_reg;".to_string()),
+            InputMode::StdinInt => Ok("scalar_reg.i = scanf(\"%\" SCNd64, &tmp_reg.i) == 1 ? tmp_reg.i : 0;".to_string()),
+            InputMode::StdinFloat => Ok("scalar_reg.f = scanf(\"%lf\", &tmp_reg.f) == 1 ? tmp_reg.f : 0;".to_string()),
             InputMode::Thermometer => Ok("scalar_reg.f = 293.15;".to_string()),
             InputMode::Clock => Ok("scalar_reg.i = time(NULL);".to_string()),
             InputMode::Random => Ok("scalar_reg.i = rand();".to_string()),
@@ -293,10 +300,10 @@ impl Architecture for C {
     fn put(&mut self, dst: &Output) -> Result<String, String> {
         match dst.mode {
             OutputMode::StdoutChar => Ok("putchar(scalar_reg.i);".to_string()),
-            OutputMode::StdoutInt => Ok("printf(\"%lld\", scalar_reg.i);".to_string()),
+            OutputMode::StdoutInt => Ok("printf(\"%\" PRId64, scalar_reg.i);".to_string()),
             OutputMode::StdoutFloat => Ok("printf(\"%.1lf\", scalar_reg.f);".to_string()),
-            OutputMode::StderrChar => Ok("fprintf(stderr, \"%c\", scalar_reg.i);".to_string()),
-            OutputMode::StderrInt => Ok("fprintf(stderr, \"%lld\", scalar_reg.i);".to_string()),
+            OutputMode::StderrChar => Ok("fputc((int)scalar_reg.i, stderr);".to_string()),
+            OutputMode::StderrInt => Ok("fprintf(stderr, \"%\" PRId64, scalar_reg.i);".to_string()),
             OutputMode::StderrFloat => Ok("fprintf(stderr, \"%.1lf\", scalar_reg.f);".to_string()),
             OutputMode::Heater => Ok("printf(\"Heating...\");".to_string()),
             OutputMode::Cooler => Ok("printf(\"Cooling...\");".to_string()),
@@ -304,16 +311,19 @@ impl Architecture for C {
         }
     }
     fn peek(&mut self) -> Result<String, String> {
-        Ok("reg = *(ffi_ptr--);".to_string())
+        Ok("scalar_reg = *(ffi_ptr--);".to_string())
     }
     fn poke(&mut self) -> Result<String, String> {
-        Ok("*(++ffi_ptr) = reg;".to_string())
+        Ok("*(++ffi_ptr) = scalar_reg;".to_string())
     }
-    fn prelude(&self, is_core: bool) -> Option<String> {
-        let mut result = r#"#include <stdint.h>
+    fn prelude(&self, _is_core: bool) -> Option<String> {
+        let result = r#"#include <inttypes.h>
+#include <stdint.h>
 #include <stdio.h>
+#include <stdlib.h>
 #include <math.h>
 #include <string.h>
+#include <time.h>
 
 typedef union cell {
     int64_t i;
@@ -327,21 +337,22 @@ unsigned int ref_ptr = 0;
 void (*funs[10000])(void);
 
 int tmp;
+
+void sage_main(void);
 "#
         .to_string();
 
-        if !is_core {
-            result = "#include <stdlib.h>\n".to_string() + &result;
-        }
-
         Some(result)
     }
 
     fn post_funs(&self, funs: Vec<i32>) -> Option<String> {
-        let mut result = String::from("int main () {\n");
+        // The program's entry point is its own function, so that `Return`
+        // outside of any procedure halts the program like it does in a procedure.
+        let mut result = String::from("int main(void) {\n");
         for fun in funs {
             result += &format!("\tfuns[{fun}] = f{fun};\n", fun = fun)
         }
+        result += "\tsage_main();\n\treturn 0;\n}\n\nvoid sage_main(void) {\n";
         Some(result)
     }
 
@@ -350,7 +361,7 @@ int tmp;
     }
 
     fn postlude(&self, _is_core: bool) -> Option<String> {
-        Some("return 0;\n}".to_string())
+        Some("}".to_string())
     }
 }
 
@@ -5,10 +5,10 @@
 //!
 //! ## Current Structure
 //!
-//! Right now, this module is a bit empty, only implementing C (GCC only)
-//! as a compiler target. This is due to the fact that it has been much
-//! simpler to build the language on top of the virtual machine when there
-//! are fewer implementations to change.
+//! Right now, this module is a bit empty, only implementing C99,
+//! SageOS, and x86-64 assembly for Linux as compiler targets. This is due to the
+//! fact that it has been much simpler to build the language on top of the
+//! virtual machine when there are fewer implementations to change.
 //!
 //! ## Future Structure
 //!
@@ -39,11 +39,11 @@
 pub mod c;
 pub use c::*;
 
-// pub mod sage_os;
-// pub use sage_os::*;
+pub mod sage_os;
+pub use sage_os::*;
 
-// pub mod x86;
-// pub use x86::*;
+pub mod x86;
+pub use x86::*;
 
 use log::info;
 
@@ -117,6 +117,12 @@ pub trait Architecture {
 
 /// Implement a compiler for the given target.
 pub trait CompiledTarget: Architecture {
+    /// Check that an instruction can be compiled for the target.
+    /// Every instruction is checked before any of the program is compiled.
+    fn check_op(&self, _op: &vm::StandardOp) -> Result<(), String> {
+        Ok(())
+    }
+
     fn build_op(
         &mut self,
         op: &vm::CoreOp,
@@ -209,6 +215,9 @@ pub trait CompiledTarget: Architecture {
     /// Compile the core variant of the machine code (must be implemented for every target).
     fn build_core(&mut self, program: &vm::CoreProgram) -> Result<String, String> {
         info!("Compiling core program for target {}", self.name());
+        for op in &program.0 {
+            self.check_op(&vm::StandardOp::CoreOp(op.clone()))?;
+        }
         let (main_ops, function_defs) = program.clone().get_main_and_functions();
         let mut result = self.prelude(true).unwrap_or("".to_string());
 
@@ -260,6 +269,9 @@ pub trait CompiledTarget: Architecture {
     /// Compile the standard variant of the machine code (should be implemented for every target possible).
     fn build_std(&mut self, program: &vm::StandardProgram) -> Result<String, String> {
         info!("Compiling standard program for target {}", self.name());
+        for op in &program.0 {
+            self.check_op(op)?;
+        }
         let (main_ops, function_defs) = program.clone().get_main_and_functions();
         let mut result = self.prelude(false).unwrap_or("".to_string());
 
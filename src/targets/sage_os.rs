@@ -1,28 +1,20 @@
-//! # C Target
+//! # SageOS Target
 //!
-//! An implementation of the virtual machine for the C language.
+//! An implementation of the virtual machine for [SageOS](https://github.com/adam-mcdaniel/sage-os).
 //!
-//! This allows the virtual machine to target C programs.
-//!
-//! ## Portability
-//!
-//! Right now, this target only supports GCC due to a quirk
-//! with the way this implementation compiles functions.
-//! For some reason, Clang doesn't like nested functions,
-//! even though the function's addresses can still be known
-//! as labels at compile time. I'm really not sure why Clang
-//! *chooses* not to compile nested functions. This can be
-//! fixed by this implementations by just moving function definitions
-//! code outside of the `main` function, since the virtual machine
-//! does not depend on defining functions at runtime.
-use super::{Architecture, CompiledTarget};
+//! This target generates C code which is compiled against the SageOS
+//! userspace library (`sage.h`). The core and standard instructions are
+//! generated exactly like the C target, but the runtime is built around
+//! the `salloc`/`sfree` allocator instead of the C standard library's `malloc`,
+//! and I/O goes through the SageOS terminal.
+use super::{Architecture, CompiledTarget, C};
 use crate::{
     side_effects::{Input, InputMode, Output, OutputMode},
     vm::{CoreOp, StandardOp},
 };
 
-/// The type for the C target which implements the `Target` trait.
-/// This allows the compiler to target the C language.
+/// The type for the SageOS target which implements the `Target` trait.
+/// This allows the compiler to target the SageOS userspace.
 #[derive(Default)]
 pub struct SageOS;
 
@@ -37,68 +29,31 @@ impl Architecture for SageOS {
     fn supports_output(&self, o: &Output) -> bool {
         matches!(
             o.mode,
-            OutputMode::StdoutChar | OutputMode::StdoutFloat | OutputMode::StdoutInt
+            OutputMode::StdoutChar
+                | OutputMode::StdoutFloat
+                | OutputMode::StdoutInt
+                | OutputMode::StderrChar
+                | OutputMode::StderrFloat
+                | OutputMode::StderrInt
         )
     }
 
     fn op(&mut self, op: &CoreOp) -> String {
-        match op {
-            CoreOp::Comment(text) => {
-                // format!("// {}", text.replace('\n', "\n// ").replace('\r', ""))
-                let mut comment = String::new();
-                for line in text.split('\n') {
-                    comment += &format!("// {}", line.trim());
-                }
-                comment
-            }
-            CoreOp::While => "while (reg.i) {".to_string(),
-            CoreOp::If => "if (reg.i) {".to_string(),
-            CoreOp::Else => "} else {".to_string(),
-            CoreOp::Set(n) => format!("reg.i = {};", n),
-            CoreOp::Call => "funs[reg.i]();".to_string(),
-            CoreOp::Return => "return;".to_string(),
-            CoreOp::Save => "*ptr = reg;".to_string(),
-            CoreOp::Restore => "reg = *ptr;".to_string(),
-            CoreOp::Move(n) => format!("ptr += {};", n),
-            CoreOp::Where => "reg.p = ptr;".to_string(),
-            CoreOp::Deref => "*ref++ = ptr; ptr = ptr->p;".to_string(),
-            CoreOp::Refer => "ptr = *--ref;".to_string(),
-            CoreOp::Index => "reg.p += ptr->i;".to_string(),
-            CoreOp::BitwiseNand => "reg.i = ~(reg.i & ptr->i);".to_string(),
-            CoreOp::Add => "reg.i += ptr->i;".to_string(),
-            CoreOp::Sub => "reg.i -= ptr->i;".to_string(),
-            CoreOp::Mul => "reg.i *= ptr->i;".to_string(),
-            CoreOp::Div => "reg.i /= ptr->i;".to_string(),
-            CoreOp::Rem => "reg.i %= ptr->i;".to_string(),
-            CoreOp::IsNonNegative => "reg.i = reg.i >= 0;".to_string(),
-            _ => unreachable!("Invalid op for SageOS target {op:?}"),
-        }
+        // The core instructions don't depend on the C standard library.
+        C.op(op)
     }
 
     fn std_op(&mut self, op: &StandardOp) -> Result<String, String> {
         Ok(match op {
-            StandardOp::Call(ffi) => format!("__{}();", ffi.name),
+            StandardOp::Alloc => {
+                "scalar_reg.p = (cell*)salloc(scalar_reg.i * sizeof(cell));".to_string()
+            }
+            StandardOp::Free => "sfree((void*)scalar_reg.p);".to_string(),
             StandardOp::Peek => self.peek()?,
             StandardOp::Poke => self.poke()?,
-            StandardOp::Set(n) => format!("reg.f = {};", n),
-            StandardOp::ToInt => "reg.i = reg.f;".to_string(),
-            StandardOp::ToFloat => "reg.f = reg.i;".to_string(),
-            StandardOp::ACos => "reg.f = acos(reg.f);".to_string(),
-            StandardOp::ASin => "reg.f = asin(reg.f);".to_string(),
-            StandardOp::ATan => "reg.f = atan(reg.f);".to_string(),
-            StandardOp::Sin => "reg.f = sin(reg.f);".to_string(),
-            StandardOp::Cos => "reg.f = cos(reg.f);".to_string(),
-            StandardOp::Tan => "reg.f = tan(reg.f);".to_string(),
-            StandardOp::Add => "reg.f += ptr->f;".to_string(),
-            StandardOp::Sub => "reg.f -= ptr->f;".to_string(),
-            StandardOp::Mul => "reg.f *= ptr->f;".to_string(),
-            StandardOp::Div => "reg.f /= ptr->f;".to_string(),
-            StandardOp::Rem => "reg.f = fmod(reg.f, ptr->f);".to_string(),
-            StandardOp::Pow => "reg.f = pow(reg.f, ptr->f);".to_string(),
-            StandardOp::IsNonNegative => "reg.i = reg.f >= 0;".to_string(),
-            StandardOp::Alloc => "reg.p = (cell*)salloc(reg.i * sizeof(reg));".to_string(),
-            StandardOp::Free => "sfree((void*)reg.p);".to_string(),
-            _ => return Err(format!("Invalid standard op for SageOS target {op:?}")),
+            _ => C
+                .std_op(op)
+                .map_err(|_| format!("Invalid standard op for SageOS target {op:?}"))?,
         })
     }
 
@@ -110,11 +65,11 @@ impl Architecture for SageOS {
     }
 
     fn declare_proc(&mut self, label_id: usize) -> String {
-        format!("void f{label_id}() {{")
+        format!("void f{label_id}(void) {{")
     }
 
     fn name(&self) -> &str {
-        "C"
+        "SageOS"
     }
     fn version(&self) -> &str {
         "1.0"
@@ -127,37 +82,40 @@ impl Architecture for SageOS {
     fn get(&mut self, src: &Input) -> Result<String, String> {
         let ch = src.channel.0;
         match src.mode {
-            InputMode::StdinChar => Ok("reg.i = sgetchar();".to_string()),
-            InputMode::StdinInt => Ok("scanf(\"%lld\", &reg.i);".to_string()),
-            InputMode::StdinFloat => Ok("scanf(\"%lf\", &reg.f);".to_string()),
-            InputMode::Thermometer => Ok("reg.f = 293.15;".to_string()),
-            InputMode::Clock => Ok("reg.i = time(NULL);".to_string()),
-            InputMode::Random => Ok("reg.i = rand();".to_string()),
+            InputMode::StdinChar => Ok("scalar_reg.i = sgetchar();".to_string()),
+            InputMode::StdinInt => Ok("scalar_reg.i = sgetint(10);".to_string()),
+            InputMode::StdinFloat => Ok("scalar_reg.f = sgetfloat();".to_string()),
+            InputMode::Thermometer => Ok("scalar_reg.f = 293.15;".to_string()),
+            InputMode::Clock => Ok("scalar_reg.i = get_time();".to_string()),
             InputMode::Button => Ok(format!(
-                "printf(\"Button #{ch}: \"); reg.i = getchar() == 'y'; while (getchar() != 10);"
+                "printf(\"Button #{ch}: \"); scalar_reg.i = sgetchar() == 'y'; while (sgetchar() != '\\n');"
             )),
             _ => Err("Input not supported by this target".to_string()),
         }
     }
 
     fn put(&mut self, dst: &Output) -> Result<String, String> {
+        // SageOS has no separate error stream, so errors are written to the terminal.
         match dst.mode {
-            OutputMode::StdoutChar => Ok("putchar(reg.i);".to_string()),
-            OutputMode::StdoutInt => Ok("putint(reg.i, 10);".to_string()),
-            OutputMode::StdoutFloat => Ok("putfloat(reg.f);".to_string()),
-            OutputMode::StderrChar => Ok("fprintf(stderr, \"%c\", reg.i);".to_string()),
-            OutputMode::StderrInt => Ok("fprintf(stderr, \"%lld\", reg.i);".to_string()),
-            OutputMode::StderrFloat => Ok("fprintf(stderr, \"%lf\", reg.f);".to_string()),
+            OutputMode::StdoutChar | OutputMode::StderrChar => {
+                Ok("putchar(scalar_reg.i);".to_string())
+            }
+            OutputMode::StdoutInt | OutputMode::StderrInt => {
+                Ok("putint(scalar_reg.i, 10);".to_string())
+            }
+            OutputMode::StdoutFloat | OutputMode::StderrFloat => {
+                Ok("putfloat(scalar_reg.f);".to_string())
+            }
             OutputMode::Heater => Ok("printf(\"Heating...\");".to_string()),
             OutputMode::Cooler => Ok("printf(\"Cooling...\");".to_string()),
             _ => Err("Output not supported by this target".to_string()),
         }
     }
     fn peek(&mut self) -> Result<String, String> {
-        Ok("reg = *(ffi_ptr--);".to_string())
+        Ok("scalar_reg = *(ffi_ptr--);".to_string())
     }
     fn poke(&mut self) -> Result<String, String> {
-        Ok("*(++ffi_ptr) = reg;".to_string())
+        Ok("*(++ffi_ptr) = scalar_reg;".to_string())
     }
     fn prelude(&self, _is_core: bool) -> Option<String> {
         /*
@@ -188,6 +146,7 @@ impl Architecture for SageOS {
 #include <string.h>
 #include <stdint.h>
 #include <ctype.h>
+#include <math.h>
 #include <sage.h>
 
 typedef union cell {
@@ -196,12 +155,14 @@ typedef union cell {
     union cell *p;
 } cell;
 
-union cell *tape, *refs, *ptr, **ref, reg, *ffi_channel, *ffi_ptr;
-uint8_t *heap_start, *heap_end;
+cell *tape, **refs, *ptr, scalar_reg, vector_reg[1024], tmp_reg, *ffi_channel, *ffi_ptr;
+uint8_t *heap_start, *heap_end, heap[0x800000];
 uint64_t heap_remaining;
 unsigned int ref_ptr = 0;
 void (**funs)(void);
 
+void sage_main(void);
+
 /*
 void __get_file_size() {
     const cell *file_name_cells = ffi_ptr[0].p;
@@ -488,41 +449,42 @@ void salloc_init(uint8_t *start, uint8_t *end) {
     // printf("salloc_init: %p - %p\n", heap_start, heap_end);
 }
 
+// Every block starts with a header holding its size in words (including
+// the header). Free memory is always zeroed, so any nonzero word found
+// while scanning free memory is the header of an allocated block.
 void *salloc(uint32_t bytes) {
-	// printf("salloc: %d\n", bytes);
-	uint64_t words = (bytes + 128) / 8;
-    uint64_t *ret = (uint64_t*)heap_start;
-    while (*ret != 0) {
-		// printf("salloc: %p taken with reserved words=%ld, bytes=%ld\n", ret, *ret, *ret * 8);
-        ret += *ret;
-        if ((uintptr_t)ret >= (uintptr_t)heap_end) {
-            // printf("salloc: out of memory\n");
-            // printf("salloc: tried to allocate %d bytes\n", bytes);
-            return NULL;
+    uint64_t words = (bytes + 7) / 8 + 1;
+    uint64_t *ret = (uint64_t*)heap_start, *end = (uint64_t*)heap_end;
+    while (ret + words <= end) {
+        if (*ret != 0) {
+            // Skip over the allocated block.
+            ret += *ret;
+            continue;
         }
+        // Check that the free space is large enough for the block.
+        uint64_t i = 1;
+        while (i < words && ret[i] == 0) {
+            i++;
+        }
+        if (i == words) {
+            *ret = words;
+            heap_remaining -= words * 8;
+            return ret + 1;
+        }
+        ret += i;
     }
-    *ret = words;
-    ret++;
-    uint64_t amount_allocated = words * 8;
-    heap_remaining -= amount_allocated;
-	// printf("salloc: %p (remaining=%d)\n", ret, (uint32_t)(heap_end - ((uint8_t*)ret + bytes)));
-    return ret;
+    return NULL;
 }
 
 void sfree(void *ptr) {
-	// printf("sfree: %p\n", ptr);
-	if ((uintptr_t)ptr < (uintptr_t)heap_start || (uintptr_t)ptr >= (uintptr_t)heap_end) {
-		// printf("sfree: invalid pointer\n");
-		return;
-	}
-
-    uint64_t *p = ptr;
-    uint64_t words = *(p - 1);
-    memset(p - 1, 0, words / 8);
+    if ((uintptr_t)ptr <= (uintptr_t)heap_start || (uintptr_t)ptr >= (uintptr_t)heap_end) {
+        return;
+    }
 
+    uint64_t *p = (uint64_t*)ptr - 1;
+    uint64_t words = *p;
+    memset(p, 0, words * 8);
     heap_remaining += words * 8;
-
-    return;
 }
 
 void __malloc() {
@@ -743,6 +705,45 @@ int64_t sgetint(uint64_t radix) {
     return num * sign;
 }
 
+double sgetfloat() {
+    double num = 0;
+    double sign = 1;
+    int64_t c = sgetchar();
+    while (isws(c)) {
+        c = sgetchar();
+    }
+    if (c == '-') {
+        sign = -1;
+        c = sgetchar();
+    }
+    else if (c == '+') {
+        c = sgetchar();
+    }
+    while (isdigit(c)) {
+        num = num * 10 + (c - '0');
+        c = sgetchar();
+    }
+    if (c == '.') {
+        double scale = 0.1;
+        c = sgetchar();
+        while (isdigit(c)) {
+            num += (c - '0') * scale;
+            scale /= 10;
+            c = sgetchar();
+        }
+    }
+    if (c == 'e' || c == 'E') {
+        int64_t exponent = sgetint(10);
+        for (; exponent > 0; exponent--) {
+            num *= 10;
+        }
+        for (; exponent < 0; exponent++) {
+            num /= 10;
+        }
+    }
+    return num * sign;
+}
+
 void __getchar() {
     ffi_ptr++;
     ffi_ptr[0].i = (int64_t)sgetchar();
@@ -943,26 +944,27 @@ void __get_tablet_event() {
 
     fn post_funs(&self, funs: Vec<i32>) -> Option<String> {
         let mut result = String::from(
-            r#"int main () {
-    uint8_t buf[0x800000] = {0};
-    salloc_init(buf, buf + sizeof(buf));
+            r#"int main(void) {
+    salloc_init(heap, heap + sizeof(heap));
 
-    funs = (void(**)(void))salloc(200 * sizeof(void*));
+    funs = (void(**)(void))salloc(10000 * sizeof(void*));
     ffi_channel = (cell*)salloc(256 * sizeof(cell));
     tape = (cell*)salloc(50000 * sizeof(cell));
-    refs = (cell*)salloc(128 * sizeof(cell));
+    refs = (cell**)salloc(1024 * sizeof(cell*));
 
     sgetchar_init();
 
     ptr = tape;
-    ref = (cell**)refs;
-    reg.i = 0;
+    scalar_reg.i = 0;
     ffi_ptr = ffi_channel;
 "#,
         );
         for fun in funs {
             result += &format!("\tfuns[{fun}] = f{fun};\n", fun = fun)
         }
+        // The program's entry point is its own function, so that `Return`
+        // outside of any procedure halts the program.
+        result += "\tsage_main();\n\treturn 0;\n}\n\nvoid sage_main(void) {\n";
         Some(result)
     }
 
@@ -971,7 +973,7 @@ void __get_tablet_event() {
     }
 
     fn postlude(&self, _is_core: bool) -> Option<String> {
-        Some("return 0;\n}".to_string())
+        Some("}".to_string())
     }
 }
 
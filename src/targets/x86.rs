@@ -0,0 +1,699 @@
+//! # x86-64 Target
+//!
+//! An implementation of the virtual machine for x86-64 Linux.
+//!
+//! This target emits GNU assembler source (AT&T syntax), which can be assembled
+//! and linked against the C standard library with `gcc out.s -o out -lm`.
+//!
+//! The virtual machine's state is kept in callee-saved registers, so that it
+//! survives calls into the C standard library:
+//!
+//! * `%r12` is the pointer on the tape.
+//! * `%r13` is the address of the register, which is an array of cells.
+//!   The scalar register is the first cell of the array.
+//! * `%r14` is the top of the stack of pointers saved by `Deref` instructions.
+//! * `%rbp` is the index for loops over the cells of the register.
+//! * `%rbx` saves the stack pointer while the stack is realigned for a C call.
+//!
+//! Procedures are compiled to native functions, and `Call` jumps through a
+//! table of their addresses. Pointers are native addresses, so pointer
+//! offsets are scaled by the size of a cell.
+use super::{Architecture, CompiledTarget};
+use crate::{
+    side_effects::{Input, InputMode, Output, OutputMode},
+    vm::{CoreOp, StandardOp},
+};
+
+/// The number of cells on the tape.
+const TAPE_SIZE: usize = 200000;
+/// The number of cells in the register.
+const REGISTER_SIZE: usize = 1024;
+/// The maximum number of nested `Deref` instructions.
+const REFS_SIZE: usize = 1024;
+/// The number of cells in the channel used to pass values to foreign functions.
+const FFI_CHANNEL_SIZE: usize = 256;
+
+/// The type for the x86-64 target which implements the `Target` trait.
+/// This allows the compiler to target x86-64 assembly for Linux.
+#[derive(Default)]
+pub struct X86_64 {
+    /// The number of labels generated for `If` and `While` blocks so far.
+    label_count: usize,
+    /// The label IDs of the `If` and `While` blocks being compiled.
+    blocks: Vec<usize>,
+    /// Whether a function is being compiled (`Return` exits the program otherwise).
+    in_function: bool,
+}
+
+impl X86_64 {
+    /// Start a new `If` or `While` block, and get its label ID.
+    fn begin_block(&mut self) -> usize {
+        let id = self.label_count;
+        self.label_count += 1;
+        self.blocks.push(id);
+        id
+    }
+
+    /// Get the label ID of the innermost block.
+    fn current_block(&self) -> usize {
+        *self.blocks.last().expect("no block to match")
+    }
+}
+
+/// Join several instructions into the text of one VM instruction.
+fn lines(instructions: &[&str]) -> String {
+    instructions.join("\n\t")
+}
+
+/// Call a function in the C standard library, aligning the stack first.
+fn c_call(function: &str) -> String {
+    format!("mov %rsp, %rbx\n\tand $-16, %rsp\n\tcall {function}@PLT\n\tmov %rbx, %rsp")
+}
+
+/// Add a constant number of bytes to a register or memory operand.
+fn add_imm(bytes: i64, dst: &str) -> String {
+    if i32::try_from(bytes).is_ok() {
+        format!("addq ${bytes}, {dst}")
+    } else {
+        format!("movabs ${bytes}, %rax\n\tadd %rax, {dst}")
+    }
+}
+
+/// Perform an operation on the first `n` cells of the register.
+///
+/// The operation is given the operands for the register cell and the tape
+/// cell to use. Cells after the first are processed in a loop indexed by `%rbp`.
+/// Operations may use `%rax`, `%rcx`, `%rdx`, `%xmm0-1`, and the local label `2`.
+fn vector(n: usize, op: impl Fn(&str, &str) -> String) -> String {
+    if n == 1 {
+        return op("(%r13)", "(%r12)");
+    }
+    format!(
+        "xor %ebp, %ebp\n1:\n\t{}\n\tinc %rbp\n\tcmp ${n}, %rbp\n\tjb 1b",
+        op("(%r13,%rbp,8)", "(%r12,%rbp,8)")
+    )
+}
+
+/// Get the number of cells of the register which an instruction uses.
+fn register_cells(op: &StandardOp) -> usize {
+    match op {
+        StandardOp::CoreOp(CoreOp::Set(vals)) => vals.len(),
+        StandardOp::Set(vals) => vals.len(),
+        StandardOp::CoreOp(
+            CoreOp::Store(n)
+            | CoreOp::Load(n)
+            | CoreOp::Index(n)
+            | CoreOp::Offset(_, n)
+            | CoreOp::BitwiseNand(n)
+            | CoreOp::BitwiseAnd(n)
+            | CoreOp::BitwiseOr(n)
+            | CoreOp::BitwiseXor(n)
+            | CoreOp::BitwiseNot(n)
+            | CoreOp::LeftShift(n)
+            | CoreOp::LogicalRightShift(n)
+            | CoreOp::ArithmeticRightShift(n)
+            | CoreOp::And(n)
+            | CoreOp::Or(n)
+            | CoreOp::Not(n)
+            | CoreOp::Add(n)
+            | CoreOp::Sub(n)
+            | CoreOp::Mul(n)
+            | CoreOp::Div(n)
+            | CoreOp::Rem(n)
+            | CoreOp::Neg(n)
+            | CoreOp::Inc(n)
+            | CoreOp::Dec(n)
+            | CoreOp::Swap(n)
+            | CoreOp::IsNonNegative(n),
+        )
+        | StandardOp::ToInt(n)
+        | StandardOp::ToFloat(n)
+        | StandardOp::Add(n)
+        | StandardOp::Sub(n)
+        | StandardOp::Mul(n)
+        | StandardOp::Div(n)
+        | StandardOp::Rem(n)
+        | StandardOp::Neg(n)
+        | StandardOp::IsNonNegative(n)
+        | StandardOp::Sin(n)
+        | StandardOp::Cos(n)
+        | StandardOp::Tan(n)
+        | StandardOp::ASin(n)
+        | StandardOp::ACos(n)
+        | StandardOp::ATan(n)
+        | StandardOp::Pow(n) => *n,
+        _ => 0,
+    }
+}
+
+/// Apply a floating point function from the C math library to the first `n` cells of the register.
+fn float_fn(n: usize, function: &str) -> String {
+    vector(n, |reg, _| {
+        format!(
+            "movsd {reg}, %xmm0\n\t{}\n\tmovsd %xmm0, {reg}",
+            c_call(function)
+        )
+    })
+}
+
+/// Apply a binary floating point function from the C math library to the first `n` cells
+/// of the register and the tape.
+fn float_binary_fn(n: usize, function: &str) -> String {
+    vector(n, |reg, tape| {
+        format!(
+            "movsd {reg}, %xmm0\n\tmovsd {tape}, %xmm1\n\t{}\n\tmovsd %xmm0, {reg}",
+            c_call(function)
+        )
+    })
+}
+
+/// Apply an SSE2 floating point arithmetic instruction to the first `n` cells of the register.
+fn float_arith(n: usize, instruction: &str) -> String {
+    vector(n, |reg, tape| {
+        format!("movsd {reg}, %xmm0\n\t{instruction} {tape}, %xmm0\n\tmovsd %xmm0, {reg}")
+    })
+}
+
+/// Print the scalar register as a float to the given C stream.
+fn put_float(stream: &str) -> String {
+    format!(
+        "mov {stream}@GOTPCREL(%rip), %rax\n\tmov (%rax), %rdi\n\tmovsd (%r13), %xmm0\n\t{}",
+        c_call("sage_put_float")
+    )
+}
+
+/// Print the scalar register with a `printf` format to the given C stream.
+fn put_formatted(stream: &str, format: &str) -> String {
+    lines(&[
+        &format!("mov {stream}@GOTPCREL(%rip), %rax"),
+        "mov (%rax), %rdi",
+        &format!("lea {format}(%rip), %rsi"),
+        "mov (%r13), %rdx",
+        "xor %eax, %eax",
+        &c_call("fprintf"),
+    ])
+}
+
+/// Read the scalar register with a `scanf` format, leaving zero on failure.
+fn get_formatted(format: &str) -> String {
+    lines(&[
+        "movq $0, (%r13)",
+        &format!("lea {format}(%rip), %rdi"),
+        "mov %r13, %rsi",
+        "xor %eax, %eax",
+        &c_call("scanf"),
+    ])
+}
+
+impl Architecture for X86_64 {
+    fn supports_input(&self, i: &Input) -> bool {
+        matches!(
+            i.mode,
+            InputMode::StdinChar
+                | InputMode::StdinFloat
+                | InputMode::StdinInt
+                | InputMode::Thermometer
+                | InputMode::Clock
+                | InputMode::Random
+        )
+    }
+
+    fn supports_output(&self, o: &Output) -> bool {
+        matches!(
+            o.mode,
+            OutputMode::StdoutChar
+                | OutputMode::StdoutFloat
+                | OutputMode::StdoutInt
+                | OutputMode::StderrChar
+                | OutputMode::StderrFloat
+                | OutputMode::StderrInt
+        )
+    }
+
+    fn op(&mut self, op: &CoreOp) -> String {
+        match op {
+            CoreOp::Comment(text) => {
+                format!("# {}", text.replace('\n', "\n\t# ").replace('\r', ""))
+            }
+            CoreOp::While => {
+                let id = self.begin_block();
+                format!(".Lwhile_{id}:\n\tcmpq $0, (%r13)\n\tje .Lend_{id}")
+            }
+            CoreOp::If => {
+                let id = self.begin_block();
+                format!("cmpq $0, (%r13)\n\tje .Lelse_{id}")
+            }
+            CoreOp::Else => {
+                let id = self.current_block();
+                format!("jmp .Lend_{id}\n.Lelse_{id}:")
+            }
+            CoreOp::Set(n) => {
+                let mut result = vec![];
+                for (i, val) in n.iter().enumerate() {
+                    if i32::try_from(*val).is_ok() {
+                        result.push(format!("movq ${val}, {}(%r13)", i * 8));
+                    } else {
+                        result.push(format!("movabs ${val}, %rax"));
+                        result.push(format!("mov %rax, {}(%r13)", i * 8));
+                    }
+                }
+                result.join("\n\t")
+            }
+            CoreOp::Call => lines(&[
+                "mov (%r13), %rax",
+                "lea sage_functions(%rip), %rcx",
+                "call *(%rcx,%rax,8)",
+            ]),
+            CoreOp::Return if self.in_function => "ret".to_string(),
+            CoreOp::Return => "jmp .Lsage_exit".to_string(),
+
+            CoreOp::Store(1) => lines(&["mov (%r13), %rax", "mov %rax, (%r12)"]),
+            CoreOp::Load(1) => lines(&["mov (%r12), %rax", "mov %rax, (%r13)"]),
+            CoreOp::Store(n) => {
+                format!("mov %r13, %rsi\n\tmov %r12, %rdi\n\tmov ${n}, %ecx\n\trep movsq")
+            }
+            CoreOp::Load(n) => {
+                format!("mov %r12, %rsi\n\tmov %r13, %rdi\n\tmov ${n}, %ecx\n\trep movsq")
+            }
+
+            CoreOp::Move(n) => add_imm(*n as i64 * 8, "%r12"),
+            CoreOp::Where => "mov %r12, (%r13)".to_string(),
+            CoreOp::Deref => lines(&["mov %r12, (%r14)", "add $8, %r14", "mov (%r12), %r12"]),
+            CoreOp::Refer => lines(&["sub $8, %r14", "mov (%r14), %r12"]),
+            CoreOp::Offset(n, size) => vector(*size, |reg, _| add_imm(*n as i64 * 8, reg)),
+            CoreOp::Index(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rax\n\tshl $3, %rax\n\tadd %rax, {reg}")
+            }),
+
+            CoreOp::Add(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rax\n\tadd %rax, {reg}")
+            }),
+            CoreOp::Sub(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rax\n\tsub %rax, {reg}")
+            }),
+            CoreOp::Mul(n) => vector(*n, |reg, tape| {
+                format!("mov {reg}, %rax\n\timul {tape}, %rax\n\tmov %rax, {reg}")
+            }),
+            // Division by zero leaves the register unchanged, like the interpreter.
+            CoreOp::Div(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rcx\n\ttest %rcx, %rcx\n\tjz 2f\n\tmov {reg}, %rax\n\tcqo\n\tidiv %rcx\n\tmov %rax, {reg}\n2:")
+            }),
+            CoreOp::Rem(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rcx\n\ttest %rcx, %rcx\n\tjz 2f\n\tmov {reg}, %rax\n\tcqo\n\tidiv %rcx\n\tmov %rdx, {reg}\n2:")
+            }),
+            CoreOp::Neg(n) => vector(*n, |reg, _| format!("negq {reg}")),
+            CoreOp::Inc(n) => vector(*n, |reg, _| format!("incq {reg}")),
+            CoreOp::Dec(n) => vector(*n, |reg, _| format!("decq {reg}")),
+            CoreOp::Swap(n) => {
+                vector(*n, |reg, tape| {
+                    format!("mov {reg}, %rax\n\tmov {tape}, %rcx\n\tmov %rcx, {reg}\n\tmov %rax, {tape}")
+                })
+            }
+
+            CoreOp::And(n) => vector(*n, |reg, tape| {
+                format!("xor %eax, %eax\n\tcmpq $0, {reg}\n\tje 2f\n\tcmpq $0, {tape}\n\tsetne %al\n2:\n\tmov %rax, {reg}")
+            }),
+            CoreOp::Or(n) => vector(*n, |reg, tape| {
+                format!("mov {reg}, %rax\n\tor {tape}, %rax\n\tsetne %al\n\tmovzbq %al, %rax\n\tmov %rax, {reg}")
+            }),
+            CoreOp::Not(n) => vector(*n, |reg, _| {
+                format!("cmpq $0, {reg}\n\tsete %al\n\tmovzbq %al, %rax\n\tmov %rax, {reg}")
+            }),
+
+            CoreOp::BitwiseNand(n) => vector(*n, |reg, tape| {
+                format!("mov {reg}, %rax\n\tand {tape}, %rax\n\tnot %rax\n\tmov %rax, {reg}")
+            }),
+            CoreOp::BitwiseAnd(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rax\n\tand %rax, {reg}")
+            }),
+            CoreOp::BitwiseOr(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rax\n\tor %rax, {reg}")
+            }),
+            CoreOp::BitwiseXor(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rax\n\txor %rax, {reg}")
+            }),
+            CoreOp::BitwiseNot(n) => vector(*n, |reg, _| format!("notq {reg}")),
+
+            CoreOp::LeftShift(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rcx\n\tshlq %cl, {reg}")
+            }),
+            CoreOp::LogicalRightShift(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rcx\n\tshrq %cl, {reg}")
+            }),
+            CoreOp::ArithmeticRightShift(n) => vector(*n, |reg, tape| {
+                format!("mov {tape}, %rcx\n\tsarq %cl, {reg}")
+            }),
+
+            CoreOp::IsNonNegative(n) => vector(*n, |reg, _| {
+                format!("mov {reg}, %rax\n\tnot %rax\n\tshr $63, %rax\n\tmov %rax, {reg}")
+            }),
+
+            CoreOp::End | CoreOp::Function | CoreOp::Put(_) | CoreOp::Get(_) => {
+                unreachable!("Invalid core op for x86-64 target")
+            }
+        }
+    }
+
+    fn std_op(&mut self, op: &StandardOp) -> Result<String, String> {
+        Ok(match op {
+            StandardOp::Call(ffi) => c_call(&format!("__{}", ffi.name)),
+            StandardOp::Peek => self.peek()?,
+            StandardOp::Poke => self.poke()?,
+            StandardOp::Set(n) => {
+                let mut result = vec![];
+                for (i, val) in n.iter().enumerate() {
+                    result.push(format!("movabs ${}, %rax", val.to_bits() as i64));
+                    result.push(format!("mov %rax, {}(%r13)", i * 8));
+                }
+                result.join("\n\t")
+            }
+
+            StandardOp::ToInt(n) => vector(*n, |reg, _| {
+                format!("cvttsd2si {reg}, %rax\n\tmov %rax, {reg}")
+            }),
+            StandardOp::ToFloat(n) => vector(*n, |reg, _| {
+                format!("cvtsi2sdq {reg}, %xmm0\n\tmovsd %xmm0, {reg}")
+            }),
+
+            StandardOp::ACos(n) => float_fn(*n, "acos"),
+            StandardOp::ASin(n) => float_fn(*n, "asin"),
+            StandardOp::ATan(n) => float_fn(*n, "atan"),
+            StandardOp::Sin(n) => float_fn(*n, "sin"),
+            StandardOp::Cos(n) => float_fn(*n, "cos"),
+            StandardOp::Tan(n) => float_fn(*n, "tan"),
+
+            StandardOp::Add(n) => float_arith(*n, "addsd"),
+            StandardOp::Sub(n) => float_arith(*n, "subsd"),
+            StandardOp::Mul(n) => float_arith(*n, "mulsd"),
+            StandardOp::Div(n) => float_arith(*n, "divsd"),
+            StandardOp::Rem(n) => float_binary_fn(*n, "fmod"),
+            StandardOp::Pow(n) => float_binary_fn(*n, "pow"),
+            StandardOp::Neg(n) => vector(*n, |reg, _| format!("btcq $63, {reg}")),
+
+            StandardOp::IsNonNegative(n) => vector(*n, |reg, _| {
+                format!("movsd {reg}, %xmm0\n\txorpd %xmm1, %xmm1\n\txor %eax, %eax\n\tucomisd %xmm1, %xmm0\n\tsetae %al\n\tmov %rax, {reg}")
+            }),
+
+            StandardOp::Alloc => lines(&[
+                "mov (%r13), %rdi",
+                "shl $3, %rdi",
+                &c_call("malloc"),
+                "mov %rax, (%r13)",
+            ]),
+            StandardOp::Free => lines(&["mov (%r13), %rdi", &c_call("free")]),
+            StandardOp::CoreOp(_) => unreachable!("Invalid standard op for x86-64 target"),
+        })
+    }
+
+    fn end(&mut self, matching: &CoreOp, fun: Option<usize>) -> String {
+        match (matching, fun) {
+            (CoreOp::Function, _) => {
+                self.in_function = false;
+                "ret".to_string()
+            }
+            (CoreOp::While, _) => {
+                let id = self.blocks.pop().expect("no block to end");
+                format!("jmp .Lwhile_{id}\n.Lend_{id}:")
+            }
+            (CoreOp::If, _) => {
+                let id = self.blocks.pop().expect("no block to end");
+                format!(".Lelse_{id}:\n.Lend_{id}:")
+            }
+            (CoreOp::Else, _) => {
+                let id = self.blocks.pop().expect("no block to end");
+                format!(".Lend_{id}:")
+            }
+            _ => unreachable!("Invalid matching op for end"),
+        }
+    }
+
+    fn declare_proc(&mut self, label_id: usize) -> String {
+        self.in_function = true;
+        format!("sage_fn_{label_id}:")
+    }
+
+    fn name(&self) -> &str {
+        "x86-64"
+    }
+    fn version(&self) -> &str {
+        "1.0"
+    }
+
+    fn supports_floats(&self) -> bool {
+        true
+    }
+
+    fn get(&mut self, src: &Input) -> Result<String, String> {
+        match src.mode {
+            InputMode::StdinChar => Ok(lines(&[
+                &c_call("getchar"),
+                "cmp $-1, %eax",
+                "jne 2f",
+                "xor %eax, %eax",
+                "2:",
+                "movslq %eax, %rax",
+                "mov %rax, (%r13)",
+            ])),
+            InputMode::StdinInt => Ok(get_formatted(".Lfmt_int")),
+            InputMode::StdinFloat => Ok(get_formatted(".Lfmt_float")),
+            InputMode::Thermometer => Ok(lines(&[
+                &format!("movabs ${}, %rax", 295.15f64.to_bits() as i64),
+                "mov %rax, (%r13)",
+            ])),
+            InputMode::Clock => Ok(lines(&[
+                "xor %edi, %edi",
+                &c_call("time"),
+                "mov %rax, (%r13)",
+            ])),
+            InputMode::Random => Ok(lines(&[
+                &c_call("rand"),
+                "movslq %eax, %rax",
+                "mov %rax, (%r13)",
+            ])),
+            _ => Err("Input not supported by this target".to_string()),
+        }
+    }
+
+    fn put(&mut self, dst: &Output) -> Result<String, String> {
+        match dst.mode {
+            OutputMode::StdoutChar => Ok(lines(&["mov (%r13), %rdi", &c_call("putchar")])),
+            OutputMode::StdoutInt => Ok(put_formatted("stdout", ".Lfmt_int")),
+            OutputMode::StdoutFloat => Ok(put_float("stdout")),
+            OutputMode::StderrChar => Ok(lines(&[
+                "mov (%r13), %rdi",
+                "mov stderr@GOTPCREL(%rip), %rax",
+                "mov (%rax), %rsi",
+                &c_call("fputc"),
+            ])),
+            OutputMode::StderrInt => Ok(put_formatted("stderr", ".Lfmt_int")),
+            OutputMode::StderrFloat => Ok(put_float("stderr")),
+            _ => Err("Output not supported by this target".to_string()),
+        }
+    }
+    fn peek(&mut self) -> Result<String, String> {
+        Ok(lines(&[
+            "mov sage_ffi_ptr(%rip), %rax",
+            "mov (%rax), %rcx",
+            "sub $8, %rax",
+            "mov %rax, sage_ffi_ptr(%rip)",
+            "mov %rcx, (%r13)",
+        ]))
+    }
+    fn poke(&mut self) -> Result<String, String> {
+        Ok(lines(&[
+            "mov sage_ffi_ptr(%rip), %rax",
+            "add $8, %rax",
+            "mov %rax, sage_ffi_ptr(%rip)",
+            "mov (%r13), %rcx",
+            "mov %rcx, (%rax)",
+        ]))
+    }
+    fn prelude(&self, _is_core: bool) -> Option<String> {
+        Some(format!(
+            r#"# Generated by the Sage compiler for x86-64 Linux.
+# Assemble and link with: gcc out.s -o out -lm
+	.section .note.GNU-stack,"",@progbits
+
+	.bss
+	.align 16
+sage_tape:
+	.zero {tape}
+sage_register:
+	.zero {register}
+sage_refs:
+	.zero {refs}
+	.globl sage_ffi_channel
+sage_ffi_channel:
+	.zero {ffi}
+
+	.data
+	.globl sage_ffi_ptr
+sage_ffi_ptr:
+	.quad sage_ffi_channel
+
+	.section .rodata
+.Lfmt_int:
+	.string "%lld"
+.Lfmt_float:
+	.string "%lf"
+.Lfmt_fixed:
+	.string "%.*f"
+.Lfmt_exp:
+	.string "%.*e"
+.Lfmt_exp_suffix:
+	.string "e%ld"
+.Lstr_nan:
+	.string "NaN"
+	.align 8
+.Lfloat_small:
+	.double 1e-4
+.Lfloat_large:
+	.double 1e16
+
+	.text
+# Print a float in xmm0 to the C stream in rdi, using the shortest
+# representation that reads back as the same value (like the interpreter).
+sage_put_float:
+	push %rbx
+	push %r12
+	push %r13
+	sub $64, %rsp
+	mov %rdi, %rbx
+	movsd %xmm0, 48(%rsp)
+	ucomisd %xmm0, %xmm0
+	jp .Lput_float_nan
+	# Use fixed notation for zero, and for magnitudes in [1e-4, 1e16).
+	lea .Lfmt_fixed(%rip), %r13
+	mov $1, %r12d
+	movq %xmm0, %rax
+	btr $63, %rax
+	movq %rax, %xmm1
+	xorpd %xmm2, %xmm2
+	ucomisd %xmm2, %xmm1
+	je .Lput_float_loop
+	ucomisd .Lfloat_small(%rip), %xmm1
+	jb .Lput_float_exp
+	ucomisd .Lfloat_large(%rip), %xmm1
+	jb .Lput_float_loop
+.Lput_float_exp:
+	lea .Lfmt_exp(%rip), %r13
+	xor %r12d, %r12d
+.Lput_float_loop:
+	mov %rsp, %rdi
+	mov $48, %esi
+	mov %r13, %rdx
+	mov %r12d, %ecx
+	movsd 48(%rsp), %xmm0
+	mov $1, %eax
+	call snprintf@PLT
+	mov %rsp, %rdi
+	xor %esi, %esi
+	call strtod@PLT
+	ucomisd 48(%rsp), %xmm0
+	jp .Lput_float_next
+	je .Lput_float_found
+.Lput_float_next:
+	inc %r12d
+	cmp $24, %r12d
+	jbe .Lput_float_loop
+.Lput_float_found:
+	lea .Lfmt_fixed(%rip), %rax
+	cmp %rax, %r13
+	je .Lput_float_done
+	# Print the exponent without a sign or leading zeroes.
+	mov %rsp, %rdi
+	mov $101, %esi
+	call strchr@PLT
+	test %rax, %rax
+	jz .Lput_float_done
+	movb $0, (%rax)
+	lea 1(%rax), %r12
+	mov %rsp, %rdi
+	mov %rbx, %rsi
+	call fputs@PLT
+	mov %r12, %rdi
+	xor %esi, %esi
+	mov $10, %edx
+	call strtol@PLT
+	mov %rbx, %rdi
+	lea .Lfmt_exp_suffix(%rip), %rsi
+	mov %rax, %rdx
+	xor %eax, %eax
+	call fprintf@PLT
+	jmp .Lput_float_return
+.Lput_float_nan:
+	lea .Lstr_nan(%rip), %rdi
+	mov %rbx, %rsi
+	call fputs@PLT
+	jmp .Lput_float_return
+.Lput_float_done:
+	mov %rsp, %rdi
+	mov %rbx, %rsi
+	call fputs@PLT
+.Lput_float_return:
+	add $64, %rsp
+	pop %r13
+	pop %r12
+	pop %rbx
+	ret
+
+"#,
+            tape = TAPE_SIZE * 8,
+            register = REGISTER_SIZE * 8,
+            refs = REFS_SIZE * 8,
+            ffi = FFI_CHANNEL_SIZE * 8,
+        ))
+    }
+
+    fn pre_funs(&self, funs: Vec<i32>) -> Option<String> {
+        let mut funs = funs;
+        funs.sort();
+        let mut result = String::from("\t.section .data.rel.ro\n\t.align 8\nsage_functions:\n");
+        for fun in funs {
+            result += &format!("\t.quad sage_fn_{fun}\n");
+        }
+        result += "\n\t.text\n";
+        Some(result)
+    }
+
+    fn post_funs(&self, _funs: Vec<i32>) -> Option<String> {
+        Some(
+            r#"
+	.globl main
+main:
+	push %rbp
+	lea sage_tape(%rip), %r12
+	lea sage_register(%rip), %r13
+	lea sage_refs(%rip), %r14
+"#
+            .to_string(),
+        )
+    }
+
+    fn postop(&self) -> Option<String> {
+        Some("\n".to_string())
+    }
+
+    fn postlude(&self, _is_core: bool) -> Option<String> {
+        Some(format!(
+            ".Lsage_exit:\n\txor %edi, %edi\n\t{}\n",
+            c_call("exit")
+        ))
+    }
+
+    fn indentation(&self) -> Option<String> {
+        Some("\t".to_string())
+    }
+}
+
+impl CompiledTarget for X86_64 {
+    fn check_op(&self, op: &StandardOp) -> Result<(), String> {
+        let n = register_cells(op);
+        if n > REGISTER_SIZE {
+            return Err(format!(
+                "{op} uses {n} cells of the register, but the x86-64 target only has {REGISTER_SIZE}"
+            ));
+        }
+        Ok(())
+    }
+}
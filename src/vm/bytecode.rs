@@ -0,0 +1,740 @@
+//! # Bytecode Module
+//!
+//! This module implements a compact binary encoding of virtual machine programs,
+//! which is much smaller and faster to load than the textual virtual machine code.
+//!
+//! ## Format
+//!
+//! A bytecode file is laid out as follows:
+//!
+//! | Size     | Contents                                                     |
+//! |----------|--------------------------------------------------------------|
+//! | 4 bytes  | The magic number `\0SGB`                                     |
+//! | 2 bytes  | The version of the format (little endian)                    |
+//! | 1 byte   | The variant of the program: `0` for core, `1` for standard   |
+//! | varint   | The number of instructions                                   |
+//! | ...      | The instructions                                             |
+//! | varint   | The number of location markers in the source map             |
+//! | ...      | The location markers                                         |
+//! | 4 bytes  | The CRC-32 checksum of everything before it (little endian)  |
+//!
+//! Each instruction is a tag byte followed by its operands. Core instructions use
+//! tags below `0x80`, and the instructions only in the standard variant use tags
+//! from `0x80` upwards. Unsigned integers are LEB128 varints, signed integers are
+//! zigzag encoded varints, floats are 8 little endian bytes, and strings and lists
+//! are prefixed with their length. Comments are kept, so that the location markers
+//! of the program's source map still point at the same instructions after it's loaded.
+//! Each marker is the index of its instruction, followed by the line, the column,
+//! the offset, the optional length, and the optional filename of its location.
+use super::{CoreOp, CoreProgram, SourceMap, StandardOp, StandardProgram};
+use crate::{
+    parse::SourceCodeLocation,
+    side_effects::{
+        Axis, Channel, Color, Direction, FFIBinding, Input, InputMode, Output, OutputMode,
+    },
+};
+use ::std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// The magic number at the start of every bytecode file.
+pub const BYTECODE_MAGIC: [u8; 4] = *b"\0SGB";
+/// The version of the bytecode format written by this version of Sage.
+pub const BYTECODE_VERSION: u16 = 2;
+
+/// The flag for a program which only uses core instructions.
+const CORE_FLAG: u8 = 0;
+/// The flag for a program which uses standard instructions.
+const STANDARD_FLAG: u8 = 1;
+/// The size of the magic number, the version, and the variant flag.
+const HEADER_SIZE: usize = 7;
+/// The size of the checksum at the end of the bytecode.
+const CHECKSUM_SIZE: usize = 4;
+
+/// An error from loading a program from bytecode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// The bytes don't start with the magic number of Sage bytecode.
+    NotBytecode,
+    /// The bytecode uses a version of the format which isn't supported.
+    UnsupportedVersion(u16),
+    /// The checksum doesn't match the contents of the bytecode, so it's corrupted.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The bytecode ended in the middle of the program.
+    UnexpectedEnd,
+    /// The byte at the given offset isn't a valid tag for the kind of item there.
+    InvalidTag {
+        kind: &'static str,
+        tag: u8,
+        offset: usize,
+    },
+    /// The integer at the given offset is too large.
+    InvalidInteger(usize),
+    /// The string at the given offset isn't valid UTF-8.
+    InvalidString(usize),
+    /// There are more bytes after the end of the program, from the given offset.
+    TrailingBytes(usize),
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::NotBytecode => write!(f, "not a Sage bytecode file"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported bytecode version {version} (expected version {BYTECODE_VERSION})"
+            ),
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "bytecode is corrupted: expected checksum {expected:#010x}, found {actual:#010x}"
+            ),
+            Self::UnexpectedEnd => write!(f, "unexpected end of bytecode"),
+            Self::InvalidTag { kind, tag, offset } => {
+                write!(f, "invalid {kind} tag {tag:#04x} at offset {offset}")
+            }
+            Self::InvalidInteger(offset) => write!(f, "invalid integer at offset {offset}"),
+            Self::InvalidString(offset) => write!(f, "invalid UTF-8 string at offset {offset}"),
+            Self::TrailingBytes(offset) => {
+                write!(f, "unexpected bytes after the program at offset {offset}")
+            }
+        }
+    }
+}
+
+impl ::std::error::Error for BytecodeError {}
+
+impl CoreProgram {
+    /// Encode the program as bytecode, without a source map.
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        self.to_bytecode_with_source_map(&SourceMap::default())
+    }
+
+    /// Encode the program as bytecode, along with its source map.
+    pub fn to_bytecode_with_source_map(&self, map: &SourceMap) -> Vec<u8> {
+        encode_program(CORE_FLAG, &self.0, map)
+    }
+}
+
+impl StandardProgram {
+    /// Encode the program as bytecode, without a source map.
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        self.to_bytecode_with_source_map(&SourceMap::default())
+    }
+
+    /// Encode the program as bytecode, along with its source map.
+    pub fn to_bytecode_with_source_map(&self, map: &SourceMap) -> Vec<u8> {
+        encode_program(STANDARD_FLAG, &self.0, map)
+    }
+}
+
+/// Do the given bytes start with the magic number of Sage bytecode?
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(&BYTECODE_MAGIC)
+}
+
+/// Load a program from bytecode. This returns a core program if the bytecode
+/// was encoded from a core program, and a standard program otherwise.
+pub fn from_bytecode(bytes: &[u8]) -> Result<Result<CoreProgram, StandardProgram>, BytecodeError> {
+    Ok(from_bytecode_with_source_map(bytes)?.0)
+}
+
+/// Load a program from bytecode like `from_bytecode`, along with its source map.
+#[allow(clippy::type_complexity)]
+pub fn from_bytecode_with_source_map(
+    bytes: &[u8],
+) -> Result<(Result<CoreProgram, StandardProgram>, SourceMap), BytecodeError> {
+    if !is_bytecode(bytes) {
+        return Err(BytecodeError::NotBytecode);
+    }
+    if bytes.len() < HEADER_SIZE + CHECKSUM_SIZE {
+        return Err(BytecodeError::UnexpectedEnd);
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != BYTECODE_VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+
+    let (contents, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+    let expected = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    let actual = crc32(contents);
+    if expected != actual {
+        return Err(BytecodeError::ChecksumMismatch { expected, actual });
+    }
+
+    let mut reader = Reader {
+        bytes: contents,
+        offset: HEADER_SIZE,
+    };
+    let program = match bytes[6] {
+        CORE_FLAG => Ok(CoreProgram(Vec::decode(&mut reader)?)),
+        STANDARD_FLAG => Err(StandardProgram(Vec::decode(&mut reader)?)),
+        tag => {
+            return Err(BytecodeError::InvalidTag {
+                kind: "variant",
+                tag,
+                offset: 6,
+            })
+        }
+    };
+    let markers = Vec::decode(&mut reader)?;
+    if reader.offset < contents.len() {
+        return Err(BytecodeError::TrailingBytes(reader.offset));
+    }
+    let map = match &program {
+        Ok(program) => {
+            SourceMap::with_locations(&StandardProgram::from(program.clone()).0, markers)
+        }
+        Err(program) => SourceMap::with_locations(&program.0, markers),
+    };
+    Ok((program, map))
+}
+
+/// Encode a program with the given variant flag, instructions, and source map.
+fn encode_program<T: Encode>(flag: u8, ops: &[T], map: &SourceMap) -> Vec<u8> {
+    let mut bytes = BYTECODE_MAGIC.to_vec();
+    bytes.extend(BYTECODE_VERSION.to_le_bytes());
+    bytes.push(flag);
+    ops.len().encode(&mut bytes);
+    for op in ops {
+        op.encode(&mut bytes);
+    }
+    map.markers().len().encode(&mut bytes);
+    for (i, location) in map.markers() {
+        i.encode(&mut bytes);
+        location.encode(&mut bytes);
+    }
+    let checksum = crc32(&bytes);
+    bytes.extend(checksum.to_le_bytes());
+    bytes
+}
+
+/// Calculate the CRC-32 checksum (the same one used by zip and PNG) of some bytes.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Write an unsigned integer as a LEB128 varint.
+fn write_varint(mut n: u64, bytes: &mut Vec<u8>) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+/// Reads the items encoded in bytecode, keeping track of the offset.
+struct Reader<'a> {
+    /// The bytecode, without its checksum.
+    bytes: &'a [u8],
+    /// The offset of the next byte to read.
+    offset: usize,
+}
+
+impl Reader<'_> {
+    /// Read a single byte.
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        let byte = *self
+            .bytes
+            .get(self.offset)
+            .ok_or(BytecodeError::UnexpectedEnd)?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    /// Read the given number of bytes.
+    fn take(&mut self, len: usize) -> Result<&[u8], BytecodeError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or(BytecodeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    /// Read a LEB128 varint.
+    fn varint(&mut self) -> Result<u64, BytecodeError> {
+        let offset = self.offset;
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = (byte & 0x7f) as u64;
+            // The last byte can only hold the top bit of the integer.
+            if shift == 63 && bits > 1 {
+                break;
+            }
+            n |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(BytecodeError::InvalidInteger(offset))
+    }
+
+    /// Read a length, which can't be more than the number of bytes left,
+    /// since every item takes at least one byte.
+    fn len(&mut self) -> Result<usize, BytecodeError> {
+        let len = usize::decode(self)?;
+        if len > self.bytes.len() - self.offset {
+            return Err(BytecodeError::UnexpectedEnd);
+        }
+        Ok(len)
+    }
+}
+
+/// An item which can be encoded in bytecode.
+trait Encode: Sized {
+    /// Append the encoding of the item to the bytes.
+    fn encode(&self, bytes: &mut Vec<u8>);
+    /// Read the item from the bytecode.
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError>;
+}
+
+impl Encode for u8 {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.push(*self);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        reader.byte()
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        write_varint(*self as u64, bytes);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        let offset = reader.offset;
+        Self::try_from(reader.varint()?).map_err(|_| BytecodeError::InvalidInteger(offset))
+    }
+}
+
+impl Encode for i64 {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        // Zigzag encode the integer, so that small negative numbers are small too.
+        write_varint(((self << 1) ^ (self >> 63)) as u64, bytes);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        let n = reader.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+}
+
+impl Encode for isize {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        (*self as i64).encode(bytes);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        let offset = reader.offset;
+        Self::try_from(i64::decode(reader)?).map_err(|_| BytecodeError::InvalidInteger(offset))
+    }
+}
+
+impl Encode for f64 {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.to_le_bytes());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        let mut float = [0; 8];
+        float.copy_from_slice(reader.take(8)?);
+        Ok(Self::from_le_bytes(float))
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.len().encode(bytes);
+        bytes.extend(self.as_bytes());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        let offset = reader.offset;
+        let len = reader.len()?;
+        let string = reader.take(len)?.to_vec();
+        Self::from_utf8(string).map_err(|_| BytecodeError::InvalidString(offset))
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.len().encode(bytes);
+        for item in self {
+            item.encode(bytes);
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        let len = reader.len()?;
+        let mut items = Self::with_capacity(len);
+        for _ in 0..len {
+            items.push(T::decode(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            None => bytes.push(0),
+            Some(item) => {
+                bytes.push(1);
+                item.encode(bytes);
+            }
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        let offset = reader.offset;
+        match reader.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(reader)?)),
+            tag => Err(BytecodeError::InvalidTag {
+                kind: "option",
+                tag,
+                offset,
+            }),
+        }
+    }
+}
+
+impl<T: Encode, U: Encode> Encode for (T, U) {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.0.encode(bytes);
+        self.1.encode(bytes);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok((T::decode(reader)?, U::decode(reader)?))
+    }
+}
+
+impl Encode for SourceCodeLocation {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.line.encode(bytes);
+        self.column.encode(bytes);
+        self.offset.encode(bytes);
+        self.length.encode(bytes);
+        self.filename.encode(bytes);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(Self {
+            line: usize::decode(reader)?,
+            column: usize::decode(reader)?,
+            offset: usize::decode(reader)?,
+            length: Option::decode(reader)?,
+            filename: Option::decode(reader)?,
+        })
+    }
+}
+
+/// Implement `Encode` for an enum, by giving each of its variants a tag.
+/// A variant is encoded as its tag, followed by each of its fields in order.
+///
+/// An enum which wraps another enum in one of its variants, like `StandardOp(CoreOp)`,
+/// encodes that variant the same way as the inner enum, so the tags of the other
+/// variants must not overlap with the inner enum's tags.
+macro_rules! tagged {
+    ($kind:literal, $ty:ident { $($tag:literal => $variant:ident $(($($field:ident),+))?),+ $(,)? }) => {
+        impl Encode for $ty {
+            fn encode(&self, bytes: &mut Vec<u8>) {
+                match self {
+                    $($ty::$variant $(($($field),+))? => {
+                        bytes.push($tag);
+                        $($($field.encode(bytes);)+)?
+                    })+
+                }
+            }
+
+            fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+                let offset = reader.offset;
+                Ok(match reader.byte()? {
+                    $($tag => $ty::$variant $(($({
+                        let $field = Encode::decode(reader)?;
+                        $field
+                    }),+))?,)+
+                    tag => return Err(BytecodeError::InvalidTag { kind: $kind, tag, offset }),
+                })
+            }
+        }
+    };
+    ($kind:literal, $ty:ident($inner:ident) { $($tag:literal => $variant:ident $(($($field:ident),+))?),+ $(,)? }) => {
+        impl Encode for $ty {
+            fn encode(&self, bytes: &mut Vec<u8>) {
+                match self {
+                    $ty::$inner(inner) => inner.encode(bytes),
+                    $($ty::$variant $(($($field),+))? => {
+                        bytes.push($tag);
+                        $($($field.encode(bytes);)+)?
+                    })+
+                }
+            }
+
+            fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+                let offset = reader.offset;
+                Ok(match reader.byte()? {
+                    $($tag => $ty::$variant $(($({
+                        let $field = Encode::decode(reader)?;
+                        $field
+                    }),+))?,)+
+                    // Any other tag belongs to the inner enum.
+                    _ => {
+                        reader.offset = offset;
+                        $ty::$inner($inner::decode(reader)?)
+                    }
+                })
+            }
+        }
+    };
+}
+
+tagged!("instruction", CoreOp {
+    0x00 => Comment(comment),
+    0x01 => Set(values),
+    0x02 => Function,
+    0x03 => Call,
+    0x04 => Return,
+    0x05 => While,
+    0x06 => If,
+    0x07 => Else,
+    0x08 => End,
+    0x09 => Store(n),
+    0x0a => Load(n),
+    0x0b => Move(n),
+    0x0c => Where,
+    0x0d => Deref,
+    0x0e => Refer,
+    0x0f => Index(n),
+    0x10 => Offset(offset, n),
+    0x11 => BitwiseNand(n),
+    0x12 => BitwiseAnd(n),
+    0x13 => BitwiseOr(n),
+    0x14 => BitwiseXor(n),
+    0x15 => BitwiseNot(n),
+    0x16 => LeftShift(n),
+    0x17 => LogicalRightShift(n),
+    0x18 => ArithmeticRightShift(n),
+    0x19 => And(n),
+    0x1a => Or(n),
+    0x1b => Not(n),
+    0x1c => Add(n),
+    0x1d => Sub(n),
+    0x1e => Mul(n),
+    0x1f => Div(n),
+    0x20 => Rem(n),
+    0x21 => Neg(n),
+    0x22 => Inc(n),
+    0x23 => Dec(n),
+    0x24 => Swap(n),
+    0x25 => IsNonNegative(n),
+    0x26 => Get(input),
+    0x27 => Put(output),
+});
+
+// Core instructions are encoded the same way in both variants.
+tagged!("instruction", StandardOp(CoreOp) {
+    0x80 => Set(values),
+    0x81 => Alloc,
+    0x82 => Free,
+    0x83 => ToInt(n),
+    0x84 => ToFloat(n),
+    0x85 => Add(n),
+    0x86 => Sub(n),
+    0x87 => Mul(n),
+    0x88 => Div(n),
+    0x89 => Rem(n),
+    0x8a => Neg(n),
+    0x8b => IsNonNegative(n),
+    0x8c => Sin(n),
+    0x8d => Cos(n),
+    0x8e => Tan(n),
+    0x8f => ASin(n),
+    0x90 => ACos(n),
+    0x91 => ATan(n),
+    0x92 => Pow(n),
+    0x93 => Peek,
+    0x94 => Poke,
+    0x95 => Call(binding),
+});
+
+impl Encode for FFIBinding {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.name.encode(bytes);
+        self.input_cells.encode(bytes);
+        self.output_cells.encode(bytes);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(Self::new(
+            String::decode(reader)?,
+            usize::decode(reader)?,
+            usize::decode(reader)?,
+        ))
+    }
+}
+
+impl Encode for Channel {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.0.encode(bytes);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(Self(usize::decode(reader)?))
+    }
+}
+
+impl Encode for Input {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.mode.encode(bytes);
+        self.channel.encode(bytes);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(Self {
+            mode: InputMode::decode(reader)?,
+            channel: Channel::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Output {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.mode.encode(bytes);
+        self.channel.encode(bytes);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, BytecodeError> {
+        Ok(Self {
+            mode: OutputMode::decode(reader)?,
+            channel: Channel::decode(reader)?,
+        })
+    }
+}
+
+tagged!("input mode", InputMode {
+    0x00 => StdinChar,
+    0x01 => StdinInt,
+    0x02 => StdinFloat,
+    0x03 => Random,
+    0x04 => DPad(direction),
+    0x05 => Button,
+    0x06 => Keyboard,
+    0x07 => JoyStick(direction),
+    0x08 => Clock,
+    0x09 => Accelerometer(axis),
+    0x0a => Gyroscope(axis),
+    0x0b => Microphone,
+    0x0c => RedLight,
+    0x0d => GreenLight,
+    0x0e => BlueLight,
+    0x0f => Brightness,
+    0x10 => Humidity,
+    0x11 => Barometer,
+    0x12 => Magnetometer(axis),
+    0x13 => Thermometer,
+    0x14 => RainGauge,
+    0x15 => UVSensor,
+    0x16 => WindSpeed,
+    0x17 => WindDirection,
+    0x18 => PressureGauge,
+    0x19 => FlowSensor,
+    0x1a => VolumeSensor,
+    0x1b => WeightSensor,
+    0x1c => PHSensor,
+    0x1d => ConductivitySensor,
+    0x1e => Speedometer(axis),
+    0x1f => Odometer,
+    0x20 => Position(axis),
+    0x21 => Compass,
+    0x22 => Proximity,
+    0x23 => Altimeter,
+    0x24 => DepthSensor,
+    0x25 => AnalogPin,
+    0x26 => DigitalPin,
+    0x27 => Custom(name),
+});
+
+tagged!("output mode", OutputMode {
+    0x00 => StdoutChar,
+    0x01 => StdoutInt,
+    0x02 => StdoutFloat,
+    0x03 => StderrChar,
+    0x04 => StderrInt,
+    0x05 => StderrFloat,
+    0x06 => PrinterChar,
+    0x07 => PrinterInt,
+    0x08 => PrinterFloat,
+    0x09 => Brightness,
+    0x0a => AnalogPin,
+    0x0b => DigitalPin,
+    0x0c => StepperMotor,
+    0x0d => Solenoid,
+    0x0e => Valve,
+    0x0f => MotorSpeed,
+    0x10 => Servo,
+    0x11 => Temperature,
+    0x12 => Pump,
+    0x13 => Fan,
+    0x14 => Blower,
+    0x15 => Heater,
+    0x16 => Cooler,
+    0x17 => Pressure,
+    0x18 => Buzzer,
+    0x19 => Bell,
+    0x1a => Note,
+    0x1b => SpeakerVolume,
+    0x1c => SpeakerFrequency,
+    0x1d => UpdateDisplay,
+    0x1e => ClearDisplay,
+    0x1f => SetCursorRow,
+    0x20 => SetCursorColumn,
+    0x21 => MoveCursorUp,
+    0x22 => MoveCursorDown,
+    0x23 => MoveCursorLeft,
+    0x24 => MoveCursorRight,
+    0x25 => SetCursorChar(color),
+    0x26 => SetCursorPixel(color),
+    0x27 => Custom(name),
+});
+
+tagged!("axis", Axis {
+    0x00 => X,
+    0x01 => Y,
+    0x02 => Z,
+});
+
+tagged!("direction", Direction {
+    0x00 => Up,
+    0x01 => Down,
+    0x02 => Left,
+    0x03 => Right,
+});
+
+tagged!("color", Color {
+    0x00 => Black,
+    0x01 => White,
+    0x02 => Red,
+    0x03 => Green,
+    0x04 => Blue,
+    0x05 => Yellow,
+    0x06 => Cyan,
+    0x07 => Magenta,
+    0x08 => Orange,
+    0x09 => RGB(r, g, b),
+});
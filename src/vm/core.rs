@@ -31,22 +31,29 @@ impl CoreProgram {
     /// Flatten a core program so that all of its functions
     /// are defined sequentially at the beginning.
     pub fn flatten(self) -> Self {
-        Self(flatten(self.0).0)
+        Self(flatten(self.0, |op| Some(op)).0)
+    }
+
+    /// Flatten a core program like `flatten`, and also get the index in the
+    /// flattened program of each of the program's instructions.
+    pub fn flatten_with_indices(self) -> (Self, Vec<usize>) {
+        let (code, indices) = flatten_with_indices(self.0, |op| Some(op));
+        (Self(code), indices)
     }
 
     /// Get the code outside of any functions.
     pub fn get_main(&self) -> Vec<CoreOp> {
-        flatten(self.0.clone()).2
+        flatten(self.0.clone(), |op| Some(op)).2
     }
 
     /// Get the code for each function.
     pub fn get_functions(&self) -> HashMap<i32, Vec<CoreOp>> {
-        flatten(self.0.clone()).1
+        flatten(self.0.clone(), |op| Some(op)).1
     }
 
     /// Get the code outside of any functions, and the code for each function.
     pub fn get_main_and_functions(self) -> (Vec<CoreOp>, HashMap<i32, Vec<CoreOp>>) {
-        let (_, functions, main) = flatten(self.0);
+        let (_, functions, main) = flatten(self.0, |op| Some(op));
         (main, functions)
     }
 }
@@ -56,8 +63,15 @@ impl CoreProgram {
 /// and un-nest them while preserving the order in which functions are defined.
 ///
 /// All the function definitions will be placed at the top of the returned list.
-fn flatten(code: Vec<CoreOp>) -> (Vec<CoreOp>, HashMap<i32, Vec<CoreOp>>, Vec<CoreOp>) {
-    let mut functions: HashMap<i32, Vec<CoreOp>> = HashMap::new();
+///
+/// The instructions can be of any type, where `core_op` gets the core instruction
+/// of an instruction if it is one.
+#[allow(clippy::type_complexity)]
+pub(super) fn flatten<T: Clone>(
+    code: Vec<T>,
+    core_op: impl Fn(&T) -> Option<&CoreOp>,
+) -> (Vec<T>, HashMap<i32, Vec<T>>, Vec<T>) {
+    let mut functions: HashMap<i32, Vec<T>> = HashMap::new();
 
     // The current function body we are in.
     let mut fun = -1;
@@ -71,8 +85,8 @@ fn flatten(code: Vec<CoreOp>) -> (Vec<CoreOp>, HashMap<i32, Vec<CoreOp>>, Vec<Co
     // All of the instructions which are not part of a function definition.
     let mut main_instructions = vec![];
     for op in code {
-        match &op {
-            CoreOp::Function => {}
+        match core_op(&op) {
+            Some(CoreOp::Function) => {}
             _ => {
                 if scope_stack.is_empty() {
                     // If we are not defining a function,
@@ -82,8 +96,8 @@ fn flatten(code: Vec<CoreOp>) -> (Vec<CoreOp>, HashMap<i32, Vec<CoreOp>>, Vec<Co
             }
         }
 
-        match op {
-            CoreOp::Function => {
+        match core_op(&op) {
+            Some(CoreOp::Function) => {
                 // If we are declaring a new function,
                 // push the info about the current scope onto the scope
                 // stack to resume later.
@@ -98,12 +112,12 @@ fn flatten(code: Vec<CoreOp>) -> (Vec<CoreOp>, HashMap<i32, Vec<CoreOp>>, Vec<Co
                     fun = functions.len() as i32
                 }
             }
-            CoreOp::If | CoreOp::While => {
+            Some(CoreOp::If | CoreOp::While) => {
                 // Increment the number of matching `End`
                 // instructions to end the scope.
                 matching_end += 1
             }
-            CoreOp::End => {
+            Some(CoreOp::End) => {
                 // If the scope has ended
                 if matching_end == 0 {
                     // Get the function body we're defining.
@@ -146,6 +160,27 @@ fn flatten(code: Vec<CoreOp>) -> (Vec<CoreOp>, HashMap<i32, Vec<CoreOp>>, Vec<Co
     (result, result_functions, main_instructions)
 }
 
+/// Flatten a list of instructions like `flatten`, and also get the index in the
+/// flattened list of each of the instructions.
+pub(super) fn flatten_with_indices<T: Clone>(
+    code: Vec<T>,
+    core_op: impl Fn(&T) -> Option<&CoreOp>,
+) -> (Vec<T>, Vec<usize>) {
+    let mut indices = vec![0; code.len()];
+    let (flattened, _, _) = flatten(code.into_iter().enumerate().collect(), |(_, op)| {
+        core_op(op)
+    });
+    let code = flattened
+        .into_iter()
+        .enumerate()
+        .map(|(new, (old, op))| {
+            indices[old] = new;
+            op
+        })
+        .collect();
+    (code, indices)
+}
+
 impl fmt::Display for CoreProgram {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut comment_count = 0;
@@ -200,6 +235,11 @@ pub enum CoreOp {
     Set(Vec<i64>),
 
     /// Create a new function.
+    ///
+    /// Functions are numbered by their position in the program: the Nth `Function`
+    /// instruction is function N, whether or not it has run, and even if it's inside
+    /// a block that's skipped. This is the number `Call` takes, and the compiled
+    /// targets number their functions the same way.
     Function,
     /// Calls the nth function defined in the program, where n is the value of the register.
     Call,
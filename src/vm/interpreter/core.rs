@@ -2,7 +2,11 @@
 //!
 //! This module implements an interpreter for the Core virtual machine
 //! variant.
-use crate::vm::{CoreOp, CoreProgram, Device, StandardDevice};
+use super::{Debuggable, Decoded, Limits, Meter, Profile, Trap, TrapKind};
+use crate::vm::{
+    CoreOp, CoreProgram, Device, SourceMap, StandardDevice, StandardOp, StandardProgram,
+};
+use ::std::borrow::Borrow;
 
 impl Default for CoreInterpreter<StandardDevice> {
     fn default() -> Self {
@@ -23,9 +27,9 @@ where
     register: Vec<i64>,
     /// The turing tape (composed of integer cells)
     cells: Vec<i64>,
-    /// The addresses of defined functions. `functions[N]` is the
-    /// instruction pointer for the Nth function's code.
-    functions: Vec<usize>,
+    /// The decoded program being stepped through by a debugger. Programs which
+    /// are run all at once are decoded when they're run instead.
+    program: Decoded<CoreOp>,
     /// The call stack of instruction pointers. Whenever a function
     /// is called, the instruction pointer is pushed here. Whenever
     /// a function returns, its instruction pointer is popped from here.
@@ -37,6 +41,11 @@ where
     i: usize,
     /// Is the interpreter finished interpreting?s
     done: bool,
+    /// The limits on the resources the program can use.
+    limits: Limits,
+    /// The source map of the program, used to report where errors happen in the
+    /// source code. Without one, errors only report the procedures they happen in.
+    map: Option<SourceMap>,
 }
 
 impl<T> CoreInterpreter<T>
@@ -49,19 +58,38 @@ where
             pointer: 0,
             register: vec![0; 1024],
             cells: vec![],
-            functions: vec![],
+            program: Decoded::default(),
             calls: vec![],
             refs: vec![],
             i: 0,
             done: false,
+            limits: Limits::default(),
+            map: None,
         }
     }
 
+    /// Limit the resources the program can use. A program which hits
+    /// a limit is stopped with a trap.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Report errors at the locations in the source code given by the program's source map.
+    pub fn with_source_map(mut self, map: SourceMap) -> Self {
+        self.map = Some(map);
+        self
+    }
+
     fn reg_scalar(&self) -> i64 {
-        self.register[0]
+        // The register is empty after loading zero cells.
+        self.register.first().copied().unwrap_or(0)
     }
 
     fn reg_mut_scalar(&mut self) -> &mut i64 {
+        if self.register.is_empty() {
+            self.register.push(0);
+        }
         &mut self.register[0]
     }
 
@@ -74,24 +102,21 @@ where
     }
 
     /// Fetch the current instruction pointed to in the program
-    fn fetch<'a>(&self, code: &'a CoreProgram) -> Option<&'a CoreOp> {
-        if self.i < code.0.len() {
-            Some(&code.0[self.i])
-        } else {
-            None
-        }
+    fn fetch<'a, Op: Borrow<CoreOp>>(&self, program: &'a Decoded<Op>) -> Option<&'a CoreOp> {
+        program.fetch(self.i).map(Borrow::borrow)
     }
 
     /// Dereference the current pointer on the tape.
-    fn deref(&mut self) {
+    fn deref(&mut self) -> Result<(), TrapKind> {
         // Add the old pointer to the dereference stack.
         self.refs.push(self.pointer);
-        let cell = *self.get_cell();
+        let cell = *self.get_cell()?;
         if cell < 0 {
-            panic!("Dereferencing negative cell with value {cell:?}");
+            return Err(TrapKind::invalid_address(cell));
         }
         // Set the pointer to the address on the tape.
         self.pointer = cell as usize;
+        Ok(())
     }
 
     /// Undo a dereference.
@@ -108,43 +133,17 @@ where
     }
 
     /// Call the Nth function defined in the program, where N is the value of the register.
-    fn call(&mut self, code: &CoreProgram) -> Result<(), String> {
-        // If the function has been defined
-        if self.functions.len() > self.reg_scalar() as usize {
-            // Push the current instruction pointer to the call stack
-            self.calls.push(self.i);
-            self.i = self.functions[self.reg_scalar() as usize];
-            Ok(())
-        } else {
-            // If the function hasn't been defined yet, we'll have to find it.
-
-            // Push the return address onto the call stack.
-            self.calls.push(self.i);
-            // Scan all the function definitions from the start of the program until we find it.
-            self.i = 0;
-            let mut count = -1;
-            while count < self.reg_scalar() {
-                // Every time we find a function, it will increment `count`.
-                match self.fetch(code) {
-                    Some(CoreOp::Function) => {
-                        count += 1;
-                    }
-                    Some(_) => {}
-                    None => return Err(format!("function {} not defined", self.reg_scalar())),
-                }
-                // If `count` hasn't reached the function we want,
-                // keep going.
-                if count < self.reg_scalar() {
-                    self.i += 1;
-                }
-            }
-
-            // If we've reached the function we want, add it to the definitions.
-            if !self.functions.contains(&self.i) {
-                self.functions.push(self.i);
-                self.functions.sort()
+    fn call<Op>(&mut self, program: &Decoded<Op>) -> Result<(), TrapKind> {
+        self.limits.check_call_depth(self.calls.len())?;
+        let n = self.reg_scalar();
+        match program.jumps().function(n) {
+            Some(function) => {
+                // Push the current instruction pointer to the call stack
+                self.calls.push(self.i);
+                self.i = function;
+                Ok(())
             }
-            Ok(())
+            None => Err(format!("function {n} not defined").into()),
         }
     }
 
@@ -159,134 +158,142 @@ where
         }
     }
 
-    /// Jump to the matching "Else" for this "If" instruction.
-    fn jmp_to_else(&mut self, code: &CoreProgram) {
-        let mut matching = 1;
-        while matching > 0 {
-            self.i += 1;
-            match self.fetch(code) {
-                Some(CoreOp::If) | Some(CoreOp::While) | Some(CoreOp::Function) => matching += 1,
-                Some(CoreOp::End) => matching -= 1,
-                Some(CoreOp::Else) if matching == 1 => return,
-                _ => {}
-            }
+    /// Jump to the matching instruction for the current "If", "Else", "While",
+    /// "Function", or "End" instruction, if it has one.
+    ///
+    /// An "If" jumps to its "Else" (or its "End"), the other instructions
+    /// which start a block jump to their "End", and the "End" of a "While"
+    /// jumps back to the "While".
+    fn jump<Op>(&mut self, program: &Decoded<Op>) {
+        if let Some(target) = program.jumps().target(self.i) {
+            self.i = target;
         }
     }
 
-    /// Jump to the matching "End" for this "Else", "While",
-    /// or "Function" instruction.
-    fn jmp_to_end(&mut self, code: &CoreProgram) {
-        let mut matching = 1;
-        while matching > 0 {
-            self.i += 1;
-            match self.fetch(code) {
-                Some(CoreOp::If) | Some(CoreOp::While) | Some(CoreOp::Function) => matching += 1,
-                Some(CoreOp::End) => matching -= 1,
-                _ => {}
+    /// Get the current cell pointed to on the turing tape.
+    fn get_cell(&mut self) -> Result<&mut i64, TrapKind> {
+        self.grow(self.pointer.saturating_add(1))?;
+        Ok(&mut self.cells[self.pointer])
+    }
+
+    /// Grow the tape to hold at least the given number of cells,
+    /// unless that's more than the limit on the size of the tape.
+    fn grow(&mut self, len: usize) -> Result<(), TrapKind> {
+        if len > self.cells.len() {
+            self.limits.check_tape_size(len)?;
+            // Grow the tape in chunks, so that it isn't resized for every cell.
+            let chunks = (len - self.cells.len()).div_ceil(1000);
+            let mut new_len = self.cells.len().saturating_add(chunks.saturating_mul(1000));
+            if let Some(max) = self.limits.tape_size {
+                new_len = new_len.min(max);
             }
+            // The pointer can be moved anywhere, so failing to allocate the tape is a trap.
+            self.cells
+                .try_reserve_exact(new_len - self.cells.len())
+                .map_err(|_| format!("out of memory growing the tape to {new_len} cells"))?;
+            self.cells.resize(new_len, 0);
         }
+        Ok(())
     }
 
-    /// Jump back to the matching instruction for a given "End" instruction.
-    ///
-    /// ```hs
-    /// If (ends up here)
-    ///     ...
-    /// End (calls jmp_back_to_matching)
-    /// ```
-    fn jmp_back_to_matching(&mut self, code: &CoreProgram) {
-        let mut matching = 1;
-        while matching > 0 {
-            self.i -= 1;
-            match self.fetch(code) {
-                Some(CoreOp::If) | Some(CoreOp::While) | Some(CoreOp::Function) => matching -= 1,
-                Some(CoreOp::End) => matching += 1,
-                _ => {}
-            }
+    /// Make sure the register and the tape hold the cells an instruction operates on,
+    /// so that a malformed program traps instead of indexing out of bounds.
+    fn check_operands(&mut self, (register, tape): (usize, usize)) -> Result<(), TrapKind> {
+        if register > self.register.len() {
+            return Err(TrapKind::RegisterOutOfBounds(register, self.register.len()));
         }
+        self.grow(self.pointer.saturating_add(tape))
     }
 
-    /// Get the matching instruction for a given "End" instruction.
+    /// Run a core program using this interpreter and its device.
     ///
-    /// ```hs
-    /// If (this function would return this instruction)
-    ///    ...
-    /// End (called on this as the current instruction)
-    /// ```
-    fn get_matching_for_end<'a>(&mut self, code: &'a CoreProgram) -> Option<&'a CoreOp> {
-        let mut matching = 1;
-        let mut i = self.i;
-        while matching > 0 && i > 0 {
-            i -= 1;
-            match code.0[i] {
-                CoreOp::If | CoreOp::While | CoreOp::Function => matching -= 1,
-                CoreOp::End => matching += 1,
-                _ => {}
+    /// If the program fails, the trap reports where it failed and the call stack.
+    pub fn run(mut self, code: &CoreProgram) -> Result<T, Trap> {
+        let program = Decoded::from(code);
+        let mut meter = Meter::start(&self.limits);
+        while !self.done {
+            if let Err(kind) = self.step_metered(&program, &mut meter) {
+                return Err(self.trap(kind, code, &program));
             }
         }
-
-        if matching == 0 {
-            Some(&code.0[i])
-        } else {
-            None
-        }
+        Ok(self.device)
     }
 
-    /// Get the current cell pointed to on the turing tape.
-    fn get_cell(&mut self) -> &mut i64 {
-        while self.pointer >= self.cells.len() {
-            self.cells.extend(vec![0; 1000]);
+    /// Run a program using this interpreter and its device, while recording the
+    /// instructions it executes in the given profile.
+    ///
+    /// The profile is filled in even if the program fails.
+    pub fn run_with_profile(
+        mut self,
+        code: &CoreProgram,
+        profile: &mut Profile,
+    ) -> Result<T, Trap> {
+        let program = Decoded::from(code);
+        let mut meter = Meter::start(&self.limits);
+        while !self.done {
+            profile.record_with(self.i, &self.calls, |i| program.address(i));
+            if let Err(kind) = self.step_metered(&program, &mut meter) {
+                return Err(self.trap(kind, code, &program));
+            }
         }
+        Ok(self.device)
+    }
 
-        &mut self.cells[self.pointer]
+    /// Report an error at the current instruction, with the call stack that led to it.
+    fn trap(&self, kind: TrapKind, code: &CoreProgram, program: &Decoded<&CoreOp>) -> Trap {
+        let map = self.map.clone().unwrap_or_else(|| SourceMap::from(code));
+        let calls = self
+            .calls
+            .iter()
+            .map(|&i| program.address(i))
+            .collect::<Vec<_>>();
+        Trap::new(kind, &map, program.address(self.i), &calls)
     }
 
-    /// Run a core program using this interpreter and its device.
-    pub fn run(mut self, code: &CoreProgram) -> Result<T, String> {
-        while !self.done {
-            self.step(code)?
+    /// Run a single step of the interpreter, counting the instruction against its limits.
+    fn step_metered(
+        &mut self,
+        program: &Decoded<&CoreOp>,
+        meter: &mut Meter,
+    ) -> Result<(), TrapKind> {
+        // Reaching the end of the program doesn't count as an instruction. Comments
+        // were left out when the program was decoded, so they aren't counted either.
+        if self.fetch(program).is_some() {
+            meter.tick(&self.limits)?;
         }
-        Ok(self.device)
+        self.step(program)
     }
 
     /// Run a single step of the interpreter.
-    fn step(&mut self, code: &CoreProgram) -> Result<(), String> {
-        if let Some(op) = self.fetch(code) {
+    fn step<Op: Borrow<CoreOp>>(&mut self, program: &Decoded<Op>) -> Result<(), TrapKind> {
+        if let Some(op) = self.fetch(program) {
+            self.check_operands(operands(op))?;
             match op {
                 CoreOp::Comment(_) => {}
                 CoreOp::Set(n) => *self.reg_mut_vector() = n.clone(),
-                CoreOp::Function => {
-                    if !self.functions.contains(&self.i) {
-                        self.functions.push(self.i);
-                        self.functions.sort()
-                    }
-                    self.jmp_to_end(code)
-                }
-                CoreOp::Call => self.call(code)?,
+                CoreOp::Function => self.jump(program),
+                CoreOp::Call => self.call(program)?,
                 CoreOp::Return => self.ret(),
                 CoreOp::While => {
                     if self.reg_scalar() == 0 {
-                        self.jmp_to_end(code)
+                        self.jump(program)
                     }
                 }
                 CoreOp::If => {
                     if self.reg_scalar() == 0 {
-                        self.jmp_to_else(code)
+                        self.jump(program)
                     }
                 }
-                CoreOp::Else => self.jmp_to_end(code),
+                CoreOp::Else => self.jump(program),
                 CoreOp::End => {
                     if self.reg_scalar() != 0 {
-                        if let Some(CoreOp::While) = self.get_matching_for_end(code) {
-                            self.jmp_back_to_matching(code)
-                        }
+                        // Only the "End" of a "While" has somewhere to jump back to.
+                        self.jump(program)
                     }
                 }
 
                 CoreOp::Load(n) => {
-                    while self.pointer + n >= self.cells.len() {
-                        self.cells.extend(vec![0; 1000]);
-                    }
+                    self.grow(self.pointer.saturating_add(*n).saturating_add(1))?;
 
                     self.reg_mut_vector().clear();
                     // let cells = self.get_cells();
@@ -297,9 +304,7 @@ where
                 }
 
                 CoreOp::Store(n) => {
-                    while self.pointer + n >= self.cells.len() {
-                        self.cells.extend(vec![0; 1000]);
-                    }
+                    self.grow(self.pointer.saturating_add(*n).saturating_add(1))?;
                     for i in 0..*n {
                         let val = self.reg_vector()[i];
                         self.cells[self.pointer + i] = val;
@@ -307,13 +312,19 @@ where
                 }
                 CoreOp::Move(n) => {
                     if *n >= 0 {
-                        self.pointer += *n as usize
+                        self.pointer = self.pointer.checked_add(*n as usize).ok_or_else(|| {
+                            format!(
+                                "Instruction #{} tried to move the pointer past the end of the tape.",
+                                program.address(self.i)
+                            )
+                        })?
                     } else {
                         if self.pointer < -*n as usize {
                             return Err(format!(
                                 "Instruction #{} tried to move the pointer to a negative index.",
-                                self.i
-                            ));
+                                program.address(self.i)
+                            )
+                            .into());
                         }
                         self.pointer -= -*n as usize
                     }
@@ -322,15 +333,17 @@ where
                 CoreOp::Where => *self.reg_mut_scalar() = self.pointer as i64,
                 CoreOp::Offset(offset, n) => {
                     for i in 0..*n {
-                        self.reg_mut_vector()[i] += *offset as i64;
+                        self.reg_mut_vector()[i] =
+                            self.reg_vector()[i].wrapping_add(*offset as i64);
                     }
                 }
-                CoreOp::Deref => self.deref(),
+                CoreOp::Deref => self.deref()?,
                 CoreOp::Refer => self.refer()?,
 
                 CoreOp::Index(n) => {
                     for i in 0..*n {
-                        self.reg_mut_vector()[i] += self.cells[self.pointer + i];
+                        self.reg_mut_vector()[i] =
+                            self.reg_vector()[i].wrapping_add(self.cells[self.pointer + i]);
                     }
                 }
                 CoreOp::BitwiseNand(n) => {
@@ -361,61 +374,65 @@ where
                 }
                 CoreOp::LeftShift(n) => {
                     for i in 0..*n {
-                        self.reg_mut_vector()[i] <<= self.cells[self.pointer + i];
+                        self.reg_mut_vector()[i] =
+                            self.reg_vector()[i].wrapping_shl(self.cells[self.pointer + i] as u32);
                     }
                 }
 
                 CoreOp::LogicalRightShift(n) => {
                     for i in 0..*n {
-                        self.reg_mut_vector()[i] = (self.reg_vector()[i] as u64
-                            >> self.cells[self.pointer + i] as u64)
+                        self.reg_mut_vector()[i] = (self.reg_vector()[i] as u64)
+                            .wrapping_shr(self.cells[self.pointer + i] as u32)
                             as i64;
                     }
                 }
 
                 CoreOp::ArithmeticRightShift(n) => {
                     for i in 0..*n {
-                        self.reg_mut_vector()[i] >>= self.cells[self.pointer + i];
+                        self.reg_mut_vector()[i] =
+                            self.reg_vector()[i].wrapping_shr(self.cells[self.pointer + i] as u32);
                     }
                 }
 
                 CoreOp::Add(n) => {
                     for i in 0..*n {
                         let val = self.cells[self.pointer + i];
-                        self.reg_mut_vector()[i] += val;
+                        self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_add(val);
                     }
                 }
                 CoreOp::Sub(n) => {
                     for i in 0..*n {
                         let val = self.cells[self.pointer + i];
-                        self.reg_mut_vector()[i] -= val;
+                        self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_sub(val);
                     }
                 }
                 CoreOp::Mul(n) => {
                     for i in 0..*n {
                         let val = self.cells[self.pointer + i];
-                        self.reg_mut_vector()[i] *= val;
+                        self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_mul(val);
                     }
                 }
                 CoreOp::Div(n) => {
                     for i in 0..*n {
                         let val = self.cells[self.pointer + i];
-                        if val != 0 {
-                            self.reg_mut_vector()[i] /= val;
+                        if val == 0 {
+                            return Err(TrapKind::DivisionByZero);
                         }
+                        self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_div(val);
                     }
                 }
                 CoreOp::Rem(n) => {
                     for i in 0..*n {
                         let val = self.cells[self.pointer + i];
-                        if val != 0 {
-                            self.reg_mut_vector()[i] %= val;
+                        if val == 0 {
+                            return Err(TrapKind::DivisionByZero);
                         }
+                        self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_rem(val);
                     }
                 }
                 CoreOp::Neg(n) => {
                     for i in 0..*n {
-                        self.reg_mut_vector()[i] *= -1;
+                        self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_neg();
                     }
                 }
                 CoreOp::And(n) => {
@@ -440,12 +457,12 @@ where
 
                 CoreOp::Inc(n) => {
                     for i in 0..*n {
-                        self.reg_mut_vector()[i] += 1;
+                        self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_add(1);
                     }
                 }
                 CoreOp::Dec(n) => {
                     for i in 0..*n {
-                        self.reg_mut_vector()[i] -= 1;
+                        self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_sub(1);
                     }
                 }
 
@@ -473,3 +490,90 @@ where
         Ok(())
     }
 }
+
+/// Get the number of cells in the register and on the tape (at the pointer)
+/// which an instruction operates on.
+pub(super) fn operands(op: &CoreOp) -> (usize, usize) {
+    match op {
+        // Loads and stores grow the tape themselves.
+        CoreOp::Store(n) => (*n, 0),
+        CoreOp::Offset(_, n)
+        | CoreOp::BitwiseNot(n)
+        | CoreOp::Neg(n)
+        | CoreOp::Not(n)
+        | CoreOp::Inc(n)
+        | CoreOp::Dec(n)
+        | CoreOp::IsNonNegative(n) => (*n, 0),
+        CoreOp::Index(n)
+        | CoreOp::BitwiseNand(n)
+        | CoreOp::BitwiseAnd(n)
+        | CoreOp::BitwiseOr(n)
+        | CoreOp::BitwiseXor(n)
+        | CoreOp::LeftShift(n)
+        | CoreOp::LogicalRightShift(n)
+        | CoreOp::ArithmeticRightShift(n)
+        | CoreOp::Add(n)
+        | CoreOp::Sub(n)
+        | CoreOp::Mul(n)
+        | CoreOp::Div(n)
+        | CoreOp::Rem(n)
+        | CoreOp::And(n)
+        | CoreOp::Or(n)
+        | CoreOp::Swap(n) => (*n, *n),
+        _ => (0, 0),
+    }
+}
+
+impl<T> Debuggable for CoreInterpreter<T>
+where
+    T: Device,
+{
+    type Program = CoreProgram;
+
+    fn instructions(code: &CoreProgram) -> Vec<StandardOp> {
+        StandardProgram::from(code.clone()).0
+    }
+
+    fn load(&mut self, code: &CoreProgram) {
+        self.program = Decoded::from(code).cloned();
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        // The program is moved out while it runs, so the interpreter can be borrowed mutably.
+        let program = ::std::mem::take(&mut self.program);
+        let result = CoreInterpreter::step(self, &program);
+        self.program = program;
+        result.map_err(|kind| kind.to_string())
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn instruction_pointer(&self) -> usize {
+        self.program.address(self.i)
+    }
+
+    fn register(&self) -> &[i64] {
+        &self.register
+    }
+
+    fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    fn tape(&self) -> &[i64] {
+        &self.cells
+    }
+
+    fn call_stack(&self) -> Vec<usize> {
+        self.calls
+            .iter()
+            .map(|&i| self.program.address(i))
+            .collect()
+    }
+
+    fn deref_stack(&self) -> &[usize] {
+        &self.refs
+    }
+}
@@ -0,0 +1,492 @@
+//! # Debugger Module
+//!
+//! This module implements an interactive step debugger for the virtual machine
+//! interpreters. The debugger runs a program one instruction at a time, and lets
+//! the user set breakpoints on instructions or source code lines, watch the register
+//! and cells on the tape for changes, and inspect the call and dereference stacks.
+//!
+//! Instructions are numbered the same way as in the annotated listing of the
+//! virtual machine code (`sage -t core-vm -d ...`), where comments are not counted.
+//! Source code lines are found using the program's source map.
+use crate::{
+    parse::SourceCodeLocation,
+    vm::{CoreOp, SourceMap, StandardOp},
+};
+use ::std::{
+    collections::BTreeSet,
+    io::{Result as IoResult, Write},
+};
+
+/// The number of instructions shown before and after the current instruction by `list`.
+const LIST_RADIUS: usize = 5;
+/// The number of cells shown by `print` when no count is given.
+const PRINT_CELLS: usize = 8;
+
+const HELP: &str = "Commands:
+  step [n]          (s)  run the next n instructions (default 1)
+  next              (n)  run until the next source line
+  continue          (c)  run until a breakpoint, a watched value changes, or the program ends
+  break <instr>     (b)  break before the instruction with the given index
+  break line <line>      break when the program reaches the given source line
+  watch reg         (w)  stop when the register changes
+  watch <addr>           stop when the cell at the given address changes
+  clear                  remove all breakpoints and watches
+  print [addr [n]]  (p)  print the register, and n cells on the tape (default: at the pointer)
+  stack             (bt) print the call stack and the dereference stack
+  list              (l)  list the instructions around the current instruction
+  help              (h)  print this message
+  quit              (q)  stop debugging
+Numbers may be written in decimal, or in hexadecimal with a `0x` prefix.";
+
+/// An interpreter which can be stepped through by the debugger.
+pub trait Debuggable {
+    /// The type of program the interpreter runs.
+    type Program;
+
+    /// Get the instructions of a program, in the order they are indexed by the interpreter.
+    fn instructions(code: &Self::Program) -> Vec<StandardOp>;
+    /// Decode a program and start stepping through it from the beginning.
+    fn load(&mut self, code: &Self::Program);
+    /// Run the next instruction of the loaded program. Comments are skipped.
+    fn step(&mut self) -> Result<(), String>;
+    /// Has the interpreter finished running the program?
+    fn is_done(&self) -> bool;
+    /// The index of the next instruction to run.
+    fn instruction_pointer(&self) -> usize;
+    /// The contents of the register.
+    fn register(&self) -> &[i64];
+    /// The position of the pointer on the tape.
+    fn pointer(&self) -> usize;
+    /// The cells on the tape which have been used so far.
+    fn tape(&self) -> &[i64];
+    /// The instruction pointers saved on the call stack.
+    fn call_stack(&self) -> Vec<usize>;
+    /// The tape pointers saved on the dereference stack.
+    fn deref_stack(&self) -> &[usize];
+}
+
+/// A place where the debugger stops the program.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Breakpoint {
+    /// Stop before running the instruction with this index.
+    Instruction(usize),
+    /// Stop when the program reaches this line in the source code.
+    Line(usize),
+}
+
+/// A value which the debugger watches for changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Watch {
+    /// The first cell of the register.
+    Register,
+    /// The cell on the tape at this address.
+    Cell(usize),
+}
+
+/// An interactive debugger for a program running in an interpreter.
+pub struct Debugger<I>
+where
+    I: Debuggable,
+{
+    /// The interpreter running the program.
+    interpreter: I,
+    /// The instructions of the program.
+    ops: Vec<StandardOp>,
+    /// The index of each instruction in the listing of the program,
+    /// which doesn't count comments.
+    numbers: Vec<usize>,
+    /// The source code location of each instruction, if it's known.
+    map: SourceMap,
+    /// The source code of the program, used to show the current line.
+    source: Option<String>,
+    /// The breakpoints set by the user.
+    breakpoints: BTreeSet<Breakpoint>,
+    /// The values watched by the user, and their last known values.
+    watches: Vec<(Watch, i64)>,
+    /// The error which stopped the program, if any.
+    error: Option<String>,
+}
+
+impl<I> Debugger<I>
+where
+    I: Debuggable,
+{
+    /// Create a debugger for a program, which will be run by the given interpreter.
+    pub fn new(mut interpreter: I, code: I::Program) -> Self {
+        let ops = I::instructions(&code);
+        interpreter.load(&code);
+
+        let map = SourceMap::new(&ops);
+        let mut numbers = Vec::with_capacity(ops.len());
+        let mut number = 0;
+        for op in &ops {
+            numbers.push(number);
+            if !matches!(op, StandardOp::CoreOp(CoreOp::Comment(_))) {
+                number += 1;
+            }
+        }
+
+        Self {
+            interpreter,
+            ops,
+            numbers,
+            map,
+            source: None,
+            breakpoints: BTreeSet::new(),
+            watches: vec![],
+            error: None,
+        }
+    }
+
+    /// Find the source code locations of the instructions with the program's source map.
+    /// Without one, only the procedures of the instructions are known.
+    pub fn with_source_map(mut self, map: SourceMap) -> Self {
+        self.map = map;
+        self
+    }
+
+    /// Show lines from this source code when stopping the program.
+    pub fn with_source(mut self, source: impl ToString) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    /// Get the interpreter running the program.
+    pub fn interpreter(&self) -> &I {
+        &self.interpreter
+    }
+
+    /// Is the program finished (or stopped by an error)?
+    pub fn is_finished(&self) -> bool {
+        self.error.is_some()
+            || self.interpreter.is_done()
+            || self.interpreter.instruction_pointer() >= self.ops.len()
+    }
+
+    /// Get the index of the next instruction to run, as it appears in the listing of the program.
+    pub fn current_instruction(&self) -> Option<usize> {
+        self.numbers
+            .get(self.interpreter.instruction_pointer())
+            .copied()
+            .filter(|_| !self.is_finished())
+    }
+
+    /// Get the source code location of the next instruction to run, if it's known.
+    pub fn current_location(&self) -> Option<&SourceCodeLocation> {
+        if self.is_finished() {
+            return None;
+        }
+        self.location_of(self.interpreter.instruction_pointer())
+    }
+
+    /// Set a breakpoint.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.insert(breakpoint);
+    }
+
+    /// Watch a value for changes.
+    pub fn add_watch(&mut self, watch: Watch) {
+        let value = self.watched_value(watch);
+        self.watches.push((watch, value));
+    }
+
+    /// Print the current state of the debugger before any commands are run.
+    pub fn start(&self, output: &mut impl Write) -> IoResult<()> {
+        writeln!(output, "Type `help` for a list of commands.")?;
+        self.show_position(output)
+    }
+
+    /// Execute a debugger command, writing the results to the output.
+    /// This returns `false` when the user wants to stop debugging.
+    pub fn execute(&mut self, command: &str, output: &mut impl Write) -> IoResult<bool> {
+        let words = command.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            [] => {}
+            ["step" | "s"] => self.resume(output, |_| true)?,
+            ["step" | "s", n] => match parse_number(n) {
+                Some(n) => {
+                    let mut remaining = n;
+                    self.resume(output, |_| {
+                        remaining = remaining.saturating_sub(1);
+                        remaining == 0
+                    })?
+                }
+                None => writeln!(output, "Invalid number of steps `{n}`")?,
+            },
+            ["next" | "n"] => {
+                let start = self.current_location().map(|loc| loc.line);
+                self.resume(output, |debugger| {
+                    debugger
+                        .current_location()
+                        .is_some_and(|loc| Some(loc.line) != start)
+                })?
+            }
+            ["continue" | "c"] => self.resume(output, |_| false)?,
+            ["break" | "b", "line", line] => match parse_number(line) {
+                Some(line) => {
+                    self.add_breakpoint(Breakpoint::Line(line));
+                    writeln!(output, "Breakpoint set at line {line}")?
+                }
+                None => writeln!(output, "Invalid line number `{line}`")?,
+            },
+            ["break" | "b", instruction] => match parse_number(instruction) {
+                Some(n) if n < self.instruction_count() => {
+                    self.add_breakpoint(Breakpoint::Instruction(n));
+                    writeln!(output, "Breakpoint set at instruction {n:08x}")?
+                }
+                _ => writeln!(output, "Invalid instruction index `{instruction}`")?,
+            },
+            ["watch" | "w", "reg"] => {
+                self.add_watch(Watch::Register);
+                writeln!(output, "Watching the register")?
+            }
+            ["watch" | "w", addr] => match parse_number(addr) {
+                Some(addr) => {
+                    self.add_watch(Watch::Cell(addr));
+                    writeln!(output, "Watching cell {addr}")?
+                }
+                None => writeln!(output, "Invalid address `{addr}`")?,
+            },
+            ["clear"] => {
+                self.breakpoints.clear();
+                self.watches.clear();
+                writeln!(output, "Removed all breakpoints and watches")?
+            }
+            ["print" | "p"] => self.print_state(self.interpreter.pointer(), PRINT_CELLS, output)?,
+            ["print" | "p", addr] | ["print" | "p", addr, _] => {
+                let count = words.get(2).map(|n| parse_number(n));
+                match (parse_number(addr), count) {
+                    (Some(addr), None) => self.print_state(addr, PRINT_CELLS, output)?,
+                    (Some(addr), Some(Some(count))) => self.print_state(addr, count, output)?,
+                    _ => writeln!(output, "Invalid address or cell count")?,
+                }
+            }
+            ["stack" | "bt"] => self.print_stacks(output)?,
+            ["list" | "l"] => self.print_listing(output)?,
+            ["help" | "h"] => writeln!(output, "{HELP}")?,
+            ["quit" | "q"] => return Ok(false),
+            _ => writeln!(
+                output,
+                "Unknown command `{}`, type `help` for a list of commands",
+                command.trim()
+            )?,
+        }
+        Ok(true)
+    }
+
+    /// Run the program until `should_stop` returns true after an instruction,
+    /// a breakpoint is hit, a watched value changes, or the program finishes.
+    fn resume(
+        &mut self,
+        output: &mut impl Write,
+        mut should_stop: impl FnMut(&Self) -> bool,
+    ) -> IoResult<()> {
+        if self.is_finished() {
+            return writeln!(output, "The program is not running");
+        }
+
+        loop {
+            let previous_line = self.current_location().map(|loc| loc.line);
+            if let Err(e) = self.step_instruction() {
+                writeln!(output, "Error: {e}")?;
+                self.error = Some(e);
+                break;
+            }
+
+            if self.is_finished() {
+                writeln!(output, "The program finished")?;
+                break;
+            }
+
+            if self.report_watches(output)? {
+                break;
+            }
+
+            if let Some(breakpoint) = self.hit_breakpoint(previous_line) {
+                match breakpoint {
+                    Breakpoint::Instruction(n) => {
+                        writeln!(output, "Breakpoint at instruction {n:08x}")?
+                    }
+                    Breakpoint::Line(line) => writeln!(output, "Breakpoint at line {line}")?,
+                }
+                break;
+            }
+
+            if should_stop(self) {
+                break;
+            }
+        }
+        self.show_position(output)
+    }
+
+    /// Run the next instruction.
+    fn step_instruction(&mut self) -> Result<(), String> {
+        self.interpreter.step()
+    }
+
+    /// Get the breakpoint at the current instruction, if there is one.
+    /// Line breakpoints are only hit when the program enters the line from another line.
+    fn hit_breakpoint(&self, previous_line: Option<usize>) -> Option<Breakpoint> {
+        let instruction = Breakpoint::Instruction(self.current_instruction()?);
+        if self.breakpoints.contains(&instruction) {
+            return Some(instruction);
+        }
+
+        let line = self.current_location()?.line;
+        if Some(line) != previous_line && self.breakpoints.contains(&Breakpoint::Line(line)) {
+            return Some(Breakpoint::Line(line));
+        }
+        None
+    }
+
+    /// Print the watched values which have changed, and update their last known values.
+    /// This returns whether any values changed.
+    fn report_watches(&mut self, output: &mut impl Write) -> IoResult<bool> {
+        let mut changed = false;
+        for i in 0..self.watches.len() {
+            let (watch, old) = self.watches[i];
+            let new = self.watched_value(watch);
+            if new != old {
+                match watch {
+                    Watch::Register => writeln!(output, "Register changed: {old} -> {new}")?,
+                    Watch::Cell(addr) => writeln!(output, "Cell {addr} changed: {old} -> {new}")?,
+                }
+                self.watches[i].1 = new;
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Get the current value of a watched value.
+    fn watched_value(&self, watch: Watch) -> i64 {
+        match watch {
+            Watch::Register => self.interpreter.register().first().copied().unwrap_or(0),
+            Watch::Cell(addr) => self.cell(addr),
+        }
+    }
+
+    /// Get the value of a cell on the tape. Cells which haven't been used yet are zero.
+    fn cell(&self, addr: usize) -> i64 {
+        self.interpreter.tape().get(addr).copied().unwrap_or(0)
+    }
+
+    /// The number of instructions in the listing of the program.
+    fn instruction_count(&self) -> usize {
+        self.ops
+            .iter()
+            .filter(|op| !matches!(op, StandardOp::CoreOp(CoreOp::Comment(_))))
+            .count()
+    }
+
+    /// Get the source code location of the instruction at the given index in the program.
+    fn location_of(&self, i: usize) -> Option<&SourceCodeLocation> {
+        self.map.location(i)
+    }
+
+    /// Print the instruction which will run next, and its location in the source code.
+    fn show_position(&self, output: &mut impl Write) -> IoResult<()> {
+        if let Some(e) = &self.error {
+            return writeln!(output, "The program stopped with an error: {e}");
+        }
+        if self.is_finished() {
+            return Ok(());
+        }
+
+        let ip = self.interpreter.instruction_pointer();
+        writeln!(output, "=> {:08x}: {}", self.numbers[ip], self.ops[ip])?;
+        if let Some(loc) = self.location_of(ip) {
+            write!(output, "   at {loc}")?;
+            match self.source_line(loc.line) {
+                Some(code) => writeln!(output, ": {}", code.trim())?,
+                None => writeln!(output)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Get a line of the source code, if the source code is known.
+    fn source_line(&self, line: usize) -> Option<&str> {
+        self.source.as_ref()?.lines().nth(line.checked_sub(1)?)
+    }
+
+    /// Print the register, the tape pointer, and `count` cells on the tape starting at `addr`.
+    fn print_state(&self, addr: usize, count: usize, output: &mut impl Write) -> IoResult<()> {
+        writeln!(
+            output,
+            "register: {}",
+            self.interpreter.register().first().copied().unwrap_or(0)
+        )?;
+        writeln!(output, "pointer: {}", self.interpreter.pointer())?;
+        let cells = (addr..addr + count)
+            .map(|addr| self.cell(addr).to_string())
+            .collect::<Vec<_>>();
+        writeln!(
+            output,
+            "cells {addr}..{}: [{}]",
+            addr + count,
+            cells.join(", ")
+        )
+    }
+
+    /// Print the call stack and the dereference stack, with the most recent entries first.
+    fn print_stacks(&self, output: &mut impl Write) -> IoResult<()> {
+        writeln!(output, "call stack:")?;
+        if !self.is_finished() {
+            self.print_frame(0, self.interpreter.instruction_pointer(), output)?;
+        }
+        for (depth, ip) in self.interpreter.call_stack().iter().rev().enumerate() {
+            self.print_frame(depth + 1, *ip, output)?;
+        }
+
+        writeln!(output, "deref stack:")?;
+        for (depth, pointer) in self.interpreter.deref_stack().iter().rev().enumerate() {
+            writeln!(output, "  #{depth} {pointer}")?;
+        }
+        Ok(())
+    }
+
+    /// Print a frame of the call stack, at the instruction with the given index in the program.
+    fn print_frame(&self, depth: usize, ip: usize, output: &mut impl Write) -> IoResult<()> {
+        let number = self.numbers.get(ip).copied().unwrap_or_default();
+        write!(output, "  #{depth} {number:08x}")?;
+        match self.location_of(ip) {
+            Some(loc) => writeln!(output, " at {loc}"),
+            None => writeln!(output),
+        }
+    }
+
+    /// Print the instructions around the current instruction.
+    fn print_listing(&self, output: &mut impl Write) -> IoResult<()> {
+        let current = match self.current_instruction() {
+            Some(n) => n,
+            None => return writeln!(output, "The program is not running"),
+        };
+
+        for (i, op) in self.ops.iter().enumerate() {
+            if matches!(op, StandardOp::CoreOp(CoreOp::Comment(_))) {
+                continue;
+            }
+            let number = self.numbers[i];
+            if number + LIST_RADIUS < current || number > current + LIST_RADIUS {
+                continue;
+            }
+            let marker = if number == current { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&Breakpoint::Instruction(number)) {
+                "*"
+            } else {
+                " "
+            };
+            writeln!(output, "{marker}{breakpoint}{number:08x}: {op}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a number written in decimal, or in hexadecimal with a `0x` prefix.
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
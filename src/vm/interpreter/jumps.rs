@@ -0,0 +1,223 @@
+//! # Jump Table Module
+//!
+//! This module implements the jump tables the interpreters use for control flow.
+//! Instead of scanning the program for the matching `Else` or `End` every time a
+//! branch or loop runs, the interpreters match up every block in the program once,
+//! and then jump straight to the matching instruction.
+//!
+//! Before a program is run, it's decoded into a compact array of its instructions
+//! without comments, which the jump table and the interpreter's instruction pointer
+//! index into. The address of each instruction in the original program is kept,
+//! for reporting traps, profiling, and debugging.
+use crate::vm::{CoreOp, CoreProgram, StandardOp, StandardProgram};
+
+/// The instructions which start, continue, or end a block of code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Block {
+    If,
+    Else,
+    While,
+    Function,
+    End,
+    /// Any other instruction.
+    Other,
+}
+
+impl From<&CoreOp> for Block {
+    fn from(op: &CoreOp) -> Self {
+        match op {
+            CoreOp::If => Self::If,
+            CoreOp::Else => Self::Else,
+            CoreOp::While => Self::While,
+            CoreOp::Function => Self::Function,
+            CoreOp::End => Self::End,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl From<&StandardOp> for Block {
+    fn from(op: &StandardOp) -> Self {
+        match op {
+            StandardOp::CoreOp(op) => Self::from(op),
+            _ => Self::Other,
+        }
+    }
+}
+
+/// The matching instructions for every block in a program, and the
+/// addresses of its functions.
+#[derive(Clone, Debug, Default)]
+pub(super) struct JumpTable {
+    /// The instruction to jump to from each instruction which starts or ends a block:
+    ///
+    /// - `If` jumps to its `Else` (or its `End`, if it has no `Else`) when the condition is false.
+    /// - `Else`, `While`, and `Function` jump to their `End`.
+    /// - The `End` of a `While` jumps back to the `While`.
+    ///
+    /// Blocks without an `End` jump to the end of the program. Every other
+    /// instruction is `None`.
+    targets: Vec<Option<usize>>,
+    /// The address of each function in the program. `functions[N]` is the
+    /// instruction pointer of the Nth function's code.
+    functions: Vec<usize>,
+}
+
+impl JumpTable {
+    /// Match up the blocks in a program with the given instructions.
+    fn new(blocks: impl ExactSizeIterator<Item = Block>) -> Self {
+        let len = blocks.len();
+        let mut targets = vec![None; len];
+        let mut functions = vec![];
+        // The instructions which started each of the blocks we're in, and their `Else`s.
+        let mut open: Vec<(Block, usize, Vec<usize>)> = vec![];
+
+        for (i, block) in blocks.enumerate() {
+            match block {
+                Block::If | Block::While | Block::Function => {
+                    if block == Block::Function {
+                        functions.push(i);
+                    }
+                    open.push((block, i, vec![]));
+                }
+                Block::Else => match open.last_mut() {
+                    Some((_, _, elses)) => elses.push(i),
+                    // An `Else` outside of any block skips the rest of the program.
+                    None => targets[i] = Some(len),
+                },
+                Block::End => {
+                    let Some((block, start, elses)) = open.pop() else {
+                        continue;
+                    };
+                    targets[start] = Some(match (block, elses.first()) {
+                        (Block::If, Some(&first_else)) => first_else,
+                        _ => i,
+                    });
+                    for else_ in elses {
+                        targets[else_] = Some(i);
+                    }
+                    if block == Block::While {
+                        targets[i] = Some(start);
+                    }
+                }
+                Block::Other => {}
+            }
+        }
+
+        // Any blocks which weren't ended skip the rest of the program.
+        for (_, start, elses) in open {
+            targets[start] = Some(len);
+            for else_ in elses {
+                targets[else_] = Some(len);
+            }
+        }
+
+        Self { targets, functions }
+    }
+
+    /// The instruction to jump to from the instruction at the given index.
+    pub(super) fn target(&self, i: usize) -> Option<usize> {
+        self.targets.get(i).copied().flatten()
+    }
+
+    /// The address of the Nth function in the program, if it exists.
+    pub(super) fn function(&self, n: i64) -> Option<usize> {
+        usize::try_from(n)
+            .ok()
+            .and_then(|n| self.functions.get(n).copied())
+    }
+}
+
+/// A program decoded for an interpreter: its instructions without comments,
+/// and the jump table for them.
+///
+/// Programs which are run all at once are decoded into references to their
+/// instructions, and programs which are stepped through by a debugger own
+/// a copy of them.
+pub(super) struct Decoded<Op> {
+    /// The instructions of the program, without comments.
+    ops: Vec<Op>,
+    /// The address of each instruction in the original program, followed
+    /// by the length of the original program.
+    addresses: Vec<usize>,
+    /// The jump table for the decoded instructions.
+    jumps: JumpTable,
+}
+
+impl<'a, Op> Decoded<&'a Op> {
+    /// Decode the given instructions, leaving out the comments.
+    fn new(program: &'a [Op], is_comment: impl Fn(&Op) -> bool) -> Self
+    where
+        for<'b> Block: From<&'b Op>,
+    {
+        let (mut addresses, ops): (Vec<usize>, Vec<&Op>) = program
+            .iter()
+            .enumerate()
+            .filter(|(_, op)| !is_comment(op))
+            .unzip();
+        addresses.push(program.len());
+        let jumps = JumpTable::new(ops.iter().map(|&op| Block::from(op)));
+        Self {
+            ops,
+            addresses,
+            jumps,
+        }
+    }
+
+    /// Copy the instructions, so the decoded program doesn't borrow the original program.
+    pub(super) fn cloned(self) -> Decoded<Op>
+    where
+        Op: Clone,
+    {
+        Decoded {
+            ops: self.ops.into_iter().cloned().collect(),
+            addresses: self.addresses,
+            jumps: self.jumps,
+        }
+    }
+}
+
+impl<Op> Decoded<Op> {
+    /// Fetch the instruction at the given index.
+    pub(super) fn fetch(&self, i: usize) -> Option<&Op> {
+        self.ops.get(i)
+    }
+
+    /// The address in the original program of the instruction at the given index.
+    /// Indices past the last instruction are the end of the original program.
+    pub(super) fn address(&self, i: usize) -> usize {
+        match self.addresses.get(i) {
+            Some(&addr) => addr,
+            None => self.addresses.last().copied().unwrap_or(0),
+        }
+    }
+
+    /// The jump table for the decoded instructions.
+    pub(super) fn jumps(&self) -> &JumpTable {
+        &self.jumps
+    }
+}
+
+impl<Op> Default for Decoded<Op> {
+    fn default() -> Self {
+        Self {
+            ops: vec![],
+            addresses: vec![0],
+            jumps: JumpTable::default(),
+        }
+    }
+}
+
+impl<'a> From<&'a CoreProgram> for Decoded<&'a CoreOp> {
+    fn from(program: &'a CoreProgram) -> Self {
+        Self::new(&program.0, |op| matches!(op, CoreOp::Comment(_)))
+    }
+}
+
+impl<'a> From<&'a StandardProgram> for Decoded<&'a StandardOp> {
+    fn from(program: &'a StandardProgram) -> Self {
+        Self::new(&program.0, |op| {
+            matches!(op, StandardOp::CoreOp(CoreOp::Comment(_)))
+        })
+    }
+}
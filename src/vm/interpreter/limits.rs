@@ -0,0 +1,105 @@
+//! # Limits Module
+//!
+//! This module implements the limits on the resources an interpreter lets a program
+//! use, for running untrusted code. A program can be limited in the number of
+//! instructions it executes, the size of its tape, the depth of its call stack, and
+//! how long it runs. When a program hits a limit, the interpreter stops it with a trap.
+use super::TrapKind;
+use ::std::time::{Duration, Instant};
+
+/// The number of instructions executed between checks of the time limit,
+/// since reading the clock is much slower than running an instruction.
+const TIMEOUT_CHECK_INTERVAL: u64 = 4096;
+
+/// The limits on the resources a program can use while it runs.
+/// By default, a program can use as much as it likes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum number of instructions the program can execute.
+    pub instructions: Option<u64>,
+    /// The maximum number of cells on the tape, including the heap.
+    pub tape_size: Option<usize>,
+    /// The maximum number of procedure calls on the call stack.
+    pub call_depth: Option<usize>,
+    /// The maximum time the program can run for.
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    /// Limit the number of instructions the program can execute.
+    pub fn with_instructions(mut self, instructions: u64) -> Self {
+        self.instructions = Some(instructions);
+        self
+    }
+
+    /// Limit the number of cells on the tape, including the heap.
+    pub fn with_tape_size(mut self, tape_size: usize) -> Self {
+        self.tape_size = Some(tape_size);
+        self
+    }
+
+    /// Limit the number of procedure calls on the call stack.
+    pub fn with_call_depth(mut self, call_depth: usize) -> Self {
+        self.call_depth = Some(call_depth);
+        self
+    }
+
+    /// Limit the time the program can run for.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Check that the tape can grow to the given number of cells.
+    pub(super) fn check_tape_size(&self, len: usize) -> Result<(), TrapKind> {
+        match self.tape_size {
+            Some(max) if len > max => Err(TrapKind::TapeLimit(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that another procedure can be called with the given depth of the call stack.
+    pub(super) fn check_call_depth(&self, depth: usize) -> Result<(), TrapKind> {
+        match self.call_depth {
+            Some(max) if depth >= max => Err(TrapKind::CallDepthLimit(max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Measures the instructions a program executes and the time it runs for,
+/// against the limits on the interpreter running it.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Meter {
+    /// The number of instructions executed so far.
+    executed: u64,
+    /// When the program started running, if it has a time limit.
+    started: Option<Instant>,
+}
+
+impl Meter {
+    /// Start measuring a program as it starts running.
+    pub(super) fn start(limits: &Limits) -> Self {
+        Self {
+            executed: 0,
+            // The clock is only read if it's needed, since it's unavailable on some platforms.
+            started: limits.timeout.map(|_| Instant::now()),
+        }
+    }
+
+    /// Count an instruction which is about to be executed, and check the limits.
+    pub(super) fn tick(&mut self, limits: &Limits) -> Result<(), TrapKind> {
+        self.executed += 1;
+        if let Some(max) = limits.instructions {
+            if self.executed > max {
+                return Err(TrapKind::InstructionLimit(max));
+            }
+        }
+        if let (Some(timeout), Some(started)) = (limits.timeout, self.started) {
+            if self.executed.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && started.elapsed() > timeout {
+                return Err(TrapKind::Timeout(timeout));
+            }
+        }
+        Ok(())
+    }
+}
@@ -6,7 +6,7 @@
 //! supplying the input and handling the output of the program. For testing the compiler,
 //! assembler, and virtual machine, we use a `TestingDevice` object to supply sample input
 //! and capture the output to test against the predicted output.
-use crate::side_effects::{FFIBinding, Input, InputMode, Output, OutputMode};
+use crate::side_effects::{FFIBinding, FFICall, FFIFunction, Input, InputMode, Output, OutputMode};
 
 use log::{error, trace, warn};
 
@@ -14,10 +14,22 @@ mod core;
 pub use self::core::*;
 mod std;
 pub use self::std::*;
+mod debug;
+pub use self::debug::*;
+mod trap;
+pub use self::trap::*;
+mod profile;
+pub use self::profile::*;
+mod limits;
+pub use self::limits::*;
+mod jumps;
+use self::jumps::*;
 
 use ::std::{
     collections::{HashMap, VecDeque},
-    io::{stdin, stdout, Read, Write},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::{stderr, stdin, stdout, Read, Write},
+    sync::{Arc, Mutex, PoisonError},
 };
 
 /// Create an input / output device for the virtual machine interpreter
@@ -40,9 +52,10 @@ pub trait Device {
 
     /// FFI call to the device. This will get the FFI binding for the device
     /// and call the function associated with the binding. If the tape is
-    /// provided, the foreign function may mutate the tape. Otherwise all
-    /// interaction with the FFI is done through the FFI channel.
-    fn ffi_call(&mut self, ffi: &FFIBinding, tape: Option<&mut Vec<i64>>) -> Result<(), String>;
+    /// provided, the foreign function may mutate the cells on the tape, but
+    /// it can't grow the tape. Otherwise all interaction with the FFI is done
+    /// through the FFI channel.
+    fn ffi_call(&mut self, ffi: &FFIBinding, tape: Option<&mut [i64]>) -> Result<(), String>;
 }
 
 /// A device used for testing the compiler. This simply keeps a buffer
@@ -51,9 +64,9 @@ pub trait Device {
 ///
 /// The tests interpret the program and populate the device with output.
 /// Then, we check the devices output against the correct output.
-#[derive(Debug, Default)]
+#[derive(Default)]
 pub struct TestingDevice {
-    pub ffi: HashMap<FFIBinding, fn(&mut VecDeque<i64>, Option<&mut Vec<i64>>)>,
+    pub ffi: HashMap<FFIBinding, FFIFunction>,
     pub ffi_channel: VecDeque<i64>,
     pub input: VecDeque<i64>,
     pub output: Vec<(i64, Output)>,
@@ -74,6 +87,16 @@ impl TestingDevice {
         }
     }
 
+    /// Bind a host function to a foreign function, replacing any previous binding.
+    pub fn add_binding(
+        &mut self,
+        ffi: FFIBinding,
+        f: impl FnMut(&mut FFICall) -> Result<(), String> + Send + 'static,
+    ) {
+        trace!("Adding ffi binding to testing device: {}", ffi);
+        self.ffi.insert(ffi, Box::new(f));
+    }
+
     pub fn new_raw(input: Vec<i64>) -> Self {
         Self {
             ffi: HashMap::new(),
@@ -175,10 +198,13 @@ impl TestingDevice {
 
     /// Get the output of the testing device as a string (ascii).
     pub fn output_str(&self) -> String {
-        let mut result = String::new();
-        for (ch, _) in &self.output {
-            result.push(*ch as i8 as u8 as char)
-        }
+        // The characters are the bytes of UTF-8 encoded text.
+        let bytes = self
+            .output
+            .iter()
+            .map(|(ch, _)| *ch as u8)
+            .collect::<Vec<_>>();
+        let result = String::from_utf8_lossy(&bytes).to_string();
         trace!("Output from testing device: {}", result);
         result
     }
@@ -188,6 +214,17 @@ impl TestingDevice {
     }
 }
 
+impl Debug for TestingDevice {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("TestingDevice")
+            .field("ffi", &self.ffi.keys().collect::<Vec<_>>())
+            .field("ffi_channel", &self.ffi_channel)
+            .field("input", &self.input)
+            .field("output", &self.output)
+            .finish()
+    }
+}
+
 /// Make the testing device work with the interpreter.
 impl Device for TestingDevice {
     fn get(&mut self, src: Input) -> Result<i64, String> {
@@ -238,11 +275,12 @@ impl Device for TestingDevice {
         Ok(())
     }
 
-    fn ffi_call(&mut self, ffi: &FFIBinding, tape: Option<&mut Vec<i64>>) -> Result<(), String> {
-        if let Some(f) = self.ffi.get(ffi) {
+    fn ffi_call(&mut self, ffi: &FFIBinding, tape: Option<&mut [i64]>) -> Result<(), String> {
+        if let Some(f) = self.ffi.get_mut(ffi) {
             trace!("Calling FFI: {}", ffi);
-            f(&mut self.ffi_channel, tape);
-            Ok(())
+            let mut call = FFICall::new(ffi, &mut self.ffi_channel, tape);
+            f(&mut call)?;
+            call.finish()
         } else {
             error!("FFI call not found: {:?}", ffi);
             Err(format!("ffi call not found: {:?}", ffi))
@@ -253,12 +291,23 @@ impl Device for TestingDevice {
 /// A device used for standard input and output.
 /// This simply retrieves a character from standard-in with `get`,
 /// and writes a character to standard-out with `put`.
-#[derive(Debug, Clone)]
+///
+/// Clones of the device share its bindings, and any state they keep between calls.
+#[derive(Clone)]
 pub struct StandardDevice {
-    ffi: HashMap<FFIBinding, fn(&mut VecDeque<i64>, Option<&mut Vec<i64>>)>,
+    ffi: HashMap<FFIBinding, Arc<Mutex<FFIFunction>>>,
     ffi_channel: VecDeque<i64>,
 }
 
+impl Debug for StandardDevice {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("StandardDevice")
+            .field("ffi", &self.ffi.keys().collect::<Vec<_>>())
+            .field("ffi_channel", &self.ffi_channel)
+            .finish()
+    }
+}
+
 impl Default for StandardDevice {
     fn default() -> Self {
         let mut result = Self {
@@ -266,18 +315,15 @@ impl Default for StandardDevice {
             ffi_channel: VecDeque::new(),
         };
 
-        result.add_binding(
-            FFIBinding::new("square_root".to_string(), 1, 1),
-            |channel, _| {
-                let val = as_float(channel.pop_front().unwrap());
-                channel.push_back(as_int(val.sqrt()));
-            },
-        );
-
-        result.add_binding(FFIBinding::new("add".to_string(), 2, 1), |channel, _| {
-            let a = as_float(channel.pop_front().unwrap());
-            let b = as_float(channel.pop_front().unwrap());
-            channel.push_back(as_int(a + b));
+        result.add_binding(FFIBinding::new("square_root".to_string(), 1, 1), |call| {
+            let val = call.float_arg()?;
+            call.ret_float(val.sqrt())
+        });
+
+        result.add_binding(FFIBinding::new("add".to_string(), 2, 1), |call| {
+            let a = call.float_arg()?;
+            let b = call.float_arg()?;
+            call.ret_float(a + b)
         });
 
         result
@@ -285,13 +331,15 @@ impl Default for StandardDevice {
 }
 
 impl StandardDevice {
+    /// Bind a host function to a foreign function, replacing any previous binding.
+    /// The function can keep state between calls, like a handle to a host resource.
     pub fn add_binding(
         &mut self,
         ffi: FFIBinding,
-        f: fn(&mut VecDeque<i64>, Option<&mut Vec<i64>>),
+        f: impl FnMut(&mut FFICall) -> Result<(), String> + Send + 'static,
     ) {
         trace!("Adding ffi binding to VM interpreter: {}", ffi);
-        self.ffi.insert(ffi, f);
+        self.ffi.insert(ffi, Arc::new(Mutex::new(Box::new(f))));
     }
 
     fn get_char(&mut self) -> Result<char, String> {
@@ -372,12 +420,21 @@ impl Device for StandardDevice {
     }
 
     fn put(&mut self, val: i64, dst: Output) -> Result<(), String> {
-        // Print the character without a newline
+        // Print the character without a newline. Characters are written as raw bytes,
+        // so that UTF-8 encoded text is printed correctly.
         match dst.mode {
-            OutputMode::StdoutChar => print!("{}", val as u8 as char),
+            OutputMode::StdoutChar => {
+                if stdout().write_all(&[val as u8]).is_err() {
+                    return Err(String::from("could not write output"));
+                }
+            }
             OutputMode::StdoutInt => print!("{}", val),
             OutputMode::StdoutFloat => print!("{:?}", as_float(val)),
-            OutputMode::StderrChar => eprint!("{}", val as u8 as char),
+            OutputMode::StderrChar => {
+                if stderr().write_all(&[val as u8]).is_err() {
+                    return Err(String::from("could not write output"));
+                }
+            }
             OutputMode::StderrInt => eprint!("{}", val),
             OutputMode::StderrFloat => eprint!("{:?}", as_float(val)),
             _ => {
@@ -408,11 +465,14 @@ impl Device for StandardDevice {
         Ok(())
     }
 
-    fn ffi_call(&mut self, ffi: &FFIBinding, tape: Option<&mut Vec<i64>>) -> Result<(), String> {
+    fn ffi_call(&mut self, ffi: &FFIBinding, tape: Option<&mut [i64]>) -> Result<(), String> {
         if let Some(f) = self.ffi.get(ffi) {
             trace!("Calling FFI: {}", ffi);
-            f(&mut self.ffi_channel, tape);
-            Ok(())
+            // A binding which panicked in another clone of the device can still be called.
+            let mut f = f.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut call = FFICall::new(ffi, &mut self.ffi_channel, tape);
+            f(&mut call)?;
+            call.finish()
         } else {
             error!("FFI call not found: {:?}", ffi);
             Err(format!("ffi call not found: {:?}", ffi))
@@ -0,0 +1,209 @@
+//! # Profiler Module
+//!
+//! This module implements an instruction-level profiler for the virtual machine
+//! interpreters. While an interpreter runs a program, the profiler counts the
+//! instructions it executes, and attributes each of them to the procedure and the
+//! line of source code it came from (using the program's source map).
+//!
+//! The results can be reported as a flat profile, which lists the procedures and
+//! source lines where the program spent its time, or as folded stacks, which
+//! flame graph tools like `inferno` and `flamegraph.pl` read.
+use crate::vm::{CoreOp, CoreProgram, SourceMap, StandardOp, StandardProgram};
+use ::std::{
+    collections::HashMap,
+    fmt::{Display, Formatter, Result as FmtResult, Write},
+};
+
+/// The name of the frame for instructions outside of any named procedure.
+const MAIN: &str = "<main>";
+
+/// The number of instructions a program executed in each procedure and on each
+/// line of source code, recorded by an interpreter while it runs the program.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    /// The source map of the program being profiled.
+    map: SourceMap,
+    /// Is the instruction at each index a comment? Comments aren't counted.
+    comments: Vec<bool>,
+    /// The number of times each instruction was executed.
+    counts: Vec<u64>,
+    /// The index of each distinct call stack in `stack_counts`. A call stack is
+    /// the list of instructions which made each call, followed by the procedure
+    /// containing the current instruction.
+    stacks: HashMap<Vec<Option<String>>, usize>,
+    /// The number of instructions executed with each distinct call stack.
+    stack_counts: Vec<u64>,
+    /// The call stack of the last instruction executed, used to avoid looking up
+    /// the call stack again until a procedure is called or returns.
+    current: Option<(usize, Option<usize>, usize)>,
+}
+
+impl Profile {
+    /// Create an empty profile for a program with the given instructions.
+    pub fn new(ops: &[StandardOp]) -> Self {
+        Self {
+            map: SourceMap::new(ops),
+            comments: ops
+                .iter()
+                .map(|op| matches!(op, StandardOp::CoreOp(CoreOp::Comment(_))))
+                .collect(),
+            counts: vec![0; ops.len()],
+            stacks: HashMap::new(),
+            stack_counts: vec![],
+            current: None,
+        }
+    }
+
+    /// Attribute the instructions to the lines of source code given by the program's
+    /// source map. Without one, only the procedures of the instructions are known.
+    pub fn with_source_map(mut self, map: SourceMap) -> Self {
+        self.map = map;
+        self
+    }
+
+    /// Record that the interpreter is about to execute the instruction at the given
+    /// index, with the given instruction pointers saved on its call stack.
+    pub fn record(&mut self, i: usize, calls: &[usize]) {
+        self.record_with(i, calls, |i| i)
+    }
+
+    /// Record an instruction like `record`, where the instruction pointers are
+    /// turned into addresses in the profiled program with the given function.
+    pub(super) fn record_with(
+        &mut self,
+        i: usize,
+        calls: &[usize],
+        address: impl Fn(usize) -> usize,
+    ) {
+        let i = address(i);
+        if self.comments.get(i).copied().unwrap_or(true) {
+            return;
+        }
+        self.counts[i] += 1;
+
+        // The call stack only changes when a procedure is called or returns,
+        // which always changes the depth of the stack or the innermost call.
+        let depth = calls.len();
+        let caller = calls.last().copied();
+        let stack = match self.current {
+            Some((d, c, stack)) if d == depth && c == caller => stack,
+            _ => {
+                let frames = calls
+                    .iter()
+                    .map(|&call| address(call))
+                    .chain(::std::iter::once(i))
+                    .map(|i| self.map.procedure(i).map(str::to_string))
+                    .collect::<Vec<_>>();
+                let next = self.stack_counts.len();
+                let stack = *self.stacks.entry(frames).or_insert(next);
+                if stack == next {
+                    self.stack_counts.push(0);
+                }
+                self.current = Some((depth, caller, stack));
+                stack
+            }
+        };
+        self.stack_counts[stack] += 1;
+    }
+
+    /// The total number of instructions executed.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The number of instructions executed in each procedure, not counting the procedures
+    /// it called, sorted from the most to the fewest. Unnamed procedures are `None`.
+    pub fn procedures(&self) -> Vec<(Option<&str>, u64)> {
+        let mut procedures = HashMap::new();
+        for (i, &count) in self.counts.iter().enumerate() {
+            if count > 0 {
+                *procedures.entry(self.map.procedure(i)).or_insert(0) += count;
+            }
+        }
+        sorted_by_count(procedures)
+    }
+
+    /// The number of instructions executed on each line of source code, sorted from
+    /// the most to the fewest. The lines are given as the filename and the line number.
+    pub fn lines(&self) -> Vec<((Option<&str>, usize), u64)> {
+        let mut lines = HashMap::new();
+        for (i, &count) in self.counts.iter().enumerate() {
+            if let (true, Some(loc)) = (count > 0, self.map.location(i)) {
+                *lines
+                    .entry((loc.filename.as_deref(), loc.line))
+                    .or_insert(0) += count;
+            }
+        }
+        sorted_by_count(lines)
+    }
+
+    /// The number of instructions executed with each call stack, in the folded stack
+    /// format read by flame graph tools. Each line is the names of the procedures on the
+    /// stack, from the outermost to the innermost, separated by semicolons, followed by
+    /// the number of instructions executed.
+    pub fn folded_stacks(&self) -> String {
+        let mut stacks: HashMap<String, u64> = HashMap::new();
+        for (frames, &stack) in &self.stacks {
+            let name = frames
+                .iter()
+                .map(|frame| frame.as_deref().unwrap_or(MAIN))
+                .collect::<Vec<_>>()
+                .join(";");
+            *stacks.entry(name).or_insert(0) += self.stack_counts[stack];
+        }
+        let mut stacks = stacks.into_iter().collect::<Vec<_>>();
+        stacks.sort();
+
+        let mut result = String::new();
+        for (name, count) in stacks {
+            writeln!(result, "{name} {count}").unwrap();
+        }
+        result
+    }
+}
+
+impl From<&CoreProgram> for Profile {
+    fn from(program: &CoreProgram) -> Self {
+        Self::new(&StandardProgram::from(program.clone()).0)
+    }
+}
+
+impl From<&StandardProgram> for Profile {
+    fn from(program: &StandardProgram) -> Self {
+        Self::new(&program.0)
+    }
+}
+
+/// Display the flat profile of the program.
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let total = self.total();
+        // Get the percentage of the total instructions executed.
+        let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+
+        writeln!(f, "instructions executed: {total}")?;
+        writeln!(f)?;
+        writeln!(f, "{:>12} {:>7}  procedure", "self", "%")?;
+        for (name, count) in self.procedures() {
+            let name = name.unwrap_or(MAIN);
+            writeln!(f, "{count:>12} {:>6.2}%  {name}", percent(count))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:>12} {:>7}  line", "count", "%")?;
+        for ((filename, line), count) in self.lines() {
+            write!(f, "{count:>12} {:>6.2}%  ", percent(count))?;
+            if let Some(filename) = filename {
+                write!(f, "{filename}:")?;
+            }
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sort the counts from the most to the fewest, breaking ties by their keys.
+fn sorted_by_count<K: Ord>(counts: HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    counts
+}
@@ -3,7 +3,13 @@
 //! This module implements an interpreter for the Standard virtual machine
 //! variant.
 
-use crate::vm::{CoreOp, Device, StandardDevice, StandardOp, StandardProgram};
+use super::{Debuggable, Decoded, Limits, Meter, Profile, Trap, TrapKind};
+use crate::vm::{CoreOp, Device, SourceMap, StandardDevice, StandardOp, StandardProgram};
+use crate::NULL;
+use ::std::{borrow::Borrow, collections::BTreeMap};
+
+/// The number of cells left free for the stack before the heap is first allocated.
+const STACK_RESERVE: usize = 30000;
 
 /// A function to reinterpret the bits of an integer as a float.
 pub fn as_float(n: i64) -> f64 {
@@ -34,9 +40,9 @@ where
     register: Vec<i64>,
     /// The turing tape (composed of integer cells)
     cells: Vec<i64>,
-    /// The addresses of defined functions. `functions[N]` is the
-    /// instruction pointer for the Nth function's code.
-    functions: Vec<usize>,
+    /// The decoded program being stepped through by a debugger. Programs which
+    /// are run all at once are decoded when they're run instead.
+    program: Decoded<StandardOp>,
     /// The call stack of instruction pointers. Whenever a function
     /// is called, the instruction pointer is pushed here. Whenever
     /// a function returns, its instruction pointer is popped from here.
@@ -48,6 +54,18 @@ where
     i: usize,
     /// Is the interpreter finished interpreting?s
     done: bool,
+    /// The limits on the resources the program can use.
+    limits: Limits,
+    /// The source map of the program, used to report where errors happen in the
+    /// source code. Without one, errors only report the procedures they happen in.
+    map: Option<SourceMap>,
+    /// The blocks of memory currently allocated on the heap.
+    /// This maps the address of each block to its size.
+    allocated: BTreeMap<usize, usize>,
+    /// The blocks of memory which have been freed, and can be reused
+    /// by later allocations. This maps the address of each block to its size.
+    /// Adjacent free blocks are merged together.
+    free_blocks: BTreeMap<usize, usize>,
 }
 
 impl<T> StandardInterpreter<T>
@@ -60,19 +78,40 @@ where
             pointer: 0,
             register: vec![0; 1024],
             cells: vec![],
-            functions: vec![],
+            program: Decoded::default(),
             calls: vec![],
             refs: vec![],
             i: 0,
             done: false,
+            limits: Limits::default(),
+            map: None,
+            allocated: BTreeMap::new(),
+            free_blocks: BTreeMap::new(),
         }
     }
 
+    /// Limit the resources the program can use. A program which hits
+    /// a limit is stopped with a trap.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Report errors at the locations in the source code given by the program's source map.
+    pub fn with_source_map(mut self, map: SourceMap) -> Self {
+        self.map = Some(map);
+        self
+    }
+
     fn reg_scalar(&self) -> i64 {
-        self.register[0]
+        // The register is empty after loading zero cells.
+        self.register.first().copied().unwrap_or(0)
     }
 
     fn reg_mut_scalar(&mut self) -> &mut i64 {
+        if self.register.is_empty() {
+            self.register.push(0);
+        }
         &mut self.register[0]
     }
 
@@ -85,24 +124,24 @@ where
     }
 
     /// Fetch the current instruction pointed to in the program
-    fn fetch<'a>(&self, code: &'a StandardProgram) -> Option<&'a StandardOp> {
-        if self.i < code.0.len() {
-            Some(&code.0[self.i])
-        } else {
-            None
-        }
+    fn fetch<'a, Op: Borrow<StandardOp>>(
+        &self,
+        program: &'a Decoded<Op>,
+    ) -> Option<&'a StandardOp> {
+        program.fetch(self.i).map(Borrow::borrow)
     }
 
     /// Dereference the current pointer on the tape.
-    fn deref(&mut self) {
+    fn deref(&mut self) -> Result<(), TrapKind> {
         // Add the old pointer to the dereference stack.
         self.refs.push(self.pointer);
-        let cell = *self.get_cell();
+        let cell = *self.get_cell()?;
         if cell < 0 {
-            panic!("Dereferencing negative cell with value {cell:?}");
+            return Err(TrapKind::invalid_address(cell));
         }
         // Set the pointer to the address on the tape.
-        self.pointer = *self.get_cell() as usize;
+        self.pointer = *self.get_cell()? as usize;
+        Ok(())
     }
 
     /// Undo a dereference.
@@ -119,43 +158,17 @@ where
     }
 
     /// Call the Nth function defined in the program, where N is the value of the register.
-    fn call(&mut self, code: &StandardProgram) -> Result<(), String> {
-        // If the function has been defined
-        if self.functions.len() > self.reg_scalar() as usize {
-            // Push the current instruction pointer to the call stack
-            self.calls.push(self.i);
-            self.i = self.functions[self.reg_scalar() as usize];
-            Ok(())
-        } else {
-            // If the function hasn't been defined yet, we'll have to find it.
-
-            // Push the return address onto the call stack.
-            self.calls.push(self.i);
-            // Scan all the function definitions from the start of the program until we find it.
-            self.i = 0;
-            let mut count = -1;
-            while count < self.reg_scalar() {
-                // Every time we find a function, it will increment `count`.
-                match self.fetch(code) {
-                    Some(StandardOp::CoreOp(CoreOp::Function)) => {
-                        count += 1;
-                    }
-                    Some(_) => {}
-                    None => return Err(format!("function {} not defined", self.reg_scalar())),
-                }
-                // If `count` hasn't reached the function we want,
-                // keep going.
-                if count < self.reg_scalar() {
-                    self.i += 1;
-                }
-            }
-
-            // If we've reached the function we want, add it to the definitions.
-            if !self.functions.contains(&self.i) {
-                self.functions.push(self.i);
-                self.functions.sort()
+    fn call<Op>(&mut self, program: &Decoded<Op>) -> Result<(), TrapKind> {
+        self.limits.check_call_depth(self.calls.len())?;
+        let n = self.reg_scalar();
+        match program.jumps().function(n) {
+            Some(function) => {
+                // Push the current instruction pointer to the call stack
+                self.calls.push(self.i);
+                self.i = function;
+                Ok(())
             }
-            Ok(())
+            None => Err(format!("function {n} not defined").into()),
         }
     }
 
@@ -170,146 +183,261 @@ where
         }
     }
 
-    /// Jump to the matching "Else" for this "If" instruction.
-    fn jmp_to_else(&mut self, code: &StandardProgram) {
-        let mut matching = 1;
-        while matching > 0 {
-            self.i += 1;
-            match self.fetch(code) {
-                Some(StandardOp::CoreOp(CoreOp::If))
-                | Some(StandardOp::CoreOp(CoreOp::While))
-                | Some(StandardOp::CoreOp(CoreOp::Function)) => matching += 1,
-                Some(StandardOp::CoreOp(CoreOp::End)) => matching -= 1,
-                Some(StandardOp::CoreOp(CoreOp::Else)) if matching == 1 => return,
-                _ => {}
-            }
+    /// Jump to the matching instruction for the current "If", "Else", "While",
+    /// "Function", or "End" instruction, if it has one.
+    ///
+    /// An "If" jumps to its "Else" (or its "End"), the other instructions
+    /// which start a block jump to their "End", and the "End" of a "While"
+    /// jumps back to the "While".
+    fn jump<Op>(&mut self, program: &Decoded<Op>) {
+        if let Some(target) = program.jumps().target(self.i) {
+            self.i = target;
         }
     }
 
-    /// Jump to the matching "End" for this "Else", "While",
-    /// or "Function" instruction.
-    fn jmp_to_end(&mut self, code: &StandardProgram) {
-        let mut matching = 1;
-        while matching > 0 {
-            self.i += 1;
-            match self.fetch(code) {
-                Some(StandardOp::CoreOp(CoreOp::If))
-                | Some(StandardOp::CoreOp(CoreOp::While))
-                | Some(StandardOp::CoreOp(CoreOp::Function)) => matching += 1,
-                Some(StandardOp::CoreOp(CoreOp::End)) => matching -= 1,
-                _ => {}
-            }
+    /// Allocate a block of `size` cells on the heap, and return its address.
+    ///
+    /// The first freed block large enough to hold the allocation is reused.
+    /// If there are no such blocks, the block is allocated at the end of the tape.
+    fn alloc(&mut self, size: i64) -> Result<usize, TrapKind> {
+        if size < 0 {
+            return Err(format!("cannot allocate {size} cells").into());
         }
+        // Every allocation gets at least one cell, so that every address is unique.
+        let size = (size as usize).max(1);
+
+        let reusable = self
+            .free_blocks
+            .iter()
+            .find(|(_, free_size)| **free_size >= size)
+            .map(|(addr, free_size)| (*addr, *free_size));
+
+        let addr = if let Some((addr, free_size)) = reusable {
+            self.free_blocks.remove(&addr);
+            // Put the unused remainder of the block back on the free list.
+            if free_size > size {
+                self.free_blocks.insert(addr + size, free_size - size);
+            }
+            // Clear the memory left behind by the previous allocation.
+            self.cells[addr..addr + size].fill(0);
+            addr
+        } else {
+            // If the virtual machine doesn't have enough cells for the stack,
+            // allocate some before the heap.
+            let stack_reserve = if self.cells.len() < STACK_RESERVE {
+                STACK_RESERVE
+            } else {
+                0
+            };
+            self.limits.check_tape_size(
+                self.cells
+                    .len()
+                    .saturating_add(stack_reserve)
+                    .saturating_add(size),
+            )?;
+            self.cells
+                .try_reserve_exact(stack_reserve.saturating_add(size))
+                .map_err(|_| format!("out of memory allocating {size} cells"))?;
+            if stack_reserve > 0 {
+                self.cells.extend(vec![0; STACK_RESERVE]);
+            }
+            // Allocate new space at the end of the tape, starting
+            // at the last cell (which is never used by a previous block).
+            let addr = self.cells.len() - 1;
+            self.cells.extend(vec![0; size]);
+            addr
+        };
+
+        self.allocated.insert(addr, size);
+        Ok(addr)
     }
 
-    /// Jump back to the matching instruction for a given "End" instruction.
+    /// Free the block of memory allocated at the given address.
     ///
-    /// ```hs
-    /// If (ends up here)
-    ///     ...
-    /// End (calls jmp_back_to_matching)
-    /// ```
-    fn jmp_back_to_matching(&mut self, code: &StandardProgram) {
-        let mut matching = 1;
-        while matching > 0 {
-            self.i -= 1;
-            match self.fetch(code) {
-                Some(StandardOp::CoreOp(CoreOp::If))
-                | Some(StandardOp::CoreOp(CoreOp::While))
-                | Some(StandardOp::CoreOp(CoreOp::Function)) => matching -= 1,
-                Some(StandardOp::CoreOp(CoreOp::End)) => matching += 1,
-                _ => {}
-            }
+    /// Freeing a null pointer does nothing. Freeing an address which was
+    /// never allocated, or which was already freed, is an error, which is
+    /// reported with the address of the instruction which freed it.
+    fn free(&mut self, addr: i64, instruction: usize) -> Result<(), String> {
+        if addr == NULL {
+            return Ok(());
         }
-    }
 
-    /// Get the matching instruction for a given "End" instruction.
-    ///
-    /// ```hs
-    /// If (this function would return this instruction)
-    ///    ...
-    /// End (called on this as the current instruction)
-    /// ```
-    fn get_matching_for_end<'a>(&mut self, code: &'a StandardProgram) -> Option<&'a StandardOp> {
-        let mut matching = 1;
-        let mut i = self.i;
-        while matching > 0 && i > 0 {
-            i -= 1;
-            match code.0[i] {
-                StandardOp::CoreOp(CoreOp::If)
-                | StandardOp::CoreOp(CoreOp::While)
-                | StandardOp::CoreOp(CoreOp::Function) => matching -= 1,
-                StandardOp::CoreOp(CoreOp::End) => matching += 1,
-                _ => {}
+        let size = if addr < 0 {
+            None
+        } else {
+            self.allocated.remove(&(addr as usize))
+        };
+        let (mut addr, mut size) = match size {
+            Some(size) => (addr as usize, size),
+            None if self.is_freed(addr) => {
+                return Err(format!(
+                    "Instruction #{} tried to free address {addr}, which was already freed",
+                    instruction
+                ))
+            }
+            None => {
+                return Err(format!(
+                    "Instruction #{} tried to free address {addr}, which was never allocated",
+                    instruction
+                ))
             }
+        };
+
+        // Merge the block with the free block before it, if they're adjacent.
+        if let Some((&prev_addr, &prev_size)) = self.free_blocks.range(..addr).next_back() {
+            if prev_addr + prev_size == addr {
+                self.free_blocks.remove(&prev_addr);
+                addr = prev_addr;
+                size += prev_size;
+            }
+        }
+        // Merge the block with the free block after it, if they're adjacent.
+        if let Some(next_size) = self.free_blocks.remove(&(addr + size)) {
+            size += next_size;
         }
 
-        if matching == 0 {
-            Some(&code.0[i])
-        } else {
-            None
+        self.free_blocks.insert(addr, size);
+        Ok(())
+    }
+
+    /// Is the given address inside of a block which has been freed?
+    fn is_freed(&self, addr: i64) -> bool {
+        if addr < 0 {
+            return false;
         }
+        let addr = addr as usize;
+        self.free_blocks
+            .range(..=addr)
+            .next_back()
+            .is_some_and(|(free_addr, free_size)| addr < free_addr + free_size)
     }
 
     /// Get the current cell pointed to on the turing tape.
-    fn get_cell(&mut self) -> &mut i64 {
-        while self.pointer >= self.cells.len() {
-            self.cells.extend(vec![0; 1000]);
+    fn get_cell(&mut self) -> Result<&mut i64, TrapKind> {
+        self.grow(self.pointer.saturating_add(1))?;
+        Ok(&mut self.cells[self.pointer])
+    }
+
+    /// Grow the tape to hold at least the given number of cells,
+    /// unless that's more than the limit on the size of the tape.
+    fn grow(&mut self, len: usize) -> Result<(), TrapKind> {
+        if len > self.cells.len() {
+            self.limits.check_tape_size(len)?;
+            // Grow the tape in chunks, so that it isn't resized for every cell.
+            let chunks = (len - self.cells.len()).div_ceil(1000);
+            let mut new_len = self.cells.len().saturating_add(chunks.saturating_mul(1000));
+            if let Some(max) = self.limits.tape_size {
+                new_len = new_len.min(max);
+            }
+            // The pointer can be moved anywhere, so failing to allocate the tape is a trap.
+            self.cells
+                .try_reserve_exact(new_len - self.cells.len())
+                .map_err(|_| format!("out of memory growing the tape to {new_len} cells"))?;
+            self.cells.resize(new_len, 0);
         }
+        Ok(())
+    }
 
-        &mut self.cells[self.pointer]
+    /// Make sure the register and the tape hold the cells an instruction operates on,
+    /// so that a malformed program traps instead of indexing out of bounds.
+    fn check_operands(&mut self, (register, tape): (usize, usize)) -> Result<(), TrapKind> {
+        if register > self.register.len() {
+            return Err(TrapKind::RegisterOutOfBounds(register, self.register.len()));
+        }
+        self.grow(self.pointer.saturating_add(tape))
     }
 
     /// Run a core program using this interpreter and its device.
-    pub fn run(mut self, code: &StandardProgram) -> Result<T, String> {
+    ///
+    /// If the program fails, the trap reports where it failed and the call stack.
+    pub fn run(mut self, code: &StandardProgram) -> Result<T, Trap> {
+        let program = Decoded::from(code);
+        let mut meter = Meter::start(&self.limits);
         while !self.done {
-            self.step(code)?
+            if let Err(kind) = self.step_metered(&program, &mut meter) {
+                return Err(self.trap(kind, code, &program));
+            }
         }
 
         Ok(self.device)
     }
 
+    /// Run a program using this interpreter and its device, while recording the
+    /// instructions it executes in the given profile.
+    ///
+    /// The profile is filled in even if the program fails.
+    pub fn run_with_profile(
+        mut self,
+        code: &StandardProgram,
+        profile: &mut Profile,
+    ) -> Result<T, Trap> {
+        let program = Decoded::from(code);
+        let mut meter = Meter::start(&self.limits);
+        while !self.done {
+            profile.record_with(self.i, &self.calls, |i| program.address(i));
+            if let Err(kind) = self.step_metered(&program, &mut meter) {
+                return Err(self.trap(kind, code, &program));
+            }
+        }
+        Ok(self.device)
+    }
+
+    /// Report an error at the current instruction, with the call stack that led to it.
+    fn trap(&self, kind: TrapKind, code: &StandardProgram, program: &Decoded<&StandardOp>) -> Trap {
+        let map = self.map.clone().unwrap_or_else(|| SourceMap::from(code));
+        let calls = self
+            .calls
+            .iter()
+            .map(|&i| program.address(i))
+            .collect::<Vec<_>>();
+        Trap::new(kind, &map, program.address(self.i), &calls)
+    }
+
+    /// Run a single step of the interpreter, counting the instruction against its limits.
+    fn step_metered(
+        &mut self,
+        program: &Decoded<&StandardOp>,
+        meter: &mut Meter,
+    ) -> Result<(), TrapKind> {
+        // Reaching the end of the program doesn't count as an instruction. Comments
+        // were left out when the program was decoded, so they aren't counted either.
+        if self.fetch(program).is_some() {
+            meter.tick(&self.limits)?;
+        }
+        self.step(program)
+    }
+
     /// Run a single step of the interpreter.
-    fn step(&mut self, code: &StandardProgram) -> Result<(), String> {
-        if let Some(op) = self.fetch(code) {
+    fn step<Op: Borrow<StandardOp>>(&mut self, program: &Decoded<Op>) -> Result<(), TrapKind> {
+        if let Some(op) = self.fetch(program) {
+            self.check_operands(operands(op))?;
             match op {
                 StandardOp::CoreOp(core_op) => match core_op {
                     CoreOp::Comment(_) => {}
                     CoreOp::Set(n) => *self.reg_mut_vector() = n.clone(),
-                    CoreOp::Function => {
-                        if !self.functions.contains(&self.i) {
-                            self.functions.push(self.i);
-                            self.functions.sort()
-                        }
-                        self.jmp_to_end(code)
-                    }
-                    CoreOp::Call => self.call(code)?,
+                    CoreOp::Function => self.jump(program),
+                    CoreOp::Call => self.call(program)?,
                     CoreOp::Return => self.ret(),
                     CoreOp::While => {
                         if self.reg_scalar() == 0 {
-                            self.jmp_to_end(code)
+                            self.jump(program)
                         }
                     }
                     CoreOp::If => {
                         if self.reg_scalar() == 0 {
-                            self.jmp_to_else(code)
+                            self.jump(program)
                         }
                     }
-                    CoreOp::Else => self.jmp_to_end(code),
+                    CoreOp::Else => self.jump(program),
                     CoreOp::End => {
                         if self.reg_scalar() != 0 {
-                            if let Some(StandardOp::CoreOp(CoreOp::While)) =
-                                self.get_matching_for_end(code)
-                            {
-                                self.jmp_back_to_matching(code)
-                            }
+                            // Only the "End" of a "While" has somewhere to jump back to.
+                            self.jump(program)
                         }
                     }
 
                     CoreOp::Load(n) => {
-                        while self.pointer + n >= self.cells.len() {
-                            self.cells.extend(vec![0; 1000]);
-                        }
+                        self.grow(self.pointer.saturating_add(*n).saturating_add(1))?;
 
                         self.reg_mut_vector().clear();
 
@@ -320,9 +448,7 @@ where
                     }
 
                     CoreOp::Store(n) => {
-                        while self.pointer + n >= self.cells.len() {
-                            self.cells.extend(vec![0; 1000]);
-                        }
+                        self.grow(self.pointer.saturating_add(*n).saturating_add(1))?;
 
                         for i in 0..*n {
                             let val = self.reg_vector()[i];
@@ -333,13 +459,19 @@ where
                     // CoreOp::Store(n) => self.register = *self.get_cell(),
                     CoreOp::Move(n) => {
                         if *n >= 0 {
-                            self.pointer += *n as usize
+                            self.pointer =
+                                self.pointer.checked_add(*n as usize).ok_or_else(|| {
+                                    format!(
+                                        "Instruction #{} tried to move the pointer past the end of the tape.",
+                                        program.address(self.i)
+                                    )
+                                })?
                         } else {
                             if self.pointer < -*n as usize {
                                 return Err(format!(
                                     "Instruction #{} tried to move the pointer to a negative index.",
-                                    self.i
-                                ));
+                                    program.address(self.i)
+                                ).into());
                             }
                             self.pointer -= -*n as usize
                         }
@@ -348,15 +480,17 @@ where
                     CoreOp::Where => *self.reg_mut_scalar() = self.pointer as i64,
                     CoreOp::Offset(offset, n) => {
                         for i in 0..*n {
-                            self.reg_mut_vector()[i] += *offset as i64;
+                            self.reg_mut_vector()[i] =
+                                self.reg_vector()[i].wrapping_add(*offset as i64);
                         }
                     }
-                    CoreOp::Deref => self.deref(),
+                    CoreOp::Deref => self.deref()?,
                     CoreOp::Refer => self.refer()?,
 
                     CoreOp::Index(n) => {
                         for i in 0..*n {
-                            self.reg_mut_vector()[i] += self.cells[self.pointer + i];
+                            self.reg_mut_vector()[i] =
+                                self.reg_vector()[i].wrapping_add(self.cells[self.pointer + i]);
                         }
                     }
                     CoreOp::BitwiseNand(n) => {
@@ -387,7 +521,8 @@ where
                     }
                     CoreOp::LeftShift(n) => {
                         for i in 0..*n {
-                            self.reg_mut_vector()[i] <<= self.cells[self.pointer + i];
+                            self.reg_mut_vector()[i] = self.reg_vector()[i]
+                                .wrapping_shl(self.cells[self.pointer + i] as u32);
                         }
                     }
                     // CoreOp::LogicalRightShift => {
@@ -395,55 +530,58 @@ where
                     // }
                     CoreOp::LogicalRightShift(n) => {
                         for i in 0..*n {
-                            self.reg_mut_vector()[i] = (self.reg_vector()[i] as u64
-                                >> self.cells[self.pointer + i] as u64)
+                            self.reg_mut_vector()[i] = (self.reg_vector()[i] as u64)
+                                .wrapping_shr(self.cells[self.pointer + i] as u32)
                                 as i64;
                         }
                     }
 
                     CoreOp::ArithmeticRightShift(n) => {
                         for i in 0..*n {
-                            self.reg_mut_vector()[i] >>= self.cells[self.pointer + i];
+                            self.reg_mut_vector()[i] = self.reg_vector()[i]
+                                .wrapping_shr(self.cells[self.pointer + i] as u32);
                         }
                     }
 
                     CoreOp::Add(n) => {
                         for i in 0..*n {
                             let val = self.cells[self.pointer + i];
-                            self.reg_mut_vector()[i] += val;
+                            self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_add(val);
                         }
                     }
                     CoreOp::Sub(n) => {
                         for i in 0..*n {
                             let val = self.cells[self.pointer + i];
-                            self.reg_mut_vector()[i] -= val;
+                            self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_sub(val);
                         }
                     }
                     CoreOp::Mul(n) => {
                         for i in 0..*n {
                             let val = self.cells[self.pointer + i];
-                            self.reg_mut_vector()[i] *= val;
+                            self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_mul(val);
                         }
                     }
                     CoreOp::Div(n) => {
                         for i in 0..*n {
                             let val = self.cells[self.pointer + i];
-                            if val != 0 {
-                                self.reg_mut_vector()[i] /= val;
+                            if val == 0 {
+                                return Err(TrapKind::DivisionByZero);
                             }
+                            self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_div(val);
                         }
                     }
                     CoreOp::Rem(n) => {
                         for i in 0..*n {
                             let val = self.cells[self.pointer + i];
-                            if val != 0 {
-                                self.reg_mut_vector()[i] %= val;
+                            if val == 0 {
+                                return Err(TrapKind::DivisionByZero);
                             }
+                            self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_rem(val);
                         }
                     }
                     CoreOp::Neg(n) => {
                         for i in 0..*n {
-                            self.reg_mut_vector()[i] *= -1;
+                            self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_neg();
                         }
                     }
 
@@ -469,12 +607,12 @@ where
 
                     CoreOp::Inc(n) => {
                         for i in 0..*n {
-                            self.reg_mut_vector()[i] += 1;
+                            self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_add(1);
                         }
                     }
                     CoreOp::Dec(n) => {
                         for i in 0..*n {
-                            self.reg_mut_vector()[i] -= 1;
+                            self.reg_mut_vector()[i] = self.reg_vector()[i].wrapping_sub(1);
                         }
                     }
 
@@ -603,21 +741,14 @@ where
                 }
 
                 StandardOp::Alloc => {
-                    // If the virtual machine doesn't have a thousand cells,
-                    // allocate some.
-                    if self.cells.len() < 30000 {
-                        self.cells.extend(vec![0; 30000]);
-                    }
-                    // Save the address of where the new cells will start.
-                    let result = self.cells.len() - 1;
-                    // Allocate new space at the end of the type.
-                    self.cells.extend(vec![0; self.reg_scalar() as usize]);
+                    let result = self.alloc(self.reg_scalar())?;
                     // Store the address of the new space in the register.
                     *self.reg_mut_scalar() = result as i64;
                 }
-                StandardOp::Free => {}
+                StandardOp::Free => self.free(self.reg_scalar(), program.address(self.i))?,
                 StandardOp::Call(binding) => {
-                    self.device.ffi_call(binding, Some(&mut self.cells))?;
+                    self.device
+                        .ffi_call(binding, Some(self.cells.as_mut_slice()))?;
                 }
             }
             self.i += 1
@@ -627,3 +758,82 @@ where
         Ok(())
     }
 }
+
+/// Get the number of cells in the register and on the tape (at the pointer)
+/// which an instruction operates on.
+fn operands(op: &StandardOp) -> (usize, usize) {
+    match op {
+        StandardOp::CoreOp(op) => super::operands(op),
+        StandardOp::ToInt(n)
+        | StandardOp::ToFloat(n)
+        | StandardOp::Neg(n)
+        | StandardOp::IsNonNegative(n)
+        | StandardOp::Sin(n)
+        | StandardOp::Cos(n)
+        | StandardOp::Tan(n)
+        | StandardOp::ASin(n)
+        | StandardOp::ACos(n)
+        | StandardOp::ATan(n) => (*n, 0),
+        StandardOp::Add(n)
+        | StandardOp::Sub(n)
+        | StandardOp::Mul(n)
+        | StandardOp::Div(n)
+        | StandardOp::Rem(n)
+        | StandardOp::Pow(n) => (*n, *n),
+        _ => (0, 0),
+    }
+}
+
+impl<T> Debuggable for StandardInterpreter<T>
+where
+    T: Device,
+{
+    type Program = StandardProgram;
+
+    fn instructions(code: &StandardProgram) -> Vec<StandardOp> {
+        code.0.clone()
+    }
+
+    fn load(&mut self, code: &StandardProgram) {
+        self.program = Decoded::from(code).cloned();
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        // The program is moved out while it runs, so the interpreter can be borrowed mutably.
+        let program = ::std::mem::take(&mut self.program);
+        let result = StandardInterpreter::step(self, &program);
+        self.program = program;
+        result.map_err(|kind| kind.to_string())
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn instruction_pointer(&self) -> usize {
+        self.program.address(self.i)
+    }
+
+    fn register(&self) -> &[i64] {
+        &self.register
+    }
+
+    fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    fn tape(&self) -> &[i64] {
+        &self.cells
+    }
+
+    fn call_stack(&self) -> Vec<usize> {
+        self.calls
+            .iter()
+            .map(|&i| self.program.address(i))
+            .collect()
+    }
+
+    fn deref_stack(&self) -> &[usize] {
+        &self.refs
+    }
+}
@@ -0,0 +1,152 @@
+//! # Trap Module
+//!
+//! This module implements the errors which stop a virtual machine program while it runs,
+//! like dereferencing a null pointer or dividing by zero. When an interpreter traps,
+//! the failing instruction is mapped back to the source code with the program's
+//! source map, and the call stack is reported as the names of the procedures being run.
+//! Programs which hit the interpreter's limits on their resources are also stopped with a trap.
+use crate::{parse::SourceCodeLocation, vm::SourceMap, NULL, PANIC};
+use ::std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    time::Duration,
+};
+
+/// The reason an interpreter stopped running a program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrapKind {
+    /// The program dereferenced the `NULL` pointer.
+    NullPointer,
+    /// The program dereferenced a negative address.
+    InvalidPointer(i64),
+    /// The program panicked, after printing its own message.
+    Panic,
+    /// The program divided an integer by zero.
+    DivisionByZero,
+    /// An instruction operated on more cells of the register than it holds.
+    /// This holds the number of cells, and the size of the register.
+    RegisterOutOfBounds(usize, usize),
+    /// The program executed more than the maximum number of instructions.
+    InstructionLimit(u64),
+    /// The program's tape grew past the maximum number of cells.
+    TapeLimit(usize),
+    /// The program made more than the maximum number of nested procedure calls.
+    CallDepthLimit(usize),
+    /// The program ran for longer than its time limit.
+    Timeout(Duration),
+    /// Any other error, like running out of memory or an I/O error.
+    Other(String),
+}
+
+impl TrapKind {
+    /// Get the kind of trap caused by dereferencing a negative address.
+    pub fn invalid_address(address: i64) -> Self {
+        match address {
+            NULL => Self::NullPointer,
+            PANIC => Self::Panic,
+            _ => Self::InvalidPointer(address),
+        }
+    }
+}
+
+impl From<String> for TrapKind {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+impl Display for TrapKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::NullPointer => write!(f, "null pointer dereference"),
+            Self::InvalidPointer(address) => {
+                write!(f, "dereferenced invalid address {address}")
+            }
+            Self::Panic => write!(f, "the program panicked"),
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::RegisterOutOfBounds(cells, len) => {
+                write!(f, "operated on {cells} cells of a register holding {len}")
+            }
+            Self::InstructionLimit(max) => {
+                write!(f, "exceeded the limit of {max} instructions")
+            }
+            Self::TapeLimit(max) => write!(f, "exceeded the limit of {max} cells on the tape"),
+            Self::CallDepthLimit(max) => {
+                write!(f, "exceeded the limit of {max} nested procedure calls")
+            }
+            Self::Timeout(timeout) => write!(f, "exceeded the time limit of {timeout:?}"),
+            Self::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// A procedure on the call stack when a program trapped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StackFrame {
+    /// The name of the procedure, if it has one.
+    pub procedure: Option<String>,
+    /// The location in the source code which the procedure was running.
+    pub location: Option<SourceCodeLocation>,
+}
+
+impl Display for StackFrame {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.procedure.as_deref().unwrap_or("<main>"))?;
+        if let Some(location) = &self.location {
+            write!(f, " at {location}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An error which stopped a program while it was running.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trap {
+    /// Why the program stopped.
+    pub kind: TrapKind,
+    /// The index of the instruction which failed.
+    pub instruction: usize,
+    /// The call stack when the program stopped, starting with the innermost procedure.
+    pub stack: Vec<StackFrame>,
+}
+
+impl Trap {
+    /// Create a trap for an instruction which failed, given the source map of the
+    /// program and the instruction pointers saved on the interpreter's call stack.
+    pub fn new(kind: TrapKind, map: &SourceMap, instruction: usize, calls: &[usize]) -> Self {
+        let stack = ::std::iter::once(&instruction)
+            .chain(calls.iter().rev())
+            .map(|&i| StackFrame {
+                procedure: map.procedure(i).map(str::to_string),
+                location: map.location(i).cloned(),
+            })
+            .collect();
+        Self {
+            kind,
+            instruction,
+            stack,
+        }
+    }
+
+    /// The location in the source code of the instruction which failed, if it's known.
+    pub fn location(&self) -> Option<&SourceCodeLocation> {
+        self.stack.first()?.location.as_ref()
+    }
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        // A panicking program prints its own message, so only the stack is reported.
+        if self.kind != TrapKind::Panic {
+            write!(f, "runtime error")?;
+            if let Some(location) = self.location() {
+                write!(f, " at {location}")?;
+            }
+            writeln!(f, ": {}", self.kind)?;
+        }
+        writeln!(f, "stack backtrace:")?;
+        for (depth, frame) in self.stack.iter().enumerate() {
+            writeln!(f, "  #{depth} {frame}")?;
+        }
+        Ok(())
+    }
+}
@@ -65,6 +65,14 @@ pub use self::std::*;
 mod interpreter;
 pub use interpreter::*;
 
+mod optimize;
+
+mod source_map;
+pub use source_map::*;
+
+mod bytecode;
+pub use bytecode::*;
+
 /// An error generated by the virtual machine.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum Error {
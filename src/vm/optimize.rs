@@ -0,0 +1,291 @@
+//! # Optimization Module
+//!
+//! This module implements optimization passes over virtual machine code.
+//!
+//! The assembler emits virtual machine code instruction by instruction, so
+//! the generated code is full of runs like `mov 3; mov -3`, and `store 1; load 1`
+//! pairs that don't do anything. The optimizer folds these away with a peephole
+//! pass, and can also remove code that will never run.
+//!
+//! ## Optimization Levels
+//!
+//! 1. Level `0` does not modify the program at all.
+//! 2. Level `1` performs peephole optimizations: folding pointer movements,
+//!    removing redundant register traffic, and evaluating constant operations.
+//! 3. Level `2` (and above) also removes dead code: instructions after a `ret`
+//!    in the same block, and the bodies of functions that are never called.
+//!
+//! Functions are called by their index in the program, so removing a function
+//! would change the index of every function after it. Instead, the body of a
+//! function that is never called is removed, and an empty function is left in its place.
+//! A function is considered callable if its index appears in a `set` instruction
+//! in any reachable code, which is how the assembler refers to functions.
+//!
+//! Comments are never removed or reordered, even in dead code, so that the source
+//! map of a program can be moved onto the optimized program by its comments.
+use super::{CoreOp, CoreProgram, StandardOp, StandardProgram, VirtualMachineProgram};
+use std::collections::{BTreeSet, HashMap};
+
+/// The optimization level which enables peephole optimizations.
+const PEEPHOLE_LEVEL: usize = 1;
+/// The optimization level which enables dead code elimination.
+const DEAD_CODE_LEVEL: usize = 2;
+
+impl CoreProgram {
+    /// Optimize the program with the given optimization level.
+    pub fn optimize(self, level: usize) -> Self {
+        match StandardProgram::from(self).optimize(level).code() {
+            Ok(core) => core,
+            // The optimizer never introduces standard instructions.
+            Err(_) => unreachable!("optimized core program contains standard instructions"),
+        }
+    }
+}
+
+impl StandardProgram {
+    /// Optimize the program with the given optimization level.
+    pub fn optimize(self, level: usize) -> Self {
+        let mut code = self.0;
+        if level >= DEAD_CODE_LEVEL {
+            code = remove_unreachable_code(code);
+            code = remove_uncalled_functions(code);
+        }
+        if level >= PEEPHOLE_LEVEL {
+            // Keep folding instructions until the code stops changing.
+            loop {
+                let optimized = peephole(code.clone());
+                if optimized == code {
+                    break;
+                }
+                code = optimized;
+            }
+        }
+        Self(code)
+    }
+}
+
+/// Get the core instruction of a standard instruction, if it is one.
+fn as_core(op: &StandardOp) -> Option<&CoreOp> {
+    match op {
+        StandardOp::CoreOp(op) => Some(op),
+        _ => None,
+    }
+}
+
+/// Does this instruction begin a block that is terminated by an `End`?
+fn begins_block(op: &StandardOp) -> bool {
+    matches!(
+        as_core(op),
+        Some(CoreOp::Function | CoreOp::If | CoreOp::While)
+    )
+}
+
+/// Perform a single peephole optimization pass over the code.
+fn peephole(code: Vec<StandardOp>) -> Vec<StandardOp> {
+    let mut result = Vec::with_capacity(code.len());
+    for op in code {
+        push_folded(&mut result, op);
+    }
+    result
+}
+
+/// Push an instruction to the code, folding it into the previous
+/// instruction if the pair can be simplified.
+fn push_folded(code: &mut Vec<StandardOp>, op: StandardOp) {
+    // Comments are kept, but they are skipped over when folding instructions.
+    let last = match &op {
+        StandardOp::CoreOp(CoreOp::Comment(_)) => None,
+        StandardOp::CoreOp(_) => code
+            .iter()
+            .rposition(|op| !matches!(op, StandardOp::CoreOp(CoreOp::Comment(_)))),
+        _ => None,
+    };
+
+    let (i, prev, next) = match (last, &op) {
+        (Some(i), StandardOp::CoreOp(next)) => match as_core(&code[i]) {
+            Some(prev) => (i, prev.clone(), next.clone()),
+            None => return code.push(op),
+        },
+        _ => return code.push(op),
+    };
+
+    match (prev, next) {
+        // Combine consecutive pointer movements.
+        (CoreOp::Move(n), CoreOp::Move(m)) => {
+            code.remove(i);
+            if n + m != 0 {
+                push_folded(code, StandardOp::CoreOp(CoreOp::Move(n + m)));
+            }
+        }
+        // Moving the pointer before undoing a dereference does nothing.
+        (CoreOp::Move(_), CoreOp::Refer) => {
+            code.remove(i);
+            push_folded(code, op);
+        }
+        // Set the register before moving the pointer, so that moves can be combined.
+        (CoreOp::Move(n), CoreOp::Set(val)) => {
+            code.remove(i);
+            push_folded(code, StandardOp::CoreOp(CoreOp::Set(val)));
+            push_folded(code, StandardOp::CoreOp(CoreOp::Move(n)));
+        }
+        // Dereferencing and immediately undoing it does nothing.
+        (CoreOp::Deref, CoreOp::Refer) => {
+            code.remove(i);
+        }
+
+        // A value set in the register which is overwritten before it's used is unnecessary.
+        (CoreOp::Set(old), CoreOp::Set(new)) if new.len() >= old.len() => {
+            code.remove(i);
+            push_folded(code, op);
+        }
+        (CoreOp::Set(old), CoreOp::Load(n)) if n >= old.len() => {
+            code.remove(i);
+            push_folded(code, op);
+        }
+        (CoreOp::Set(old), CoreOp::Where) if old.len() <= 1 => {
+            code.remove(i);
+            push_folded(code, op);
+        }
+        // Evaluate operations on constants.
+        (CoreOp::Set(val), CoreOp::IsNonNegative(1)) if val.len() == 1 => {
+            code.remove(i);
+            push_folded(
+                code,
+                StandardOp::CoreOp(CoreOp::Set(vec![(val[0] >= 0) as i64])),
+            );
+        }
+        (CoreOp::Set(val), CoreOp::Not(1)) if val.len() == 1 => {
+            code.remove(i);
+            push_folded(
+                code,
+                StandardOp::CoreOp(CoreOp::Set(vec![(val[0] == 0) as i64])),
+            );
+        }
+
+        // After a store or a load, the register and the tape already hold the same values.
+        (CoreOp::Store(m), CoreOp::Store(n) | CoreOp::Load(n)) if n <= m => {}
+        (CoreOp::Load(m), CoreOp::Load(n) | CoreOp::Store(n)) if n <= m => {}
+
+        // Remove empty else branches and empty conditionals.
+        (CoreOp::Else, CoreOp::End) => {
+            code.remove(i);
+            push_folded(code, op);
+        }
+        (CoreOp::If, CoreOp::End) => {
+            code.remove(i);
+        }
+
+        _ => code.push(op),
+    }
+}
+
+/// Remove the instructions following a `Return` instruction, up until the end of its block.
+fn remove_unreachable_code(code: Vec<StandardOp>) -> Vec<StandardOp> {
+    let mut result = Vec::with_capacity(code.len());
+    let mut code = code.into_iter();
+    while let Some(op) = code.next() {
+        let is_return = matches!(as_core(&op), Some(CoreOp::Return));
+        result.push(op);
+        if !is_return {
+            continue;
+        }
+
+        // Skip everything until the `Else` or `End` that terminates the current block.
+        let mut depth = 0;
+        while let Some(op) = code.next() {
+            match as_core(&op) {
+                // Function definitions are kept, so that the indices of the functions don't change.
+                Some(CoreOp::Function) => {
+                    result.push(op);
+                    let mut function_depth = 1;
+                    for op in code.by_ref() {
+                        if begins_block(&op) {
+                            function_depth += 1;
+                        } else if let Some(CoreOp::End) = as_core(&op) {
+                            function_depth -= 1;
+                        }
+                        result.push(op);
+                        if function_depth == 0 {
+                            break;
+                        }
+                    }
+                }
+                Some(CoreOp::If | CoreOp::While) => depth += 1,
+                Some(CoreOp::Comment(_)) => result.push(op),
+                Some(CoreOp::Else) if depth == 0 => {
+                    result.push(op);
+                    break;
+                }
+                Some(CoreOp::End) if depth == 0 => {
+                    result.push(op);
+                    break;
+                }
+                Some(CoreOp::End) => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+    result
+}
+
+/// Remove the bodies of the functions which are never called.
+/// The definitions of the functions are kept, so that the indices of the functions don't change,
+/// along with the comments in their bodies.
+fn remove_uncalled_functions(code: Vec<StandardOp>) -> Vec<StandardOp> {
+    // The function that each instruction belongs to (`None` for the main program),
+    // and whether the instruction begins or ends the function's definition.
+    let mut owners = Vec::with_capacity(code.len());
+    // The constants set by each function, which may refer to other functions.
+    let mut constants: HashMap<Option<usize>, BTreeSet<i64>> = HashMap::new();
+    // The stack of blocks: the function each block belongs to, and whether it's a function definition.
+    let mut blocks: Vec<(Option<usize>, bool)> = vec![];
+    let mut function_count = 0;
+    for op in &code {
+        let owner = blocks.last().and_then(|(owner, _)| *owner);
+        match as_core(op) {
+            Some(CoreOp::Function) => {
+                owners.push((Some(function_count), true));
+                blocks.push((Some(function_count), true));
+                function_count += 1;
+            }
+            Some(CoreOp::If | CoreOp::While) => {
+                owners.push((owner, false));
+                blocks.push((owner, false));
+            }
+            Some(CoreOp::End) => {
+                let is_function_end = matches!(blocks.pop(), Some((_, true)));
+                owners.push((owner, is_function_end));
+            }
+            Some(CoreOp::Set(values)) => {
+                constants.entry(owner).or_default().extend(values);
+                owners.push((owner, false));
+            }
+            _ => owners.push((owner, false)),
+        }
+    }
+
+    // Find every function that could be called from the main program.
+    let mut called = BTreeSet::new();
+    let mut worklist = vec![None];
+    while let Some(owner) = worklist.pop() {
+        for value in constants.get(&owner).into_iter().flatten() {
+            if *value >= 0 && (*value as usize) < function_count && called.insert(*value as usize) {
+                worklist.push(Some(*value as usize));
+            }
+        }
+    }
+
+    // Keep the function definitions, but remove the bodies of uncalled functions.
+    code.into_iter()
+        .zip(owners)
+        .filter(|(op, (owner, is_boundary))| match owner {
+            Some(function) => {
+                *is_boundary
+                    || called.contains(function)
+                    || matches!(as_core(op), Some(CoreOp::Comment(_)))
+            }
+            None => true,
+        })
+        .map(|(op, _)| op)
+        .collect()
+}
@@ -0,0 +1,313 @@
+//! # Source Maps
+//!
+//! The LIR compiler marks the code it generates with the location of each expression
+//! in the source code, and with the name of each procedure it defines. The assembler
+//! records the location of each marker alongside the program it assembles, and a
+//! `SourceMap` collects them into a table from the index of each instruction in a
+//! virtual machine program to the source code it was compiled from, which debuggers,
+//! profilers, and runtime error reports can look up.
+//!
+//! The markers are also left in the program as comments, for people reading the
+//! virtual machine code, but the source map doesn't read its locations back out of them.
+//! The optimizer keeps every comment in its place, so a program's source map can be
+//! moved onto the optimized program by matching up the comments of the two programs.
+use super::{CoreOp, CoreProgram, StandardOp, StandardProgram};
+use crate::parse::SourceCodeLocation;
+use ::std::collections::HashMap;
+
+/// The source code location and procedure of each instruction in a virtual machine program.
+///
+/// Instructions are indexed the same way as by the interpreters, where comments are counted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// The index of each location marker in the program, and the location it marks.
+    markers: Vec<(usize, SourceCodeLocation)>,
+    /// The location in the source code of each instruction, if it's known.
+    locations: Vec<Option<SourceCodeLocation>>,
+    /// The name of the procedure containing each instruction, if it has one.
+    procedures: Vec<Option<String>>,
+}
+
+impl SourceMap {
+    /// Build the source map of a program's instructions without any source code
+    /// locations, for programs which weren't assembled from source code. Only the
+    /// names of the program's procedures are known.
+    pub fn new(ops: &[StandardOp]) -> Self {
+        Self::with_locations(ops, vec![])
+    }
+
+    /// Build the source map of a program's instructions from the location markers
+    /// recorded by the assembler, given as the index of each marker in the program
+    /// and its location.
+    pub fn with_locations(ops: &[StandardOp], markers: Vec<(usize, SourceCodeLocation)>) -> Self {
+        Self {
+            locations: source_locations(ops, &markers),
+            procedures: procedure_names(ops),
+            markers,
+        }
+    }
+
+    /// Get the index of each location marker in the program, and the location it marks.
+    pub fn markers(&self) -> &[(usize, SourceCodeLocation)] {
+        &self.markers
+    }
+
+    /// The number of instructions in the program.
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// Is the program empty?
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Get the location in the source code of the instruction at an index, if it's known.
+    pub fn location(&self, i: usize) -> Option<&SourceCodeLocation> {
+        self.locations.get(i)?.as_ref()
+    }
+
+    /// Get the name of the procedure containing the instruction at an index.
+    /// Instructions outside of any procedure, and in anonymous procedures, have no name.
+    pub fn procedure(&self, i: usize) -> Option<&str> {
+        self.procedures.get(i)?.as_deref()
+    }
+
+    /// Move the location markers onto a new version of the program, where `index`
+    /// gets the new index of the instruction at an index in the old program.
+    /// Markers on instructions which aren't in the new program are dropped.
+    pub(super) fn moved(&self, ops: &[StandardOp], index: impl Fn(usize) -> Option<usize>) -> Self {
+        let markers = self
+            .markers
+            .iter()
+            .filter_map(|(i, location)| Some((index(*i)?, location.clone())))
+            .collect();
+        Self::with_locations(ops, markers)
+    }
+
+    /// Move the location markers onto a version of the program with the same comments
+    /// in the same order, like the program after it's optimized.
+    pub(super) fn moved_by_comments(&self, old: &[StandardOp], new: &[StandardOp]) -> Self {
+        let comments = |ops: &[StandardOp]| {
+            ops.iter()
+                .enumerate()
+                .filter(|(_, op)| matches!(op, StandardOp::CoreOp(CoreOp::Comment(_))))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+        let moved: HashMap<usize, usize> = comments(old).into_iter().zip(comments(new)).collect();
+        self.moved(new, |i| moved.get(&i).copied())
+    }
+}
+
+impl From<&CoreProgram> for SourceMap {
+    fn from(program: &CoreProgram) -> Self {
+        Self::new(&StandardProgram::from(program.clone()).0)
+    }
+}
+
+impl From<&StandardProgram> for SourceMap {
+    fn from(program: &StandardProgram) -> Self {
+        Self::new(&program.0)
+    }
+}
+
+impl CoreProgram {
+    /// Flatten the program like `flatten`, and move its source map onto the flattened program.
+    pub fn flatten_with_source_map(self, map: &SourceMap) -> (Self, SourceMap) {
+        let (program, indices) = self.flatten_with_indices();
+        let map = map.moved(&StandardProgram::from(program.clone()).0, |i| {
+            indices.get(i).copied()
+        });
+        (program, map)
+    }
+
+    /// Optimize the program like `optimize`, and move its source map onto the optimized program.
+    pub fn optimize_with_source_map(self, level: usize, map: &SourceMap) -> (Self, SourceMap) {
+        let old = StandardProgram::from(self.clone()).0;
+        let program = self.optimize(level);
+        let map = map.moved_by_comments(&old, &StandardProgram::from(program.clone()).0);
+        (program, map)
+    }
+
+    /// Add the lines of source code which the instructions came from to the program,
+    /// as comments after its location markers in the source map. This makes the
+    /// annotated listing of the program (`{:#}`) show the source code alongside
+    /// the instructions.
+    ///
+    /// The given function gets the source code of a file (or of the main program, if
+    /// the file is `None`).
+    pub fn with_source_lines(
+        self,
+        map: &SourceMap,
+        source: impl FnMut(Option<&str>) -> Option<String>,
+    ) -> Self {
+        Self(interleave_source_lines(
+            self.0,
+            map,
+            CoreOp::Comment,
+            source,
+        ))
+    }
+}
+
+impl StandardProgram {
+    /// Flatten the program like `flatten`, and move its source map onto the flattened program.
+    pub fn flatten_with_source_map(self, map: &SourceMap) -> (Self, SourceMap) {
+        let (program, indices) = self.flatten_with_indices();
+        let map = map.moved(&program.0, |i| indices.get(i).copied());
+        (program, map)
+    }
+
+    /// Optimize the program like `optimize`, and move its source map onto the optimized program.
+    pub fn optimize_with_source_map(self, level: usize, map: &SourceMap) -> (Self, SourceMap) {
+        let old = self.0.clone();
+        let program = self.optimize(level);
+        let map = map.moved_by_comments(&old, &program.0);
+        (program, map)
+    }
+
+    /// Add the lines of source code which the instructions came from to the program,
+    /// as comments after its location markers in the source map. This makes the
+    /// annotated listing of the program (`{:#}`) show the source code alongside
+    /// the instructions.
+    ///
+    /// The given function gets the source code of a file (or of the main program, if
+    /// the file is `None`).
+    pub fn with_source_lines(
+        self,
+        map: &SourceMap,
+        source: impl FnMut(Option<&str>) -> Option<String>,
+    ) -> Self {
+        Self(interleave_source_lines(
+            self.0,
+            map,
+            |comment| StandardOp::CoreOp(CoreOp::Comment(comment)),
+            source,
+        ))
+    }
+}
+
+/// Insert a comment with the line of source code after each location marker
+/// which starts a new line.
+fn interleave_source_lines<T>(
+    ops: Vec<T>,
+    map: &SourceMap,
+    make_comment: impl Fn(String) -> T,
+    mut source: impl FnMut(Option<&str>) -> Option<String>,
+) -> Vec<T> {
+    let markers: HashMap<usize, &SourceCodeLocation> = map
+        .markers()
+        .iter()
+        .map(|(i, location)| (*i, location))
+        .collect();
+    // The source code of each file, split into lines.
+    let mut files: HashMap<Option<String>, Option<Vec<String>>> = HashMap::new();
+    let mut previous = None;
+    let mut result = Vec::with_capacity(ops.len());
+    for (i, op) in ops.into_iter().enumerate() {
+        let location = markers.get(&i);
+        result.push(op);
+        let Some(location) = location else {
+            continue;
+        };
+        let line = (location.filename.clone(), location.line);
+        if previous.as_ref() == Some(&line) {
+            continue;
+        }
+        let lines = files.entry(location.filename.clone()).or_insert_with(|| {
+            source(location.filename.as_deref())
+                .map(|code| code.lines().map(str::to_string).collect())
+        });
+        if let Some(text) = lines
+            .as_ref()
+            .and_then(|lines| lines.get(location.line.wrapping_sub(1)))
+        {
+            result.push(make_comment(format!(
+                "{:>4} | {}",
+                location.line,
+                text.trim_end()
+            )));
+        }
+        previous = Some(line);
+    }
+    result
+}
+
+/// Get the source code location of each instruction in a program, if it's known,
+/// from the index of each location marker in the program and its location.
+///
+/// Every instruction after a location marker comes from that location, until
+/// the end of the function containing the marker.
+fn source_locations(
+    ops: &[StandardOp],
+    markers: &[(usize, SourceCodeLocation)],
+) -> Vec<Option<SourceCodeLocation>> {
+    let markers: HashMap<usize, &SourceCodeLocation> =
+        markers.iter().map(|(i, location)| (*i, location)).collect();
+    let mut locations = Vec::with_capacity(ops.len());
+    let mut location = None;
+    // The locations from before each block was entered. Only the locations before
+    // function definitions are saved, since the code after a function isn't part of it.
+    let mut blocks = vec![];
+    for (i, op) in ops.iter().enumerate() {
+        locations.push(location.clone());
+        if let Some(&loc) = markers.get(&i) {
+            location = Some(loc.clone());
+            *locations.last_mut().unwrap() = Some(loc.clone());
+        }
+        match op {
+            StandardOp::CoreOp(CoreOp::Function) => blocks.push(Some(location.clone())),
+            StandardOp::CoreOp(CoreOp::If | CoreOp::While) => blocks.push(None),
+            StandardOp::CoreOp(CoreOp::End) => {
+                if let Some(Some(previous)) = blocks.pop() {
+                    location = previous;
+                }
+            }
+            _ => {}
+        }
+    }
+    locations
+}
+
+/// Get the name of the procedure containing each instruction in a program, if it has one.
+///
+/// The LIR compiler names each procedure with a comment like `name(args_size)`
+/// at the start of its body. The instructions before the comment, which the
+/// assembler adds to set up the procedure's stack frame, are also part of it.
+fn procedure_names(ops: &[StandardOp]) -> Vec<Option<String>> {
+    let mut names: Vec<Option<String>> = Vec::with_capacity(ops.len());
+    // The names of the functions containing the current instruction, and the index
+    // of the first instruction in each of their bodies. Other blocks are `None`.
+    let mut blocks: Vec<Option<(Option<String>, usize)>> = vec![];
+    for (i, op) in ops.iter().enumerate() {
+        let current = blocks.iter().rev().find_map(Option::as_ref);
+        names.push(current.and_then(|(name, _)| name.clone()));
+        match op {
+            StandardOp::CoreOp(CoreOp::Comment(comment)) => {
+                if let Some(Some((name @ None, start))) =
+                    blocks.iter_mut().rev().find(|b| b.is_some())
+                {
+                    *name = procedure_name(comment);
+                    for prologue in &mut names[*start..=i] {
+                        prologue.clone_from(name);
+                    }
+                }
+            }
+            StandardOp::CoreOp(CoreOp::Function) => blocks.push(Some((None, i + 1))),
+            StandardOp::CoreOp(CoreOp::If | CoreOp::While) => blocks.push(None),
+            StandardOp::CoreOp(CoreOp::End) => {
+                blocks.pop();
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// Get the name of a procedure from the comment which starts its body.
+fn procedure_name(comment: &str) -> Option<String> {
+    let (name, args_size) = comment.strip_suffix(')')?.rsplit_once('(')?;
+    args_size.parse::<usize>().ok()?;
+    Some(name.to_string())
+}
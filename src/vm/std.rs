@@ -35,7 +35,10 @@
 //! This way, a developer can write a program in such a manner that user input
 //! cannot be confused with custom encoded instructions sent to and from the I/O device
 //! using `Put` and `Get`.
-use super::{CoreOp, CoreProgram, Error, VirtualMachineProgram};
+use super::{
+    core::{flatten, flatten_with_indices},
+    CoreOp, CoreProgram, Error, VirtualMachineProgram,
+};
 use crate::side_effects::*;
 use core::fmt;
 use std::collections::HashMap;
@@ -119,128 +122,39 @@ impl StandardProgram {
     /// Flatten a core program so that all of its functions
     /// are defined sequentially at the beginning.
     pub fn flatten(self) -> Self {
-        Self(flatten(self.0).0)
+        Self(flatten(self.0, core_op).0)
+    }
+
+    /// Flatten a standard program like `flatten`, and also get the index in the
+    /// flattened program of each of the program's instructions.
+    pub fn flatten_with_indices(self) -> (Self, Vec<usize>) {
+        let (code, indices) = flatten_with_indices(self.0, core_op);
+        (Self(code), indices)
     }
 
     /// Get the code outside of any functions.
     pub fn get_main(&self) -> Vec<StandardOp> {
-        flatten(self.0.clone()).2
+        flatten(self.0.clone(), core_op).2
     }
 
     /// Get the code for each function.
     pub fn get_functions(&self) -> HashMap<i32, Vec<StandardOp>> {
-        flatten(self.0.clone()).1
+        flatten(self.0.clone(), core_op).1
     }
 
     /// Get the code outside of any functions, and the code for each function.
     pub fn get_main_and_functions(self) -> (Vec<StandardOp>, HashMap<i32, Vec<StandardOp>>) {
-        let (_, functions, main) = flatten(self.0);
+        let (_, functions, main) = flatten(self.0, core_op);
         (main, functions)
     }
 }
 
-/// Take all of the functions defined in a list of StandardOps,
-/// and flatten their definitions. This will take nested functions
-/// and un-nest them while preserving the order in which functions are defined.
-///
-/// All the function definitions will be placed at the top of the returned list.
-fn flatten(
-    code: Vec<StandardOp>,
-) -> (
-    Vec<StandardOp>,
-    HashMap<i32, Vec<StandardOp>>,
-    Vec<StandardOp>,
-) {
-    let mut functions: HashMap<i32, Vec<StandardOp>> = HashMap::new();
-
-    // The current function body we are in.
-    let mut fun = -1;
-    // Keep track of when we end the current function,
-    // instead of just an if-else-conditional or a while loop.
-    // This is essentially the number of end statements remaining before
-    // we can end the scope.
-    let mut matching_end = 0;
-    // Keep track of each `matching_end`, and the scope we were previously in, for each nested scope.
-    let mut scope_stack = vec![];
-    // The main instructions of the program. (Not in a function.)
-    let mut main_instructions = vec![];
-    for std_op in code {
-        match &std_op {
-            StandardOp::CoreOp(CoreOp::Function) => {}
-            _ => {
-                if scope_stack.is_empty() {
-                    // If we are not defining a function,
-                    // push the instruction to the main instructions.
-                    main_instructions.push(std_op.clone());
-                }
-            }
-        }
-
-        if let StandardOp::CoreOp(op) = &std_op {
-            match op {
-                CoreOp::Function => {
-                    // If we are declaring a new function,
-                    // push the info about the current scope onto the scope
-                    // stack to resume later.
-                    scope_stack.push((fun, matching_end));
-                    // Reset the matching-end counter for the new scope.
-                    matching_end = 0;
-                    // Start defining the next function.
-                    fun += 1;
-                    // If that function is already defined,
-                    // just go past the last function defined.
-                    if functions.contains_key(&fun) {
-                        fun = functions.len() as i32
-                    }
-                }
-                CoreOp::If | CoreOp::While => {
-                    // Increment the number of matching `End`
-                    // instructions to end the scope.
-                    matching_end += 1
-                }
-                CoreOp::End => {
-                    // If the scope has ended
-                    if matching_end == 0 {
-                        // Get the function body we're defining.
-                        functions.entry(fun).or_default().push(std_op);
-                        // Resume flattening the previous scope.
-                        (fun, matching_end) = scope_stack.pop().unwrap();
-                        continue;
-                    } else {
-                        // Otherwise, the scope is still going.
-                        // Decrement the counter and continue.
-                        matching_end -= 1;
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        // Insert the current instruction to the right function's definition.
-        functions.entry(fun).or_default().push(std_op);
+/// Get the core instruction of a standard instruction, if it is one.
+fn core_op(op: &StandardOp) -> Option<&CoreOp> {
+    match op {
+        StandardOp::CoreOp(op) => Some(op),
+        _ => None,
     }
-
-    // Clone the functions so that we can remove them from the map.
-    let mut result_functions = functions.clone();
-    result_functions.remove(&-1);
-
-    // The final output code.
-    let mut result = vec![];
-
-    // For every function, insert its body into the resulting output code.
-    for i in 0..=functions.len() as i32 {
-        if let Some(body) = functions.remove(&i) {
-            result.extend(body);
-        }
-    }
-
-    // Insert the remaining code into the output code.
-    if let Some(body) = functions.remove(&-1) {
-        result.extend(body);
-    }
-
-    // Return the output code
-    (result, result_functions, main_instructions)
 }
 
 impl fmt::Display for StandardProgram {
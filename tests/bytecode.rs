@@ -0,0 +1,154 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{
+    parse::parse_vm_bytecode,
+    runtime::{Program, RuntimeError, SourceType},
+    side_effects::{Axis, Color, FFIBinding, Input, InputMode, Output, OutputMode},
+    vm::*,
+    Runtime,
+};
+
+#[test]
+fn test_round_trip_frontend() {
+    with_large_stack(test_round_trip_frontend_helper);
+}
+
+fn test_round_trip_frontend_helper() {
+    let code = r#"def average(xs: [Float * 3]): Float = (xs[0] + xs[1] + xs[2]) / 3.0;
+
+println("average: ", average([1.5, -2.0, 4.0]));
+"#;
+    let runtime = Runtime::with_device(TestingDevice::new("")).with_filename("main.sg");
+    let program = runtime.compile(code).unwrap();
+    assert!(!program.is_core());
+
+    // The program is the same after it's loaded, including its source map.
+    let bytecode = program.to_bytecode();
+    assert!(is_bytecode(&bytecode));
+    let loaded = runtime.load(&bytecode).unwrap();
+    assert!(loaded == program);
+    let (map, loaded_map) = (program.source_map(), loaded.source_map());
+    assert!((0..map.len()).any(|i| map.location(i).is_some()));
+    assert_eq!(map.len(), loaded_map.len());
+    for i in 0..map.len() {
+        assert_eq!(map.location(i), loaded_map.location(i));
+        assert_eq!(map.procedure(i), loaded_map.procedure(i));
+    }
+
+    let device = runtime.run_bytecode(&bytecode).unwrap();
+    assert_eq!(device.output_str(), "average: 1.1666666666666667\n");
+}
+
+#[test]
+fn test_round_trip_instructions() {
+    let program = StandardProgram(vec![
+        StandardOp::CoreOp(CoreOp::Comment("@ main.sg:1:1 — ünïcode".to_string())),
+        StandardOp::CoreOp(CoreOp::Set(vec![i64::MIN, -1, 0, 1, i64::MAX])),
+        StandardOp::CoreOp(CoreOp::Move(-300)),
+        StandardOp::CoreOp(CoreOp::Offset(-5, 2)),
+        StandardOp::CoreOp(CoreOp::Function),
+        StandardOp::CoreOp(CoreOp::Return),
+        StandardOp::CoreOp(CoreOp::End),
+        StandardOp::CoreOp(CoreOp::Get(Input::new(
+            InputMode::Speedometer(Some(Axis::Y)),
+            3,
+        ))),
+        StandardOp::CoreOp(CoreOp::Get(Input::new(
+            InputMode::Custom("sensor".to_string()),
+            0,
+        ))),
+        StandardOp::CoreOp(CoreOp::Put(Output::new(
+            OutputMode::SetCursorChar(Color::RGB(1, 2, 255)),
+            1 << 40,
+        ))),
+        StandardOp::Set(vec![1.5, -0.0, f64::INFINITY, f64::MIN_POSITIVE]),
+        StandardOp::Call(FFIBinding::new("host_function".to_string(), 2, 1)),
+        StandardOp::Alloc,
+        StandardOp::Pow(4),
+        StandardOp::Poke,
+    ]);
+
+    let bytecode = program.to_bytecode();
+    assert_eq!(&bytecode[..4], &BYTECODE_MAGIC);
+    assert!(from_bytecode(&bytecode) == Ok(Err(program)));
+
+    // Core programs are loaded as core programs.
+    let program = CoreProgram(vec![
+        CoreOp::Set(vec![42]),
+        CoreOp::Put(Output::stdout_int()),
+    ]);
+    assert!(parse_vm_bytecode(&program.to_bytecode()) == Ok(Ok(program.clone())));
+    // A standard program of only core instructions is still a standard program.
+    let program = StandardProgram::from(program);
+    assert!(from_bytecode(&program.to_bytecode()) == Ok(Err(program)));
+}
+
+#[test]
+fn test_invalid_bytecode() {
+    let bytecode = CoreProgram(vec![
+        CoreOp::Set(vec![1]),
+        CoreOp::Put(Output::stdout_int()),
+    ])
+    .to_bytecode();
+
+    assert!(matches!(
+        from_bytecode(b"set 1\nput stdout.int"),
+        Err(BytecodeError::NotBytecode)
+    ));
+    assert!(matches!(
+        from_bytecode(&bytecode[..8]),
+        Err(BytecodeError::UnexpectedEnd)
+    ));
+
+    // Newer versions of the format aren't loaded.
+    let mut newer = bytecode.clone();
+    newer[4] = 3;
+    assert!(matches!(
+        from_bytecode(&newer),
+        Err(BytecodeError::UnsupportedVersion(3))
+    ));
+
+    // Any corrupted byte is caught by the checksum.
+    for i in 6..bytecode.len() {
+        let mut corrupted = bytecode.clone();
+        corrupted[i] ^= 0x10;
+        assert!(
+            matches!(
+                from_bytecode(&corrupted),
+                Err(BytecodeError::ChecksumMismatch { .. })
+            ),
+            "corrupting byte {i} wasn't detected"
+        );
+    }
+    let Err(err) = parse_vm_bytecode(&bytecode[..bytecode.len() - 1]) else {
+        panic!("truncated bytecode was loaded");
+    };
+    assert!(err.starts_with("bytecode is corrupted"), "{err}");
+}
+
+#[test]
+fn test_runtime_load_core_vm() {
+    let bytecode = Program::from(Err(StandardProgram(vec![
+        StandardOp::Set(vec![1.5]),
+        StandardOp::CoreOp(CoreOp::Put(Output::stdout_float())),
+    ])))
+    .to_bytecode();
+
+    let device = Runtime::with_device(TestingDevice::new(""))
+        .run_bytecode(&bytecode)
+        .unwrap();
+    assert_eq!(device.output_str(), "1.5");
+
+    // Core VM programs can't be standard programs.
+    let result = Runtime::with_device(TestingDevice::new(""))
+        .with_source_type(SourceType::CoreVM)
+        .load(&bytecode);
+    assert!(matches!(result, Err(RuntimeError::InvalidSource(_))));
+
+    let result = Runtime::with_device(TestingDevice::new("")).run_bytecode(b"\0SGB");
+    assert!(matches!(
+        result,
+        Err(RuntimeError::Bytecode(BytecodeError::UnexpectedEnd))
+    ));
+}
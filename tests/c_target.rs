@@ -1,36 +1,118 @@
+mod common;
+
+use common::{with_large_stack, TempFile};
+use log::warn;
 use sage::{lir::Compile, parse::*, targets::*};
 use std::{
     fs::{read_dir, read_to_string},
+    io::Write,
     path::PathBuf,
-    io::Write
 };
-use log::warn;
 
 const INPUT: &str = "2 4 8 16 32 64 128 256 512 1024 2048 4096";
 const CALL_STACK_SIZE: usize = 8192;
+/// The C compilers to test the generated code against.
+const COMPILERS: &[&str] = &["gcc", "clang"];
+/// The generated code must be strictly conforming C99.
+const CFLAGS: &[&str] = &["-std=c99", "-Wall", "-Werror"];
+/// Examples whose output can't be compared against the expected output.
+const SKIPPED_EXAMPLES: &[&str] = &[
+    // These print pointers, which differ between the targets.
+    "static.sg",
+    "sequence.sg",
+    "sequence2.sg",
+];
+/// A comma separated list of compilers to skip, for machines that don't have them installed.
+const SKIP_COMPILERS_VAR: &str = "SAGE_SKIP_C_COMPILERS";
+
+/// Get the compilers to test against.
+///
+/// Every compiler must be installed, unless it's explicitly skipped with `SAGE_SKIP_C_COMPILERS`.
+fn compilers() -> Vec<&'static str> {
+    let skipped = std::env::var(SKIP_COMPILERS_VAR).unwrap_or_default();
+    let mut result = vec![];
+    for &compiler in COMPILERS {
+        if skipped.split(',').any(|skipped| skipped.trim() == compiler) {
+            warn!(
+                "Skipping tests for C compiler `{compiler}`, since it's in {SKIP_COMPILERS_VAR}."
+            );
+            continue;
+        }
+        assert!(
+            std::process::Command::new(compiler)
+                .arg("--version")
+                .output()
+                .is_ok(),
+            "Could not find C compiler `{compiler}`. Install it, or add it to {SKIP_COMPILERS_VAR} to skip its tests."
+        );
+        result.push(compiler);
+    }
+    result
+}
 
 #[test]
 fn test_c_target_frontend_examples() {
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(1)
-        .stack_size(512 * 1024 * 1024)
-        .build_global()
-        .unwrap();
-    // Compiling most examples overflows the tiny stack for tests.
-    // So, we spawn a new thread with a larger stack size.
-    let child = std::thread::Builder::new()
-        .stack_size(512 * 1024 * 1024)
-        .spawn(test_c_target_frontend_examples_helper)
-        .unwrap();
-
-    // Wait for the thread to finish.
-    child.join().unwrap();
+    for compiler in compilers() {
+        with_large_stack(move || test_c_target_frontend_examples_helper(compiler));
+    }
+}
+
+#[test]
+fn test_c_target_out_of_bounds_panic() {
+    with_large_stack(test_c_target_out_of_bounds_panic_helper);
 }
 
-fn test_c_target_frontend_examples_helper() {
-    let mut total_failures: i32 = 0;
-    let mut total_attempts = 0;
+fn test_c_target_out_of_bounds_panic_helper() {
+    let frontend_code = parse_frontend(
+        r#"def get(xs: &[Int], i: Int): Int = xs[i];
+
+let xs = [1, 2, 3];
+println(get(&xs, 2));
+println(get(&xs, 3));
+"#,
+        Some("main.sg"),
+    )
+    .unwrap();
+    let c_code = match frontend_code.compile().unwrap() {
+        Ok(asm_code) => C
+            .build_core(&asm_code.assemble(CALL_STACK_SIZE).unwrap().flatten())
+            .unwrap(),
+        Err(asm_code) => C
+            .build_std(&asm_code.assemble(CALL_STACK_SIZE).unwrap().flatten())
+            .unwrap(),
+    };
+
+    for compiler in compilers() {
+        let c_code_file = TempFile::new(&format!("tmp_c_code_{compiler}_out_of_bounds.c"));
+        let c_exe_file = TempFile::new(&format!("tmp_c_code_{compiler}_out_of_bounds.exe"));
+        let (c_code_path, c_exe_path) = (c_code_file.path(), c_exe_file.path());
+        std::fs::write(c_code_path, &c_code).unwrap();
+        let c_compile_output = std::process::Command::new(compiler)
+            .args(CFLAGS)
+            .arg(c_code_path)
+            .arg("-o")
+            .arg(c_exe_path)
+            .arg("-lm")
+            .output()
+            .unwrap();
+        assert!(
+            c_compile_output.status.success(),
+            "Could not compile C code with `{compiler}`: {c_compile_output:?}"
+        );
+
+        // The program stops at the out of bounds index, after printing where it was.
+        let c_output = std::process::Command::new(c_exe_path)
+            .output()
+            .unwrap();
+        assert!(!c_output.status.success());
+        assert_eq!(
+            String::from_utf8(c_output.stderr).unwrap(),
+            "panic at main.sg:1:36: index out of bounds\n"
+        );
+    }
+}
 
+fn test_c_target_frontend_examples_helper(compiler: &str) {
     for entry in read_dir("examples/frontend/").unwrap() {
         let entry = entry.unwrap();
         let path = entry.path();
@@ -50,6 +132,10 @@ fn test_c_target_frontend_examples_helper() {
                 .to_str()
                 .unwrap_or_else(|| panic!("Could not get file name of path `{path:?}`"))
                 .to_string();
+            if SKIPPED_EXAMPLES.contains(&file_name.as_str()) {
+                warn!("Skipping the C test with `{compiler}` for `{path:?}`");
+                continue;
+            }
             let correct_output_path = PathBuf::from("examples/test-output")
                 .join(file_name.clone())
                 .with_extension("txt");
@@ -102,76 +188,67 @@ fn test_c_target_frontend_examples_helper() {
             .unwrap();
 
             let c_code = match vm_code {
-                Ok(vm_code) => {
-                    C.build_core(&vm_code.flatten()).unwrap()
-                }
-                Err(vm_code) => {
-                    C.build_std(&vm_code.flatten()).unwrap()
-                }
+                Ok(vm_code) => C.build_core(&vm_code.flatten()).unwrap(),
+                Err(vm_code) => C.build_std(&vm_code.flatten()).unwrap(),
             };
 
-            // Write the C code to a file.
-            let c_code_path = format!("tmp_c_code_{file_name}.c");
-            std::fs::write(&c_code_path, c_code).unwrap();
+            // Write the C code to a file, which is removed when the test is done with it.
+            let c_code_file = TempFile::new(&format!("tmp_c_code_{compiler}_{file_name}.c"));
+            let c_code_path = c_code_file.path();
+            std::fs::write(c_code_path, c_code).unwrap();
 
             // Compile the C code.
-            let c_exe_path = format!("tmp_c_code_{file_name}.exe");
-            let c_compile_output = std::process::Command::new("gcc")
-                .arg(&c_code_path)
+            let c_exe_file = TempFile::new(&format!("tmp_c_code_{compiler}_{file_name}.exe"));
+            let c_exe_path = c_exe_file.path();
+            let c_compile_output = std::process::Command::new(compiler)
+                .args(CFLAGS)
+                .arg(c_code_path)
                 .arg("-o")
-                .arg(&c_exe_path)
+                .arg(c_exe_path)
                 .arg("-lm")
                 .output()
                 .unwrap();
 
             if !c_compile_output.status.success() {
                 panic!(
-                    "Could not compile C code for `{path:?}`: {c_compile_output:?}"
+                    "Could not compile C code for `{path:?}` with `{compiler}`: {c_compile_output:?}"
                 );
             }
 
             // Run the C code with the input, and confirm that the output matches the expected output.
             let stdin = std::process::Stdio::piped();
             let stdout = std::process::Stdio::piped();
-            let mut c_exe = match std::process::Command::new(&format!("./{c_exe_path}"))
+            let mut c_exe = match std::process::Command::new(c_exe_path)
                 .stdin(stdin)
                 .stdout(stdout)
-                .spawn() {
+                .spawn()
+            {
                 Ok(v) => v,
-                Err(e) => panic!("Could not run C code for `{path:?}`: {e}")
+                Err(e) => panic!("Could not run C code for `{path:?}`: {e}"),
             };
-                
-            if c_exe.stdin.as_mut().unwrap().write_all(INPUT.as_bytes()).is_err() {
-                panic!("Could not write to stdin of program `{path:?}`");
+
+            // Programs that don't read their input may exit before it's written.
+            if let Err(e) = c_exe.stdin.as_mut().unwrap().write_all(INPUT.as_bytes()) {
+                if e.kind() != std::io::ErrorKind::BrokenPipe {
+                    panic!("Could not write to stdin of program `{path:?}`");
+                }
             }
 
             // Get stdout from the C program.
             let c_output = c_exe.wait_with_output().unwrap().stdout;
             // Convert both to strings
-            let correct_output = correct_output.iter().map(|byte| *byte as u8).collect::<Vec<_>>();
+            let correct_output = correct_output
+                .iter()
+                .map(|byte| *byte as u8)
+                .collect::<Vec<_>>();
 
             let c_output = String::from_utf8(c_output).unwrap();
             let correct_output = String::from_utf8(correct_output).unwrap();
 
-            if c_output != correct_output {
-                warn!(
-                    "Output did not match correct output for program `{path:?}`:\n{c_output}\n  !=   \n{correct_output}"
-                );
-                total_failures += 1;
-            }
-            total_attempts += 1;
-
-            // Remove the C code and executable.
-            std::fs::remove_file(&c_code_path).unwrap();
-            std::fs::remove_file(&c_exe_path).unwrap();
+            assert_eq!(
+                c_output, correct_output,
+                "Output did not match correct output for program `{path:?}` with `{compiler}`"
+            );
         }
     }
-
-    // We just use a threshold of 30% failure, because the frontend examples print out pointers
-    // And the frontend examples all print pointers differently. Same for floats.
-    if total_failures as f64 / total_attempts as f64 > 0.3 {
-        panic!(
-            "Too many failures in frontend examples: {total_failures} failures out of {total_attempts} attempts"
-        );
-    }
 }
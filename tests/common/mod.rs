@@ -0,0 +1,42 @@
+//! Helpers shared by the integration tests.
+
+/// Run the given test on a thread with a larger stack.
+pub fn with_large_stack(test: impl FnOnce() + Send + 'static) {
+    // Compiling most programs overflows the tiny stack for tests.
+    // The pool may already have been built by another test.
+    let _ = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .stack_size(512 * 1024 * 1024)
+        .build_global();
+    let child = std::thread::Builder::new()
+        .stack_size(512 * 1024 * 1024)
+        .spawn(test)
+        .unwrap();
+
+    // Wait for the thread to finish.
+    child.join().unwrap();
+}
+
+/// A file in the temporary directory, which is removed when it's dropped,
+/// even if the test using it fails.
+#[allow(dead_code)] // Only the tests of the compiled targets write files.
+pub struct TempFile(std::path::PathBuf);
+
+#[allow(dead_code)]
+impl TempFile {
+    /// Get a temporary file with the given name, which is unique to this process.
+    pub fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("sage-{}-{name}", std::process::id())))
+    }
+
+    /// Get the path of the file.
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{
+    lir::{Compile, Error},
+    parse::parse_frontend,
+};
+use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+
+/// Get the names, files, and lines of the definitions an error was annotated with.
+fn definitions(err: &Error) -> Vec<(String, Option<String>, usize)> {
+    match err {
+        Error::Annotated(_, annotation) => annotation
+            .definitions()
+            .into_iter()
+            .map(|(name, loc)| (name.to_string(), loc.filename.clone(), loc.line))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Get the line of the source code that an error was reported at.
+fn error_line(err: &Error) -> Option<usize> {
+    match err {
+        Error::Annotated(_, annotation) => annotation.location().map(|loc| loc.line),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_many_errors_in_one_compile() {
+    with_large_stack(|| {
+        let path = "examples/frontend/typecheck-many-errors.sg";
+        let code = read_to_string(path).unwrap();
+        let err = parse_frontend(&code, Some(path))
+            .unwrap()
+            .compile()
+            .map(|_| ())
+            .unwrap_err();
+
+        // Each of the procedures is checked, even after an error in an earlier one.
+        let errs = err.into_errors();
+        assert_eq!(
+            errs.iter().map(error_line).collect::<Vec<_>>(),
+            vec![Some(11), Some(15), Some(20)]
+        );
+
+        // The definitions involved in each error are reported with it.
+        assert_eq!(errs[0].related_symbols(), vec!["add".to_string()]);
+        assert_eq!(errs[1].related_symbols(), vec!["Point".to_string()]);
+        assert!(errs[2].related_symbols().is_empty());
+        let path = Some(path.to_string());
+        assert_eq!(
+            definitions(&errs[0]),
+            vec![("add".to_string(), path.clone(), 6)]
+        );
+        assert_eq!(definitions(&errs[1]), vec![("Point".to_string(), path, 1)]);
+        assert!(definitions(&errs[2]).is_empty());
+    })
+}
+
+#[test]
+fn test_related_definitions_in_scope() {
+    with_large_stack(|| {
+        let dir = std::env::temp_dir().join(format!("sage-definitions-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let module = dir.join("shapes.sg");
+        write(
+            &module,
+            "struct Point {\n    x: Int,\n}\n\ndef scale(p: Point, k: Int): Point = p;\n",
+        )
+        .unwrap();
+        let main = dir.join("main.sg");
+        let code = r#"import shapes;
+
+def scale(x: Float): Float = x * 2.0;
+
+def f() {
+    struct Point {
+        y: Int,
+    }
+    let p: Point = 5;
+}
+
+println(shapes::scale({x=1}, True));
+println(scale(1.0));
+f();
+"#;
+        let err = parse_frontend(code, main.to_str())
+            .unwrap()
+            .compile()
+            .map(|_| ())
+            .unwrap_err();
+        remove_dir_all(&dir).unwrap();
+
+        // The definitions are the ones in scope where the error is, even if they're
+        // in another module, or shadow another definition with the same name.
+        let mut errs = err.into_errors();
+        errs.sort_by_key(error_line);
+        let main = main.to_str().map(String::from);
+        let module = module.to_str().map(String::from);
+        assert_eq!(
+            errs.iter().map(definitions).collect::<Vec<_>>(),
+            vec![
+                vec![("Point".to_string(), main, 6)],
+                vec![("shapes::scale".to_string(), module, 5)],
+            ]
+        );
+    })
+}
+
+#[test]
+fn test_error_in_top_level_procedure_has_location() {
+    with_large_stack(|| {
+        let code = "def g(y: Bool): Int = y;\nprintln(g(True));\n";
+        let err = parse_frontend(code, Some("main.sg"))
+            .unwrap()
+            .compile()
+            .map(|_| ())
+            .unwrap_err();
+
+        // The error is reported at the definition of the procedure.
+        let errs = err.into_errors();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(error_line(&errs[0]), Some(1));
+    })
+}
+
+#[test]
+fn test_combine_errors() {
+    assert!(Error::combine([Ok(()), Ok(())]).is_ok());
+
+    // A single error is returned as it is.
+    let err = Error::combine([Ok(()), Err(Error::SymbolNotDefined("x".to_string()))]);
+    assert!(matches!(err, Err(Error::SymbolNotDefined(_))));
+
+    // Nested groups of errors are flattened.
+    let err = Error::combine([
+        Err(Error::SymbolNotDefined("x".to_string())),
+        Error::combine([
+            Err(Error::TypeNotDefined("T".to_string())),
+            Err(Error::SymbolNotDefined("y".to_string())),
+        ]),
+    ])
+    .unwrap_err();
+    assert_eq!(err.into_errors().len(), 3);
+}
@@ -8,6 +8,7 @@ use log::warn;
 
 const INPUT: &str = "2 4 8 16 32 64 128 256 512 1024 2048 4096";
 const CALL_STACK_SIZE: usize = 8192;
+const OPT_LEVEL: usize = 2;
 
 #[test]
 fn test_frontend_examples() {
@@ -109,12 +110,12 @@ fn test_frontend_examples_helper() {
             }
             .unwrap();
 
-            let device = match vm_code {
+            let device = match &vm_code {
                 Ok(vm_code) => CoreInterpreter::new(TestingDevice::new(INPUT))
-                    .run(&vm_code)
+                    .run(vm_code)
                     .unwrap_or_else(|_| panic!("Could not interpret code in `{path:?}`")),
                 Err(vm_code) => StandardInterpreter::new(TestingDevice::new(INPUT))
-                    .run(&vm_code)
+                    .run(vm_code)
                     .unwrap_or_else(|_| panic!("Could not interpret code in `{path:?}`")),
             };
 
@@ -126,6 +127,19 @@ fn test_frontend_examples_helper() {
             if let Some(correct_error) = correct_error {
                 panic!("Expected error `{correct_error:?}` but got output `{output_text:?}` for program `{path:?}`")
             }
+
+            // The optimized program must behave identically.
+            let device = match vm_code {
+                Ok(vm_code) => CoreInterpreter::new(TestingDevice::new(INPUT))
+                    .run(&vm_code.optimize(OPT_LEVEL))
+                    .unwrap_or_else(|_| panic!("Could not interpret optimized code in `{path:?}`")),
+                Err(vm_code) => StandardInterpreter::new(TestingDevice::new(INPUT))
+                    .run(&vm_code.optimize(OPT_LEVEL))
+                    .unwrap_or_else(|_| panic!("Could not interpret optimized code in `{path:?}`")),
+            };
+            if device.output_vals() != correct_output {
+                panic!("{:?} != {correct_output_text:?}, optimized device output did not match correct output for program {path:?}", device.output_str())
+            }
         }
     }
 }
@@ -215,12 +229,12 @@ fn test_lir_examples_helper() {
             }
             .unwrap();
 
-            let device = match vm_code {
+            let device = match &vm_code {
                 Ok(vm_code) => CoreInterpreter::new(TestingDevice::new(INPUT))
-                    .run(&vm_code)
+                    .run(vm_code)
                     .unwrap_or_else(|_| panic!("Could not interpret code in `{path:?}`")),
                 Err(vm_code) => StandardInterpreter::new(TestingDevice::new(INPUT))
-                    .run(&vm_code)
+                    .run(vm_code)
                     .unwrap_or_else(|_| panic!("Could not interpret code in `{path:?}`")),
             };
 
@@ -232,6 +246,19 @@ fn test_lir_examples_helper() {
             if let Some(correct_error) = correct_error {
                 panic!("Expected error `{correct_error:?}` but got output `{output_text:?}` for program `{path:?}`")
             }
+
+            // The optimized program must behave identically.
+            let device = match vm_code {
+                Ok(vm_code) => CoreInterpreter::new(TestingDevice::new(INPUT))
+                    .run(&vm_code.optimize(OPT_LEVEL))
+                    .unwrap_or_else(|_| panic!("Could not interpret optimized code in `{path:?}`")),
+                Err(vm_code) => StandardInterpreter::new(TestingDevice::new(INPUT))
+                    .run(&vm_code.optimize(OPT_LEVEL))
+                    .unwrap_or_else(|_| panic!("Could not interpret optimized code in `{path:?}`")),
+            };
+            if device.output_vals() != correct_output {
+                panic!("{:?} != {correct_output_text:?}, optimized device output did not match correct output for program {path:?}", device.output_str())
+            }
         }
     }
 }
@@ -319,12 +346,12 @@ fn test_asm_examples_helper() {
 
             let vm_code = vm_code.unwrap();
 
-            let device = match vm_code {
+            let device = match &vm_code {
                 Ok(vm_code) => CoreInterpreter::new(TestingDevice::new(INPUT))
-                    .run(&vm_code)
+                    .run(vm_code)
                     .unwrap_or_else(|_| panic!("Could not interpret code in `{path:?}`")),
                 Err(vm_code) => StandardInterpreter::new(TestingDevice::new(INPUT))
-                    .run(&vm_code)
+                    .run(vm_code)
                     .unwrap_or_else(|_| panic!("Could not interpret code in `{path:?}`")),
             };
 
@@ -336,6 +363,19 @@ fn test_asm_examples_helper() {
             if let Some(correct_error) = correct_error {
                 panic!("Expected error `{correct_error:?}` but got output `{output_text:?}` for program `{path:?}`")
             }
+
+            // The optimized program must behave identically.
+            let device = match vm_code {
+                Ok(vm_code) => CoreInterpreter::new(TestingDevice::new(INPUT))
+                    .run(&vm_code.optimize(OPT_LEVEL))
+                    .unwrap_or_else(|_| panic!("Could not interpret optimized code in `{path:?}`")),
+                Err(vm_code) => StandardInterpreter::new(TestingDevice::new(INPUT))
+                    .run(&vm_code.optimize(OPT_LEVEL))
+                    .unwrap_or_else(|_| panic!("Could not interpret optimized code in `{path:?}`")),
+            };
+            if device.output_vals() != correct_output {
+                panic!("{:?} != {correct_output_text:?}, optimized device output did not match correct output for program {path:?}", device.output_str())
+            }
         }
     }
 }
@@ -0,0 +1,194 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{lir::Compile, parse::*, side_effects::*, vm::*};
+use std::sync::{Arc, Mutex};
+
+const CALL_STACK_SIZE: usize = 8192;
+
+/// Compile and run the given frontend program on a device, and return the device.
+fn run(code: &str, device: TestingDevice) -> Result<TestingDevice, Trap> {
+    let expr = parse_frontend(code, None).unwrap();
+    let asm_code = expr
+        .compile()
+        .unwrap()
+        .expect_err("foreign functions need the standard variant");
+    let (vm_code, map) = asm_code.assemble_with_source_map(CALL_STACK_SIZE).unwrap();
+    StandardInterpreter::new(device)
+        .with_source_map(map)
+        .run(&vm_code)
+}
+
+#[test]
+fn test_stateful_binding() {
+    with_large_stack(test_stateful_binding_helper);
+}
+
+fn test_stateful_binding_helper() {
+    // The binding owns a counter, and the host keeps a log of the calls.
+    let log = Arc::new(Mutex::new(vec![]));
+    let mut device = TestingDevice::new("");
+    let mut count = 0;
+    let host_log = log.clone();
+    device.add_binding(FFIBinding::new("next".to_string(), 1, 1), move |call| {
+        let step = call.int_arg()?;
+        count += step;
+        host_log.lock().unwrap().push(count);
+        call.ret_int(count)
+    });
+
+    let device = run(
+        r#"extern def next(step: Int): Int;
+
+println(next(1));
+println(next(2));
+println(next(3));
+"#,
+        device,
+    )
+    .unwrap();
+    assert_eq!(device.output_str(), "1\n3\n6\n");
+    assert_eq!(*log.lock().unwrap(), vec![1, 3, 6]);
+}
+
+#[test]
+fn test_typed_arguments() {
+    with_large_stack(test_typed_arguments_helper);
+}
+
+fn test_typed_arguments_helper() {
+    let mut device = TestingDevice::new("");
+    device.add_binding(FFIBinding::new("scale".to_string(), 3, 1), |call| {
+        let x = call.float_arg()?;
+        let ch = call.char_arg()?;
+        let times = call.int_arg()?;
+        call.ret_float(x * times as f64 + ch.to_digit(10).unwrap_or(0) as f64)
+    });
+    // Write a greeting for the string at the pointer into the buffer.
+    device.add_binding(FFIBinding::new("greet".to_string(), 2, 1), |call| {
+        let name = call.pointer_arg()?;
+        let buf = call.pointer_arg()?;
+        let greeting = format!("hi {}", call.read_str(name)?);
+        let mut cells: Vec<i64> = greeting.bytes().map(i64::from).collect();
+        cells.push(0);
+        call.write(buf, &cells)?;
+        call.ret_pointer(buf)
+    });
+
+    let device = run(
+        r#"extern def scale(x: Float, digit: Char, times: Int): Float;
+extern def greet(name: &Char, buf: &mut Char): &Char;
+
+println(scale(1.5, '2', 4));
+let name = ['s', 'a', 'g', 'e', '\0'];
+let mut buf = ['\0'] * 16;
+let greeting = greet(&name as &Char, &mut buf as &mut Char);
+for let mut i = 0; greeting[i] != '\0'; i += 1 {
+    print(greeting[i]);
+}
+println();
+"#,
+        device,
+    )
+    .unwrap();
+    assert_eq!(device.output_str(), "8.0\nhi sage\n");
+}
+
+#[test]
+fn test_binding_errors_trap() {
+    with_large_stack(test_binding_errors_trap_helper);
+}
+
+fn test_binding_errors_trap_helper() {
+    // The binding declares one argument, but tries to read two.
+    let mut device = TestingDevice::new("");
+    device.add_binding(FFIBinding::new("greedy".to_string(), 1, 1), |call| {
+        let a = call.int_arg()?;
+        let b = call.int_arg()?;
+        call.ret_int(a + b)
+    });
+    let trap = run(
+        r#"extern def greedy(x: Int): Int;
+
+println(greedy(1));
+"#,
+        device,
+    )
+    .unwrap_err();
+    assert_eq!(
+        trap.kind,
+        TrapKind::Other("ffi greedy(1) -> 1 read more than its 1 argument cells".to_string())
+    );
+    assert_eq!(trap.location().map(|loc| loc.line), Some(3));
+}
+
+#[test]
+fn test_unused_arguments_trap() {
+    with_large_stack(test_unused_arguments_trap_helper);
+}
+
+fn test_unused_arguments_trap_helper() {
+    // The binding declares two arguments and a result, but only reads one argument.
+    let mut device = TestingDevice::new("");
+    device.add_binding(FFIBinding::new("lazy".to_string(), 2, 1), |call| {
+        let a = call.int_arg()?;
+        call.ret_int(a)
+    });
+    let trap = run(
+        r#"extern def lazy(x: Int, y: Int): Int;
+
+println(lazy(1, 2));
+"#,
+        device,
+    )
+    .unwrap_err();
+    assert_eq!(
+        trap.kind,
+        TrapKind::Other("ffi lazy(2) -> 1 only read 1 of its 2 argument cells".to_string())
+    );
+
+    // The binding reads its argument, but never writes its result.
+    let mut device = StandardDevice::default();
+    device.add_binding(FFIBinding::new("silent".to_string(), 1, 1), |call| {
+        call.int_arg()?;
+        Ok(())
+    });
+    device.poke(5).unwrap();
+    let err = device
+        .ffi_call(&FFIBinding::new("silent".to_string(), 1, 1), None)
+        .unwrap_err();
+    assert_eq!(err, "ffi silent(1) -> 1 only wrote 0 of its 1 result cells");
+
+    // Bindings which use the raw channel must leave the right number of cells in it.
+    let binding = FFIBinding::new("raw".to_string(), 2, 1);
+    let mut channel = [1, 2].into_iter().collect();
+    let mut call = FFICall::new(&binding, &mut channel, None);
+    call.channel().push_back(7);
+    assert_eq!(
+        call.finish().unwrap_err(),
+        "ffi raw(2) -> 1 left 3 cells in the ffi channel instead of 1"
+    );
+}
+
+#[test]
+fn test_devices_are_send_and_clone() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    let mut device = StandardDevice::default();
+    let mut count = 0;
+    device.add_binding(FFIBinding::new("count".to_string(), 0, 1), move |call| {
+        count += 1;
+        call.ret_int(count)
+    });
+    assert_send(&device);
+    assert_send(&TestingDevice::new(""));
+    assert_send(&sage::Runtime::with_device(device.clone()));
+
+    // Clones of a device share the state of its bindings.
+    let binding = FFIBinding::new("count".to_string(), 0, 1);
+    let mut clone = device.clone();
+    device.ffi_call(&binding, None).unwrap();
+    clone.ffi_call(&binding, None).unwrap();
+    assert_eq!(device.peek(), Ok(1));
+    assert_eq!(clone.peek(), Ok(2));
+}
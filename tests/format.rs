@@ -0,0 +1,177 @@
+mod common;
+
+use common::with_large_stack;
+use sage::frontend::{format_code, is_formatted};
+use std::fs::{read_dir, read_to_string};
+use std::path::PathBuf;
+
+/// Get the paths of all the frontend examples, and the standard library modules.
+fn frontend_sources() -> Vec<PathBuf> {
+    let mut paths = vec![];
+    for dir in [
+        "examples/frontend",
+        "examples/frontend/modules",
+        "examples/sage-os",
+        "src/frontend/std",
+    ] {
+        for entry in read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "sg") {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_format_spacing() {
+    with_large_stack(|| {
+        let code = "let mut x=-5*(2+y);\nx+=*ptr-1;\nlet b=x<3&&!done;\n";
+        assert_eq!(
+            format_code(code).unwrap(),
+            "let mut x = -5 * (2 + y);\nx += *ptr - 1;\nlet b = x < 3 && !done;\n"
+        );
+
+        // Angle brackets around types aren't spaced like comparisons.
+        let code = "def id<T>( x:T ):T=x;\nlet v=id<Int>(1)+sizeof<Int>();\n";
+        assert_eq!(
+            format_code(code).unwrap(),
+            "def id<T>(x: T): T = x;\nlet v = id<Int>(1) + sizeof<Int>();\n"
+        );
+
+        let code = "let r=&mut x;\nlet f=def[&mut total](n:Int):(Int)->Int=n;\n";
+        assert_eq!(
+            format_code(code).unwrap(),
+            "let r = &mut x;\nlet f = def[&mut total](n: Int): (Int) -> Int = n;\n"
+        );
+
+        let code = "let y=x>0?x:-x;\n'outer:while True{break 'outer;}\n";
+        assert_eq!(
+            format_code(code).unwrap(),
+            "let y = x > 0 ? x : -x;\n'outer: while True { break 'outer; }\n"
+        );
+    })
+}
+
+#[test]
+fn test_format_indentation() {
+    with_large_stack(|| {
+        let code = r#"
+def main() {
+if True {
+        println("hello");
+  }
+
+
+  let x = 1
+  + 2;
+}
+main();
+"#;
+        assert_eq!(
+            format_code(code).unwrap(),
+            r#"def main() {
+    if True {
+        println("hello");
+    }
+
+    let x = 1
+        + 2;
+}
+main();
+"#
+        );
+
+        // A bracket closed on a later line brings the rest of that line back out.
+        let code = "def f(a: Int,\nb: Int): Int {\nreturn a + b;\n}\n";
+        assert_eq!(
+            format_code(code).unwrap(),
+            "def f(a: Int,\n    b: Int): Int {\n    return a + b;\n}\n"
+        );
+
+        // The bindings after the first in a `let` continue the statement.
+        let code = "let a: Int = 1,\nb: Int = 2;\ndef f() {\nlet x = 1,\ny = 2;\n}\n";
+        assert_eq!(
+            format_code(code).unwrap(),
+            "let a: Int = 1,\n    b: Int = 2;\ndef f() {\n    let x = 1,\n        y = 2;\n}\n"
+        );
+    })
+}
+
+#[test]
+fn test_format_trailing_commas() {
+    with_large_stack(|| {
+        // Multi-line bodies end with a comma.
+        let code = "struct Point {\nx: Int,\ny: Int\n}\nenum E {\nA,\nB Int\n}\n";
+        assert_eq!(
+            format_code(code).unwrap(),
+            "struct Point {\n    x: Int,\n    y: Int,\n}\nenum E {\n    A,\n    B Int,\n}\n"
+        );
+        let code = "let p = {\nx = 1,\ny = 2\n};\nmatch p.x {\n1 => 2,\n_ => 3\n}\n";
+        assert_eq!(
+            format_code(code).unwrap(),
+            "let p = {\n    x = 1,\n    y = 2,\n};\nmatch p.x {\n    1 => 2,\n    _ => 3,\n}\n"
+        );
+
+        // Single-line bodies don't.
+        let code = "struct Point {x: Int, y: Int,}\nlet p = {x=1, y=2,};\nlet {x, y,} = p;\n";
+        assert_eq!(
+            format_code(code).unwrap(),
+            "struct Point {x: Int, y: Int}\nlet p = {x = 1, y = 2};\nlet {x, y} = p;\n"
+        );
+    })
+}
+
+#[test]
+fn test_format_keeps_comments() {
+    with_large_stack(|| {
+        let code = r#"// A comment at the start.
+def f(x: Int): Int {
+// A comment inside.
+    return x+1;  // A trailing comment.
+}
+/* A block
+   comment. */
+let c = '/'; # A comment in the grammar's style.
+let s = "// Not a comment.";
+"#;
+        assert_eq!(
+            format_code(code).unwrap(),
+            r#"// A comment at the start.
+def f(x: Int): Int {
+    // A comment inside.
+    return x + 1; // A trailing comment.
+}
+/* A block
+   comment. */
+let c = '/'; # A comment in the grammar's style.
+let s = "// Not a comment.";
+"#
+        );
+    })
+}
+
+#[test]
+fn test_format_invalid_code() {
+    with_large_stack(|| {
+        assert!(format_code("let x = ;").is_err());
+        assert!(is_formatted("let x = 1;\n").unwrap());
+        assert!(!is_formatted("let x=1;\n").unwrap());
+        assert!(format_code("let s = \"unterminated;").is_err());
+    })
+}
+
+#[test]
+fn test_format_examples() {
+    with_large_stack(|| {
+        for path in frontend_sources() {
+            let code = read_to_string(&path).unwrap();
+            let formatted =
+                format_code(&code).unwrap_or_else(|e| panic!("failed to format {path:?}: {e}"));
+            // The examples and the standard library are all in the canonical style.
+            assert_eq!(formatted, code, "{path:?} is not formatted");
+        }
+    })
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{
+    runtime::RuntimeError,
+    side_effects::FFIBinding,
+    vm::{
+        CoreInterpreter, CoreOp, CoreProgram, Limits, StandardInterpreter, StandardOp,
+        StandardProgram, TestingDevice, Trap, TrapKind,
+    },
+    Runtime,
+};
+use std::time::Duration;
+
+/// Run a frontend program with the given limits, and return the trap which stopped it.
+fn run_until_trap(code: &str, limits: Limits) -> Trap {
+    match Runtime::with_device(TestingDevice::new(""))
+        .with_filename("main.sg")
+        .with_limits(limits)
+        .run(code)
+    {
+        Err(RuntimeError::Trap(trap)) => trap,
+        Err(e) => panic!("the program failed to compile: {e}"),
+        Ok(_) => panic!("the program should have trapped"),
+    }
+}
+
+/// A program which never halts.
+fn infinite_loop() -> CoreProgram {
+    CoreProgram(vec![CoreOp::Set(vec![1]), CoreOp::While, CoreOp::End])
+}
+
+#[test]
+fn test_instruction_limit() {
+    let trap = CoreInterpreter::new(TestingDevice::default())
+        .with_limits(Limits::default().with_instructions(1000))
+        .run(&infinite_loop())
+        .unwrap_err();
+    assert_eq!(trap.kind, TrapKind::InstructionLimit(1000));
+    assert_eq!(
+        trap.to_string(),
+        "runtime error: exceeded the limit of 1000 instructions\nstack backtrace:\n  #0 <main>\n"
+    );
+
+    // A program which finishes within its budget runs normally.
+    let program = CoreProgram(vec![
+        CoreOp::Set(vec![3]),
+        CoreOp::While,
+        CoreOp::Dec(1),
+        CoreOp::End,
+    ]);
+    CoreInterpreter::new(TestingDevice::default())
+        .with_limits(Limits::default().with_instructions(8))
+        .run(&program)
+        .unwrap();
+    let trap = CoreInterpreter::new(TestingDevice::default())
+        .with_limits(Limits::default().with_instructions(7))
+        .run(&program)
+        .unwrap_err();
+    assert_eq!(trap.kind, TrapKind::InstructionLimit(7));
+}
+
+#[test]
+fn test_instruction_limit_ignores_comments() {
+    // The same program as above, annotated like the compiler's output.
+    let program = StandardProgram(vec![
+        StandardOp::CoreOp(CoreOp::Comment("@ main.sg:1:1".to_string())),
+        StandardOp::CoreOp(CoreOp::Set(vec![3])),
+        StandardOp::CoreOp(CoreOp::Comment("@ main.sg:2:1".to_string())),
+        StandardOp::CoreOp(CoreOp::While),
+        StandardOp::CoreOp(CoreOp::Comment("@ main.sg:3:5".to_string())),
+        StandardOp::CoreOp(CoreOp::Dec(1)),
+        StandardOp::CoreOp(CoreOp::End),
+        StandardOp::CoreOp(CoreOp::Comment("end of program".to_string())),
+    ]);
+    StandardInterpreter::new(TestingDevice::default())
+        .with_limits(Limits::default().with_instructions(8))
+        .run(&program)
+        .unwrap();
+    let trap = StandardInterpreter::new(TestingDevice::default())
+        .with_limits(Limits::default().with_instructions(7))
+        .run(&program)
+        .unwrap_err();
+    assert_eq!(trap.kind, TrapKind::InstructionLimit(7));
+
+    let program = CoreProgram(vec![
+        CoreOp::Comment("@ main.sg:1:1".to_string()),
+        CoreOp::Set(vec![3]),
+        CoreOp::While,
+        CoreOp::Comment("@ main.sg:3:5".to_string()),
+        CoreOp::Dec(1),
+        CoreOp::End,
+    ]);
+    CoreInterpreter::new(TestingDevice::default())
+        .with_limits(Limits::default().with_instructions(8))
+        .run(&program)
+        .unwrap();
+}
+
+#[test]
+fn test_timeout() {
+    let program = StandardProgram::from(infinite_loop());
+    let trap = StandardInterpreter::new(TestingDevice::default())
+        .with_limits(Limits::default().with_timeout(Duration::from_millis(50)))
+        .run(&program)
+        .unwrap_err();
+    assert_eq!(trap.kind, TrapKind::Timeout(Duration::from_millis(50)));
+}
+
+#[test]
+fn test_tape_limit_on_move() {
+    // Moving far along the tape and storing a value would grow it past the limit.
+    let program = CoreProgram(vec![CoreOp::Move(1 << 40), CoreOp::Store(1)]);
+    let trap = CoreInterpreter::new(TestingDevice::default())
+        .with_limits(Limits::default().with_tape_size(4096))
+        .run(&program)
+        .unwrap_err();
+    assert_eq!(trap.kind, TrapKind::TapeLimit(4096));
+    assert_eq!(trap.instruction, 1);
+}
+
+#[test]
+fn test_tape_limit_on_alloc() {
+    let program = StandardProgram(vec![
+        StandardOp::CoreOp(CoreOp::Set(vec![1 << 40])),
+        StandardOp::Alloc,
+    ]);
+    let trap = StandardInterpreter::new(TestingDevice::default())
+        .with_limits(Limits::default().with_tape_size(1 << 20))
+        .run(&program)
+        .unwrap_err();
+    assert_eq!(trap.kind, TrapKind::TapeLimit(1 << 20));
+}
+
+#[test]
+fn test_ffi_cannot_grow_tape() {
+    with_large_stack(test_ffi_cannot_grow_tape_helper);
+}
+
+fn test_ffi_cannot_grow_tape_helper() {
+    let result = Runtime::with_device(TestingDevice::new(""))
+        .with_limits(Limits::default().with_tape_size(1 << 20))
+        .with_ffi(FFIBinding::new("grow".to_string(), 0, 1), |call| {
+            // The binding only gets the cells on the tape, so it can't write past its end.
+            let len = call.tape().ok_or("no tape".to_string())?.len();
+            assert!(len <= 1 << 20);
+            call.write(len, &[1])?;
+            call.ret_int(0)
+        })
+        .run("extern def grow(): Int;\nprintln(grow());\n");
+    match result {
+        Err(RuntimeError::Trap(trap)) => assert!(
+            matches!(&trap.kind, TrapKind::Other(message) if message.contains("wrote outside of the tape")),
+            "{trap}"
+        ),
+        _ => panic!("expected a trap"),
+    }
+}
+
+#[test]
+fn test_call_depth_limit() {
+    with_large_stack(test_call_depth_limit_helper);
+}
+
+fn test_call_depth_limit_helper() {
+    let trap = run_until_trap(
+        r#"def forever(n: Int): Int = forever(n + 1);
+
+println(forever(0));
+"#,
+        Limits::default().with_call_depth(16),
+    );
+    assert_eq!(trap.kind, TrapKind::CallDepthLimit(16));
+    assert_eq!(trap.stack.len(), 17);
+    assert_eq!(trap.stack[0].procedure.as_deref(), Some("forever"));
+    assert_eq!(trap.location().map(|loc| loc.line), Some(1));
+}
+
+#[test]
+fn test_limits_in_runtime() {
+    with_large_stack(test_limits_in_runtime_helper);
+}
+
+fn test_limits_in_runtime_helper() {
+    let trap = run_until_trap(
+        r#"let mut i = 0;
+while True {
+    i += 1;
+}
+"#,
+        Limits::default().with_instructions(100_000),
+    );
+    assert_eq!(trap.kind, TrapKind::InstructionLimit(100_000));
+    assert!(matches!(trap.location().map(|loc| loc.line), Some(2..=3)));
+
+    // Programs within their limits run normally.
+    let device = Runtime::with_device(TestingDevice::new(""))
+        .with_limits(
+            Limits::default()
+                .with_instructions(1_000_000)
+                .with_tape_size(1 << 20)
+                .with_call_depth(64)
+                .with_timeout(Duration::from_secs(60)),
+        )
+        .run("let xs = [1, 2, 3];\nprintln(xs[0] + xs[1] + xs[2]);\n")
+        .unwrap();
+    assert_eq!(device.output_str(), "6\n");
+}
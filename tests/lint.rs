@@ -0,0 +1,191 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{
+    frontend,
+    lir::{Env, TypeCheck},
+};
+
+const FILENAME: &str = "lint.sg";
+
+/// Get the warnings for some code, with the lines they're reported at.
+fn lint(code: &str) -> Vec<(String, usize)> {
+    let expr = frontend::parse(code, Some(FILENAME)).unwrap();
+    let mut warnings = frontend::unused_imports(code, Some(FILENAME)).unwrap();
+    warnings.extend(expr.lint(&Env::default(), Some(FILENAME)));
+    warnings
+        .iter()
+        .map(|warning| (warning.to_string(), warning.location().unwrap().line))
+        .collect()
+}
+
+#[test]
+fn test_lint_unused_variables() {
+    with_large_stack(|| {
+        let code = r#"
+def f(a: Int, b: Int, _c: Int): Int {
+    let x = 1;
+    let _y = 2;
+    return a;
+}
+let p = {x = 1, y = 2};
+let unused = p.x;
+println(f(1, 2, 3));
+"#;
+        assert_eq!(
+            lint(code),
+            vec![
+                ("unused variable `x`".to_string(), 3),
+                ("unused parameter `b`".to_string(), 2),
+                ("unused variable `unused`".to_string(), 8),
+            ]
+        );
+    })
+}
+
+#[test]
+fn test_lint_unreachable_code() {
+    with_large_stack(|| {
+        let code = r#"
+from std::panic import exit;
+def f(n: Int): Int {
+    if n > 0 {
+        return n;
+        println("after return");
+    }
+    while True {
+        break;
+        println("after break");
+    }
+    exit(1);
+    return 0;
+}
+println(f(1));
+"#;
+        assert_eq!(
+            lint(code),
+            vec![
+                ("unreachable code".to_string(), 6),
+                ("unreachable code".to_string(), 10),
+                ("unreachable code".to_string(), 13),
+            ]
+        );
+    })
+}
+
+#[test]
+fn test_lint_shadowing() {
+    with_large_stack(|| {
+        let code = r#"
+def f(n: Int): Int {
+    let n = n + 1;
+    for let i = 0; i < n; i += 1 {
+        println(i);
+    }
+    for let i = 0; i < n; i += 1 {
+        let i = i * 2;
+        println(i);
+    }
+    return n;
+}
+println(f(1));
+"#;
+        // Loops one after the other don't shadow each other's variables.
+        assert_eq!(
+            lint(code),
+            vec![
+                (
+                    "`n` shadows an earlier binding with the same name".to_string(),
+                    3
+                ),
+                (
+                    "`i` shadows an earlier binding with the same name".to_string(),
+                    8
+                ),
+            ]
+        );
+    })
+}
+
+#[test]
+fn test_lint_unused_imports() {
+    with_large_stack(|| {
+        let code = r#"
+import std::vec;
+import std::panic as p;
+from std::option import Option;
+from std::result import Result;
+import std::string;
+let x = Option<Int> of Nothing;
+println(x, "abc".len());
+p::exit(0);
+"#;
+        assert_eq!(
+            lint(code),
+            vec![
+                ("unused import `vec`".to_string(), 2),
+                ("unused import `Result`".to_string(), 5),
+            ]
+        );
+    })
+}
+
+#[test]
+fn test_lint_imports_extending_builtins() {
+    with_large_stack(|| {
+        // A user module which adds methods to a built-in type is used by calling them.
+        let dir = std::env::temp_dir().join(format!("sage-lint-imports-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("twice.sg"),
+            "impl Str {\n    def twice(self: Str): Int = self.length * 2;\n}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("unused.sg"), "const X = 1;\n").unwrap();
+        let filename = dir.join("main.sg");
+        let code = "import twice;\nimport unused;\nprintln(\"abc\".twice());\n";
+        let warnings = frontend::unused_imports(code, filename.to_str()).unwrap();
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|warning| (warning.to_string(), warning.location().unwrap().line))
+                .collect::<Vec<_>>(),
+            vec![("unused import `unused`".to_string(), 2)]
+        );
+        // The program really does call the method from the module.
+        let expr = frontend::parse(code, filename.to_str()).unwrap();
+        expr.type_check(&Env::default()).unwrap();
+    })
+}
+
+#[test]
+fn test_lint_other_files() {
+    with_large_stack(|| {
+        // Warnings in imported modules aren't reported.
+        let code = "from std::vec import Vec;\nlet v = Vec.make<Int>();\nprintln(v.len());\n";
+        assert_eq!(lint(code), vec![]);
+    })
+}
+
+#[test]
+fn test_cli_exit_status() {
+    // Run the CLI on some code, and get whether it succeeded.
+    let run = |name: &str, code: &str, args: &[&str]| {
+        let path = std::env::temp_dir().join(format!("sage-{name}-{}.sg", std::process::id()));
+        std::fs::write(&path, code).unwrap();
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_sage"))
+            .arg(&path)
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        std::fs::remove_file(&path).unwrap();
+        status.success()
+    };
+
+    let code = "let x = 1;\nprintln(2);\n";
+    assert!(run("warned", code, &[]));
+    // Denied warnings fail the build, so that a CI job can check for them.
+    assert!(!run("denied", code, &["--deny-warnings"]));
+    assert!(!run("invalid", "let x: Int = 'a';\n", &[]));
+}
@@ -0,0 +1,296 @@
+mod common;
+
+use common::with_large_stack;
+use sage::lsp::{read_message, write_message, LanguageServer};
+use serde_json::{json, Value};
+use std::io::Cursor;
+
+const URI: &str = "file:///tmp/sage-lsp-test.sg";
+
+const CODE: &str = r#"struct Point {
+    x: Int,
+    y: Int
+}
+
+impl Point {
+    const ZERO = 0;
+    def norm(self: &Point): Int = self.x * self.x + self.y * self.y;
+}
+
+def add(a: Int, b: Int): Int {
+    let sum = a + b;
+    return sum;
+}
+
+let p = {x=Point.ZERO, y=2};
+let n = p.norm();
+println(add(n, 2));
+"#;
+
+/// Open a document with the given code in a new server.
+fn open(code: &str) -> (LanguageServer, Vec<Value>) {
+    let mut server = LanguageServer::new();
+    let responses = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": { "uri": URI, "languageId": "sage", "version": 1, "text": code },
+        },
+    }));
+    (server, responses)
+}
+
+/// Send a request about a position in the document, and return its result.
+fn request(server: &mut LanguageServer, method: &str, line: usize, character: usize) -> Value {
+    let responses = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": {
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+        },
+    }));
+    assert_eq!(responses.len(), 1);
+    responses[0]["result"].clone()
+}
+
+/// Get the text shown when hovering over a position in the document.
+fn hover(server: &mut LanguageServer, line: usize, character: usize) -> String {
+    let result = request(server, "textDocument/hover", line, character);
+    result["contents"]["value"].as_str().unwrap().to_string()
+}
+
+/// Get the starting lines and characters of the definitions of the symbol at a position.
+fn definitions(server: &mut LanguageServer, line: usize, character: usize) -> Vec<(u64, u64)> {
+    let result = request(server, "textDocument/definition", line, character);
+    result
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|loc| {
+            assert_eq!(loc["uri"], URI);
+            let start = &loc["range"]["start"];
+            (
+                start["line"].as_u64().unwrap(),
+                start["character"].as_u64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_lsp_no_diagnostics() {
+    with_large_stack(|| {
+        let (_, responses) = open(CODE);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(responses[0]["params"]["uri"], URI);
+        assert_eq!(responses[0]["params"]["diagnostics"], json!([]));
+    })
+}
+
+#[test]
+fn test_lsp_type_error_diagnostic() {
+    with_large_stack(|| {
+        let (_, responses) = open("let x = 5;\nx += 1;\n");
+        let diagnostics = responses[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["severity"], 1);
+        // The error is reported at the assignment to the immutable variable.
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({"line": 1, "character": 0})
+        );
+    })
+}
+
+#[test]
+fn test_lsp_many_diagnostics() {
+    with_large_stack(|| {
+        let code = "def f() {\n    let x: Int = True;\n}\ndef g() {\n    let y = 1;\n    y = 2;\n}\nf();\ng();\n";
+        let (_, responses) = open(code);
+        let diagnostics = responses[0]["params"]["diagnostics"].as_array().unwrap();
+        // Both procedures are checked, even though the first has an error.
+        assert_eq!(
+            diagnostics
+                .iter()
+                .filter(|d| d["severity"] == 1)
+                .map(|d| d["range"]["start"]["line"].clone())
+                .collect::<Vec<_>>(),
+            vec![json!(1), json!(5)]
+        );
+    })
+}
+
+#[test]
+fn test_lsp_warning_diagnostics() {
+    with_large_stack(|| {
+        let (_, responses) =
+            open("def f(x: Int, y: Int): Int {\n    return x;\n}\nprintln(f(1, 2));\n");
+        let diagnostics = responses[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["severity"], 2);
+        assert_eq!(diagnostics[0]["message"], "unused parameter `y`");
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({"line": 0, "character": 0})
+        );
+    })
+}
+
+#[test]
+fn test_lsp_parse_error_diagnostic() {
+    with_large_stack(|| {
+        let (mut server, _) = open(CODE);
+        // Break the document, and then fix it again.
+        let change = |text: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": 2 },
+                    "contentChanges": [{ "text": text }],
+                },
+            })
+        };
+        let responses = server.handle(&change("let x = ;\n"));
+        let diagnostics = responses[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("parse error"));
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 0);
+
+        let responses = server.handle(&change(CODE));
+        assert_eq!(responses[0]["params"]["diagnostics"], json!([]));
+    })
+}
+
+#[test]
+fn test_lsp_hover() {
+    with_large_stack(|| {
+        let (mut server, _) = open(CODE);
+        // A local variable inside of a procedure.
+        assert_eq!(hover(&mut server, 12, 12), "```sage\nsum: Int\n```");
+        // A procedure.
+        assert_eq!(
+            hover(&mut server, 17, 9),
+            "```sage\nadd: (Int, Int) -> Int\n```"
+        );
+        // A type.
+        assert_eq!(
+            hover(&mut server, 15, 13),
+            "```sage\ntype Point = {x: Int, y: Int}\n```"
+        );
+        // An associated constant.
+        assert_eq!(hover(&mut server, 15, 19), "```sage\nZERO: Int\n```");
+        // A method called on a variable.
+        assert!(hover(&mut server, 16, 11).starts_with("```sage\nnorm: "));
+        // Nothing to hover over.
+        assert_eq!(
+            request(&mut server, "textDocument/hover", 4, 0),
+            Value::Null
+        );
+    })
+}
+
+#[test]
+fn test_lsp_definition() {
+    with_large_stack(|| {
+        let (mut server, _) = open(CODE);
+        // A procedure.
+        assert_eq!(definitions(&mut server, 17, 9), vec![(10, 4)]);
+        // A type.
+        assert_eq!(definitions(&mut server, 15, 13), vec![(0, 7)]);
+        // An associated constant.
+        assert_eq!(definitions(&mut server, 15, 19), vec![(6, 10)]);
+        // A method.
+        assert_eq!(definitions(&mut server, 16, 11), vec![(7, 8)]);
+    })
+}
+
+#[test]
+fn test_lsp_positions_after_comments() {
+    with_large_stack(|| {
+        // The block comment spans lines, and the comments come before code on the same line.
+        let code = "/* Add two\n   numbers. */\ndef add(a: Int, b: Int): Int = a + b; // The sum.\nlet x = 5; /* immutable */ x += add(1, 2);\n";
+        let (mut server, responses) = open(code);
+        let diagnostics = responses[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0]["range"]["start"],
+            json!({"line": 3, "character": 27})
+        );
+        assert_eq!(definitions(&mut server, 3, 33), vec![(2, 4)]);
+        assert_eq!(
+            hover(&mut server, 3, 33),
+            "```sage\nadd: (Int, Int) -> Int\n```"
+        );
+    })
+}
+
+#[test]
+fn test_lsp_positions_in_utf16() {
+    with_large_stack(|| {
+        // The emojis are one character each, but two UTF-16 code units,
+        // and the one in the comment is blanked out before the code is parsed.
+        let code = "def add(a: Int, b: Int): Int = a + b;\nlet x = 5; /* 😀 */ x += add(1, 2); print('😀');\n";
+        let (mut server, responses) = open(code);
+        let diagnostics = responses[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({
+                "start": {"line": 1, "character": 20},
+                "end": {"line": 1, "character": 34},
+            })
+        );
+        assert_eq!(definitions(&mut server, 1, 26), vec![(0, 4)]);
+        assert_eq!(
+            hover(&mut server, 1, 26),
+            "```sage\nadd: (Int, Int) -> Int\n```"
+        );
+    })
+}
+
+#[test]
+fn test_lsp_protocol() {
+    with_large_stack(|| {
+        // Frame a session of messages, as an editor would send them over stdio.
+        let mut input = vec![];
+        for message in [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "workspace/symbol", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ] {
+            write_message(&mut input, &message).unwrap();
+        }
+
+        let mut output = vec![];
+        LanguageServer::new()
+            .run(Cursor::new(input), &mut output)
+            .unwrap();
+
+        let mut output = Cursor::new(output);
+        let initialize = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(initialize["id"], 1);
+        let capabilities = &initialize["result"]["capabilities"];
+        assert_eq!(capabilities["textDocumentSync"], 1);
+        assert_eq!(capabilities["hoverProvider"], true);
+        assert_eq!(capabilities["definitionProvider"], true);
+
+        let unknown = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(unknown["id"], 2);
+        assert_eq!(unknown["error"]["code"], -32601);
+
+        let shutdown = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(shutdown["id"], 3);
+        assert_eq!(shutdown["result"], Value::Null);
+
+        assert!(read_message(&mut output).unwrap().is_none());
+    })
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{lir::Compile, parse::*, vm::*};
+use std::{
+    fs::{remove_dir_all, write},
+    ops::Deref,
+    path::PathBuf,
+};
+
+const CALL_STACK_SIZE: usize = 8192;
+
+/// A temporary directory of modules, which is removed when it's dropped.
+struct ModuleDir(PathBuf);
+
+impl Deref for ModuleDir {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+        &self.0
+    }
+}
+
+impl Drop for ModuleDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.0);
+    }
+}
+
+/// Write the given modules to a new directory, and return the path to it.
+fn write_modules(name: &str, modules: &[(&str, &str)]) -> ModuleDir {
+    let dir = std::env::temp_dir().join(format!("sage-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (file, code) in modules {
+        write(dir.join(file), code).unwrap();
+    }
+    ModuleDir(dir)
+}
+
+/// Parse the given frontend program, as though it were in the given directory.
+fn parse_in(dir: &PathBuf, code: &str) -> Result<sage::lir::Expr, String> {
+    parse_frontend(code, dir.join("main.sg").to_str())
+}
+
+/// Compile and run the given expression, and return its output.
+fn run(expr: sage::lir::Expr) -> String {
+    let device = match expr.compile().unwrap() {
+        Ok(asm_code) => CoreInterpreter::new(TestingDevice::new(""))
+            .run(&asm_code.assemble(CALL_STACK_SIZE).unwrap())
+            .unwrap(),
+        Err(asm_code) => StandardInterpreter::new(TestingDevice::new(""))
+            .run(&asm_code.assemble(CALL_STACK_SIZE).unwrap())
+            .unwrap(),
+    };
+    device.output_str()
+}
+
+#[test]
+fn test_import_namespaces() {
+    with_large_stack(test_import_namespaces_helper);
+}
+
+fn test_import_namespaces_helper() {
+    let dir = write_modules(
+        "import-namespaces",
+        &[
+            ("a.sg", "const X = 1; def get(): Int = X;"),
+            (
+                "b.sg",
+                "from a import get; const X = 2; def both(): Int = X + get();
+                def local(): Int { let X = 10; return X; }",
+            ),
+        ],
+    );
+    // The `X` of each module, and of the program, refer to different constants.
+    let expr = parse_in(
+        &dir,
+        "import b; import a; const X = 3; println(X, a::X, b::X, b::both(), b::local());",
+    )
+    .unwrap();
+    assert_eq!(run(expr), "312310\n");
+
+    let expr = parse_in(&dir, "from b import both, X; println(both(), X);").unwrap();
+    assert_eq!(run(expr), "32\n");
+}
+
+#[test]
+fn test_import_errors() {
+    let dir = write_modules(
+        "import-errors",
+        &[
+            ("cycle1.sg", "import cycle2; def f() {}"),
+            ("cycle2.sg", "import cycle1;"),
+            ("stmt.sg", "println(1);"),
+            ("decls.sg", "def f() {}"),
+        ],
+    );
+
+    let err = parse_in(&dir, "import cycle1;").unwrap_err();
+    assert!(err.contains("circular import"), "{err}");
+    let err = parse_in(&dir, "import stmt;").unwrap_err();
+    assert!(
+        err.contains("modules can only contain declarations"),
+        "{err}"
+    );
+    let err = parse_in(&dir, "from decls import g;").unwrap_err();
+    assert!(err.contains("does not declare `g`"), "{err}");
+    let err = parse_in(&dir, "import missing;").unwrap_err();
+    assert!(err.contains("could not import module"), "{err}");
+    let err = parse_in(&dir, "import \"decls.sg\" as a; import \"stmt.sg\" as a;").unwrap_err();
+    assert!(err.contains("namespace `a` is already used"), "{err}");
+}
+
+#[test]
+fn test_import_error_location() {
+    with_large_stack(test_import_error_location_helper);
+}
+
+fn test_import_error_location_helper() {
+    let dir = write_modules(
+        "import-location",
+        &[("bad.sg", "def bad(): Int {\n    return True;\n}\n")],
+    );
+    let expr = parse_in(&dir, "import bad;\nbad::bad();\n").unwrap();
+    // The first error reported is the one inside of the imported module.
+    let first_err = expr
+        .compile()
+        .map(|_| ())
+        .map_err(|e| e.into_errors().remove(0));
+    match first_err {
+        Err(sage::lir::Error::Annotated(_, metadata)) => {
+            let loc = metadata.location().unwrap();
+            assert_eq!(loc.filename, dir.join("bad.sg").to_str().map(String::from));
+            assert_eq!(loc.line, 2);
+        }
+        other => panic!("expected an annotated error, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_std_modules() {
+    with_large_stack(test_std_modules_helper);
+}
+
+fn test_std_modules_helper() {
+    for module in [
+        "panic", "option", "result", "iter", "vec", "hashmap", "string",
+    ] {
+        let expr = parse_frontend(format!("import std::{module};"), None)
+            .unwrap_or_else(|e| panic!("Could not parse `std::{module}`: {e}"));
+        if let Err(e) = expr.compile() {
+            panic!("Could not compile `std::{module}`: {e}")
+        }
+    }
+
+    let err = parse_frontend("import std::missing;", None).unwrap_err();
+    assert!(err.contains("there is no standard library module"), "{err}");
+}
+
+#[test]
+fn test_import_traits() {
+    with_large_stack(test_import_traits_helper);
+}
+
+fn test_import_traits_helper() {
+    let dir = write_modules(
+        "import-traits",
+        &[
+            (
+                "hash.sg",
+                "trait Hash { def hash(self: &Self): Int; }
+                impl Hash for Int { def hash(self: &Int): Int = *self + 100; }
+                def twice<T: Hash>(x: T): Int = x.hash() * 2;",
+            ),
+            ("reexport.sg", "from hash import Hash;"),
+        ],
+    );
+    // A trait imported by name is the same trait as the one in its module.
+    let expr = parse_in(
+        &dir,
+        "import hash; from reexport import Hash;
+        struct P { x: Int }
+        impl Hash for P { def hash(self: &P): Int = self.x; }
+        impl hash::Hash for Char { def hash(self: &Char): Int = 1; }
+        def once<T: Hash>(x: T): Int = x.hash();
+        println(hash::twice<P>({x=4}), hash::twice<Int>(1), once<Char>('a'));",
+    )
+    .unwrap();
+    assert_eq!(run(expr), "82021\n");
+
+    let expr = parse_in(&dir, "import hash; println(hash::twice<Bool>(True));").unwrap();
+    let err = expr.compile().unwrap_err().to_string();
+    assert!(err.contains("does not implement trait hash::Hash"), "{err}");
+}
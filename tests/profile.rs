@@ -0,0 +1,122 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{lir::Compile, parse::*, vm::*};
+
+const CALL_STACK_SIZE: usize = 8192;
+
+/// Compile and run the given frontend program, and return its profile.
+fn profile(code: &str) -> Profile {
+    let expr = parse_frontend(code, Some("main.sg")).unwrap();
+    match expr.compile().unwrap() {
+        Ok(asm_code) => {
+            let (vm_code, map) = asm_code.assemble_with_source_map(CALL_STACK_SIZE).unwrap();
+            let mut profile = Profile::from(&vm_code).with_source_map(map);
+            CoreInterpreter::new(TestingDevice::new(""))
+                .run_with_profile(&vm_code, &mut profile)
+                .unwrap();
+            profile
+        }
+        Err(asm_code) => {
+            let (vm_code, map) = asm_code.assemble_with_source_map(CALL_STACK_SIZE).unwrap();
+            let mut profile = Profile::from(&vm_code).with_source_map(map);
+            StandardInterpreter::new(TestingDevice::new(""))
+                .run_with_profile(&vm_code, &mut profile)
+                .unwrap();
+            profile
+        }
+    }
+}
+
+#[test]
+fn test_profile_recursion() {
+    with_large_stack(test_profile_recursion_helper);
+}
+
+fn test_profile_recursion_helper() {
+    let profile = profile(
+        r#"def fib(n: Int): Int {
+    if n < 2 {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+
+def outer(): Int = fib(5);
+
+println(outer());
+"#,
+    );
+
+    // Every instruction is attributed to exactly one procedure.
+    let procedures = profile.procedures();
+    assert_eq!(
+        procedures.iter().map(|(_, count)| count).sum::<u64>(),
+        profile.total()
+    );
+    assert_eq!(procedures[0].0, Some("fib"));
+    assert!(procedures.iter().any(|(name, _)| *name == Some("outer")));
+    assert!(procedures.iter().any(|(name, _)| name.is_none()));
+
+    // The body of `fib` runs more often than its definition line.
+    let lines = profile.lines();
+    let count = |line| {
+        lines
+            .iter()
+            .find(|((file, l), _)| *file == Some("main.sg") && *l == line)
+            .map(|(_, count)| *count)
+            .unwrap_or_default()
+    };
+    assert!(count(5) > count(3));
+    assert!(count(10) > 0);
+
+    // The folded stacks have an entry for each depth of the recursion.
+    let folded = profile.folded_stacks();
+    let stacks: Vec<(&str, u64)> = folded
+        .lines()
+        .map(|line| {
+            let (stack, count) = line.rsplit_once(' ').unwrap();
+            (stack, count.parse().unwrap())
+        })
+        .collect();
+    assert_eq!(
+        stacks.iter().map(|(_, count)| count).sum::<u64>(),
+        profile.total()
+    );
+    assert!(stacks.iter().any(|(stack, _)| *stack == "<main>"));
+    assert!(stacks.iter().any(|(stack, _)| *stack == "<main>;outer;fib"));
+    assert!(stacks
+        .iter()
+        .any(|(stack, _)| *stack == "<main>;outer;fib;fib;fib;fib;fib"));
+    assert!(!stacks
+        .iter()
+        .any(|(stack, _)| stack.matches("fib").count() > 5));
+
+    // The flat profile lists the procedures and the lines.
+    let flat = profile.to_string();
+    assert!(flat.starts_with(&format!("instructions executed: {}\n", profile.total())));
+    assert!(flat.contains("  fib\n"));
+    assert!(flat.contains("  main.sg:5\n"));
+}
+
+#[test]
+fn test_profile_raw_program() {
+    let program = CoreProgram(vec![
+        CoreOp::Comment("setup".to_string()),
+        CoreOp::Set(vec![3]),
+        CoreOp::While,
+        CoreOp::Dec(1),
+        CoreOp::End,
+    ]);
+    let mut profile = Profile::from(&program);
+    CoreInterpreter::new(TestingDevice::default())
+        .run_with_profile(&program, &mut profile)
+        .unwrap();
+    // The comment isn't counted, and the body and the end of the loop run three times.
+    assert_eq!(profile.total(), 1 + 1 + 3 + 3);
+    assert_eq!(
+        profile.folded_stacks(),
+        format!("<main> {}\n", profile.total())
+    );
+    assert!(profile.lines().is_empty());
+}
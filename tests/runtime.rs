@@ -0,0 +1,227 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{
+    runtime::{Program, RuntimeError, SourceType},
+    side_effects::FFIBinding,
+    vm::{TestingDevice, TrapKind},
+    Runtime,
+};
+
+#[test]
+fn test_run_frontend() {
+    with_large_stack(test_run_frontend_helper);
+}
+
+fn test_run_frontend_helper() {
+    let device = Runtime::with_device(TestingDevice::new("5"))
+        .run(
+            r#"def square(x: Int): Int = x * x;
+
+let mut n = 0;
+input(&mut n);
+println(square(n));
+"#,
+        )
+        .unwrap();
+    assert_eq!(device.output_str(), "25\n");
+}
+
+#[test]
+fn test_runtime_and_device_ffi() {
+    with_large_stack(test_runtime_and_device_ffi_helper);
+}
+
+fn test_runtime_and_device_ffi_helper() {
+    // The device has its own binding, which is still called through the runtime.
+    let mut device = TestingDevice::new("");
+    device.add_binding(FFIBinding::new("negate".to_string(), 1, 1), |call| {
+        let n = call.int_arg()?;
+        call.ret_int(-n)
+    });
+
+    let mut total = 0;
+    let device = Runtime::with_device(device)
+        .with_filename("main.sg")
+        .with_ffi(
+            FFIBinding::new("accumulate".to_string(), 2, 1),
+            move |call| {
+                total += call.int_arg()? * call.int_arg()?;
+                call.ret_int(total)
+            },
+        )
+        .run(
+            r#"extern def accumulate(a: Int, b: Int): Int;
+extern def negate(n: Int): Int;
+
+println(accumulate(2, 3));
+println(negate(accumulate(4, 5)));
+"#,
+        )
+        .unwrap();
+    assert_eq!(device.output_str(), "6\n-26\n");
+}
+
+#[test]
+fn test_compile_source_types() {
+    with_large_stack(test_compile_source_types_helper);
+}
+
+fn test_compile_source_types_helper() {
+    let program = Runtime::with_device(TestingDevice::new(""))
+        .compile("println(1 + 2);")
+        .unwrap();
+    assert!(program.is_core());
+    let program = Runtime::with_device(TestingDevice::new(""))
+        .compile("println(1.5);")
+        .unwrap();
+    assert!(matches!(program, Program::Standard(..)));
+
+    // Run virtual machine code directly.
+    let device = Runtime::with_device(TestingDevice::new(""))
+        .with_source_type(SourceType::CoreVM)
+        .run("set 3\nput stdout.int")
+        .unwrap();
+    assert_eq!(device.output_str(), "3");
+
+    // A standard program isn't a core program.
+    let std_vm_code = "set-f 1.5\nput stdout.float";
+    let result = Runtime::with_device(TestingDevice::new(""))
+        .with_source_type(SourceType::CoreVM)
+        .run(std_vm_code);
+    assert!(matches!(result, Err(RuntimeError::InvalidSource(_))));
+    let device = Runtime::with_device(TestingDevice::new(""))
+        .with_source_type(SourceType::StdVM)
+        .with_opt_level(1)
+        .run(std_vm_code)
+        .unwrap();
+    assert_eq!(device.output_str(), "1.5");
+}
+
+#[test]
+fn test_runtime_errors() {
+    with_large_stack(test_runtime_errors_helper);
+}
+
+fn test_runtime_errors_helper() {
+    let result = Runtime::with_device(TestingDevice::new("")).run("let x = ;");
+    assert!(matches!(result, Err(RuntimeError::Parse(_))));
+
+    let result = Runtime::with_device(TestingDevice::new("")).run("let x: Int = 'a';");
+    assert!(matches!(result, Err(RuntimeError::Lir(_))));
+
+    let result = Runtime::with_device(TestingDevice::new(""))
+        .with_filename("main.sg")
+        .run("let x = 0;\nprintln(1 / x);\n");
+    match result {
+        Err(RuntimeError::Trap(trap)) => {
+            assert_eq!(trap.kind, TrapKind::DivisionByZero);
+            assert_eq!(trap.location().map(|loc| loc.line), Some(2));
+        }
+        _ => panic!("expected a trap"),
+    }
+}
+
+#[test]
+fn test_malformed_programs_trap() {
+    with_large_stack(test_malformed_programs_trap_helper);
+}
+
+fn test_malformed_programs_trap_helper() {
+    let run = |source_type, code| {
+        Runtime::with_device(TestingDevice::new(""))
+            .with_source_type(source_type)
+            .run(code)
+    };
+
+    // The register only holds one cell after setting it to a single value.
+    match run(SourceType::CoreVM, "set 5\nadd 4") {
+        Err(RuntimeError::Trap(trap)) => {
+            assert_eq!(trap.kind, TrapKind::RegisterOutOfBounds(4, 1))
+        }
+        _ => panic!("expected a trap"),
+    }
+    match run(SourceType::StdVM, "set-f 1.5\nsin 3") {
+        Err(RuntimeError::Trap(trap)) => {
+            assert_eq!(trap.kind, TrapKind::RegisterOutOfBounds(3, 1))
+        }
+        _ => panic!("expected a trap"),
+    }
+
+    // Pointing past the end of the memory traps instead of aborting the host.
+    let result = run(
+        SourceType::CoreVM,
+        "set 9223372036854775807\nstore 1\nderef\nload 1",
+    );
+    assert!(matches!(
+        result,
+        Err(RuntimeError::Trap(trap)) if matches!(trap.kind, TrapKind::Other(_))
+    ));
+
+    // An empty register reads as zero, and overflowing arithmetic wraps.
+    let device = run(
+        SourceType::CoreVM,
+        "load 0\nput stdout.int\nset -1\nstore 1\nset -9223372036854775808\ndiv 1\nput stdout.int",
+    )
+    .unwrap();
+    assert_eq!(device.output_str(), "0-9223372036854775808");
+
+    // Malformed bytecode traps the same way.
+    let runtime = Runtime::with_device(TestingDevice::new("")).with_source_type(SourceType::CoreVM);
+    let bytecode = runtime.compile("set [1, 2]\nmul 3").unwrap().to_bytecode();
+    match runtime.run_bytecode(&bytecode) {
+        Err(RuntimeError::Trap(trap)) => {
+            assert_eq!(trap.kind, TrapKind::RegisterOutOfBounds(3, 2))
+        }
+        _ => panic!("expected a trap"),
+    }
+}
+
+#[test]
+fn test_closures_are_procedures() {
+    with_large_stack(test_closures_are_procedures_helper);
+}
+
+fn test_closures_are_procedures_helper() {
+    // A capturing closure and a named procedure are passed to the same monomorph.
+    let device = Runtime::with_device(TestingDevice::new(""))
+        .run(
+            r#"def apply_twice<T>(f: (T) -> T, x: T): T = f(f(x));
+def double(x: Int): Int = x * 2;
+
+let n = 5;
+let add_n = def[n](x: Int): Int = x + n;
+println(apply_twice<Int>(add_n, 1));
+println(apply_twice<Int>(double, 1));
+"#,
+        )
+        .unwrap();
+    assert_eq!(device.output_str(), "11\n4\n");
+}
+
+#[test]
+fn test_delete_closure_frees_environment() {
+    with_large_stack(test_delete_closure_frees_environment_helper);
+}
+
+fn test_delete_closure_frees_environment_helper() {
+    // Deleting a procedure without an environment does nothing.
+    let device = Runtime::with_device(TestingDevice::new(""))
+        .run("def f(): Int = 1;\nlet g = f;\ndel g;\ndel g;\nprintln(g());\n")
+        .unwrap();
+    assert_eq!(device.output_str(), "1\n");
+
+    // Deleting a closure frees its environment, so it can't be freed twice.
+    let result = Runtime::with_device(TestingDevice::new(""))
+        .with_filename("main.sg")
+        .run("let n = 1;\nlet f = def[n](): Int = n;\ndel f;\ndel f;\n");
+    match result {
+        Err(RuntimeError::Trap(trap)) => {
+            assert!(
+                matches!(trap.kind, TrapKind::Other(ref message) if message.contains("already freed"))
+            );
+            assert_eq!(trap.location().map(|loc| loc.line), Some(4));
+        }
+        _ => panic!("expected a trap"),
+    }
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{
+    lir::Compile,
+    parse::parse_frontend,
+    side_effects::{Input, Output},
+    targets::*,
+    vm::*,
+};
+
+#[test]
+fn test_sage_os_uses_allocator_hooks() {
+    let program = StandardProgram(vec![
+        StandardOp::CoreOp(CoreOp::Set(vec![4])),
+        StandardOp::Alloc, // Allocate 4 cells with `salloc`
+        StandardOp::CoreOp(CoreOp::Store(1)),
+        StandardOp::Free, // Free them with `sfree`
+    ]);
+
+    let code = SageOS.build_std(&program).unwrap();
+    assert!(code.contains("scalar_reg.p = (cell*)salloc(scalar_reg.i * sizeof(cell));"));
+    assert!(code.contains("sfree((void*)scalar_reg.p);"));
+    assert!(!code.contains("(cell*)malloc("));
+    assert!(!code.contains("free(scalar_reg.p)"));
+}
+
+#[test]
+fn test_sage_os_vector_ops() {
+    let program = CoreProgram(vec![
+        CoreOp::Set(vec![1, 2, 3]),
+        CoreOp::Store(3),
+        CoreOp::Add(3), // Double each cell of the register
+        CoreOp::Function,
+        CoreOp::Put(Output::stdout_int()),
+        CoreOp::Return,
+        CoreOp::End,
+    ]);
+
+    let code = SageOS.build_core(&program).unwrap();
+    assert!(code.contains("for (int i = 0; i < 3; i++) vector_reg[i].i += ptr[i].i;"));
+    assert!(code.contains("void f0(void) {"));
+    assert!(code.contains("putint(scalar_reg.i, 10);"));
+    // The entry point is defined after every procedure, outside of any function body.
+    assert!(code.find("void f0(void) {").unwrap() < code.find("int main(void) {").unwrap());
+    assert!(code.contains("funs[0] = f0;"));
+}
+
+#[test]
+fn test_sage_os_builds_bounds_checks() {
+    with_large_stack(test_sage_os_builds_bounds_checks_helper);
+}
+
+fn test_sage_os_builds_bounds_checks_helper() {
+    // Indexing a slice checks its bounds, which writes a panic message to stderr.
+    let code = r#"def get(xs: &[Int], i: Int): Int = xs[i];
+
+let xs = [1, 2, 3];
+println(get(&xs, 2));
+"#;
+    let program = parse_frontend(code, Some("main.sg"))
+        .unwrap()
+        .compile()
+        .unwrap();
+    let code = match program {
+        Ok(core) => SageOS.build_core(&core.assemble(8192).unwrap()),
+        Err(std) => SageOS.build_std(&std.assemble(8192).unwrap()),
+    }
+    .unwrap();
+    // The panic message is written to the terminal.
+    assert!(code.contains("putchar(scalar_reg.i);"));
+    assert!(!code.contains("stderr"));
+}
+
+#[test]
+fn test_sage_os_reads_floats_from_terminal() {
+    let program = StandardProgram(vec![StandardOp::CoreOp(CoreOp::Get(Input::stdin_float()))]);
+
+    // Floats are read from the same line buffer as characters and integers.
+    let code = SageOS.build_std(&program).unwrap();
+    assert!(code.contains("scalar_reg.f = sgetfloat();"));
+    assert!(!code.contains("scanf("));
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{lir::Compile, parse::*, vm::*};
+
+const CALL_STACK_SIZE: usize = 8192;
+
+const PROGRAM: &str = r#"def inner(x: Int): Int {
+    return 10 / x;
+}
+
+println(inner(2));
+"#;
+
+/// Compile the test program to the core virtual machine, along with its source map.
+fn compile_program() -> (CoreProgram, SourceMap) {
+    let expr = parse_frontend(PROGRAM, Some("main.sg")).unwrap();
+    expr.compile()
+        .unwrap()
+        .expect("the program should compile to the core variant")
+        .assemble_with_source_map(CALL_STACK_SIZE)
+        .unwrap()
+}
+
+#[test]
+fn test_source_map_locations() {
+    with_large_stack(test_source_map_locations_helper);
+}
+
+fn test_source_map_locations_helper() {
+    let (program, map) = compile_program();
+    assert_eq!(map.len(), program.0.len());
+
+    // The instructions in `inner` come from its definition, and its body is on line 2.
+    // The stack frame setup added by the assembler has no location.
+    let inner: Vec<usize> = (0..map.len())
+        .filter(|&i| map.procedure(i) == Some("inner"))
+        .collect();
+    assert!(!inner.is_empty());
+    assert!(map.location(inner[0]).is_none());
+    assert!(inner
+        .iter()
+        .filter_map(|&i| map.location(i))
+        .all(|loc| loc.line <= 2));
+    assert!(inner
+        .iter()
+        .any(|&i| matches!(map.location(i), Some(loc) if loc.line == 2)));
+
+    // The call to `inner` is made from the main program on line 5.
+    assert!((0..map.len())
+        .any(|i| map.procedure(i).is_none() && map.location(i).map(|loc| loc.line) == Some(5)));
+    assert!((0..map.len())
+        .filter_map(|i| map.location(i))
+        .all(|loc| loc.filename.as_deref() == Some("main.sg")));
+}
+
+#[test]
+fn test_source_lines_interleaved() {
+    with_large_stack(test_source_lines_interleaved_helper);
+}
+
+fn test_source_lines_interleaved_helper() {
+    let (program, map) = compile_program();
+    let listing = format!(
+        "{:#}",
+        program.with_source_lines(&map, |file| {
+            assert_eq!(file, Some("main.sg"));
+            Some(PROGRAM.to_string())
+        })
+    );
+    assert!(listing.contains("   2 |     return 10 / x;"));
+    assert!(listing.contains("   5 | println(inner(2));"));
+    // Each line is only shown once for a run of markers on the same line.
+    assert_eq!(listing.matches("   2 |     return 10 / x;").count(), 1);
+}
+
+#[test]
+fn test_source_map_keeps_spans() {
+    with_large_stack(test_source_map_keeps_spans_helper);
+}
+
+fn test_source_map_keeps_spans_helper() {
+    let (program, map) = compile_program();
+
+    // Each location marker is left in the program as a comment for readers of the code,
+    // but the map keeps the spans of the expressions, which the comments don't have.
+    assert!(!map.markers().is_empty());
+    for (i, location) in map.markers() {
+        assert_eq!(program.0[*i], CoreOp::Comment(location.to_comment()));
+    }
+    // Programs which weren't assembled have no locations.
+    let unassembled = SourceMap::from(&program);
+    assert!((0..unassembled.len()).all(|i| unassembled.location(i).is_none()));
+
+    let spans: Vec<&str> = (0..map.len())
+        .filter_map(|i| map.location(i))
+        .map(|loc| &PROGRAM[loc.offset..loc.offset + loc.length.unwrap()])
+        .collect();
+    assert!(spans.contains(&"return 10 / x"), "{spans:?}");
+    assert!(spans.contains(&"println(inner(2))"), "{spans:?}");
+}
+
+#[test]
+fn test_source_map_after_optimizing() {
+    with_large_stack(test_source_map_after_optimizing_helper);
+}
+
+fn test_source_map_after_optimizing_helper() {
+    let (program, map) = compile_program();
+    let (optimized, optimized_map) = program.clone().optimize_with_source_map(2, &map);
+    assert!(optimized.0.len() < program.0.len());
+    assert_eq!(optimized_map.len(), optimized.0.len());
+
+    // The markers are moved onto the same comments in the optimized program.
+    assert_eq!(optimized_map.markers().len(), map.markers().len());
+    for ((i, location), (j, optimized_location)) in
+        map.markers().iter().zip(optimized_map.markers())
+    {
+        assert_eq!(location, optimized_location);
+        assert_eq!(program.0[*i], optimized.0[*j]);
+    }
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use common::with_large_stack;
+use sage::{lir::Compile, parse::*, side_effects::Output, vm::*, NULL};
+
+const CALL_STACK_SIZE: usize = 8192;
+
+/// Compile and run the given frontend program, and return the trap which stopped it.
+fn run_until_trap(code: &str) -> Trap {
+    let expr = parse_frontend(code, Some("main.sg")).unwrap();
+    let result = match expr.compile().unwrap() {
+        Ok(asm_code) => {
+            let (program, map) = asm_code.assemble_with_source_map(CALL_STACK_SIZE).unwrap();
+            CoreInterpreter::new(TestingDevice::new(""))
+                .with_source_map(map)
+                .run(&program)
+                .map(|_| ())
+        }
+        Err(asm_code) => {
+            let (program, map) = asm_code.assemble_with_source_map(CALL_STACK_SIZE).unwrap();
+            StandardInterpreter::new(TestingDevice::new(""))
+                .with_source_map(map)
+                .run(&program)
+                .map(|_| ())
+        }
+    };
+    result.expect_err("the program should have trapped")
+}
+
+/// Get the procedure names and source lines of the frames on a trap's call stack.
+fn frames(trap: &Trap) -> Vec<(String, Option<usize>)> {
+    trap.stack
+        .iter()
+        .map(|frame| {
+            (
+                frame.procedure.clone().unwrap_or_default(),
+                frame.location.as_ref().map(|loc| loc.line),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_null_deref_traps() {
+    let program = CoreProgram(vec![
+        CoreOp::Set(vec![NULL]),
+        CoreOp::Store(1),
+        CoreOp::Deref,
+    ]);
+    let trap = CoreInterpreter::new(TestingDevice::default())
+        .run(&program)
+        .unwrap_err();
+    assert_eq!(trap.kind, TrapKind::NullPointer);
+    assert_eq!(trap.instruction, 2);
+}
+
+#[test]
+fn test_division_by_zero_stack() {
+    with_large_stack(test_division_by_zero_stack_helper);
+}
+
+fn test_division_by_zero_stack_helper() {
+    let trap = run_until_trap(
+        r#"def inner(x: Int): Int {
+    return 10 / x;
+}
+
+def outer(x: Int): Int = inner(x) + 1;
+
+println(outer(0));
+"#,
+    );
+    assert_eq!(trap.kind, TrapKind::DivisionByZero);
+    assert_eq!(
+        frames(&trap),
+        vec![
+            ("inner".to_string(), Some(2)),
+            ("outer".to_string(), Some(5)),
+            (String::new(), Some(7)),
+        ]
+    );
+    assert!(trap
+        .to_string()
+        .starts_with("runtime error at main.sg:2:12: division by zero\n"));
+}
+
+#[test]
+fn test_locations_after_block_comments() {
+    with_large_stack(test_locations_after_block_comments_helper);
+}
+
+fn test_locations_after_block_comments_helper() {
+    // The lines inside the block comment still count towards the location.
+    let trap = run_until_trap(
+        r#"/* Divide by zero
+   on line 5. */
+let y = 1;
+// A line comment.
+println(5 / (y - 1));
+"#,
+    );
+    assert_eq!(trap.kind, TrapKind::DivisionByZero);
+    assert_eq!(trap.location().map(|loc| loc.line), Some(5));
+}
+
+#[test]
+fn test_null_write_traps() {
+    with_large_stack(test_null_write_traps_helper);
+}
+
+fn test_null_write_traps_helper() {
+    let trap = run_until_trap(
+        r#"def crash() {
+    *(Null as &mut Int) = 0;
+}
+
+crash();
+"#,
+    );
+    assert_eq!(trap.kind, TrapKind::NullPointer);
+    assert_eq!(
+        frames(&trap),
+        vec![("crash".to_string(), Some(2)), (String::new(), Some(5))]
+    );
+}
+
+#[test]
+fn test_out_of_bounds_panic() {
+    with_large_stack(test_out_of_bounds_panic_helper);
+}
+
+fn test_out_of_bounds_panic_helper() {
+    let trap = run_until_trap(
+        r#"def get(xs: &[Int], i: Int): Int = xs[i];
+
+let xs = [1, 2, 3];
+println(get(&xs, 3));
+"#,
+    );
+    assert_eq!(trap.kind, TrapKind::Panic);
+    // The bounds check is reported at the index, not at the start of the definition.
+    assert_eq!(
+        trap.location().map(|loc| (loc.line, loc.column, loc.length)),
+        Some((1, 36, Some("xs[i]".len())))
+    );
+    // The program prints its own message, so the trap only reports the stack.
+    assert!(trap.to_string().starts_with("stack backtrace:\n"));
+}
+
+#[test]
+fn test_panic_messages_share_a_procedure() {
+    with_large_stack(test_panic_messages_share_a_procedure_helper);
+}
+
+fn test_panic_messages_share_a_procedure_helper() {
+    let expr = parse_frontend(
+        r#"def get(xs: &[Int], i: Int): Int = xs[i];
+
+let xs = [1, 2, 3];
+let s: &[Int] = &xs;
+println(get(&xs, 0) + s[1] + s[1..][1] + s[..2][0]);
+"#,
+        Some("main.sg"),
+    )
+    .unwrap();
+    let program = expr
+        .compile()
+        .unwrap()
+        .expect("the program should compile to the core variant")
+        .assemble(CALL_STACK_SIZE)
+        .unwrap();
+    // Every bounds check prints its message with the same procedure,
+    // instead of writing out the message one character at a time.
+    let puts = program
+        .0
+        .iter()
+        .filter(|op| **op == CoreOp::Put(Output::stderr_char()))
+        .count();
+    assert_eq!(puts, 1);
+    assert_eq!(
+        CoreInterpreter::new(TestingDevice::new(""))
+            .run(&program)
+            .unwrap()
+            .output_str(),
+        "7\n"
+    );
+}
@@ -1,4 +1,4 @@
-use sage::{side_effects::Output, vm::*};
+use sage::{runtime::Program, side_effects::Output, vm::*, Runtime};
 
 #[test]
 fn test_add() {
@@ -134,3 +134,447 @@ fn test_pointers() {
 
     assert_eq!(device.output_vals(), vec![b, a]);
 }
+
+#[test]
+fn test_optimize_peephole() {
+    let program = CoreProgram(vec![
+        CoreOp::Move(3),
+        CoreOp::Move(-3), // Cancels out the previous move
+        CoreOp::Set(vec![5]),
+        CoreOp::Store(1),
+        CoreOp::Load(1), // The register already holds the stored value
+        CoreOp::Move(2),
+        CoreOp::Set(vec![1]), // Overwritten before it's used
+        CoreOp::Set(vec![7]),
+        CoreOp::Move(-1),
+        CoreOp::Store(1),
+        CoreOp::Move(-1),
+        CoreOp::Load(1),
+        CoreOp::Put(Output::stdout_char()),
+    ]);
+
+    let optimized = program.clone().optimize(1);
+    assert_eq!(
+        optimized.0,
+        vec![
+            CoreOp::Set(vec![5]),
+            CoreOp::Store(1),
+            CoreOp::Set(vec![7]),
+            CoreOp::Move(1),
+            CoreOp::Store(1),
+            CoreOp::Move(-1),
+            CoreOp::Load(1),
+            CoreOp::Put(Output::stdout_char()),
+        ]
+    );
+
+    let device = CoreInterpreter::new(TestingDevice::default())
+        .run(&optimized)
+        .unwrap();
+    assert_eq!(device.output_vals(), vec![5]);
+}
+
+#[test]
+fn test_optimize_dead_code() {
+    let program = CoreProgram(vec![
+        // Function 0 is never called.
+        CoreOp::Function,
+        CoreOp::Set(vec![1]),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::End,
+        // Function 1 is called by the main program.
+        CoreOp::Function,
+        CoreOp::Set(vec![2]),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::Return,
+        CoreOp::Set(vec![3]), // Unreachable
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::End,
+        CoreOp::Set(vec![1]),
+        CoreOp::Call,
+    ]);
+
+    let optimized = program.clone().optimize(2);
+    assert_eq!(
+        optimized.0,
+        vec![
+            CoreOp::Function,
+            CoreOp::End,
+            CoreOp::Function,
+            CoreOp::Set(vec![2]),
+            CoreOp::Put(Output::stdout_char()),
+            CoreOp::Return,
+            CoreOp::End,
+            CoreOp::Set(vec![1]),
+            CoreOp::Call,
+        ]
+    );
+
+    let device = CoreInterpreter::new(TestingDevice::default())
+        .run(&optimized)
+        .unwrap();
+    assert_eq!(device.output_vals(), vec![2]);
+}
+
+#[test]
+fn test_alloc_reuses_freed_memory() {
+    let program = StandardProgram(vec![
+        StandardOp::CoreOp(CoreOp::Set(vec![4])),
+        StandardOp::Alloc,                                      // Allocate 4 cells
+        StandardOp::CoreOp(CoreOp::Put(Output::stdout_char())), // Print the address
+        StandardOp::CoreOp(CoreOp::Store(1)),                   // Save the address
+        StandardOp::CoreOp(CoreOp::Set(vec![2])),
+        StandardOp::Alloc, // Allocate 2 more cells after the first block
+        StandardOp::CoreOp(CoreOp::Put(Output::stdout_char())),
+        StandardOp::CoreOp(CoreOp::Load(1)),
+        StandardOp::Free, // Free the first block
+        StandardOp::CoreOp(CoreOp::Set(vec![3])),
+        StandardOp::Alloc, // Reuse the start of the first block
+        StandardOp::CoreOp(CoreOp::Put(Output::stdout_char())),
+        StandardOp::CoreOp(CoreOp::Set(vec![1])),
+        StandardOp::Alloc, // Reuse the rest of the first block
+        StandardOp::CoreOp(CoreOp::Put(Output::stdout_char())),
+    ]);
+
+    let i = StandardInterpreter::new(TestingDevice::default());
+    let device = i.run(&program).unwrap();
+
+    let addrs = device.output_vals();
+    assert_eq!(addrs[1], addrs[0] + 4);
+    assert_eq!(addrs[2], addrs[0]);
+    assert_eq!(addrs[3], addrs[0] + 3);
+}
+
+#[test]
+fn test_double_free() {
+    let program = StandardProgram(vec![
+        StandardOp::CoreOp(CoreOp::Set(vec![4])),
+        StandardOp::Alloc,
+        StandardOp::CoreOp(CoreOp::Store(1)),
+        StandardOp::Free,
+        StandardOp::CoreOp(CoreOp::Load(1)),
+        StandardOp::Free, // Free the same block again
+    ]);
+
+    let i = StandardInterpreter::new(TestingDevice::default());
+    let err = i.run(&program).unwrap_err();
+    assert!(
+        err.to_string().contains("already freed"),
+        "unexpected error: {err}"
+    );
+}
+
+#[test]
+fn test_free_unallocated() {
+    let program = StandardProgram(vec![
+        StandardOp::CoreOp(CoreOp::Set(vec![4])),
+        StandardOp::Alloc,
+        StandardOp::CoreOp(CoreOp::Inc(1)),
+        StandardOp::Free, // Free a pointer into the middle of the block
+    ]);
+
+    let i = StandardInterpreter::new(TestingDevice::default());
+    let err = i.run(&program).unwrap_err();
+    assert!(
+        err.to_string().contains("never allocated"),
+        "unexpected error: {err}"
+    );
+
+    // Freeing a null pointer does nothing.
+    let program = StandardProgram(vec![
+        StandardOp::CoreOp(CoreOp::Set(vec![sage::NULL])),
+        StandardOp::Free,
+    ]);
+    let i = StandardInterpreter::new(TestingDevice::default());
+    assert!(i.run(&program).is_ok());
+}
+
+/// Create a comment marking the instructions after it as coming from the given line.
+fn line_comment(line: usize) -> CoreOp {
+    CoreOp::Comment(format!("line {line}"))
+}
+
+/// Build the source map of a program, where the instructions after each `line_comment`
+/// come from its line.
+fn line_map(program: &CoreProgram) -> SourceMap {
+    let ops = StandardProgram::from(program.clone()).0;
+    let markers = program
+        .0
+        .iter()
+        .enumerate()
+        .filter_map(|(i, op)| match op {
+            CoreOp::Comment(comment) => Some((i, comment.strip_prefix("line ")?.parse().ok()?)),
+            _ => None,
+        })
+        .map(|(i, line)| {
+            let location = sage::parse::SourceCodeLocation {
+                line,
+                column: 1,
+                offset: 0,
+                length: None,
+                filename: Some("test.sg".to_string()),
+            };
+            (i, location)
+        })
+        .collect();
+    SourceMap::with_locations(&ops, markers)
+}
+
+/// Run some debugger commands, and return everything the debugger printed.
+fn debug_commands<I: Debuggable>(debugger: &mut Debugger<I>, commands: &[&str]) -> String {
+    let mut output = vec![];
+    for command in commands {
+        assert!(debugger.execute(command, &mut output).unwrap());
+    }
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn test_debugger() {
+    let program = CoreProgram(vec![
+        line_comment(1),
+        CoreOp::Set(vec![5]), // Instruction 0
+        CoreOp::Store(1),
+        line_comment(2),
+        CoreOp::Move(1), // Instruction 2
+        CoreOp::Set(vec![7]),
+        CoreOp::Store(1),
+        line_comment(3),
+        CoreOp::Load(1), // Instruction 5
+        CoreOp::Put(Output::stdout_char()),
+    ]);
+
+    let map = line_map(&program);
+    let mut debugger =
+        Debugger::new(CoreInterpreter::new(TestingDevice::default()), program).with_source_map(map);
+    assert_eq!(debugger.current_instruction(), Some(0));
+    assert_eq!(debugger.current_location().unwrap().line, 1);
+
+    // Stop at the start of the second line.
+    debug_commands(&mut debugger, &["break line 2", "continue"]);
+    assert_eq!(debugger.current_instruction(), Some(2));
+    assert_eq!(debugger.current_location().unwrap().line, 2);
+
+    // Stop when the cell is written to.
+    let output = debug_commands(&mut debugger, &["watch 1", "continue"]);
+    assert!(output.contains("Cell 1 changed: 0 -> 7"), "{output}");
+    assert_eq!(debugger.current_instruction(), Some(5));
+
+    // Single step through the rest of the program.
+    debug_commands(&mut debugger, &["step"]);
+    assert_eq!(debugger.current_instruction(), Some(6));
+    let output = debug_commands(&mut debugger, &["step", "step"]);
+    assert!(output.contains("The program finished"), "{output}");
+    assert!(output.contains("The program is not running"), "{output}");
+    assert!(debugger.is_finished());
+}
+
+#[test]
+fn test_debugger_stacks() {
+    let program = CoreProgram(vec![
+        CoreOp::Function, // Instruction 0
+        CoreOp::Set(vec![1]),
+        CoreOp::Return,
+        CoreOp::End,
+        CoreOp::Set(vec![0]), // Instruction 4
+        CoreOp::Call,
+        CoreOp::Refer, // Fails, because nothing was dereferenced
+    ]);
+
+    let mut debugger = Debugger::new(CoreInterpreter::new(TestingDevice::default()), program);
+    // Stop inside of the function.
+    let output = debug_commands(&mut debugger, &["break 0x1", "continue", "stack"]);
+    assert_eq!(debugger.current_instruction(), Some(1));
+    assert!(output.contains("#1 00000005"), "{output}");
+
+    // The error stops the program.
+    let output = debug_commands(&mut debugger, &["continue"]);
+    assert!(output.contains("Error: cannot Refer"), "{output}");
+    assert!(debugger.is_finished());
+}
+
+#[test]
+fn test_debugger_source_after_block_comment() {
+    let code = r#"/* A block comment
+   over two lines. */
+let mut x = 1;
+x = x + 1;
+println(x);
+"#;
+    let (program, map) = match Runtime::with_device(TestingDevice::default())
+        .with_filename("main.sg")
+        .compile(code)
+        .unwrap()
+    {
+        Program::Core(program, map) => (program, map),
+        Program::Standard(..) => panic!("expected a core program"),
+    };
+
+    // The debugger stops at the line in the file, and shows the code on that line.
+    let mut debugger = Debugger::new(CoreInterpreter::new(TestingDevice::default()), program)
+        .with_source_map(map)
+        .with_source(code);
+    let output = debug_commands(&mut debugger, &["break line 4", "continue"]);
+    assert_eq!(debugger.current_location().unwrap().line, 4);
+    assert!(output.contains("at main.sg:4:"), "{output}");
+    assert!(output.contains(": x = x + 1;"), "{output}");
+}
+
+/// Run a core program on both interpreters, and check they print the same values.
+fn run_on_both(program: CoreProgram) -> Vec<i64> {
+    let core = CoreInterpreter::new(TestingDevice::default())
+        .run(&program)
+        .unwrap();
+    let std = StandardInterpreter::new(TestingDevice::default())
+        .run(&StandardProgram::from(program))
+        .unwrap();
+    assert_eq!(core.output_vals(), std.output_vals());
+    core.output_vals()
+}
+
+#[test]
+fn test_nested_control_flow() {
+    let program = CoreProgram(vec![
+        CoreOp::Set(vec![3]), // Count down from 3
+        CoreOp::Store(1),
+        CoreOp::While,
+        CoreOp::Load(1),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::Dec(1), // Is the counter 2?
+        CoreOp::Dec(1),
+        CoreOp::Not(1),
+        CoreOp::If,
+        CoreOp::Set(vec![100]),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::Else,
+        CoreOp::Set(vec![200]),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::End,
+        CoreOp::Load(1), // Decrement the counter, and loop until it's 0
+        CoreOp::Dec(1),
+        CoreOp::Store(1),
+        CoreOp::End,
+    ]);
+
+    assert_eq!(run_on_both(program), vec![3, 200, 2, 100, 1, 200]);
+}
+
+#[test]
+fn test_call_before_definition() {
+    let program = CoreProgram(vec![
+        CoreOp::Set(vec![1]),
+        CoreOp::Call,
+        CoreOp::Set(vec![0]),
+        CoreOp::Call,
+        CoreOp::Function, // Function 0
+        CoreOp::Set(vec![10]),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::Return,
+        CoreOp::End,
+        CoreOp::Function, // Function 1, which defines a loop
+        CoreOp::While,
+        CoreOp::Set(vec![20]),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::Set(vec![0]),
+        CoreOp::End,
+        CoreOp::Return,
+        CoreOp::End,
+    ]);
+
+    assert_eq!(run_on_both(program), vec![20, 10]);
+}
+
+#[test]
+fn test_functions_numbered_by_position() {
+    // Function 0 is inside a block which never runs, but it still has its number,
+    // so the second `Function` instruction is function 1.
+    let program = CoreProgram(vec![
+        CoreOp::Set(vec![0]),
+        CoreOp::If,
+        CoreOp::Function, // Function 0
+        CoreOp::Set(vec![10]),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::Return,
+        CoreOp::End,
+        CoreOp::End,
+        CoreOp::Function, // Function 1
+        CoreOp::Set(vec![20]),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::Return,
+        CoreOp::End,
+        CoreOp::Set(vec![1]),
+        CoreOp::Call,
+        CoreOp::Set(vec![0]),
+        CoreOp::Call,
+    ]);
+
+    assert_eq!(run_on_both(program), vec![20, 10]);
+}
+
+#[test]
+fn test_call_undefined_function() {
+    let program = CoreProgram(vec![
+        CoreOp::Function,
+        CoreOp::Return,
+        CoreOp::End,
+        CoreOp::Set(vec![1]),
+        CoreOp::Call,
+    ]);
+
+    let trap = CoreInterpreter::new(TestingDevice::default())
+        .run(&program)
+        .unwrap_err();
+    assert_eq!(
+        trap.kind,
+        TrapKind::Other("function 1 not defined".to_string())
+    );
+    assert_eq!(trap.instruction, 4);
+}
+
+#[test]
+fn test_commented_control_flow() {
+    // Comments inside blocks don't change where the blocks jump,
+    // and traps report the instructions' addresses in the original program.
+    let program = CoreProgram(vec![
+        line_comment(1),
+        CoreOp::Function,
+        line_comment(2),
+        CoreOp::Set(vec![2]), // Call a function which doesn't exist
+        CoreOp::Call,
+        CoreOp::Return,
+        line_comment(3),
+        CoreOp::End,
+        CoreOp::Set(vec![2]),
+        CoreOp::While,
+        line_comment(4),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::Dec(1),
+        line_comment(5),
+        CoreOp::End,
+        CoreOp::Set(vec![0]),
+        CoreOp::If,
+        line_comment(6),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::Else,
+        line_comment(7),
+        CoreOp::Set(vec![9]),
+        CoreOp::Put(Output::stdout_char()),
+        CoreOp::End,
+    ]);
+    assert_eq!(run_on_both(program.clone()), vec![2, 1, 9]);
+
+    let mut program = program;
+    program.0.extend([CoreOp::Set(vec![0]), CoreOp::Call]);
+    let trap = CoreInterpreter::new(TestingDevice::default())
+        .with_source_map(line_map(&program))
+        .run(&program)
+        .unwrap_err();
+    assert_eq!(
+        trap.kind,
+        TrapKind::Other("function 2 not defined".to_string())
+    );
+    assert_eq!(trap.instruction, 4);
+    assert_eq!(trap.location().map(|loc| loc.line), Some(2));
+    assert_eq!(trap.stack.len(), 2);
+    assert_eq!(trap.stack[1].location.as_ref().map(|loc| loc.line), Some(7));
+}
@@ -0,0 +1,174 @@
+// The generated assembly is for x86-64 Linux, so it can only be run there.
+#![cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod common;
+
+use common::{with_large_stack, TempFile};
+use log::warn;
+use sage::{lir::Compile, parse::*, targets::*};
+use std::{
+    fs::{read_dir, read_to_string},
+    io::Write,
+    path::PathBuf,
+};
+
+const INPUT: &str = "2 4 8 16 32 64 128 256 512 1024 2048 4096";
+const CALL_STACK_SIZE: usize = 8192;
+/// Examples whose output can't be compared against the expected output.
+const SKIPPED_EXAMPLES: &[&str] = &[
+    // These print pointers, which differ between the targets.
+    "static.sg",
+    "sequence.sg",
+    "sequence2.sg",
+];
+
+#[test]
+fn test_x86_target_register_size() {
+    // The register only has room for 1024 cells.
+    let program = sage::vm::CoreProgram(vec![sage::vm::CoreOp::Set(vec![1; 1025])]);
+    assert!(X86_64::default().build_core(&program).is_err());
+    let program = sage::vm::CoreProgram(vec![sage::vm::CoreOp::Add(1024)]);
+    assert!(X86_64::default().build_core(&program).is_ok());
+}
+
+#[test]
+fn test_x86_target_frontend_examples() {
+    with_large_stack(test_x86_target_frontend_examples_helper);
+}
+
+fn test_x86_target_frontend_examples_helper() {
+    for entry in read_dir("examples/frontend/").unwrap() {
+        let entry = entry.unwrap();
+        let path = entry.path();
+        warn!("Starting test for `{path:?}`");
+        if path.is_file()
+            && matches!(
+                path.extension().map(|p| p
+                    .to_str()
+                    .expect("Couldn't get file extension of example code")
+                    .as_bytes()),
+                Some(b"sg") | None
+            )
+        {
+            let file_name = path
+                .file_name()
+                .unwrap_or_else(|| panic!("Could not get file name of path `{path:?}`"))
+                .to_str()
+                .unwrap_or_else(|| panic!("Could not get file name of path `{path:?}`"))
+                .to_string();
+            if SKIPPED_EXAMPLES.contains(&file_name.as_str()) {
+                warn!("Skipping the x86 test for `{path:?}`");
+                continue;
+            }
+            let correct_output_path = PathBuf::from("examples/test-output")
+                .join(file_name.clone())
+                .with_extension("txt");
+            let correct_error_path = PathBuf::from("examples/test-output")
+                .join(file_name.clone())
+                .with_extension("error.txt");
+            let correct_error = match read_to_string(&correct_error_path) {
+                Ok(contents) => Some(contents.replace("\r\n", "\n")),
+                Err(_) => None,
+            };
+            let correct_output_text = match read_to_string(&correct_output_path) {
+                Ok(contents) => contents.replace("\r\n", "\n"),
+                Err(_) if correct_error.is_none() => {
+                    warn!("Could not read output text file `{correct_output_path:?}` to compare against. Skipping this test.");
+                    continue;
+                }
+                Err(_) => String::new(),
+            };
+            let correct_output = correct_output_text
+                .as_bytes()
+                .iter()
+                .map(|byte| *byte as i64)
+                .collect::<Vec<_>>();
+
+            let frontend_src = read_to_string(&path)
+                .unwrap_or_else(|_| panic!("Could not read contents of file `{path:?}`"));
+            let frontend_code = parse_frontend(&frontend_src, path.to_str())
+                .unwrap_or_else(|_| panic!("Could not parse `{path:?}`"));
+            drop(frontend_src);
+            let asm_code = frontend_code.compile();
+
+            if let Err(ref e) = asm_code {
+                if let Some(correct_error) = correct_error {
+                    let text = e.to_string();
+                    if text != correct_error {
+                        panic!("{text:?} != {correct_error:?}, error did not match correct error for program {path:?}")
+                    } else {
+                        continue;
+                    }
+                } else {
+                    panic!("Could not assemble code in `{path:?}`: {e}")
+                }
+            }
+            let asm_code = asm_code.unwrap();
+
+            let vm_code = match asm_code {
+                Ok(core_asm_code) => core_asm_code.assemble(CALL_STACK_SIZE).map(Ok),
+                Err(std_asm_code) => std_asm_code.assemble(CALL_STACK_SIZE).map(Err),
+            }
+            .unwrap();
+
+            let asm_code = match vm_code {
+                Ok(vm_code) => X86_64::default().build_core(&vm_code.flatten()).unwrap(),
+                Err(vm_code) => X86_64::default().build_std(&vm_code.flatten()).unwrap(),
+            };
+
+            // Write the assembly code to a file, which is removed when the test is done with it.
+            let asm_code_file = TempFile::new(&format!("tmp_x86_code_{file_name}.s"));
+            let asm_code_path = asm_code_file.path();
+            std::fs::write(asm_code_path, asm_code).unwrap();
+
+            // Assemble and link the assembly code.
+            let exe_file = TempFile::new(&format!("tmp_x86_code_{file_name}.exe"));
+            let exe_path = exe_file.path();
+            let compile_output = std::process::Command::new("gcc")
+                .arg(asm_code_path)
+                .arg("-o")
+                .arg(exe_path)
+                .arg("-lm")
+                .output()
+                .unwrap();
+
+            if !compile_output.status.success() {
+                panic!("Could not assemble x86 code for `{path:?}`: {compile_output:?}");
+            }
+
+            // Run the program with the input, and confirm that the output matches the expected output.
+            let stdin = std::process::Stdio::piped();
+            let stdout = std::process::Stdio::piped();
+            let mut exe = match std::process::Command::new(exe_path)
+                .stdin(stdin)
+                .stdout(stdout)
+                .spawn()
+            {
+                Ok(v) => v,
+                Err(e) => panic!("Could not run x86 code for `{path:?}`: {e}"),
+            };
+
+            // Programs that don't read their input may exit before it's written.
+            if let Err(e) = exe.stdin.as_mut().unwrap().write_all(INPUT.as_bytes()) {
+                if e.kind() != std::io::ErrorKind::BrokenPipe {
+                    panic!("Could not write to stdin of program `{path:?}`");
+                }
+            }
+
+            // Get stdout from the program.
+            let output = exe.wait_with_output().unwrap().stdout;
+            // Convert both to strings
+            let correct_output = correct_output
+                .iter()
+                .map(|byte| *byte as u8)
+                .collect::<Vec<_>>();
+
+            let output = String::from_utf8(output).unwrap();
+            let correct_output = String::from_utf8(correct_output).unwrap();
+
+            assert_eq!(
+                output, correct_output,
+                "Output did not match correct output for program `{path:?}`"
+            );
+        }
+    }
+}